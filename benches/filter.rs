@@ -0,0 +1,209 @@
+#[macro_use]
+extern crate criterion;
+#[macro_use]
+extern crate slog;
+extern crate regex;
+#[macro_use]
+extern crate slog_kvfilter;
+
+use std::fmt::Debug;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::Criterion;
+use regex::Regex;
+use slog::{Drain, Level, Logger};
+use slog_kvfilter::testing::CountingDrain;
+use slog_kvfilter::{cached_context, FilterStr, KVFilter, KVFilterList};
+
+fn filter_list(filters: &[(&'static str, &[&'static str])]) -> KVFilterList<FilterStr, FilterStr> {
+    filters
+        .iter()
+        .map(|&(key, values)| (key.into(), values.iter().map(|&value| value.into()).collect()))
+        .collect()
+}
+
+fn przygienda_filter(drain: CountingDrain) -> KVFilter<CountingDrain> {
+    KVFilter::new(drain, Level::Info)
+        .only_pass_any_on_all_keys_with(Some(filter_list(&[
+            ("thread", &["100", "200"]),
+            ("direction", &["send", "receive"]),
+        ])))
+        .always_suppress_any_with(Some(filter_list(&[
+            ("deepcomp", &["1", "2"]),
+            ("deepercomp", &["4", "5"]),
+        ])))
+}
+
+static_kvfilter! {
+    /// `przygienda_filter` known at compile time
+    struct StaticPrzygienda {
+        pass { "thread" => ["100", "200"], "direction" => ["send", "receive"] }
+        suppress { "deepcomp" => ["1", "2"], "deepercomp" => ["4", "5"] }
+    }
+}
+
+/// child logger of `$log`, its context cached by the filter if `$cached`
+macro_rules! child {
+    ($log:expr, $cached:expr, $($kv:tt)*) => {
+        if $cached {
+            $log.new(cached_context(o!($($kv)*)))
+        } else {
+            $log.new(o!($($kv)*))
+        }
+    };
+}
+
+/// Mix of passing and suppressed records through a small hierarchy of loggers, in the
+/// spirit of the unit tests, with the contexts of the child loggers cached if `cached`, and
+/// 16 more keys of no interest to the filter in the context if `wide`.
+fn przygienda<F>(c: &mut Criterion, name: &str, setup: fn(CountingDrain) -> F, cached: bool, wide: bool)
+where
+    F: Drain + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    F::Err: Debug,
+{
+    let count = CountingDrain::new();
+    let filter = setup(count.clone());
+
+    let mut mainlog = Logger::root(filter.fuse(), o!("version" => "1", "host" => "localhost"));
+    if wide {
+        mainlog = mainlog.new(o!(
+            "k0" => 0, "k1" => 1, "k2" => 2, "k3" => 3, "k4" => 4, "k5" => 5, "k6" => 6, "k7" => 7,
+            "k8" => 8, "k9" => 9, "k10" => 10, "k11" => 11, "k12" => 12, "k13" => 13, "k14" => 14, "k15" => 15
+        ));
+    }
+    let sublog = child!(mainlog, cached, "thread" => "200", "sub" => "sub");
+    let subsublog = child!(sublog, cached, "direction" => "send");
+    let negsubsublog = child!(subsublog, cached, "deepcomp" => "1");
+    let wrongthread = child!(mainlog, cached, "thread" => "400", "sub" => "sub");
+
+    let log_all = || {
+        info!(mainlog, "filtered, main, no keys");
+        info!(mainlog, "unfiltered, all keys on record";
+              "thread" => "100", "direction" => "send", "packet" => 1);
+        info!(wrongthread, "filtered, wrong thread"; "direction" => "send");
+        info!(sublog, "unfiltered, direction on record"; "direction" => "receive");
+        info!(subsublog, "unfiltered, everything in context");
+        info!(subsublog, "filtered, negative on record"; "deepercomp" => "4");
+        info!(negsubsublog, "filtered, negative in context"; "packet" => 2);
+        debug!(subsublog, "filtered, level too low");
+    };
+
+    log_all();
+    assert_eq!(count.count(), 3);
+
+    c.bench_function(name, |b| b.iter(&log_all));
+}
+
+fn bench_przygienda(c: &mut Criterion) {
+    przygienda(c, "przygienda", przygienda_filter, false, false);
+    przygienda(c, "przygienda cached", |d| przygienda_filter(d).cache_context(), true, false);
+    przygienda(c, "przygienda wide", przygienda_filter, false, true);
+    przygienda(c, "przygienda wide cached", |d| przygienda_filter(d).cache_context(), true, true);
+    przygienda(
+        c,
+        "przygienda regex",
+        |d| {
+            przygienda_filter(d)
+                .only_pass_on_regex(Regex::new("unfiltered").unwrap())
+                .always_suppress_on_regex(Regex::new("negative").unwrap())
+        },
+        false,
+        false,
+    );
+    przygienda(c, "przygienda static", |d| StaticPrzygienda::new(d, Level::Info), false, false);
+}
+
+/// Spec of `keys` keys, all of them present in the logger context, so that every record has
+/// to track that many matched keys.
+fn bench_keys(c: &mut Criterion) {
+    for &keys in &[1, 16, 64, 65, 128] {
+        let count = CountingDrain::new();
+        let names: Vec<String> = (0..keys).map(|i| format!("key{}", i)).collect();
+        let filter = KVFilter::new(count.clone(), Level::Info).only_pass_any_on_all_keys(Some(slog_kvfilter::filter_list(
+            names.iter().map(|name| (name.clone(), vec!["v"])),
+        )));
+
+        let mut log = Logger::root(filter.fuse(), o!());
+        for name in names {
+            let name: &'static str = Box::leak(name.into_boxed_str());
+            log = log.new(o!(name => "v"));
+        }
+
+        let log_one = || info!(log, "unfiltered, all keys in context"; "packet" => 1);
+
+        log_one();
+        assert_eq!(count.count(), 1);
+
+        c.bench_function(&format!("{} keys", keys), |b| b.iter(&log_one));
+    }
+}
+
+/// Spec of a single key with a handful of values, the most common one, matched on the record
+/// and in the logger context.
+fn bench_single_key(c: &mut Criterion) {
+    let count = CountingDrain::new();
+    let filter = KVFilter::new(count.clone(), Level::Info)
+        .only_pass_any_on_all_keys_with(Some(filter_list(&[("tenant", &["acme", "globex", "initech", "umbrella"])])));
+
+    let log = Logger::root(filter.fuse(), o!("version" => "1", "host" => "localhost"));
+    let acme = log.new(o!("tenant" => "acme", "thread" => 100));
+    let log_all = || {
+        info!(acme, "unfiltered, tenant in context"; "packet" => 1);
+        info!(log, "unfiltered, tenant on record"; "tenant" => "globex", "packet" => 2);
+        info!(log, "filtered, other tenant"; "tenant" => "hooli", "packet" => 3);
+        info!(log, "filtered, no tenant"; "packet" => 4);
+    };
+
+    log_all();
+    assert_eq!(count.count(), 2);
+
+    c.bench_function("single key", |b| b.iter(&log_all));
+}
+
+/// Runtime updates of a spec of owned keys and values, each copying the whole spec.
+fn bench_update(c: &mut Criterion) {
+    let filter = KVFilter::new(slog::Discard, Level::Info).only_pass_any_on_all_keys(Some(slog_kvfilter::filter_list(
+        (0..64).map(|i| (format!("key{}", i), (0..8).map(|j| format!("value{}", j)).collect::<Vec<_>>())),
+    )));
+    let handle = filter.handle();
+
+    c.bench_function("update 64 keys", |b| {
+        b.iter(|| handle.update_spec(|filter| filter.always_suppress_any(None)))
+    });
+}
+
+/// Records of 8 threads at once, each record counted by the cap on its client and tracing
+/// its session, so that the threads contend for the state of the filter.
+fn bench_contended(c: &mut Criterion) {
+    const THREADS: usize = 8;
+    const RECORDS: usize = 1000;
+
+    let count = CountingDrain::new();
+    let filter = KVFilter::new(count.clone(), Level::Info)
+        .cap_per_value("client", usize::MAX, Duration::from_secs(60))
+        .sticky_on_key("session", Duration::from_secs(60));
+    let log = Logger::root(filter.fuse(), o!());
+
+    c.bench_function("8 threads capped and sticky", |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            thread::scope(|scope| {
+                for thread in 0..THREADS {
+                    let log = &log;
+                    scope.spawn(move || {
+                        for i in 0..iters as usize * RECORDS / THREADS {
+                            let value = thread * 64 + i % 64;
+                            info!(log, "passing"; "client" => value, "session" => value);
+                        }
+                    });
+                }
+            });
+            start.elapsed()
+        })
+    });
+}
+
+criterion_group!(benches, bench_przygienda, bench_keys, bench_single_key, bench_update, bench_contended);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+//! Inspects and updates the filters a running process publishes by `Registry`:
+//!
+//! ```text
+//! kvfilterctl PID list
+//! kvfilterctl PID spec NAME
+//! kvfilterctl PID stats NAME
+//! kvfilterctl PID set NAME CONFIG
+//! ```
+
+extern crate slog_kvfilter;
+
+use std::env;
+use std::process;
+
+use slog_kvfilter::RegistryClient;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let pid = match args.first().and_then(|pid| pid.parse().ok()) {
+        Some(pid) => pid,
+        None => {
+            eprintln!("usage: kvfilterctl PID list | spec NAME | stats NAME | set NAME CONFIG");
+            process::exit(2);
+        }
+    };
+
+    match RegistryClient::new(pid).request(&args[1..].join(" ")) {
+        Ok(body) => print!("{}", body),
+        Err(err) => {
+            eprintln!("kvfilterctl: {}", err);
+            process::exit(1);
+        }
+    }
+}
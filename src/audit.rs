@@ -0,0 +1,118 @@
+//! Trail of the changes made to filters at runtime, through their handles
+
+use std::collections::VecDeque;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use slog;
+use slog::KV;
+
+use clock::WallTime;
+use sync::Mutex;
+use SpecChange;
+
+/// number of entries kept, the oldest ones are dropped
+const CAPACITY: usize = 1024;
+
+/// Change made through a `KVFilterHandle`, see `KVFilterHandle::audit_trail`
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    /// wall time of the clock of the filter, see `KVFilter::clock`
+    pub at: WallTime,
+    /// who made the change and why, see `KVFilterHandle::with_actor`
+    pub actor: Option<String>,
+    /// the handle method called, with its arguments, e.g. `set_min_level(DEBG)`
+    pub action: String,
+    /// changes to the spec, for the actions replacing it
+    pub changes: Vec<SpecChange>,
+}
+
+impl KV for AuditEntry {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        if let Some(ref actor) = self.actor {
+            serializer.emit_str("kvfilter_audit_actor", actor)?;
+        }
+        serializer.emit_str("kvfilter_audit_action", &self.action)?;
+        if !self.changes.is_empty() {
+            let changes: Vec<String> = self.changes.iter().map(|change| change.to_string()).collect();
+            serializer.emit_str("kvfilter_audit_changes", &changes.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Audit trail shared between a filter and its handles
+#[derive(Default)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    /// whether the entries are logged to the drain of the filter, see `KVFilter::log_audit_trail`
+    emit: AtomicBool,
+    /// entries not logged yet, counted to check cheaply
+    pending: AtomicUsize,
+    unlogged: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn record(&self, entry: AuditEntry) {
+        if self.emit.load(Ordering::Relaxed) {
+            self.unlogged.lock().push(entry.clone());
+            self.pending.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut entries = self.entries.lock();
+        if entries.len() >= CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().iter().cloned().collect()
+    }
+
+    pub fn set_emit(&self, emit: bool) {
+        self.emit.store(emit, Ordering::Relaxed)
+    }
+
+    /// the entries to be logged since the last call
+    pub fn take_unlogged(&self) -> Vec<AuditEntry> {
+        // checked first so that records don't take the lock when nothing changed
+        if self.pending.load(Ordering::Relaxed) == 0 {
+            return Vec::new();
+        }
+        self.pending.store(0, Ordering::Relaxed);
+        ::std::mem::take(&mut *self.unlogged.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditEntry, AuditLog, CAPACITY};
+    use std::time::UNIX_EPOCH;
+
+    fn entry(action: &str) -> AuditEntry {
+        AuditEntry {
+            at: UNIX_EPOCH,
+            actor: None,
+            action: action.to_owned(),
+            changes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn boundedaudit() {
+        let log = AuditLog::default();
+        log.record(entry("first"));
+        assert!(log.take_unlogged().is_empty());
+
+        log.set_emit(true);
+        for _ in 0..CAPACITY {
+            log.record(entry("later"));
+        }
+        let entries = log.entries();
+        assert_eq!(entries.len(), CAPACITY);
+        assert!(entries.iter().all(|entry| entry.action == "later"));
+        assert_eq!(log.take_unlogged().len(), CAPACITY);
+        assert!(log.take_unlogged().is_empty());
+    }
+}
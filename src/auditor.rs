@@ -0,0 +1,133 @@
+//! Counting what a candidate configuration would pass, see `KVAuditor`
+
+use std::prelude::v1::*;
+use std::sync::Arc;
+
+use slog;
+use slog::Drain;
+
+use stats::FilterStats;
+use {FilterStr, KVFilter, KVFilterHandle};
+
+/// Drain forwarding nothing, but counting the records a filter would pass and suppress,
+/// by the values of the keys of its spec, e.g. to evaluate a new configuration against
+/// live traffic alongside the one in use:
+///
+/// ```
+/// # #[macro_use] extern crate slog;
+/// # extern crate slog_kvfilter;
+/// use slog::Drain;
+/// use slog_kvfilter::{filter_list, KVAuditor, KVFilter};
+///
+/// # fn main() {
+/// let current = KVFilter::new(slog::Discard, slog::Level::Info);
+/// let candidate = KVAuditor::new(
+///     KVFilter::new(slog::Discard, slog::Level::Info)
+///         .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])]))),
+/// );
+/// let stats = candidate.clone();
+///
+/// let log = slog::Logger::root(slog::Duplicate::new(current, candidate).fuse(), o!());
+/// info!(log, "request"; "tenant" => "globex");
+/// assert_eq!(stats.stats().suppressed, 1);
+/// # }
+/// ```
+///
+/// Clones share the counts. The drains registered by `KVFilter::route_to` are replaced,
+/// the records routed are only counted as such.
+#[derive(Clone)]
+pub struct KVAuditor {
+    filter: KVFilter<slog::Discard>,
+}
+
+impl KVAuditor {
+    /// auditor deciding like `filter`, counting by each key of its spec as by
+    /// `KVFilter::count_by_key`
+    pub fn new(filter: KVFilter<slog::Discard>) -> Self {
+        let spec = filter.spec();
+        let mut filter = spec
+            .compiled
+            .names()
+            .chain(spec.compiled_all.names())
+            .fold(filter, |filter, key| filter.count_by_key(key.to_owned()));
+        for drain in filter.routes.values_mut() {
+            *drain = Arc::new(slog::Discard);
+        }
+        KVAuditor { filter }
+    }
+
+    /// auditor deciding like the filters controlled by `handle`, following its updates, and
+    /// counting by the keys of its current spec
+    pub fn controlled_by(handle: &KVFilterHandle) -> Self {
+        KVAuditor::new(KVFilter::new(slog::Discard, slog::Level::Trace).controlled_by(handle))
+    }
+
+    /// also count by the values of `key`
+    pub fn count_by_key<K: Into<FilterStr>>(self, key: K) -> Self {
+        KVAuditor {
+            filter: self.filter.count_by_key(key),
+        }
+    }
+
+    /// handle to update the candidate configuration, see `KVFilter::handle`
+    pub fn handle(&self) -> KVFilterHandle {
+        self.filter.handle()
+    }
+
+    /// the records counted so far, with `FilterStats::keys` by the values of the keys of the
+    /// spec
+    pub fn stats(&self) -> FilterStats {
+        self.filter.handle().stats()
+    }
+}
+
+impl Drain for KVAuditor {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+        self.filter.log(record, logger_values).map(|_| ())
+    }
+
+    /// records the audited filter doesn't consider aren't counted
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        self.filter.is_enabled(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KVAuditor;
+    use slog::{self, Drain, Duplicate, Level, Logger};
+    use testing::CountingDrain;
+    use {filter_list, KVFilter};
+
+    #[test]
+    fn kvauditor() {
+        let drain = CountingDrain::new();
+        let auditor = KVAuditor::new(
+            KVFilter::new(slog::Discard, Level::Info)
+                .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+                .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])]))),
+        );
+        let log = Logger::root(Duplicate::new(drain.clone(), auditor.clone()).fuse(), o!());
+
+        info!(log, "passing"; "tenant" => "acme");
+        info!(log, "suppressed"; "tenant" => "acme", "deepcomp" => 1);
+        info!(log, "suppressed"; "tenant" => "globex");
+        warn!(log, "passing, above the level"; "tenant" => "globex");
+        assert_eq!(drain.count(), 4);
+
+        let stats = auditor.stats();
+        assert_eq!((stats.passed, stats.suppressed), (2, 2));
+        let counts: Vec<_> = stats
+            .keys
+            .iter()
+            .map(|count| (count.key.as_str(), count.value.as_str(), count.passed, count.suppressed))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("deepcomp", "1", 0, 1), ("tenant", "acme", 1, 1), ("tenant", "globex", 1, 1)]
+        );
+    }
+}
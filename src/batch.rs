@@ -0,0 +1,342 @@
+//! Evaluation of a spec against a corpus of records, e.g. to validate a configuration change
+//! against recorded production samples before deploying it.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::prelude::v1::*;
+use std::sync::Arc;
+
+use slog;
+
+use combinators::SerializedKV;
+use compiled::{MatchedBy, Polarity};
+use force::Force;
+use testing::CapturedRecord;
+use {FilterSpec, KVFilter};
+
+/// Outcome of `FilterSpec::evaluate_batch`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub passed: usize,
+    pub suppressed: usize,
+    /// counts of the records by the keys of the spec
+    pub keys: BTreeMap<String, KeyReport>,
+    /// counts of the records by the rule of the spec deciding them
+    pub rules: BTreeMap<DecidedBy, RuleReport>,
+}
+
+/// Rule of a spec deciding a record of a batch
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DecidedBy {
+    /// the `FORCE_KEY` of the record, see `KVFilter::honor_force_key`
+    Forced,
+    /// the level of the record, suppressed below the minimum level or that of its module,
+    /// or passed unfiltered above the level of the spec
+    Level,
+    /// a rule set at runtime by a trigger or a sticky key of the spec, or the pass-through
+    /// window
+    Runtime,
+    /// a value listed for `key` by `only_pass_any_on_all_keys`, `always_suppress_any` and
+    /// the like, that of the first key of the record matching if it passes
+    Value { key: String, value: String },
+    /// a `ValuePattern` of `key`, as for `Value`
+    Pattern { key: String },
+    /// no value of `key` passed on by `only_pass_any_on_all_keys` and the like
+    Unmatched { key: String },
+    /// the message, by the regexes of `only_pass_on_regex` and `always_suppress_on_regex`
+    Message,
+    /// nothing suppressing a record of a spec passing the records by default
+    Default,
+    /// anything else, e.g. `always_suppress_all`, the combinations of values, the sampling
+    /// or the caps
+    Other,
+}
+
+/// How many records a rule passed and suppressed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleReport {
+    pub passed: usize,
+    pub suppressed: usize,
+}
+
+/// How many records had a value of a key matching the filters of the spec
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyReport {
+    /// records with a value passed on by `only_pass_any_on_all_keys` and the like
+    pub matched: usize,
+    /// records with a value suppressed by `always_suppress_any` and the like
+    pub suppressed: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "passed: {}, suppressed: {}", self.passed, self.suppressed)?;
+        for (key, report) in &self.keys {
+            writeln!(
+                f,
+                "  {}: matched {}, suppressed {}",
+                key, report.matched, report.suppressed
+            )?;
+        }
+        for (rule, report) in &self.rules {
+            writeln!(f, "  by {}: passed {}, suppressed {}", rule, report.passed, report.suppressed)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DecidedBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecidedBy::Forced => f.write_str("force key"),
+            DecidedBy::Level => f.write_str("level"),
+            DecidedBy::Runtime => f.write_str("runtime rule"),
+            DecidedBy::Value { ref key, ref value } => write!(f, "{}: {}", key, value),
+            DecidedBy::Pattern { ref key } => write!(f, "{}: pattern", key),
+            DecidedBy::Unmatched { ref key } => write!(f, "{}: unmatched", key),
+            DecidedBy::Message => f.write_str("message"),
+            DecidedBy::Default => f.write_str("default"),
+            DecidedBy::Other => f.write_str("other"),
+        }
+    }
+}
+
+/// Keys of the records of a batch, as long as the batch is decided
+#[derive(Default)]
+struct Keys(HashSet<Box<str>>);
+
+impl Keys {
+    /// `key` as the `'static` key slog needs
+    fn get(&mut self, key: &str) -> &'static str {
+        if !self.0.contains(key) {
+            self.0.insert(key.into());
+        }
+        let key: &str = self.0.get(key).expect("inserted above");
+        // boxed, so not moved while the set grows, and kept until the batch is decided; the
+        // filter deciding it doesn't keep the keys of the records, and is dropped first
+        unsafe { &*(key as *const str) }
+    }
+}
+
+impl FilterSpec {
+    /// decide `records` by this spec, e.g. captured by `testing::CapturingDrain` or
+    /// synthetic ones, counting the passed and the suppressed ones. The key values of the
+    /// records are seen as being on the record, with no logger context. Runtime state of
+    /// the filter, e.g. the minimum level or rules set through a handle, isn't involved.
+    /// Captured values are text, so they never match `ValuePattern::Number`s or
+    /// `ValuePattern::Bool`s.
+    pub fn evaluate_batch<I: IntoIterator<Item = CapturedRecord>>(&self, records: I) -> Report {
+        let mut keys = Keys::default();
+        let filter = KVFilter::new(slog::Discard, self.level).with_spec(Arc::new(self.clone()));
+        let mut report = Report::default();
+        for name in self.compiled.names() {
+            report.keys.insert(name.to_owned(), KeyReport::default());
+        }
+
+        let context = slog::OwnedKVList::from(slog::OwnedKV(()));
+        for record in records {
+            let kv = SerializedKV(
+                record
+                    .key_values
+                    .iter()
+                    .map(|(key, value)| (keys.get(key), value.clone().into()))
+                    .collect(),
+            );
+            let rs = slog::RecordStatic {
+                location: &slog::RecordLocation {
+                    file: "",
+                    line: 0,
+                    column: 0,
+                    function: "",
+                    module: "",
+                },
+                tag: "",
+                level: record.level,
+            };
+            let message = &record.message;
+            let args = format_args!("{}", message);
+            let info = slog::Record::new(&rs, &args, slog::BorrowedKV(&kv));
+            let (passed, decided_by) = match filter.forced(&info) {
+                Some(force) => (force == Force::Pass, DecidedBy::Forced),
+                None => {
+                    // as in `decide_selected`, before the triggers of the record fire
+                    let ruled = filter.rules.passed_at(&info, &context).is_some();
+                    let windowed = self.pass_through.is_some() && filter.rules.in_window();
+                    let mut by_level = false;
+                    let passed = filter.decide_noting(&info, &context, &mut by_level).is_some();
+                    let decided_by = if by_level {
+                        DecidedBy::Level
+                    } else if passed && (ruled || windowed) {
+                        DecidedBy::Runtime
+                    } else {
+                        self.select(&info, &context).decided_by(&record, passed)
+                    };
+                    (passed, decided_by)
+                }
+            };
+            let counts = report.rules.entry(decided_by).or_default();
+            if passed {
+                report.passed += 1;
+                counts.passed += 1;
+            } else {
+                report.suppressed += 1;
+                counts.suppressed += 1;
+            }
+
+            for (key, counts) in &mut report.keys {
+                let value = match record.get(key) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                if self.compiled.value_matches(key, value, Polarity::Positive, record.level) {
+                    counts.matched += 1;
+                }
+                if self.compiled.value_matches(key, value, Polarity::Negative, record.level) {
+                    counts.suppressed += 1;
+                }
+            }
+        }
+
+        drop(filter);
+        report
+    }
+
+    /// rule of this spec deciding `record`, which `passed` by its key values and message,
+    /// rather than by its level or a runtime rule
+    fn decided_by(&self, record: &CapturedRecord, passed: bool) -> DecidedBy {
+        let level = record.level;
+        if passed && level < self.level {
+            return DecidedBy::Level;
+        }
+
+        let polarity = if passed { Polarity::Positive } else { Polarity::Negative };
+        for (key, value) in &record.key_values {
+            match self.compiled.matched_by(key, value, polarity, level) {
+                Some(MatchedBy::Value) => {
+                    return DecidedBy::Value {
+                        key: key.clone(),
+                        value: value.clone(),
+                    }
+                }
+                Some(MatchedBy::Pattern) => return DecidedBy::Pattern { key: key.clone() },
+                None => {}
+            }
+        }
+        if passed {
+            return if self.compiled.positive_names(level).next().is_none() {
+                DecidedBy::Default
+            } else {
+                DecidedBy::Other
+            };
+        }
+
+        let message = &record.message;
+        if self.regex.as_ref().is_some_and(|regex| !regex.is_match(message))
+            || self.neg_regex.as_ref().is_some_and(|regex| regex.is_match(message))
+        {
+            return DecidedBy::Message;
+        }
+        for key in self.compiled.positive_names(level) {
+            let matched = record
+                .key_values
+                .iter()
+                .any(|(name, value)| name == key && self.compiled.value_matches(key, value, Polarity::Positive, level));
+            if !matched {
+                return DecidedBy::Unmatched { key: key.to_owned() };
+            }
+        }
+        DecidedBy::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::Level;
+    use testing::CapturedRecord;
+    use std::time::Duration;
+
+    use super::DecidedBy;
+    use {filter_list, KVFilter, Rule, Trigger, ValuePattern};
+
+    fn record(level: Level, key_values: &[(&str, &str)]) -> CapturedRecord {
+        CapturedRecord {
+            level,
+            message: "message".to_owned(),
+            key_values: key_values
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn batchreport() {
+        let spec = KVFilter::new(::slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])])))
+            .spec();
+
+        let report = spec.evaluate_batch(vec![
+            record(Level::Info, &[("thread", "100")]),
+            record(Level::Info, &[("thread", "100"), ("deepcomp", "1")]),
+            record(Level::Info, &[("thread", "200")]),
+            record(Level::Warning, &[("thread", "200")]),
+        ]);
+
+        assert_eq!((report.passed, report.suppressed), (2, 2));
+        assert_eq!(report.keys["thread"].matched, 2);
+        assert_eq!(report.keys["thread"].suppressed, 0);
+        assert_eq!(report.keys["deepcomp"].suppressed, 1);
+        assert!(report.to_string().starts_with("passed: 2, suppressed: 2\n"));
+
+        let rules = &report.rules;
+        assert_eq!(rules.len(), 4);
+        let thread = DecidedBy::Value {
+            key: "thread".to_owned(),
+            value: "100".to_owned(),
+        };
+        assert_eq!((rules[&thread].passed, rules[&thread].suppressed), (1, 0));
+        let deepcomp = DecidedBy::Value {
+            key: "deepcomp".to_owned(),
+            value: "1".to_owned(),
+        };
+        assert_eq!((rules[&deepcomp].passed, rules[&deepcomp].suppressed), (0, 1));
+        let unmatched = DecidedBy::Unmatched {
+            key: "thread".to_owned(),
+        };
+        assert_eq!(rules[&unmatched].suppressed, 1);
+        assert_eq!(rules[&DecidedBy::Level].passed, 1);
+        assert!(report.to_string().contains("  by thread: 100: passed 1, suppressed 0\n"));
+    }
+
+    #[test]
+    /// records decided by patterns, and by the runtime rules of the triggers
+    fn batchrules() {
+        let spec = KVFilter::new(::slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .only_pass_any_pattern_on_all_keys(Some(
+                vec![("thread", vec![ValuePattern::Prefix("3".into())])]
+                    .into_iter()
+                    .collect(),
+            ))
+            .trigger(
+                Trigger::on("alert", "on", Level::Info)
+                    .enable(Rule::pass("thread", "200", Level::Info))
+                    .for_duration(Duration::from_secs(60)),
+            )
+            .spec();
+
+        let report = spec.evaluate_batch(vec![
+            record(Level::Info, &[("thread", "300")]),
+            record(Level::Info, &[("thread", "200"), ("alert", "on")]),
+            record(Level::Info, &[("thread", "200")]),
+        ]);
+
+        assert_eq!((report.passed, report.suppressed), (2, 1));
+        let pattern = DecidedBy::Pattern {
+            key: "thread".to_owned(),
+        };
+        assert_eq!(report.rules[&pattern].passed, 1);
+        assert_eq!(report.rules[&DecidedBy::Runtime].passed, 1);
+    }
+}
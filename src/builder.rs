@@ -0,0 +1,241 @@
+//! Validating construction of `KVFilter`s
+
+use std::collections::{HashMap, HashSet};
+use std::prelude::v1::*;
+
+use regex::Regex;
+use slog;
+
+use {FilterStr, KVFilter, KVFilterError, KVFilterList, KVPatternList, ValuePattern};
+
+/// Key with its values and patterns of one polarity, as given to the builder
+struct Entry {
+    key: FilterStr,
+    values: HashSet<FilterStr>,
+    patterns: Vec<ValuePattern>,
+}
+
+/// Builder of a `KVFilter` adding one key at a time, and reporting nonsense configurations
+/// by `build` rather than accepting them silently.
+///
+/// Settings not related to the key values and regexes, like `min_level`, are set on the
+/// built `KVFilter`.
+pub struct KVFilterBuilder<D: slog::Drain> {
+    drain: D,
+    level: slog::Level,
+    positive: Vec<Entry>,
+    negative: Vec<Entry>,
+    regex: Option<String>,
+    neg_regex: Option<String>,
+    max_values_per_key: Option<usize>,
+    max_values: Option<usize>,
+}
+
+impl<D: slog::Drain> KVFilterBuilder<D> {
+    /// see `KVFilter::new`
+    pub fn new(drain: D, level: slog::Level) -> Self {
+        KVFilterBuilder {
+            drain,
+            level,
+            positive: Vec::new(),
+            negative: Vec::new(),
+            regex: None,
+            neg_regex: None,
+            max_values_per_key: None,
+            max_values: None,
+        }
+    }
+
+    /// pass entries with `key` having any of `values`, see `KVFilter::only_pass_any_on_all_keys`
+    pub fn only_pass_any<K, I, V>(mut self, key: K, values: I) -> Self
+    where
+        K: Into<FilterStr>,
+        I: IntoIterator<Item = V>,
+        V: Into<FilterStr>,
+    {
+        self.positive.push(Entry {
+            key: key.into(),
+            values: values.into_iter().map(Into::into).collect(),
+            patterns: Vec::new(),
+        });
+        self
+    }
+
+    /// pass entries with `key` matching any of `patterns`, see
+    /// `KVFilter::only_pass_any_pattern_on_all_keys`
+    pub fn only_pass_any_pattern<K: Into<FilterStr>>(
+        mut self,
+        key: K,
+        patterns: Vec<ValuePattern>,
+    ) -> Self {
+        self.positive.push(Entry {
+            key: key.into(),
+            values: HashSet::new(),
+            patterns,
+        });
+        self
+    }
+
+    /// suppress entries with `key` having any of `values`, see `KVFilter::always_suppress_any`
+    pub fn always_suppress_any<K, I, V>(mut self, key: K, values: I) -> Self
+    where
+        K: Into<FilterStr>,
+        I: IntoIterator<Item = V>,
+        V: Into<FilterStr>,
+    {
+        self.negative.push(Entry {
+            key: key.into(),
+            values: values.into_iter().map(Into::into).collect(),
+            patterns: Vec::new(),
+        });
+        self
+    }
+
+    /// suppress entries with `key` matching any of `patterns`, see
+    /// `KVFilter::always_suppress_any_pattern`
+    pub fn always_suppress_any_pattern<K: Into<FilterStr>>(
+        mut self,
+        key: K,
+        patterns: Vec<ValuePattern>,
+    ) -> Self {
+        self.negative.push(Entry {
+            key: key.into(),
+            values: HashSet::new(),
+            patterns,
+        });
+        self
+    }
+
+    /// pass entries with `key` having any of `values` or matching any of `patterns`
+    #[cfg(feature = "config")]
+    pub(crate) fn only_pass_any_of(
+        mut self,
+        key: FilterStr,
+        values: HashSet<FilterStr>,
+        patterns: Vec<ValuePattern>,
+    ) -> Self {
+        self.positive.push(Entry { key, values, patterns });
+        self
+    }
+
+    /// suppress entries with `key` having any of `values` or matching any of `patterns`
+    #[cfg(feature = "config")]
+    pub(crate) fn always_suppress_any_of(
+        mut self,
+        key: FilterStr,
+        values: HashSet<FilterStr>,
+        patterns: Vec<ValuePattern>,
+    ) -> Self {
+        self.negative.push(Entry { key, values, patterns });
+        self
+    }
+
+    /// see `KVFilter::only_pass_on_regex`, the regex is compiled by `build`
+    pub fn only_pass_on_regex<S: Into<String>>(mut self, regex: S) -> Self {
+        self.regex = Some(regex.into());
+        self
+    }
+
+    /// see `KVFilter::always_suppress_on_regex`, the regex is compiled by `build`
+    pub fn always_suppress_on_regex<S: Into<String>>(mut self, regex: S) -> Self {
+        self.neg_regex = Some(regex.into());
+        self
+    }
+
+    /// fail to build with `KVFilterError::TooManyValues` once a key is given more than
+    /// `limit` values and patterns, e.g. to catch a runaway configuration generator before
+    /// a huge filter slows down the start
+    pub fn max_values_per_key(mut self, limit: usize) -> Self {
+        self.max_values_per_key = Some(limit);
+        self
+    }
+
+    /// fail to build with `KVFilterError::SpecTooLarge` once more than `limit` values and
+    /// patterns are given for all the keys together
+    pub fn max_values(mut self, limit: usize) -> Self {
+        self.max_values = Some(limit);
+        self
+    }
+
+    /// validate the configuration and build the filter
+    pub fn build(self) -> Result<KVFilter<D>, KVFilterError> {
+        self.check_size()?;
+        let positive = Self::lists(self.positive)?;
+        let negative = Self::lists(self.negative)?;
+
+        for (key, values) in &positive.0 {
+            if let Some(value) = negative
+                .0
+                .get(key)
+                .and_then(|negative| values.intersection(negative).next())
+            {
+                return Err(KVFilterError::Conflict {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+
+        let mut filter = KVFilter::new(self.drain, self.level)
+            .only_pass_any_on_all_keys_with(Some(positive.0))
+            .only_pass_any_pattern_on_all_keys(Some(positive.1))
+            .always_suppress_any_with(Some(negative.0))
+            .always_suppress_any_pattern(Some(negative.1));
+
+        if let Some(regex) = self.regex {
+            filter = filter.only_pass_on_regex(Regex::new(&regex)?);
+        }
+        if let Some(regex) = self.neg_regex {
+            filter = filter.always_suppress_on_regex(Regex::new(&regex)?);
+        }
+
+        Ok(filter)
+    }
+
+    fn check_size(&self) -> Result<(), KVFilterError> {
+        let mut total = 0;
+        for entry in self.positive.iter().chain(&self.negative) {
+            let values = entry.values.len() + entry.patterns.len();
+            if let Some(limit) = self.max_values_per_key {
+                if values > limit {
+                    return Err(KVFilterError::TooManyValues {
+                        key: entry.key.clone(),
+                        values,
+                        limit,
+                    });
+                }
+            }
+            total += values;
+        }
+        match self.max_values {
+            Some(limit) if total > limit => Err(KVFilterError::SpecTooLarge { values: total, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn lists(
+        entries: Vec<Entry>,
+    ) -> Result<(KVFilterList<FilterStr, FilterStr>, KVPatternList<FilterStr>), KVFilterError> {
+        let mut values = HashMap::new();
+        let mut patterns = HashMap::new();
+
+        for entry in entries {
+            if entry.values.is_empty() && entry.patterns.is_empty() {
+                return Err(KVFilterError::EmptyValues(entry.key));
+            }
+            if values.contains_key(&entry.key) || patterns.contains_key(&entry.key) {
+                return Err(KVFilterError::DuplicateKey(entry.key));
+            }
+            if entry.patterns.is_empty() {
+                values.insert(entry.key, entry.values);
+            } else {
+                if !entry.values.is_empty() {
+                    values.insert(entry.key.clone(), entry.values);
+                }
+                patterns.insert(entry.key, entry.patterns);
+            }
+        }
+
+        Ok((values, patterns))
+    }
+}
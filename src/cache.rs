@@ -0,0 +1,180 @@
+//! Outcomes of matching logger contexts, cached per logger, see `KVFilter::cache_context`
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use slog;
+use slog::KV;
+
+use compiled::KeySet;
+use MatchErrorPolicy;
+
+thread_local! {
+    /// whether the logger context is being serialized by `probe`
+    static PROBING: Cell<bool> = const { Cell::new(false) };
+    /// slot of the innermost `CachedContext` met by `probe`
+    static PROBED: RefCell<Option<Arc<ContextSlot>>> = const { RefCell::new(None) };
+}
+
+/// Key values of a logger whose context is matched once by the filters of
+/// `KVFilter::cache_context` rather than for each of its records, see `cached_context`
+pub struct CachedContext<T> {
+    values: T,
+    slot: Arc<ContextSlot>,
+}
+
+/// key values of a child logger whose context, along with the contexts of its parents, is
+/// matched once by the filters of `KVFilter::cache_context`, e.g.
+/// `log.new(cached_context(o!("thread" => 100)))`
+pub fn cached_context<T>(values: slog::OwnedKV<T>) -> slog::OwnedKV<CachedContext<T>>
+where
+    T: slog::SendSyncRefUnwindSafeKV,
+{
+    slog::OwnedKV(CachedContext {
+        values: values.0,
+        slot: Arc::new(ContextSlot::default()),
+    })
+}
+
+impl<T: KV> KV for CachedContext<T> {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        if PROBING.with(|probing| probing.replace(false)) {
+            PROBED.with(|probed| *probed.borrow_mut() = Some(self.slot.clone()));
+            return Err(slog::Error::Other);
+        }
+        self.values.serialize(record, serializer)
+    }
+}
+
+impl<T> fmt::Debug for CachedContext<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachedContext").finish()
+    }
+}
+
+/// serializer of `probe`, any key value given before the `CachedContext` making it unfit
+#[derive(Default)]
+struct Probe {
+    emitted: bool,
+}
+
+impl slog::Serializer for Probe {
+    fn emit_arguments(&mut self, _: slog::Key, _: &fmt::Arguments) -> slog::Result {
+        self.emitted = true;
+        Err(slog::Error::Other)
+    }
+}
+
+/// resets `PROBING`, also if a lazy value panics
+struct Probing;
+
+impl Drop for Probing {
+    fn drop(&mut self) {
+        PROBING.with(|probing| probing.set(false));
+    }
+}
+
+/// slot of the logger context `logger_values` if its innermost key values are those of a
+/// `CachedContext`, found without serializing any of them
+pub fn probe(logger_values: &slog::OwnedKVList, record: &slog::Record) -> Option<Arc<ContextSlot>> {
+    let mut probe = Probe::default();
+    {
+        PROBING.with(|probing| probing.set(true));
+        let _probing = Probing;
+        let _ = logger_values.serialize(record, &mut probe);
+    }
+    let probed = PROBED.with(|probed| probed.borrow_mut().take());
+    probed.filter(|_| !probe.emitted)
+}
+
+/// Outcome of matching a logger context alone
+pub struct ContextMatch {
+    /// positive keys matched in the context
+    pub matched: KeySet,
+    /// whether a negative key matched in the context
+    pub suppressed: bool,
+    /// the compiled filter and error policy the context was matched by
+    generation: u64,
+    on_error: MatchErrorPolicy,
+}
+
+impl ContextMatch {
+    pub fn new(matched: KeySet, suppressed: bool) -> Self {
+        ContextMatch {
+            matched,
+            suppressed,
+            generation: 0,
+            on_error: MatchErrorPolicy::default(),
+        }
+    }
+}
+
+/// The outcome of matching a logger context by the last spec it was matched by, dropped
+/// along with the logger
+#[derive(Default)]
+pub struct ContextSlot(ArcSwapOption<ContextMatch>);
+
+impl ContextSlot {
+    /// the outcome of `evaluate` for the context matched by the compiled filter of
+    /// `generation` with errors resolved by `on_error`, cached
+    pub fn get<F>(&self, generation: u64, on_error: MatchErrorPolicy, evaluate: F) -> Arc<ContextMatch>
+    where
+        F: FnOnce() -> ContextMatch,
+    {
+        if let Some(cached) = self.0.load_full() {
+            if cached.generation == generation && cached.on_error == on_error {
+                return cached;
+            }
+        }
+
+        let result = Arc::new(ContextMatch {
+            generation,
+            on_error,
+            ..evaluate()
+        });
+        self.0.store(Some(result.clone()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cached_context, probe, ContextMatch};
+    use compiled::KeySet;
+    use slog::{self, Logger};
+    use std::cell::Cell;
+    use MatchErrorPolicy;
+
+    #[test]
+    fn contextcache() {
+        let root = Logger::root(slog::Discard, o!("version" => "1"));
+        let cached = root.new(cached_context(o!("thread" => 100)));
+        let child = cached.new(o!("direction" => "send"));
+        let empty = cached.new(o!());
+
+        let rs = record_static!(slog::Level::Info, "");
+        let probed = |log: &Logger| probe(log.list(), &slog::Record::new(&rs, &format_args!(""), b!()));
+        let slot = probed(&cached).unwrap();
+        assert!(probed(&root).is_none());
+        assert!(probed(&child).is_none());
+        assert!(probed(&empty).is_some());
+
+        // only probes skip the values
+        assert_eq!(format!("{:?}", cached.list()), format!("{:?}", root.new(o!("thread" => 100)).list()));
+
+        let evaluations = Cell::new(0);
+        let evaluate = || {
+            evaluations.set(evaluations.get() + 1);
+            ContextMatch::new(KeySet::default(), true)
+        };
+        assert!(slot.get(1, MatchErrorPolicy::Unmatched, evaluate).suppressed);
+        slot.get(1, MatchErrorPolicy::Unmatched, evaluate);
+        assert!(probed(&empty).unwrap().get(1, MatchErrorPolicy::Unmatched, evaluate).suppressed);
+        assert_eq!(evaluations.get(), 1);
+        slot.get(2, MatchErrorPolicy::Unmatched, evaluate);
+        slot.get(2, MatchErrorPolicy::Matched, evaluate);
+        assert_eq!(evaluations.get(), 3);
+    }
+}
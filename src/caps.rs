@@ -0,0 +1,82 @@
+//! Caps on the number of records passed per value of a key per time window
+
+use std::prelude::v1::*;
+use std::time::Duration;
+
+use sync::{Sharded, SHARDS};
+
+use {FilterMap, FilterStr};
+
+/// number of values counted at once, `SHARDS` shards of them
+const CAPACITY: usize = 4096;
+
+/// At most `records` records per value of `key` per `window`, see `KVFilter::cap_per_value`
+#[derive(Clone, Debug)]
+pub struct GroupCap {
+    pub key: FilterStr,
+    pub records: usize,
+    pub window: Duration,
+}
+
+/// Records counted per value of the capped keys, in windows starting with the first
+/// record of a value
+#[derive(Default)]
+pub struct GroupCounts {
+    /// `Clock::now` when the window of a value started, and the records passed in it
+    counts: Sharded<FilterMap<(FilterStr, String), (Duration, usize)>>,
+}
+
+impl GroupCounts {
+    /// whether a record with `value` of the key of `cap` is within the cap, counting it
+    pub fn admit(&self, cap: &GroupCap, value: String, now: Duration) -> bool {
+        let mut counts = self.counts.write(self.counts.index(&(&*cap.key, value.as_str())));
+
+        let group = (cap.key.clone(), value);
+        if !counts.contains_key(&group) && counts.len() >= CAPACITY / SHARDS {
+            counts.retain(|_, &mut (start, _)| start.saturating_add(cap.window) > now);
+            if counts.len() >= CAPACITY / SHARDS {
+                let oldest = counts
+                    .iter()
+                    .min_by_key(|&(_, &(start, _))| start)
+                    .map(|(group, _)| group.clone());
+                if let Some(oldest) = oldest {
+                    counts.remove(&oldest);
+                }
+            }
+        }
+
+        let entry = counts.entry(group).or_insert((now, 0));
+        if entry.0.saturating_add(cap.window) <= now {
+            *entry = (now, 0);
+        }
+        if entry.1 >= cap.records {
+            return false;
+        }
+        entry.1 += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GroupCap, GroupCounts};
+    use std::time::Duration;
+
+    #[test]
+    fn windowedcounts() {
+        let counts = GroupCounts::default();
+        let cap = GroupCap {
+            key: "client_ip".into(),
+            records: 2,
+            window: Duration::from_secs(60),
+        };
+        let at = Duration::from_secs;
+
+        assert!(counts.admit(&cap, "10.0.0.1".to_string(), at(0)));
+        assert!(counts.admit(&cap, "10.0.0.1".to_string(), at(10)));
+        assert!(!counts.admit(&cap, "10.0.0.1".to_string(), at(20)));
+        assert!(counts.admit(&cap, "10.0.0.2".to_string(), at(20)));
+        assert!(!counts.admit(&cap, "10.0.0.1".to_string(), at(59)));
+        assert!(counts.admit(&cap, "10.0.0.1".to_string(), at(60)));
+    }
+}
@@ -0,0 +1,125 @@
+//! IP address ranges in the CIDR notation, see `ValuePattern::Cidr`
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::prelude::v1::*;
+use std::str::FromStr;
+
+use KVFilterError;
+
+/// Range of IP addresses, e.g. `"10.0.0.0/8".parse::<Cidr>()` or `"fd00::/8".parse()`. An
+/// address without a prefix length is a range of itself only.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// range of the addresses sharing the first `prefix` bits with `network`, `None` if
+    /// `prefix` is longer than the address
+    pub fn new(network: IpAddr, prefix: u8) -> Option<Self> {
+        let bits = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > bits {
+            return None;
+        }
+        Some(Cidr { network, prefix })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                prefix_eq(u32::from(network).into(), u32::from(addr).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_eq(u128::from(network), u128::from(addr), self.prefix, 128)
+            }
+            // e.g. `::ffff:10.0.0.1` of a dual stack socket
+            (IpAddr::V4(_), IpAddr::V6(addr)) => {
+                addr.to_ipv4_mapped().is_some_and(|addr| self.contains(IpAddr::V4(addr)))
+            }
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+
+    /// whether `value` is an address in the range, also with a port, e.g. `10.0.0.1:8080`
+    /// or `[fd00::1]:443`
+    pub fn contains_str(&self, value: &str) -> bool {
+        parse_addr(value).is_some_and(|addr| self.contains(addr))
+    }
+}
+
+/// address of `value`, which may come with a port
+pub fn parse_addr(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+fn prefix_eq(network: u128, addr: u128, prefix: u8, bits: u32) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - u32::from(prefix);
+    network >> shift == addr >> shift
+}
+
+impl FromStr for Cidr {
+    type Err = KVFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KVFilterError::InvalidCidr(s.to_owned());
+        let s = s.trim();
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => {
+                let network: IpAddr = network.parse().map_err(|_| invalid())?;
+                (network, prefix.parse().map_err(|_| invalid())?)
+            }
+            None => {
+                let network: IpAddr = s.parse().map_err(|_| invalid())?;
+                (network, if network.is_ipv4() { 32 } else { 128 })
+            }
+        };
+        Cidr::new(network, prefix).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cidr;
+
+    #[test]
+    fn cidrranges() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains_str("10.1.2.3"));
+        assert!(private.contains_str("10.1.2.3:8080"));
+        assert!(private.contains_str("::ffff:10.1.2.3"));
+        assert!(!private.contains_str("11.0.0.1"));
+        assert!(!private.contains_str("not an address"));
+
+        let local: Cidr = "fd00::/8".parse().unwrap();
+        assert!(local.contains_str("fd12::1"));
+        assert!(local.contains_str("[fd12::1]:443"));
+        assert!(!local.contains_str("fe80::1"));
+        assert!(!local.contains_str("10.1.2.3"));
+
+        let host: Cidr = "192.168.1.1".parse().unwrap();
+        assert_eq!(host.to_string(), "192.168.1.1/32");
+        assert!(host.contains_str("192.168.1.1"));
+        assert!(!host.contains_str("192.168.1.2"));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains_str("8.8.8.8"));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! Source of time for rules expiring after a while, caps, traced values and time windows,
+//! pluggable so that filters work where `std::time::Instant` doesn't, e.g. on
+//! `wasm32-unknown-unknown`, and so that tests don't have to wait
+
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(feature = "std")]
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Monotonic clock, see `KVFilter::clock`
+pub trait Clock: Send + Sync {
+    /// time elapsed since an arbitrary but fixed origin
+    fn now(&self) -> Duration;
+
+    /// time elapsed since the Unix epoch, for the time windows of rules, see `Rule::during`.
+    /// Defaults to that of `std::time::SystemTime`.
+    #[cfg(feature = "std")]
+    fn since_epoch(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+
+    /// time elapsed since the Unix epoch, for the time windows of rules, see `Rule::during`
+    #[cfg(not(feature = "std"))]
+    fn since_epoch(&self) -> Duration;
+}
+
+/// Wall time of a `Clock`, of the changes made through handles, see `Clock::since_epoch`.
+/// A `std::time::SystemTime`, without `std` the time since the Unix epoch.
+#[cfg(feature = "std")]
+pub type WallTime = SystemTime;
+#[cfg(not(feature = "std"))]
+pub type WallTime = Duration;
+
+#[cfg(feature = "std")]
+pub fn wall_time(since_epoch: Duration) -> WallTime {
+    UNIX_EPOCH + since_epoch
+}
+
+#[cfg(not(feature = "std"))]
+pub fn wall_time(since_epoch: Duration) -> WallTime {
+    since_epoch
+}
+
+/// `Clock` of the filters not given one, see `KVFilter::clock`
+#[cfg(feature = "std")]
+pub type DefaultClock = SystemClock;
+#[cfg(not(feature = "std"))]
+pub type DefaultClock = StoppedClock;
+
+/// `Clock` of `std::time::Instant`, the default. Only read once needed, so that filters
+/// not relying on time can be used on targets lacking it.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg(feature = "std")]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// `Clock` without `std`, whose time stands still at the Unix epoch until filters are given
+/// one by `KVFilter::clock`
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg(not(feature = "std"))]
+pub struct StoppedClock;
+
+#[cfg(not(feature = "std"))]
+impl Clock for StoppedClock {
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn since_epoch(&self) -> Duration {
+        Duration::ZERO
+    }
+}
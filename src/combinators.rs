@@ -0,0 +1,434 @@
+//! Drains combining the decisions of several filters over a single inner drain.
+//!
+//! The key values of a record and its logger context are matched against all the combined
+//! `KVFilter`s in a single pass over the record, rather than each of them serializing the
+//! record again as stacked drains would. The filters whose spec changes how the key values
+//! of each record are matched, by `KVFilter::select_by_key`, `log_compat`, `remap_levels` or
+//! `evaluation_budget`, and the other predicates, match them in passes of their own.
+
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::prelude::v1::*;
+
+use slog;
+use slog::KV;
+
+use compiled::{feed_record, Pass};
+use KVFilter;
+
+/// Decision of a predicate on a record at a level, the level it's passed at if it is
+pub type Decide<'a> = &'a dyn Fn(slog::Level) -> Option<slog::Level>;
+
+/// Rest of a pass shared by the predicates of a combinator, see `KVPredicate::decide_in_pass`
+pub type Before<'a> = &'a mut dyn FnMut(Option<&mut dyn Pass>, &mut dyn FnMut(Decide));
+
+/// Decision of whether a record passes, without forwarding it anywhere
+pub trait KVPredicate {
+    fn is_match(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool;
+
+    /// decide `record` along with the predicates combined before this one, matching its key
+    /// values in a single pass: `before` is called with `pass` and the pass of this predicate
+    /// if it takes part, to add theirs and feed the key values, then `then` with the decision
+    /// of this predicate, after those of the predicates before it
+    #[doc(hidden)]
+    fn decide_in_pass(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        pass: Option<&mut dyn Pass>,
+        before: Before,
+        then: &mut dyn FnMut(Decide),
+    ) {
+        before(pass, &mut *then);
+        then(&|level| {
+            at_level(record, level, |record| {
+                if self.is_match(record, logger_values) {
+                    Some(level)
+                } else {
+                    None
+                }
+            })
+        });
+    }
+}
+
+impl<D: slog::Drain> KVPredicate for KVFilter<D> {
+    /// whether `record` would be passed on to the drain, regardless of its level being boosted
+    fn is_match(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool {
+        self.decide(record, logger_values).is_some()
+    }
+
+    fn decide_in_pass(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        pass: Option<&mut dyn Pass>,
+        before: Before,
+        then: &mut dyn FnMut(Decide),
+    ) {
+        let spec = self.spec.load();
+        if !spec.shares_pass() {
+            before(pass, &mut *then);
+            return then(&|level| at_level(record, level, |record| self.decide(record, logger_values)));
+        }
+
+        let kv_matched = self.match_in_pass(&spec, record.level(), pass, &mut |pass| before(pass, &mut *then));
+
+        let scoped = spec.compiled.is_scoped() || spec.compiled_all.is_scoped();
+        then(&|level| {
+            at_level(record, level, |at| {
+                // the pass matched at the level of the record, not the one a chain boosted it to
+                if scoped && level != record.level() {
+                    self.decide(at, logger_values)
+                } else {
+                    self.decide_matched(&spec, at, logger_values, kv_matched)
+                }
+            })
+        });
+    }
+}
+
+type Predicates = Vec<Box<dyn KVPredicate + Send + Sync>>;
+
+/// run `f` with `record` at `level`
+fn at_level<R, F: FnOnce(&slog::Record) -> R>(record: &slog::Record, level: slog::Level, f: F) -> R {
+    if level == record.level() {
+        return f(record);
+    }
+    let rs = slog::RecordStatic {
+        location: record.location(),
+        tag: record.tag(),
+        level,
+    };
+    f(&slog::Record::new(&rs, record.msg(), record.kv()))
+}
+
+/// add the passes of `predicates` to `pass`, last one first, then give it to `feed`, calling
+/// `then` with the decisions of the predicates in order once it returns
+fn add_passes(
+    predicates: &[Box<dyn KVPredicate + Send + Sync>],
+    record: &slog::Record,
+    logger_values: &slog::OwnedKVList,
+    pass: Option<&mut dyn Pass>,
+    feed: &mut dyn FnMut(Option<&mut dyn Pass>),
+    then: &mut dyn FnMut(Decide),
+) {
+    match predicates.split_last() {
+        Some((last, before)) => last.decide_in_pass(
+            record,
+            logger_values,
+            pass,
+            &mut |pass, then| add_passes(before, record, logger_values, pass, &mut *feed, then),
+            then,
+        ),
+        None => feed(pass),
+    }
+}
+
+/// How a combinator combines the decisions of its predicates
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Combine {
+    All,
+    Any,
+    NotAll,
+}
+
+impl Combine {
+    /// whether `predicates` combined pass `record`, deciding them in order until that's
+    /// known, with their passes added to `pass` and given to `feed`, see `add_passes`
+    fn decide(
+        self,
+        predicates: &Predicates,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        pass: Option<&mut dyn Pass>,
+        feed: &mut dyn FnMut(Option<&mut dyn Pass>),
+    ) -> bool {
+        let mut passed = self != Combine::Any;
+        add_passes(predicates, record, logger_values, pass, feed, &mut |decide| {
+            passed = match self {
+                Combine::Any => passed || decide(record.level()).is_some(),
+                Combine::All | Combine::NotAll => passed && decide(record.level()).is_some(),
+            };
+        });
+        passed != (self == Combine::NotAll)
+    }
+}
+
+/// Key values serialized once, replayed to each of the combined filters
+#[derive(Clone)]
+pub struct SerializedKV(pub Vec<(slog::Key, SerializedValue)>);
+
+/// Value of a `SerializedKV`, numbers and booleans keeping their type so that they're
+/// replayed by the typed emits they were given by, and decided as they were
+#[derive(Clone, Debug, PartialEq)]
+pub enum SerializedValue {
+    Str(String),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+}
+
+impl SerializedValue {
+    /// the value as text, as it's formatted by the drains
+    pub fn into_text(self) -> String {
+        match self {
+            SerializedValue::Str(text) => text,
+            typed => typed.to_string(),
+        }
+    }
+}
+
+impl From<String> for SerializedValue {
+    fn from(text: String) -> Self {
+        SerializedValue::Str(text)
+    }
+}
+
+impl From<&str> for SerializedValue {
+    fn from(text: &str) -> Self {
+        SerializedValue::Str(text.to_owned())
+    }
+}
+
+impl ::std::fmt::Display for SerializedValue {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            SerializedValue::Str(ref text) => f.write_str(text),
+            SerializedValue::U64(value) => write!(f, "{}", value),
+            SerializedValue::I64(value) => write!(f, "{}", value),
+            SerializedValue::F32(value) => write!(f, "{}", value),
+            SerializedValue::F64(value) => write!(f, "{}", value),
+            SerializedValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// `val` formatted as far as it formats, where `to_string` panics on a `Display` impl
+/// returning an error
+pub fn text(val: &::std::fmt::Arguments) -> String {
+    let mut text = String::new();
+    let _ = ::std::fmt::write(&mut text, *val);
+    text
+}
+
+/// the emits of numbers and booleans of `SerializedKV`, keeping their type
+macro_rules! serialized_emits {
+    ($($emit:ident($ty:ty) => $value:ident as $as:ty),*) => {
+        $(
+            #[allow(clippy::unnecessary_cast)]
+            fn $emit(&mut self, key: slog::Key, val: $ty) -> slog::Result {
+                self.0.push((key, SerializedValue::$value(val as $as)));
+                Ok(())
+            }
+        )*
+    };
+}
+
+impl slog::Serializer for SerializedKV {
+    fn emit_arguments(&mut self, key: slog::Key, val: &::std::fmt::Arguments) -> slog::Result {
+        self.0.push((key, SerializedValue::Str(text(val))));
+        Ok(())
+    }
+
+    serialized_emits!(
+        emit_usize(usize) => U64 as u64,
+        emit_isize(isize) => I64 as i64,
+        emit_u8(u8) => U64 as u64,
+        emit_i8(i8) => I64 as i64,
+        emit_u16(u16) => U64 as u64,
+        emit_i16(i16) => I64 as i64,
+        emit_u32(u32) => U64 as u64,
+        emit_i32(i32) => I64 as i64,
+        emit_u64(u64) => U64 as u64,
+        emit_i64(i64) => I64 as i64,
+        emit_f32(f32) => F32 as f32,
+        emit_f64(f64) => F64 as f64,
+        emit_bool(bool) => Bool as bool
+    );
+}
+
+impl KV for SerializedKV {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        for &(key, ref value) in &self.0 {
+            match *value {
+                SerializedValue::Str(ref text) => serializer.emit_str(key, text)?,
+                SerializedValue::U64(value) => serializer.emit_u64(key, value)?,
+                SerializedValue::I64(value) => serializer.emit_i64(key, value)?,
+                SerializedValue::F32(value) => serializer.emit_f32(key, value)?,
+                SerializedValue::F64(value) => serializer.emit_f64(key, value)?,
+                SerializedValue::Bool(value) => serializer.emit_bool(key, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+macro_rules! combinator {
+    ($name:ident, $doc:expr, $combine:expr) => {
+        #[doc = $doc]
+        ///
+        /// Combinators are `KVPredicate`s themselves, so they can be nested, matching the key
+        /// values in the pass of the outer one; the inner drain of a nested combinator is not
+        /// used.
+        pub struct $name<D: slog::Drain> {
+            drain: D,
+            predicates: Predicates,
+        }
+
+        impl<D: slog::Drain> UnwindSafe for $name<D> {}
+        impl<D: slog::Drain> RefUnwindSafe for $name<D> {}
+
+        impl<D: slog::Drain> $name<D> {
+            pub fn new(drain: D) -> Self {
+                $name {
+                    drain,
+                    predicates: Vec::new(),
+                }
+            }
+
+            /// combine `predicate`, e.g. a `KVFilter<slog::Discard>`
+            pub fn with<P: KVPredicate + Send + Sync + 'static>(mut self, predicate: P) -> Self {
+                self.predicates.push(Box::new(predicate));
+                self
+            }
+        }
+
+        impl<D: slog::Drain> KVPredicate for $name<D> {
+            fn is_match(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool {
+                $combine.decide(&self.predicates, record, logger_values, None, &mut |pass| {
+                    feed_record(pass, record, logger_values)
+                })
+            }
+
+            fn decide_in_pass(
+                &self,
+                record: &slog::Record,
+                logger_values: &slog::OwnedKVList,
+                pass: Option<&mut dyn Pass>,
+                before: Before,
+                then: &mut dyn FnMut(Decide),
+            ) {
+                let passed = $combine.decide(&self.predicates, record, logger_values, pass, &mut |pass| {
+                    before(pass, &mut *then)
+                });
+                then(&|level| if passed { Some(level) } else { None });
+            }
+        }
+
+        impl<D: slog::Drain> slog::Drain for $name<D> {
+            type Err = D::Err;
+            type Ok = Option<D::Ok>;
+
+            fn log(
+                &self,
+                info: &slog::Record,
+                logger_values: &slog::OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                if self.is_match(info, logger_values) {
+                    self.drain.log(info, logger_values).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+
+            fn is_enabled(&self, level: slog::Level) -> bool {
+                self.drain.is_enabled(level)
+            }
+        }
+    };
+}
+
+combinator!(
+    AllOf,
+    "`Drain` passing records that all of the combined predicates pass; all records pass \
+     if there are none.",
+    Combine::All
+);
+
+combinator!(
+    AnyOf,
+    "`Drain` passing records that at least one of the combined predicates passes; no \
+     records pass if there are none.",
+    Combine::Any
+);
+
+combinator!(
+    NotFilter,
+    "`Drain` passing records that the combined predicate doesn't pass, or that not all of \
+     the combined predicates pass if there are several.",
+    Combine::NotAll
+);
+
+/// `Drain` passing records through a sequence of `KVFilter` stages, like nested `KVFilter`
+/// drains would, but matching the key values against all the stages in a single pass, like
+/// `AllOf`.
+///
+/// A record passes if every stage passes it; a stage boosting the level of the record
+/// (`KVFilter::boost_level`) affects the stages after it and the forwarded record.
+pub struct KVFilterChain<D: slog::Drain> {
+    drain: D,
+    stages: Predicates,
+}
+
+impl<D: slog::Drain> UnwindSafe for KVFilterChain<D> {}
+impl<D: slog::Drain> RefUnwindSafe for KVFilterChain<D> {}
+
+impl<D: slog::Drain> KVFilterChain<D> {
+    pub fn new(drain: D) -> Self {
+        KVFilterChain {
+            drain,
+            stages: Vec::new(),
+        }
+    }
+
+    /// append a stage, evaluated after the previous ones
+    pub fn stage(mut self, filter: KVFilter<slog::Discard>) -> Self {
+        self.stages.push(Box::new(filter));
+        self
+    }
+}
+
+impl<D: slog::Drain> slog::Drain for KVFilterChain<D> {
+    type Err = D::Err;
+    type Ok = Option<D::Ok>;
+
+    fn log(
+        &self,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        let mut level = Some(info.level());
+        add_passes(
+            &self.stages,
+            info,
+            logger_values,
+            None,
+            &mut |pass| feed_record(pass, info, logger_values),
+            &mut |decide| level = level.and_then(decide),
+        );
+
+        let level = match level {
+            Some(level) => level,
+            None => return Ok(None),
+        };
+
+        if level == info.level() {
+            self.drain.log(info, logger_values).map(Some)
+        } else {
+            let rs = slog::RecordStatic {
+                location: info.location(),
+                tag: info.tag(),
+                level,
+            };
+            self.drain
+                .log(&slog::Record::new(&rs, info.msg(), info.kv()), logger_values)
+                .map(Some)
+        }
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        self.drain.is_enabled(level)
+    }
+}
@@ -0,0 +1,1640 @@
+//! Filter spec lowered into flat tables, so that evaluating a record is a few array scans
+//! rather than nested hash lookups.
+
+#[cfg(feature = "std")]
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::BuildHasher;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::prelude::v1::*;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use aho_corasick::{AhoCorasick, Anchored, Input, StartKind};
+use slog;
+use slog::KV;
+#[cfg(feature = "serde_json")]
+use serde_json;
+
+#[cfg(feature = "fuzzy")]
+use fuzzy;
+#[cfg(feature = "nested-values")]
+use nested;
+use cidr::{self, Cidr};
+use memo::{MatchMemo, MemoPolicy, MemoStats};
+use number::{Number, Typed};
+use semver::{Version, VersionReq};
+use threshold::Threshold;
+use small_str::SmallStr;
+#[cfg(not(feature = "std"))]
+use sync::Mutex;
+use {normalize, FilterHasher, FilterList, FilterStr, KeyPosition, MatchErrorPolicy, Normalizer, ValuePattern};
+
+/// Which of the filters key values are matched against, and so when the matching can stop
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// every key has to match
+    Positive,
+    /// a single matching key is enough
+    Negative,
+}
+
+/// What matched a value, see `CompiledFilter::matched_by`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchedBy {
+    /// one of the values listed for the key
+    Value,
+    /// one of the `ValuePattern`s of the key
+    Pattern,
+}
+
+/// Set of key indexes of a `CompiledFilter`
+///
+/// Lives on the stack for up to 64 keys, which is by far the common case.
+#[derive(Clone)]
+pub enum KeySet {
+    Small(u64),
+    Large(Vec<u64>),
+}
+
+impl Default for KeySet {
+    fn default() -> Self {
+        KeySet::Small(0)
+    }
+}
+
+impl KeySet {
+    /// empty set with room for `keys` keys
+    pub fn new(keys: usize) -> Self {
+        let mut set = KeySet::default();
+        set.reset(keys);
+        set
+    }
+
+    /// empty the set, making room for `keys` keys; doesn't allocate once warmed up
+    pub fn reset(&mut self, keys: usize) {
+        if keys <= 64 {
+            *self = KeySet::Small(0);
+        } else {
+            if let KeySet::Small(_) = *self {
+                *self = KeySet::Large(Vec::new());
+            }
+            if let KeySet::Large(ref mut words) = *self {
+                words.clear();
+                words.resize(keys.div_ceil(64), 0);
+            }
+        }
+    }
+
+    fn words(&self) -> &[u64] {
+        match *self {
+            KeySet::Small(ref word) => ::std::slice::from_ref(word),
+            KeySet::Large(ref words) => words,
+        }
+    }
+
+    /// insert `index`, returning whether it wasn't present yet
+    pub fn insert(&mut self, index: usize) -> bool {
+        let word = match *self {
+            KeySet::Small(ref mut word) => word,
+            KeySet::Large(ref mut words) => &mut words[index / 64],
+        };
+        let bit = 1 << (index % 64);
+        let inserted = *word & bit == 0;
+        *word |= bit;
+        inserted
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words()[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// insert the indexes of `other`, a set of as many keys, returning how many weren't
+    /// present yet
+    #[cfg(feature = "std")]
+    pub fn extend(&mut self, other: &KeySet) -> usize {
+        let words = match *self {
+            KeySet::Small(ref mut word) => ::std::slice::from_mut(word),
+            KeySet::Large(ref mut words) => &mut words[..],
+        };
+        let mut inserted = 0;
+        for (word, &other) in words.iter_mut().zip(other.words()) {
+            inserted += (other & !*word).count_ones() as usize;
+            *word |= other;
+        }
+        inserted
+    }
+}
+
+/// Bloom filter of a set of values, cheaply rejecting most of the values not in the set
+/// before the exact lookup.
+#[derive(Clone)]
+pub struct BloomFilter {
+    hasher: FilterHasher,
+    bits: Vec<u64>,
+    /// number of bits minus one, a power of two minus one
+    mask: u64,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// bits per value, giving about 1% false positives with 7 hashes
+    const BITS_PER_VALUE: usize = 10;
+    const HASHES: u32 = 7;
+
+    pub fn new<'a, I: ExactSizeIterator<Item = &'a SmallStr>>(values: I) -> Self {
+        let bits = (values.len() * Self::BITS_PER_VALUE)
+            .next_power_of_two()
+            .max(64);
+        let mut bloom = BloomFilter {
+            hasher: FilterHasher::default(),
+            bits: vec![0; bits / 64],
+            mask: bits as u64 - 1,
+            hashes: Self::HASHES,
+        };
+
+        for value in values {
+            let (h1, h2) = bloom.hash(value);
+            for i in 0..bloom.hashes {
+                let bit = h1.wrapping_add(u64::from(i).wrapping_mul(h2)) & bloom.mask;
+                bloom.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+            }
+        }
+
+        bloom
+    }
+
+    /// two hashes for double hashing, derived from a single one
+    fn hash(&self, value: &str) -> (u64, u64) {
+        let hash = BuildHasher::hash_one(&self.hasher, value);
+        (hash, hash.rotate_left(32) | 1)
+    }
+
+    pub fn may_contain(&self, value: &str) -> bool {
+        let (h1, h2) = self.hash(value);
+        (0..self.hashes).all(|i| {
+            let bit = h1.wrapping_add(u64::from(i).wrapping_mul(h2)) & self.mask;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+}
+
+/// Sorted values of a key packed into a single buffer
+#[derive(Clone)]
+struct ValueTable {
+    data: String,
+    /// `(start, end)` of every value in `data`, in order
+    spans: Vec<(usize, usize)>,
+}
+
+impl ValueTable {
+    /// tables this small are scanned rather than bisected
+    const SCAN_LEN: usize = 8;
+
+    fn new(values: &HashSet<SmallStr>) -> Self {
+        let mut sorted: Vec<&str> = values.iter().map(|v| v.as_ref()).collect();
+        sorted.sort_unstable();
+
+        let mut table = ValueTable {
+            data: String::with_capacity(sorted.iter().map(|v| v.len()).sum()),
+            spans: Vec::with_capacity(sorted.len()),
+        };
+
+        for value in sorted {
+            let start = table.data.len();
+            table.data.push_str(value);
+            table.spans.push((start, table.data.len()));
+        }
+
+        table
+    }
+
+    fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    fn get(&self, i: usize) -> &str {
+        let (start, end) = self.spans[i];
+        &self.data[start..end]
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        if self.len() <= Self::SCAN_LEN {
+            (0..self.len()).any(|i| self.get(i) == value)
+        } else {
+            self.spans
+                .binary_search_by(|&(start, end)| self.data[start..end].cmp(value))
+                .is_ok()
+        }
+    }
+}
+
+/// `ValuePattern::Prefix`es or `ValuePattern::Substring`s of a key
+#[derive(Clone)]
+enum Patterns {
+    /// all the patterns at once
+    Automaton(AhoCorasick),
+    /// patterns matched one by one, when there are too many, or too long ones, to build the
+    /// automaton of
+    Each(Vec<SmallStr>),
+}
+
+impl Patterns {
+    fn new(patterns: &[&str], start_kind: StartKind) -> Self {
+        match AhoCorasick::builder().start_kind(start_kind).build(patterns) {
+            Ok(automaton) => Patterns::Automaton(automaton),
+            Err(_) => Patterns::Each(patterns.iter().map(|&pattern| SmallStr::from(pattern)).collect()),
+        }
+    }
+
+    /// whether `value` starts with any of the patterns, built by `StartKind::Anchored`
+    fn is_prefix_of(&self, value: &str) -> bool {
+        match *self {
+            Patterns::Automaton(ref automaton) => automaton.is_match(Input::new(value).anchored(Anchored::Yes)),
+            Patterns::Each(ref patterns) => patterns.iter().any(|pattern| value.starts_with(pattern.as_str())),
+        }
+    }
+
+    /// whether `value` contains any of the patterns
+    fn is_in(&self, value: &str) -> bool {
+        match *self {
+            Patterns::Automaton(ref automaton) => automaton.is_match(value),
+            Patterns::Each(ref patterns) => patterns.iter().any(|pattern| value.contains(pattern.as_str())),
+        }
+    }
+}
+
+/// Everything a value of a key is matched against, for one polarity
+#[derive(Clone)]
+pub struct ValueMatcher {
+    values: ValueTable,
+    /// pre-check of `values`, when there are enough of them to make it worthwhile
+    bloom: Option<BloomFilter>,
+    /// all the `ValuePattern::Prefix`es at once
+    prefixes: Option<Patterns>,
+    /// all the `ValuePattern::Substring`s at once
+    substrings: Option<Patterns>,
+    /// `ValuePattern::Cidr`s, matched after parsing the value once
+    cidrs: Vec<Cidr>,
+    /// `ValuePattern::Version`s, matched after parsing the value once
+    versions: Vec<VersionReq>,
+    thresholds: Vec<Threshold>,
+    numbers: Vec<Number>,
+    bools: Vec<bool>,
+    /// `ValuePattern::JsonPointer`s, matched after parsing the value once
+    #[cfg(feature = "serde_json")]
+    json: Vec<(FilterStr, FilterStr)>,
+    /// `ValuePattern::Fuzzy`s, with their edit distances
+    #[cfg(feature = "fuzzy")]
+    fuzzy: Vec<(FilterStr, usize)>,
+    /// outcomes of the patterns above, shared by the clones of the spec until it's compiled
+    /// again, see `KVFilter::memoize_matches`
+    memo: Option<Arc<MatchMemo>>,
+}
+
+impl ValueMatcher {
+    fn new(
+        values: &HashSet<SmallStr>,
+        patterns: &[ValuePattern],
+        bloom_threshold: Option<usize>,
+        memo: Option<(usize, MemoPolicy)>,
+    ) -> Self {
+        let costly = patterns
+            .iter()
+            .any(|p| {
+                !matches!(
+                    *p,
+                    ValuePattern::Prefix(_) | ValuePattern::Substring(_) | ValuePattern::Number(_) | ValuePattern::Bool(_)
+                )
+            });
+        let prefixes: Vec<&str> = patterns
+            .iter()
+            .filter_map(|p| match *p {
+                ValuePattern::Prefix(ref prefix) => Some(prefix.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let substrings: Vec<&str> = patterns
+            .iter()
+            .filter_map(|p| match *p {
+                ValuePattern::Substring(ref substring) => Some(substring.as_ref()),
+                _ => None,
+            })
+            .collect();
+
+        ValueMatcher {
+            values: ValueTable::new(values),
+            bloom: match bloom_threshold {
+                Some(threshold) if values.len() >= threshold => {
+                    Some(BloomFilter::new(values.iter()))
+                }
+                _ => None,
+            },
+            prefixes: if prefixes.is_empty() {
+                None
+            } else {
+                Some(Patterns::new(&prefixes, StartKind::Anchored))
+            },
+            substrings: if substrings.is_empty() {
+                None
+            } else {
+                Some(Patterns::new(&substrings, StartKind::Unanchored))
+            },
+            cidrs: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::Cidr(cidr) => Some(cidr),
+                    _ => None,
+                })
+                .collect(),
+            versions: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::Version(ref req) => Some(req.clone()),
+                    _ => None,
+                })
+                .collect(),
+            thresholds: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::Threshold(ref threshold) => Some(threshold.clone()),
+                    _ => None,
+                })
+                .collect(),
+            numbers: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::Number(number) => Some(number),
+                    _ => None,
+                })
+                .collect(),
+            bools: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::Bool(boolean) => Some(boolean),
+                    _ => None,
+                })
+                .collect(),
+            #[cfg(feature = "serde_json")]
+            json: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::JsonPointer(ref pointer, ref value) => {
+                        Some((pointer.clone(), value.clone()))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            #[cfg(feature = "fuzzy")]
+            fuzzy: patterns
+                .iter()
+                .filter_map(|p| match *p {
+                    ValuePattern::Fuzzy(ref target, max) => Some((target.clone(), max)),
+                    _ => None,
+                })
+                .collect(),
+            memo: match memo {
+                Some((capacity, policy)) if costly => Some(Arc::new(MatchMemo::new(capacity, policy))),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        self.check(value).unwrap_or(false)
+    }
+
+    /// whether a value emitted as a number or a boolean is one of the `ValuePattern::Number`s
+    /// or `ValuePattern::Bool`s, which values emitted as text never are
+    pub fn matches_typed(&self, typed: Typed) -> bool {
+        match typed {
+            Typed::Bool(boolean) => self.bools.contains(&boolean),
+            _ if self.numbers.is_empty() => false,
+            number => number.number().is_some_and(|number| self.numbers.contains(&number)),
+        }
+    }
+
+    /// whether `value` matches, `None` if it matches nothing and some of the patterns
+    /// parsing values couldn't evaluate it, nor any other, e.g. a value which isn't a
+    /// version for a `ValuePattern::Version`, see `MatchErrorPolicy`
+    pub fn check(&self, value: &str) -> Option<bool> {
+        if (self.bloom.as_ref().is_none_or(|bloom| bloom.may_contain(value))
+            && self.values.contains(value))
+            || self.prefixes.as_ref().is_some_and(|prefixes| prefixes.is_prefix_of(value))
+            || self.substrings
+                .as_ref()
+                .is_some_and(|substrings| substrings.is_in(value))
+        {
+            return Some(true);
+        }
+
+        match self.memo {
+            Some(ref memo) => memo.get(value, || self.check_costly(value)),
+            None => self.check_costly(value),
+        }
+    }
+
+    /// `check` by the patterns parsing or comparing values
+    fn check_costly(&self, value: &str) -> Option<bool> {
+        let mut parsed = false;
+        let mut unparsed = false;
+        for checked in [
+            self.check_cidrs(value),
+            self.check_versions(value),
+            self.check_thresholds(value),
+            self.check_json(value),
+        ] {
+            match checked {
+                Some(Some(true)) => return Some(true),
+                Some(Some(false)) => parsed = true,
+                Some(None) => unparsed = true,
+                None => {}
+            }
+        }
+        if self.matches_fuzzy(value) {
+            return Some(true);
+        }
+        if unparsed && !parsed {
+            None
+        } else {
+            Some(false)
+        }
+    }
+
+    // the checks of the patterns parsing values are `None` without such patterns, and
+    // `Some(None)` if the value doesn't parse
+
+    fn check_versions(&self, value: &str) -> Option<Option<bool>> {
+        if self.versions.is_empty() {
+            return None;
+        }
+        Some(
+            value
+                .parse::<Version>()
+                .ok()
+                .map(|version| self.versions.iter().any(|req| req.matches(&version))),
+        )
+    }
+
+    fn check_cidrs(&self, value: &str) -> Option<Option<bool>> {
+        if self.cidrs.is_empty() {
+            return None;
+        }
+        Some(cidr::parse_addr(value).map(|addr| self.cidrs.iter().any(|cidr| cidr.contains(addr))))
+    }
+
+    fn check_thresholds(&self, value: &str) -> Option<Option<bool>> {
+        if self.thresholds.is_empty() {
+            return None;
+        }
+        let mut compared = None;
+        for threshold in &self.thresholds {
+            match threshold.compare_str(value) {
+                Some(true) => return Some(Some(true)),
+                Some(false) => compared = Some(false),
+                None => {}
+            }
+        }
+        Some(compared)
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn check_json(&self, value: &str) -> Option<Option<bool>> {
+        if self.json.is_empty() {
+            return None;
+        }
+
+        let document: serde_json::Value = match serde_json::from_str(value) {
+            Ok(document) => document,
+            Err(_) => return Some(None),
+        };
+
+        Some(Some(self.json.iter().any(|(pointer, expected)| {
+            match document.pointer(pointer) {
+                Some(serde_json::Value::String(found)) => found == expected,
+                Some(found) => found.to_string() == *expected,
+                None => false,
+            }
+        })))
+    }
+
+    #[cfg(not(feature = "serde_json"))]
+    fn check_json(&self, _: &str) -> Option<Option<bool>> {
+        None
+    }
+
+    #[cfg(feature = "fuzzy")]
+    fn matches_fuzzy(&self, value: &str) -> bool {
+        self.fuzzy
+            .iter()
+            .any(|&(ref target, max)| fuzzy::within(value, target, max))
+    }
+
+    #[cfg(not(feature = "fuzzy"))]
+    fn matches_fuzzy(&self, _: &str) -> bool {
+        false
+    }
+}
+
+/// A key known to the filter with its matchers
+#[derive(Clone)]
+struct CompiledKey {
+    name: SmallStr,
+    positive: Option<ValueMatcher>,
+    negative: Option<ValueMatcher>,
+    /// bit `level_index` set for each level the key is matched on
+    levels: u8,
+    position: KeyPosition,
+    normalizers: Vec<Normalizer>,
+}
+
+/// number of `slog::Level`s
+const LEVELS: usize = 6;
+
+/// position of `level` in the per-level tables
+fn level_index(level: slog::Level) -> usize {
+    level.as_usize() - 1
+}
+
+/// Positive and negative filters lowered into one table of keys sorted by name; the
+/// position of a key in the table is its index in `KeySet`s.
+///
+/// Keys can be limited to some levels, so the keys needed and their counts are kept
+/// per level.
+#[derive(Clone, Default)]
+pub struct CompiledFilter {
+    keys: Vec<CompiledKey>,
+    /// keys with a positive matcher, i.e. needed to pass
+    needed: [KeySet; LEVELS],
+    positive: [usize; LEVELS],
+    negative: [usize; LEVELS],
+    /// whether some key isn't matched on all the levels
+    scoped: bool,
+    /// whether some key isn't matched at all its occurrences, or by `KeyPosition::Nearest`
+    positioned: bool,
+    nearest: bool,
+    /// the only key, if it's positive and matched by plain values everywhere
+    single: Option<SingleKey>,
+    /// distinct for each compiled table but its clones, see `ContextSlot`
+    #[cfg(feature = "std")]
+    generation: u64,
+}
+
+/// last `CompiledFilter::generation` given
+#[cfg(feature = "std")]
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+impl CompiledFilter {
+    /// keys tables this small are scanned rather than bisected
+    const SCAN_LEN: usize = 8;
+
+    pub fn new(
+        positive: &FilterList,
+        negative: &FilterList,
+        scopes: &HashMap<FilterStr, RangeInclusive<slog::Level>>,
+        positions: &HashMap<FilterStr, KeyPosition>,
+        normalizers: &HashMap<FilterStr, Vec<Normalizer>>,
+        bloom_threshold: Option<usize>,
+        memo: Option<(usize, MemoPolicy)>,
+    ) -> Self {
+        let mut names: Vec<&SmallStr> = positive.keys().chain(negative.keys()).collect();
+        names.sort_unstable();
+        names.dedup();
+
+        let no_values = HashSet::new();
+        let no_patterns = vec![];
+        let matcher = |list: &FilterList, name: &SmallStr| {
+            if !list.contains_key(name) {
+                return None;
+            }
+            let values = list.values.get(name.as_str()).unwrap_or(&no_values);
+            let normalized: HashSet<SmallStr>;
+            let values = match normalizers.get(name.as_str()) {
+                Some(normalizers) if !normalizers.is_empty() => {
+                    normalized = values
+                        .iter()
+                        .map(|value| {
+                            let mut value = value.to_string();
+                            normalize(normalizers, &mut value);
+                            SmallStr::from(value.as_str())
+                        })
+                        .collect();
+                    &normalized
+                }
+                _ => values,
+            };
+            Some(ValueMatcher::new(
+                values,
+                list.patterns.get(name.as_str()).unwrap_or(&no_patterns),
+                bloom_threshold,
+                memo,
+            ))
+        };
+
+        let plain = |name: &SmallStr| {
+            positive.patterns.get(name.as_str()).is_none_or(Vec::is_empty)
+                && !negative.contains_key(name)
+                && !scopes.contains_key(name.as_str())
+                && positions.get(name.as_str()).is_none_or(|&position| position == KeyPosition::Anywhere)
+                && normalizers.get(name.as_str()).is_none_or(Vec::is_empty)
+        };
+        let single = match names[..] {
+            [name] if positive.contains_key(name) && plain(name) => Some(SingleKey {
+                name: name.clone(),
+                values: ValueTable::new(positive.values.get(name.as_str()).unwrap_or(&no_values)),
+            }),
+            _ => None,
+        };
+
+        let keys: Vec<CompiledKey> = names
+            .into_iter()
+            .map(|name| CompiledKey {
+                name: name.clone(),
+                positive: matcher(positive, name),
+                negative: matcher(negative, name),
+                levels: scopes.get(name.as_str()).map_or(!0, |scope| {
+                    (1..=LEVELS)
+                        .filter_map(slog::Level::from_usize)
+                        .filter(|level| scope.contains(level))
+                        .fold(0, |levels, level| levels | 1 << level_index(level))
+                }),
+                position: positions.get(name.as_str()).cloned().unwrap_or_default(),
+                normalizers: normalizers.get(name.as_str()).cloned().unwrap_or_default(),
+            })
+            .collect();
+
+        let mut filter = CompiledFilter {
+            scoped: keys.iter().any(|k| k.levels != !0),
+            positioned: keys.iter().any(|k| k.position != KeyPosition::Anywhere),
+            nearest: keys.iter().any(|k| k.position == KeyPosition::Nearest),
+            single,
+            #[cfg(feature = "std")]
+            generation: GENERATION.fetch_add(1, Ordering::Relaxed) + 1,
+            ..CompiledFilter::default()
+        };
+        for level in 0..LEVELS {
+            filter.needed[level] = KeySet::new(keys.len());
+            for (index, key) in keys.iter().enumerate() {
+                if key.levels & 1 << level == 0 {
+                    continue;
+                }
+                if key.positive.is_some() {
+                    filter.needed[level].insert(index);
+                    filter.positive[level] += 1;
+                }
+                if key.negative.is_some() {
+                    filter.negative[level] += 1;
+                }
+            }
+        }
+        filter.keys = keys;
+        filter
+    }
+
+    /// number of keys in the table, the size of `KeySet`s
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// number of keys of given polarity matched on `level`
+    pub fn keys_of(&self, polarity: Polarity, level: slog::Level) -> usize {
+        match polarity {
+            Polarity::Positive => self.positive[level_index(level)],
+            Polarity::Negative => self.negative[level_index(level)],
+        }
+    }
+
+    /// whether the outcome of matching depends on the level of the record
+    pub fn is_scoped(&self) -> bool {
+        self.scoped
+    }
+
+    #[cfg(feature = "std")]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// whether a key of the table is `key` or under it, so that nested values logged under
+    /// other keys aren't serialized at all
+    #[cfg(feature = "nested-values")]
+    pub fn mentions(&self, key: &str) -> bool {
+        self.keys.iter().any(|k| nested::is_under(&k.name, key))
+    }
+
+    /// whether the outcome of matching the logger context depends on the keys of the record,
+    /// see `KeyPosition::Nearest`
+    pub fn has_nearest(&self) -> bool {
+        self.nearest
+    }
+
+    /// whether the occurrence of the key at `index` is to be matched, given whether it's in
+    /// the logger context, marking the key as `seen`
+    fn at_position(&self, index: usize, in_context: bool, seen: &mut KeySet) -> bool {
+        if !self.positioned {
+            return true;
+        }
+        match self.keys[index].position {
+            KeyPosition::Anywhere => true,
+            KeyPosition::Record => !in_context,
+            KeyPosition::Context => in_context,
+            KeyPosition::Nearest => seen.insert(index),
+        }
+    }
+
+    /// the only key of the table, if matched by its values alone, see `SingleKey`
+    pub fn single_key(&self) -> Option<&SingleKey> {
+        self.single.as_ref()
+    }
+
+    /// lookups of the memos of the matchers of the keys, see `KVFilter::memoize_matches`
+    pub fn memo_stats(&self) -> MemoStats {
+        let mut stats = MemoStats::default();
+        for key in &self.keys {
+            for matcher in key.positive.iter().chain(key.negative.iter()) {
+                if let Some(ref memo) = matcher.memo {
+                    stats.add(memo.stats());
+                }
+            }
+        }
+        stats
+    }
+
+    /// names of the keys in the table
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(|key| &*key.name)
+    }
+
+    /// names of the keys with a positive matcher on `level`, i.e. needed to pass
+    pub fn positive_names(&self, level: slog::Level) -> impl Iterator<Item = &str> {
+        (0..self.keys.len())
+            .filter(move |&index| self.in_scope(index, level) && self.keys[index].positive.is_some())
+            .map(move |index| &*self.keys[index].name)
+    }
+
+    /// whether `value` of `key` matches the matcher of `polarity`, on `level`
+    pub fn value_matches(&self, key: &str, value: &str, polarity: Polarity, level: slog::Level) -> bool {
+        self.matched_by(key, value, polarity, level).is_some()
+    }
+
+    /// what in the matcher of `polarity` matches `value` of `key` on `level`, if anything
+    pub fn matched_by(&self, key: &str, value: &str, polarity: Polarity, level: slog::Level) -> Option<MatchedBy> {
+        let index = match self.find(key) {
+            Some(index) if self.in_scope(index, level) => index,
+            _ => return None,
+        };
+        let matcher = self.matcher(index, polarity)?;
+
+        let normalizers = &self.keys[index].normalizers;
+        let normalized;
+        let value = if normalizers.is_empty() {
+            value
+        } else {
+            let mut value = value.to_owned();
+            normalize(normalizers, &mut value);
+            normalized = value;
+            &normalized
+        };
+        if matcher.values.contains(value) {
+            Some(MatchedBy::Value)
+        } else if matcher.matches(value) {
+            Some(MatchedBy::Pattern)
+        } else {
+            None
+        }
+    }
+
+    fn in_scope(&self, index: usize, level: slog::Level) -> bool {
+        self.keys[index].levels & 1 << level_index(level) != 0
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        if self.keys.len() <= Self::SCAN_LEN {
+            self.keys.iter().position(|k| k.name == key)
+        } else {
+            self.keys
+                .binary_search_by(|k| k.name.as_ref().cmp(key))
+                .ok()
+        }
+    }
+
+    fn matcher(&self, index: usize, polarity: Polarity) -> Option<&ValueMatcher> {
+        let key = &self.keys[index];
+        match polarity {
+            Polarity::Positive => key.positive.as_ref(),
+            Polarity::Negative => key.negative.as_ref(),
+        }
+    }
+}
+
+/// Spec of a single positive key with a bunch of values, the most common one, matched by a
+/// serializer comparing the values of that key only, rather than by a `DualSerializer`
+/// looking keys up in the table and tracking the ones matched
+#[derive(Clone)]
+pub struct SingleKey {
+    name: SmallStr,
+    values: ValueTable,
+}
+
+impl SingleKey {
+    /// whether `record` has one of the values of the key, on the record or in the logger
+    /// context, `None` if it's to be suppressed as a value failed to format, see `resolve`
+    pub fn is_match(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        policy: MatchErrorPolicy,
+        tmp_str: &mut String,
+        errors: &mut usize,
+    ) -> Option<bool> {
+        let mut ser = SingleKeySerializer {
+            key: self,
+            policy,
+            tmp_str,
+            errors,
+            matched: Some(false),
+        };
+        // an error means the outcome is decided, unless it's a key value failing to serialize
+        let res = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+        if res.is_err() && ser.matched == Some(false) && resolve(None, policy, ser.errors).is_none() {
+            return None;
+        }
+        ser.matched
+    }
+}
+
+struct SingleKeySerializer<'a> {
+    key: &'a SingleKey,
+    policy: MatchErrorPolicy,
+    tmp_str: &'a mut String,
+    errors: &'a mut usize,
+    matched: Option<bool>,
+}
+
+impl<'a> SingleKeySerializer<'a> {
+    fn emit_value(&mut self, key: &str, val: &fmt::Arguments) -> slog::Result {
+        if self.key.name != key {
+            return Ok(());
+        }
+        let values = &self.key.values;
+        let checked = value_str(val, &[], self.tmp_str).ok().map(|value| values.contains(value));
+        self.matched = resolve(checked, self.policy, self.errors);
+        match self.matched {
+            Some(false) => Ok(()),
+            _ => Err(slog::Error::Other),
+        }
+    }
+}
+
+impl<'a> slog::Serializer for SingleKeySerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.emit_value(key, val)
+    }
+
+    /// nested values not holding the key aren't serialized, see `CompiledFilter::mentions`
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        if !nested::is_under(&self.key.name, key) {
+            return Ok(());
+        }
+        nested::flatten(key, value, |path, val| self.emit_value(path, val))
+    }
+}
+
+/// Buffers reused by all the records matched on a thread, so that the hot path doesn't allocate
+#[derive(Default)]
+pub struct Scratch {
+    pub value: String,
+    pub message: String,
+    /// only needed for filters of more than 64 keys, smaller `KeySet`s live on the stack
+    pub positive: KeySet,
+    pub suppress_all: KeySet,
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::default());
+    /// buffers of the filters matching in a shared pass, see `Scratch::take_value`
+    static VALUES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// without threads to keep apart, the buffers of the records matched one at a time, those
+/// matched meanwhile, e.g. by an interrupt, getting buffers of their own
+#[cfg(not(feature = "std"))]
+static SCRATCH: Mutex<Option<Scratch>> = Mutex::new(None);
+#[cfg(not(feature = "std"))]
+static VALUES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+impl Scratch {
+    /// run `f` with this thread's scratch buffers
+    #[cfg(feature = "std")]
+    pub fn with<R, F: Fn(&mut Scratch) -> R>(f: F) -> R {
+        SCRATCH
+            .try_with(|scratch| match scratch.try_borrow_mut() {
+                Ok(mut scratch) => f(&mut scratch),
+                // re-entered while matching, e.g. by a lazy value logging on its own
+                Err(_) => f(&mut Scratch::default()),
+            })
+            .unwrap_or_else(|_| f(&mut Scratch::default()))
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn with<R, F: Fn(&mut Scratch) -> R>(f: F) -> R {
+        match SCRATCH.try_lock() {
+            Some(mut scratch) => f(scratch.get_or_insert_with(Scratch::default)),
+            None => f(&mut Scratch::default()),
+        }
+    }
+
+    /// a buffer of this thread to format values into, for the filters matching in a pass
+    /// shared with others, which each need one while the pass lasts; give it back by
+    /// `give_value`
+    #[cfg(feature = "std")]
+    pub fn take_value() -> String {
+        VALUES
+            .try_with(|values| values.borrow_mut().pop())
+            .ok()
+            .and_then(|value| value)
+            .unwrap_or_default()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn give_value(mut value: String) {
+        value.clear();
+        let _ = VALUES.try_with(|values| values.borrow_mut().push(value));
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn take_value() -> String {
+        VALUES.try_lock().and_then(|mut values| values.pop()).unwrap_or_default()
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn give_value(mut value: String) {
+        value.clear();
+        if let Some(mut values) = VALUES.try_lock() {
+            values.push(value);
+        }
+    }
+}
+
+/// the emits of numbers and booleans of a serializer, matched by their type as well as by
+/// their text by its `emit_value`, see `Typed`
+macro_rules! typed_emits {
+    () => {
+        typed_emits!(
+            emit_usize(usize) => U64 as u64,
+            emit_isize(isize) => I64 as i64,
+            emit_u8(u8) => U64 as u64,
+            emit_i8(i8) => I64 as i64,
+            emit_u16(u16) => U64 as u64,
+            emit_i16(i16) => I64 as i64,
+            emit_u32(u32) => U64 as u64,
+            emit_i32(i32) => I64 as i64,
+            emit_u64(u64) => U64 as u64,
+            emit_i64(i64) => I64 as i64,
+            emit_f32(f32) => F32 as f32,
+            emit_f64(f64) => F64 as f64,
+            emit_bool(bool) => Bool as bool
+        );
+    };
+    ($($emit:ident($ty:ty) => $typed:ident as $as:ty),*) => {
+        $(
+            #[allow(clippy::unnecessary_cast)]
+            fn $emit(&mut self, key: slog::Key, val: $ty) -> slog::Result {
+                self.emit_value(key, Some(Typed::$typed(val as $as)), &format_args!("{}", val))
+            }
+        )*
+    };
+}
+
+/// the emits of a `Fork`, each passed on to both of its passes
+macro_rules! forked_emits {
+    ($($emit:ident($ty:ty)),*) => {
+        $(
+            fn $emit(&mut self, key: slog::Key, val: $ty) -> slog::Result {
+                self.each(|pass| {
+                    let _ = pass.$emit(key, val);
+                });
+                self.emitted()
+            }
+        )*
+    };
+}
+
+// @todo: must that be thread-safe?
+pub struct FilteringSerializer<'a> {
+    filter: &'a CompiledFilter,
+    polarity: Polarity,
+    level: slog::Level,
+    pub matched: &'a mut KeySet,
+    matches: usize,
+    pub tmp_str: &'a mut String,
+    on_error: MatchErrorPolicy,
+    /// values the matchers couldn't evaluate
+    errors: usize,
+    /// whether the logger context is being serialized, see `serialize_context`
+    in_context: bool,
+    /// keys given so far, see `KeyPosition::Nearest`
+    seen: KeySet,
+}
+
+impl<'a> FilteringSerializer<'a> {
+    pub fn new(
+        filter: &'a CompiledFilter,
+        polarity: Polarity,
+        level: slog::Level,
+        matched: &'a mut KeySet,
+        tmp_str: &'a mut String,
+    ) -> Self {
+        matched.reset(filter.len());
+
+        FilteringSerializer {
+            filter,
+            polarity,
+            level,
+            matched,
+            matches: 0,
+            tmp_str,
+            on_error: MatchErrorPolicy::Unmatched,
+            errors: 0,
+            in_context: false,
+            seen: KeySet::new(if filter.positioned { filter.len() } else { 0 }),
+        }
+    }
+
+    /// resolve the values the matchers can't evaluate by `policy`, a key suppressing the
+    /// record by `MatchErrorPolicy::Suppress` counts as matched
+    pub fn on_error(mut self, policy: MatchErrorPolicy) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// values the matchers couldn't evaluate since last called
+    pub fn take_errors(&mut self) -> usize {
+        mem::replace(&mut self.errors, 0)
+    }
+
+    /// whether some key matched already
+    pub fn any_matched(&self) -> bool {
+        self.matches != 0
+    }
+
+    /// whether all the keys matched already
+    pub fn all_matched(&self) -> bool {
+        self.matches == self.filter.keys_of(self.polarity, self.level)
+    }
+
+    /// feed `kv` to the serializer, stopping as soon as the outcome is decided
+    pub fn serialize<K: KV + ?Sized>(&mut self, kv: &K, record: &slog::Record) {
+        feed(self, kv, record);
+    }
+
+    /// feed the logger context of the record, after the key values of the record if those
+    /// are fed at all, see `KeyPosition`
+    pub fn serialize_context(&mut self, logger_values: &slog::OwnedKVList, record: &slog::Record) {
+        self.enter_context();
+        self.serialize(logger_values, record);
+    }
+}
+
+impl<'a> Pass for FilteringSerializer<'a> {
+    fn is_decided(&self) -> bool {
+        match self.polarity {
+            Polarity::Positive => self.all_matched(),
+            Polarity::Negative => {
+                self.any_matched() || self.filter.keys_of(Polarity::Negative, self.level) == 0
+            }
+        }
+    }
+
+    fn enter_context(&mut self) {
+        self.in_context = true;
+    }
+
+    fn failed(&mut self) {
+        self.errors += 1;
+    }
+}
+
+impl<'a> FilteringSerializer<'a> {
+    fn emit_value(&mut self, key: &str, typed: Option<Typed>, val: &fmt::Arguments) -> slog::Result {
+        let index = match self.filter.find(key) {
+            Some(index)
+                if !self.matched.contains(index)
+                    && self.filter.in_scope(index, self.level)
+                    && self.filter.at_position(index, self.in_context, &mut self.seen) =>
+            {
+                index
+            }
+            _ => return Ok(()),
+        };
+
+        let matched = match self.filter.matcher(index, self.polarity) {
+            Some(matcher) if typed.is_some_and(|typed| matcher.matches_typed(typed)) => true,
+            Some(matcher) => {
+                let checked = value_str(val, &self.filter.keys[index].normalizers, self.tmp_str)
+                    .ok()
+                    .and_then(|value| matcher.check(value));
+                resolve(checked, self.on_error, &mut self.errors) != Some(false)
+            }
+            None => false,
+        };
+
+        if matched {
+            self.matched.insert(index);
+            self.matches += 1;
+
+            if self.is_decided() {
+                return Err(slog::Error::Other);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> slog::Serializer for FilteringSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.emit_value(key, None, val)
+    }
+
+    typed_emits!();
+
+    /// nested values are matched by their dotted paths, e.g. `request.headers.user_agent`
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        if !self.filter.mentions(key) {
+            return Ok(());
+        }
+        nested::flatten(key, value, |path, val| self.emit_value(path, None, val))
+    }
+}
+
+/// `val` as a string, formatted into `tmp_str` unless it's a plain one, and normalized
+fn value_str<'v>(
+    val: &'v fmt::Arguments,
+    normalizers: &[Normalizer],
+    tmp_str: &'v mut String,
+) -> Result<&'v str, fmt::Error> {
+    match val.as_str() {
+        Some(value) if normalizers.is_empty() => Ok(value),
+        _ => {
+            tmp_str.clear();
+            fmt::write(tmp_str, *val)?;
+            normalize(normalizers, tmp_str);
+            Ok(tmp_str)
+        }
+    }
+}
+
+/// the outcome of `ValueMatcher::check`, or of a value failing to format, with the errors
+/// resolved by `policy` and counted, `None` if the record is to be suppressed
+fn resolve(checked: Option<bool>, policy: MatchErrorPolicy, errors: &mut usize) -> Option<bool> {
+    if checked.is_some() {
+        return checked;
+    }
+    *errors += 1;
+    match policy {
+        MatchErrorPolicy::Matched => Some(true),
+        MatchErrorPolicy::Unmatched => Some(false),
+        MatchErrorPolicy::Suppress => None,
+    }
+}
+
+/// Serializer matching key values against both the positive and the negative matchers of
+/// a filter in a single pass, formatting each value at most once. Stops at the first
+/// negative match, or once all the positive keys matched if there are no negative ones.
+pub struct DualSerializer<'a> {
+    filter: &'a CompiledFilter,
+    level: slog::Level,
+    pub matched: &'a mut KeySet,
+    matches: usize,
+    /// whether a negative matcher matched
+    pub suppressed: bool,
+    pub tmp_str: &'a mut String,
+    budget: Option<Budget>,
+    /// key values inspected, and bytes of values formatted, so far
+    pairs: usize,
+    bytes: usize,
+    /// whether the matching stopped as the budget ran out
+    pub over_budget: bool,
+    on_error: MatchErrorPolicy,
+    /// values the matchers couldn't evaluate
+    errors: usize,
+    /// whether the logger context is being serialized, see `serialize_context`
+    in_context: bool,
+    /// keys given so far, see `KeyPosition::Nearest`
+    seen: KeySet,
+}
+
+/// Limits on the work matching a record takes, see `KVFilter::evaluation_budget`
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    pub max_pairs: usize,
+    pub max_value_bytes: usize,
+}
+
+impl<'a> DualSerializer<'a> {
+    pub fn new(
+        filter: &'a CompiledFilter,
+        level: slog::Level,
+        matched: &'a mut KeySet,
+        tmp_str: &'a mut String,
+    ) -> Self {
+        matched.reset(filter.len());
+
+        DualSerializer {
+            filter,
+            level,
+            matched,
+            matches: 0,
+            suppressed: false,
+            tmp_str,
+            budget: None,
+            pairs: 0,
+            bytes: 0,
+            over_budget: false,
+            on_error: MatchErrorPolicy::Unmatched,
+            errors: 0,
+            in_context: false,
+            seen: KeySet::new(if filter.positioned { filter.len() } else { 0 }),
+        }
+    }
+
+    /// stop matching once `budget` runs out
+    pub fn budgeted(mut self, budget: Option<Budget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// resolve the values the matchers can't evaluate by `policy`
+    pub fn on_error(mut self, policy: MatchErrorPolicy) -> Self {
+        self.on_error = policy;
+        self
+    }
+
+    /// values the matchers couldn't evaluate since last called
+    pub fn take_errors(&mut self) -> usize {
+        mem::replace(&mut self.errors, 0)
+    }
+
+    /// whether all the positive keys matched already
+    pub fn all_matched(&self) -> bool {
+        self.matches == self.filter.keys_of(Polarity::Positive, self.level)
+    }
+
+    /// feed `kv` to the serializer, stopping as soon as the outcome is decided
+    pub fn serialize<K: KV + ?Sized>(&mut self, kv: &K, record: &slog::Record) {
+        feed(self, kv, record);
+    }
+
+    /// feed the logger context of the record, after the key values of the record if those
+    /// are fed at all, see `KeyPosition`
+    pub fn serialize_context(&mut self, logger_values: &slog::OwnedKVList, record: &slog::Record) {
+        self.enter_context();
+        self.serialize(logger_values, record);
+    }
+
+    /// take the outcome of matching the logger context alone rather than feeding it, the
+    /// positive keys `matched` in it and whether it's `suppressed`
+    #[cfg(feature = "std")]
+    pub fn matched_context(&mut self, matched: &KeySet, suppressed: bool) {
+        self.matches += self.matched.extend(matched);
+        self.suppressed |= suppressed;
+    }
+
+    fn emit_value(&mut self, key: &str, typed: Option<Typed>, val: &fmt::Arguments) -> slog::Result {
+        if let Some(budget) = self.budget {
+            self.pairs += 1;
+            if self.pairs > budget.max_pairs {
+                self.over_budget = true;
+                return Err(slog::Error::Other);
+            }
+        }
+
+        let index = match self.filter.find(key) {
+            Some(index)
+                if self.filter.in_scope(index, self.level)
+                    && self.filter.at_position(index, self.in_context, &mut self.seen) =>
+            {
+                index
+            }
+            _ => return Ok(()),
+        };
+
+        let compiled = &self.filter.keys[index];
+        let positive = match compiled.positive {
+            Some(ref positive) if !self.matched.contains(index) => Some(positive),
+            _ => None,
+        };
+        if compiled.negative.is_none() && positive.is_none() {
+            return Ok(());
+        }
+
+        // a value failing to format is an error of the matchers looking at it
+        let value = value_str(val, &compiled.normalizers, self.tmp_str).ok();
+        if let (Some(budget), Some(value)) = (self.budget, value) {
+            self.bytes += value.len();
+            if self.bytes > budget.max_value_bytes {
+                self.over_budget = true;
+                return Err(slog::Error::Other);
+            }
+        }
+        if let Some(ref negative) = compiled.negative {
+            let suppressed = typed.is_some_and(|typed| negative.matches_typed(typed)) || {
+                let checked = value.and_then(|value| negative.check(value));
+                resolve(checked, self.on_error, &mut self.errors) != Some(false)
+            };
+            if suppressed {
+                self.suppressed = true;
+                return Err(slog::Error::Other);
+            }
+        }
+        if let Some(positive) = positive {
+            let checked = if typed.is_some_and(|typed| positive.matches_typed(typed)) {
+                Some(true)
+            } else {
+                resolve(value.and_then(|value| positive.check(value)), self.on_error, &mut self.errors)
+            };
+            match checked {
+                Some(true) => {
+                    self.matched.insert(index);
+                    self.matches += 1;
+
+                    if self.is_decided() {
+                        return Err(slog::Error::Other);
+                    }
+                }
+                Some(false) => {}
+                None => {
+                    self.suppressed = true;
+                    return Err(slog::Error::Other);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Pass for DualSerializer<'a> {
+    fn is_decided(&self) -> bool {
+        self.suppressed
+            || self.over_budget
+            || self.all_matched() && self.filter.keys_of(Polarity::Negative, self.level) == 0
+    }
+
+    fn enter_context(&mut self) {
+        self.in_context = true;
+    }
+
+    fn failed(&mut self) {
+        if resolve(None, self.on_error, &mut self.errors).is_none() {
+            self.suppressed = true;
+        }
+    }
+}
+
+impl<'a> slog::Serializer for DualSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.emit_value(key, None, val)
+    }
+
+    typed_emits!();
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        if !self.filter.mentions(key) {
+            return Ok(());
+        }
+        nested::flatten(key, value, |path, val| self.emit_value(path, None, val))
+    }
+}
+
+/// Serializer matching key values until its outcome is decided, possibly in a pass shared
+/// with others, see `Fork`
+pub trait Pass: slog::Serializer {
+    /// whether looking at further key values can't change the outcome
+    fn is_decided(&self) -> bool;
+
+    /// the logger context of the record is fed next, see `KeyPosition`
+    fn enter_context(&mut self);
+
+    /// a key value failed to serialize, leaving the values after it unseen
+    fn failed(&mut self);
+}
+
+/// feed `kv` to `pass`, stopping as soon as the outcome is decided
+pub fn feed<K: KV + ?Sized>(pass: &mut dyn Pass, kv: &K, record: &slog::Record) {
+    if pass.is_decided() {
+        return;
+    }
+
+    // an error is how we stop the iteration once decided, any other is a key value
+    // failing to serialize, leaving the values after it unseen
+    if kv.serialize(record, pass).is_err() && !pass.is_decided() {
+        pass.failed();
+    }
+}
+
+/// feed the key values of `record`, then `logger_values`, to `pass`
+pub fn feed_record(pass: Option<&mut dyn Pass>, record: &slog::Record, logger_values: &slog::OwnedKVList) {
+    if let Some(pass) = pass {
+        feed(pass, &record.kv(), record);
+        pass.enter_context();
+        feed(pass, logger_values, record);
+    }
+}
+
+/// Two `Pass`es fed the same key values, each until its outcome is decided, so that the
+/// key values of a record are matched against several filters in a single pass; forks
+/// nest for more
+pub struct Fork<'a>(pub &'a mut dyn Pass, pub &'a mut dyn Pass);
+
+impl<'a> Fork<'a> {
+    fn each<F: FnMut(&mut dyn Pass)>(&mut self, mut f: F) {
+        for pass in [&mut *self.0, &mut *self.1] {
+            if !pass.is_decided() {
+                f(pass);
+            }
+        }
+    }
+
+    fn emitted(&self) -> slog::Result {
+        if self.is_decided() {
+            Err(slog::Error::Other)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Pass for Fork<'a> {
+    fn is_decided(&self) -> bool {
+        self.0.is_decided() && self.1.is_decided()
+    }
+
+    fn enter_context(&mut self) {
+        self.0.enter_context();
+        self.1.enter_context();
+    }
+
+    fn failed(&mut self) {
+        self.each(|pass| pass.failed());
+    }
+}
+
+impl<'a> slog::Serializer for Fork<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        // each pass stops by an error once decided
+        self.each(|pass| {
+            let _ = pass.emit_arguments(key, val);
+        });
+        self.emitted()
+    }
+
+    forked_emits!(
+        emit_usize(usize),
+        emit_isize(isize),
+        emit_bool(bool),
+        emit_u8(u8),
+        emit_i8(i8),
+        emit_u16(u16),
+        emit_i16(i16),
+        emit_u32(u32),
+        emit_i32(i32),
+        emit_f32(f32),
+        emit_u64(u64),
+        emit_i64(i64),
+        emit_f64(f64)
+    );
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        self.each(|pass| {
+            let _ = pass.emit_serde(key, value);
+        });
+        self.emitted()
+    }
+}
+
+/// Keys lowering the minimum level of records carrying them, see `KVFilter::min_level_on_key`
+#[derive(Clone, Default)]
+pub struct LevelKeys {
+    keys: Vec<(FilterStr, slog::Level)>,
+}
+
+impl LevelKeys {
+    pub fn insert(&mut self, key: FilterStr, level: slog::Level) {
+        self.keys.retain(|(k, _)| *k != key);
+        self.keys.push((key, level));
+    }
+
+    /// least important level any of the keys lowers the minimum to
+    pub fn lowest(&self) -> Option<slog::Level> {
+        self.keys.iter().map(|&(_, level)| level).max()
+    }
+
+    /// minimum level of `record` within `logger_values`, given the minimum level without
+    /// any of the keys present
+    pub fn min_level(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        min_level: slog::Level,
+    ) -> slog::Level {
+        let mut ser = LevelKeysSerializer {
+            keys: self,
+            lowest: self.lowest().unwrap_or(min_level),
+            min_level,
+        };
+        // an error means the lowest level was found already, or a value failed to serialize
+        let _ = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+        ser.min_level
+    }
+}
+
+struct LevelKeysSerializer<'a> {
+    keys: &'a LevelKeys,
+    lowest: slog::Level,
+    min_level: slog::Level,
+}
+
+impl<'a> slog::Serializer for LevelKeysSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, _: &fmt::Arguments) -> slog::Result {
+        for &(ref k, level) in &self.keys.keys {
+            if *k == key && level > self.min_level {
+                self.min_level = level;
+            }
+        }
+
+        if self.min_level >= self.lowest {
+            return Err(slog::Error::Other);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, _: &dyn slog::SerdeValue) -> slog::Result {
+        self.emit_arguments(key, &format_args!(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, KeySet, ValueTable};
+    use small_str::SmallStr;
+
+    #[test]
+    /// small and large key sets behave the same
+    fn keyset() {
+        for &keys in &[1, 64, 65, 200] {
+            let mut set = KeySet::new(keys);
+
+            assert!((0..keys).all(|i| !set.contains(i)));
+            assert!((0..keys).step_by(3).all(|i| set.insert(i)));
+            assert!(!set.insert(0));
+            assert!((0..keys).all(|i| set.contains(i) == (i % 3 == 0)));
+
+            set.reset(keys);
+            assert!((0..keys).all(|i| !set.contains(i)));
+        }
+    }
+
+    #[test]
+    /// bloom filters have no false negatives and rarely false positives
+    fn bloomfilter() {
+        let values: Vec<SmallStr> = (0..1000).map(|i| SmallStr::from(format!("value{}", i).as_str())).collect();
+        let bloom = BloomFilter::new(values.iter());
+
+        assert!(values.iter().all(|v| bloom.may_contain(v)));
+
+        let false_positives = (1000..11000)
+            .filter(|i| bloom.may_contain(&format!("value{}", i)))
+            .count();
+
+        assert!(false_positives < 500, "{} false positives", false_positives);
+    }
+
+    #[test]
+    /// both scanned and bisected value tables find exactly their values
+    fn valuetable() {
+        for &len in &[0, 1, 8, 9, 100] {
+            let values = (0..len).map(|i| SmallStr::from(format!("v{}", i * 2).as_str())).collect();
+            let table = ValueTable::new(&values);
+
+            assert!((0..len).all(|i| table.contains(&format!("v{}", i * 2))));
+            assert!((0..len).all(|i| !table.contains(&format!("v{}", i * 2 + 1))));
+            assert!(!table.contains(""));
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Filter specs from the typed configuration of applications, see `IntoFilterSpec`
+
+#[cfg(feature = "config")]
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use slog;
+#[cfg(feature = "config")]
+use serde::Serialize;
+#[cfg(feature = "config")]
+use serde_json::{self, Value};
+
+#[cfg(feature = "config")]
+use directives::{parse_level, ModuleLevels};
+#[cfg(feature = "config")]
+use {FilterStr, KVFilterBuilder, Number, ValuePattern};
+use {FilterSpec, KVFilterError};
+
+/// Configuration of an application turning into the spec of a filter, e.g. a struct with
+/// the tenants to log, deserialized along with the rest of the configuration, see
+/// `KVFilter::from_config`.
+///
+/// With the `config` feature, `spec_from_config` implements it for a `Serialize` struct in
+/// a single call, configuring by the names of its fields.
+pub trait IntoFilterSpec {
+    /// spec of filters of `level`, see `KVFilter::new`
+    fn to_filter_spec(&self, level: slog::Level) -> Result<Arc<FilterSpec>, KVFilterError>;
+}
+
+/// spec configured by the fields of `config`, a struct or a map serialized by serde:
+///
+/// * a field holding values passes the records with any of them, on the key named by the
+///   field, see `KVFilterBuilder::only_pass_any`. Strings are matched by their text,
+///   numbers by their value as `ValuePattern::Number` does and booleans as
+///   `ValuePattern::Bool` does, e.g. `retries = 3` of a TOML file matching both
+///   `emit_u64(3)` and `emit_f64(3.0)`, but not `"3"`.
+/// * a field named with a `!` prefix suppresses the records with any of its values on the
+///   key named by the rest, see `KVFilterBuilder::always_suppress_any`, e.g. by
+///   `#[serde(rename = "!deepcomp")]`
+/// * a field holding a struct or a map sets the minimum levels of the modules it names,
+///   e.g. `{"hyper": "error", "app::db": "trace"}`, or `"off"`, see `KVFilter::directives`
+/// * fields holding nothing or no values are left out, e.g. an `Option` of `None`
+///
+/// ```
+/// # extern crate serde;
+/// # #[macro_use] extern crate serde_derive;
+/// # extern crate slog;
+/// # extern crate slog_kvfilter;
+/// use std::collections::HashMap;
+/// use std::sync::Arc;
+///
+/// use slog_kvfilter::{spec_from_config, FilterSpec, IntoFilterSpec, KVFilter, KVFilterError};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct LogConfig {
+///     tenant: Vec<String>,
+///     #[serde(rename = "!subsystem")]
+///     muted_subsystems: Vec<String>,
+///     levels: HashMap<String, String>,
+/// }
+///
+/// impl IntoFilterSpec for LogConfig {
+///     fn to_filter_spec(&self, level: slog::Level) -> Result<Arc<FilterSpec>, KVFilterError> {
+///         spec_from_config(self, level)
+///     }
+/// }
+///
+/// # fn main() {
+/// let config = LogConfig {
+///     tenant: vec!["acme".to_owned()],
+///     muted_subsystems: vec!["cache".to_owned()],
+///     levels: vec![("hyper".to_owned(), "error".to_owned())].into_iter().collect(),
+/// };
+/// let _filter = KVFilter::from_config(slog::Discard, slog::Level::Info, &config).unwrap();
+/// # }
+/// ```
+#[cfg(feature = "config")]
+pub fn spec_from_config<T: Serialize + ?Sized>(
+    config: &T,
+    level: slog::Level,
+) -> Result<Arc<FilterSpec>, KVFilterError> {
+    let fields = match serde_json::to_value(config) {
+        Ok(Value::Object(fields)) => fields,
+        Ok(_) => return Err(KVFilterError::InvalidConfig("not a struct or a map".to_owned())),
+        Err(err) => return Err(KVFilterError::InvalidConfig(err.to_string())),
+    };
+
+    let mut builder = KVFilterBuilder::new(slog::Discard, level);
+    let mut modules = ModuleLevels::default();
+    for (name, value) in fields {
+        match value {
+            Value::Null => {}
+            Value::Array(ref values) if values.is_empty() => {}
+            Value::Object(levels) => {
+                for (module, level) in levels {
+                    let level = match level {
+                        Value::Null => continue,
+                        Value::String(ref level) => parse_level(level),
+                        _ => None,
+                    };
+                    match level {
+                        Some(level) => modules.insert(module.into(), level),
+                        None => {
+                            return Err(KVFilterError::InvalidConfig(format!(
+                                "level of module {} in {}",
+                                module, name
+                            )))
+                        }
+                    }
+                }
+            }
+            value => {
+                let (values, patterns) = literals(&name, value)?;
+                builder = match name.strip_prefix('!') {
+                    Some(key) => builder.always_suppress_any_of(key.to_owned().into(), values, patterns),
+                    None => builder.only_pass_any_of(name.into(), values, patterns),
+                };
+            }
+        }
+    }
+
+    let filter = builder.build()?;
+    filter.spec.update(|spec| spec.modules.extend(modules));
+    Ok(filter.spec())
+}
+
+/// strings, and patterns of the numbers and booleans, of the value or of each value of
+/// the sequence of the field `name`
+#[cfg(feature = "config")]
+fn literals(name: &str, value: Value) -> Result<(HashSet<FilterStr>, Vec<ValuePattern>), KVFilterError> {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    let mut strings = HashSet::new();
+    let mut patterns = Vec::new();
+    for value in values {
+        match value {
+            Value::String(value) => {
+                strings.insert(value.into());
+            }
+            Value::Bool(value) => patterns.push(ValuePattern::Bool(value)),
+            Value::Number(ref number) => {
+                let number = match (number.as_u64(), number.as_i64(), number.as_f64()) {
+                    (Some(number), _, _) => Number::from(number),
+                    (_, Some(number), _) => Number::from(number),
+                    (_, _, Some(number)) => Number::from(number),
+                    _ => return Err(KVFilterError::InvalidConfig(format!("number {} of {}", number, name))),
+                };
+                patterns.push(ValuePattern::Number(number));
+            }
+            _ => {
+                return Err(KVFilterError::InvalidConfig(format!(
+                    "values of {} are not strings, numbers or booleans",
+                    name
+                )))
+            }
+        }
+    }
+    Ok((strings, patterns))
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::spec_from_config;
+    use slog::{Drain, Level, Logger};
+    use testing::CountingDrain;
+    use {AllOf, KVFilter};
+
+    #[derive(Serialize)]
+    struct LogConfig {
+        tenant: Vec<&'static str>,
+        #[serde(rename = "!deepcomp")]
+        suppressed: Vec<u32>,
+        region: Option<&'static str>,
+        levels: HashMap<&'static str, &'static str>,
+    }
+
+    #[test]
+    fn typedconfig() {
+        let drain = CountingDrain::new();
+        let mut config = LogConfig {
+            tenant: vec!["acme", "initech"],
+            suppressed: vec![1],
+            region: None,
+            levels: HashMap::new(),
+        };
+
+        let spec = spec_from_config(&config, Level::Info).unwrap();
+        let log = Logger::root(KVFilter::new(drain.clone(), Level::Info).with_spec(spec).fuse(), o!());
+        info!(log, "passing"; "tenant" => "acme");
+        info!(log, "not passing"; "tenant" => "globex");
+        info!(log, "not passing, suppressed"; "tenant" => "acme", "deepcomp" => 1);
+        assert_eq!(drain.count(), 1);
+
+        config.levels.insert(module_path!(), "error");
+        let spec = spec_from_config(&config, Level::Info).unwrap();
+        let log = Logger::root(KVFilter::new(drain.clone(), Level::Info).with_spec(spec).fuse(), o!());
+        warn!(log, "not passing, below the level of the module"; "tenant" => "acme");
+        error!(log, "passing"; "tenant" => "acme");
+        assert_eq!(drain.count(), 2);
+
+        config.levels.insert("hyper", "loud");
+        assert!(spec_from_config(&config, Level::Info).is_err());
+        assert!(spec_from_config(&vec!["acme"], Level::Info).is_err());
+    }
+
+    #[derive(Serialize)]
+    struct TypedConfig {
+        retries: Vec<f64>,
+        #[serde(rename = "!sampled")]
+        sampled: bool,
+    }
+
+    #[test]
+    fn typedliterals() {
+        let drain = CountingDrain::new();
+        let config = TypedConfig {
+            retries: vec![3.0, 0.5],
+            sampled: false,
+        };
+
+        let spec = spec_from_config(&config, Level::Info).unwrap();
+        let log = Logger::root(KVFilter::new(drain.clone(), Level::Info).with_spec(spec).fuse(), o!());
+        info!(log, "passing"; "retries" => 3u64);
+        info!(log, "passing"; "retries" => 3.0f64);
+        info!(log, "passing"; "retries" => 0.5f32, "sampled" => true);
+        info!(log, "not passing"; "retries" => "3.5");
+        info!(log, "not passing, suppressed"; "retries" => 3, "sampled" => false);
+        assert_eq!(drain.count(), 3);
+
+        // strings of the literals are text, not the values
+        info!(log, "not passing, a string"; "retries" => "3");
+        info!(log, "passing, not suppressed by a string"; "retries" => 3, "sampled" => "false");
+        assert_eq!(drain.count(), 4);
+
+        // nor in a pass shared with other filters
+        let spec = spec_from_config(&config, Level::Info).unwrap();
+        let filter = KVFilter::new(slog::Discard, Level::Info).with_spec(spec);
+        let log = Logger::root(AllOf::new(drain.clone()).with(filter).fuse(), o!());
+        info!(log, "passing"; "retries" => 3u8);
+        info!(log, "not passing, a string"; "retries" => "3");
+        info!(log, "not passing, suppressed"; "retries" => 0.5f32, "sampled" => false);
+        assert_eq!(drain.count(), 5);
+    }
+}
@@ -0,0 +1,401 @@
+//! Registry of correlation ids, e.g. request ids, whose records bypass filtering
+
+use std::fmt;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use slog;
+use slog::KV;
+
+use sync::{RwLock, Sharded};
+use {FilterMap, FilterStr};
+
+/// number of values traced at once by default
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+struct Traced {
+    /// `Clock::now` when the value expires
+    expires: Duration,
+    /// `CorrelationRegistry::uses` when the value was traced or last matched
+    used: AtomicU64,
+}
+
+/// values of the keys of a shard
+type Values = FilterMap<FilterStr, FilterMap<String, Traced>>;
+
+/// Values of keys traced until they expire, at most `capacity` of them, and at most
+/// `per_key` of each key
+///
+/// The values are sharded by their hash, so that tracing a value only locks its shard,
+/// unless the limits are reached and values are dropped to make room. The keys are locked
+/// before the shards.
+pub struct CorrelationRegistry {
+    /// number of values, checked before taking the lock
+    len: AtomicUsize,
+    capacity: AtomicUsize,
+    per_key: AtomicUsize,
+    /// keys traced, with their number of values, kept until values are dropped to make room
+    keys: RwLock<FilterMap<FilterStr, AtomicUsize>>,
+    traced: Sharded<Values>,
+    /// ticks on each trace and match, ordering the uses of the values
+    uses: AtomicU64,
+    /// values dropped to make room
+    evictions: AtomicU64,
+}
+
+impl Default for CorrelationRegistry {
+    fn default() -> Self {
+        CorrelationRegistry {
+            len: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(DEFAULT_CAPACITY),
+            per_key: AtomicUsize::new(usize::MAX),
+            keys: RwLock::new(FilterMap::default()),
+            traced: Sharded::default(),
+            uses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+}
+
+/// count one more, unless `limit` is reached
+fn reserve(count: &AtomicUsize, limit: usize) -> bool {
+    count
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+            if count < limit {
+                Some(count + 1)
+            } else {
+                None
+            }
+        })
+        .is_ok()
+}
+
+impl CorrelationRegistry {
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn set_per_key_capacity(&self, capacity: usize) {
+        self.per_key.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::Relaxed)
+    }
+
+    fn tick(&self) -> u64 {
+        self.uses.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Relaxed) == 0
+    }
+
+    /// trace `value` of `key` until `expires`, dropping expired values and, if full, the
+    /// one expiring first, or if the values of the key are, the least recently used of them
+    pub fn insert(&self, key: FilterStr, value: String, expires: Duration, now: Duration) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        let per_key = self.per_key.load(Ordering::Relaxed);
+        if capacity == 0 || per_key == 0 {
+            return;
+        }
+
+        {
+            let keys = self.keys.read();
+            if let Some(count) = keys.get(&key) {
+                let mut traced = self
+                    .traced
+                    .write(self.traced.index(&(&*key, value.as_str())));
+                self.drop_expired(&keys, &mut traced, now);
+                let used = AtomicU64::new(self.tick());
+                if let Some(traced) = traced
+                    .get_mut(&key)
+                    .and_then(|values| values.get_mut(&value))
+                {
+                    *traced = Traced { expires, used };
+                    return;
+                }
+                if reserve(&self.len, capacity) {
+                    if reserve(count, per_key) {
+                        traced
+                            .entry(key)
+                            .or_default()
+                            .insert(value, Traced { expires, used });
+                        return;
+                    }
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        }
+        self.insert_evicting(key, value, expires, now, capacity, per_key);
+    }
+
+    /// drop the expired values of a shard
+    fn drop_expired(
+        &self,
+        keys: &FilterMap<FilterStr, AtomicUsize>,
+        traced: &mut Values,
+        now: Duration,
+    ) {
+        for (key, values) in traced.iter_mut() {
+            let before = values.len();
+            values.retain(|_, traced| traced.expires > now);
+            let dropped = before - values.len();
+            if dropped > 0 {
+                if let Some(count) = keys.get(key) {
+                    count.fetch_sub(dropped, Ordering::Relaxed);
+                }
+                self.len.fetch_sub(dropped, Ordering::Relaxed);
+            }
+        }
+        traced.retain(|_, values| !values.is_empty());
+    }
+
+    /// `insert` of a value of a key not traced yet, or when the registry or the values of
+    /// the key are full, locking all the shards
+    fn insert_evicting(
+        &self,
+        key: FilterStr,
+        value: String,
+        expires: Duration,
+        now: Duration,
+        capacity: usize,
+        per_key: usize,
+    ) {
+        let mut keys = self.keys.write();
+        let mut traced = self.traced.write_all();
+        let index = self.traced.index(&(&*key, value.as_str()));
+        for shard in traced.iter_mut() {
+            for values in shard.values_mut() {
+                values.retain(|_, traced| traced.expires > now);
+            }
+            shard.retain(|_, values| !values.is_empty());
+        }
+
+        let already = traced[index]
+            .get(&key)
+            .is_some_and(|values| values.contains_key(&value));
+        if !already {
+            loop {
+                let of_key = traced
+                    .iter()
+                    .filter_map(|shard| shard.get(&key))
+                    .map(FilterMap::len)
+                    .sum::<usize>();
+                if of_key < per_key {
+                    break;
+                }
+                let least_used = traced
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, shard)| shard.get(&key).map(|values| (index, values)))
+                    .flat_map(|(index, values)| {
+                        values.iter().map(move |(value, traced)| {
+                            (traced.used.load(Ordering::Relaxed), index, value.clone())
+                        })
+                    })
+                    .min();
+                match least_used {
+                    Some((_, index, least_used)) => {
+                        if let Some(values) = traced[index].get_mut(&key) {
+                            values.remove(&least_used);
+                        }
+                        self.evictions.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => break,
+                }
+            }
+        }
+        let mut len: usize = traced
+            .iter()
+            .flat_map(|shard| shard.values())
+            .map(FilterMap::len)
+            .sum();
+        while !already && len >= capacity {
+            let first = traced
+                .iter()
+                .enumerate()
+                .flat_map(|(index, shard)| {
+                    shard.iter().flat_map(move |(key, values)| {
+                        values.iter().map(move |(value, traced)| {
+                            (traced.expires, index, key.clone(), value.clone())
+                        })
+                    })
+                })
+                .min();
+            match first {
+                Some((_, index, key, value)) => {
+                    if let Some(values) = traced[index].get_mut(&key) {
+                        values.remove(&value);
+                    }
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                    len -= 1;
+                }
+                None => break,
+            }
+        }
+
+        let used = AtomicU64::new(self.tick());
+        traced[index]
+            .entry(key)
+            .or_default()
+            .insert(value, Traced { expires, used });
+
+        // counted again from scratch, while nothing else is traced or dropped
+        keys.clear();
+        let mut len = 0;
+        for shard in &traced {
+            for (key, values) in shard.iter().filter(|&(_, values)| !values.is_empty()) {
+                keys.entry(key.clone())
+                    .or_insert_with(|| AtomicUsize::new(0))
+                    .fetch_add(values.len(), Ordering::Relaxed);
+                len += values.len();
+            }
+        }
+        self.len.store(len, Ordering::Relaxed);
+    }
+
+    /// stop tracing `value` of `key`, returning whether it was traced
+    pub fn remove(&self, key: &str, value: &str) -> bool {
+        let keys = self.keys.read();
+        let mut traced = self.traced.write(self.traced.index(&(key, value)));
+        let removed = traced
+            .get_mut(key)
+            .is_some_and(|values| values.remove(value).is_some());
+        if removed {
+            if let Some(count) = keys.get(key) {
+                count.fetch_sub(1, Ordering::Relaxed);
+            }
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// whether `record` carries a traced value which hasn't expired at `now`
+    pub fn is_match(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        now: Duration,
+    ) -> bool {
+        let keys = self.keys.read();
+        let mut ser = CorrelationSerializer {
+            registry: self,
+            keys: &keys,
+            now,
+            value: String::new(),
+            matched: false,
+        };
+        // an error means a value matched already, or failed to serialize
+        let _ = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+        ser.matched
+    }
+}
+
+struct CorrelationSerializer<'a> {
+    registry: &'a CorrelationRegistry,
+    keys: &'a FilterMap<FilterStr, AtomicUsize>,
+    now: Duration,
+    value: String,
+    matched: bool,
+}
+
+impl<'a> slog::Serializer for CorrelationSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if self.keys.contains_key(key) {
+            self.value.clear();
+            fmt::write(&mut self.value, *val)?;
+            let traced = &self.registry.traced;
+            let traced = traced.read(traced.index(&(key, self.value.as_str())));
+            let traced = traced
+                .get(key)
+                .and_then(|values| values.get(&self.value))
+                .filter(|traced| traced.expires > self.now);
+            if let Some(traced) = traced {
+                traced.used.store(self.registry.tick(), Ordering::Relaxed);
+                self.matched = true;
+                return Err(slog::Error::Other);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CorrelationRegistry;
+    use std::time::Duration;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn is_traced(registry: &CorrelationRegistry, key: &str, value: &str) -> bool {
+        let traced = registry.traced.read(registry.traced.index(&(key, value)));
+        traced
+            .get(key)
+            .is_some_and(|values| values.contains_key(value))
+    }
+
+    #[test]
+    fn boundedregistry() {
+        let registry = CorrelationRegistry::default();
+        registry.set_capacity(2);
+
+        registry.insert("request_id".into(), "a".to_owned(), secs(30), secs(0));
+        registry.insert("request_id".into(), "b".to_owned(), secs(10), secs(0));
+        // full, "b" expires first
+        registry.insert("user".into(), "c".to_owned(), secs(20), secs(0));
+
+        assert!(is_traced(&registry, "request_id", "a"));
+        assert!(!is_traced(&registry, "request_id", "b"));
+        assert!(is_traced(&registry, "user", "c"));
+
+        // "c" has expired and makes room
+        registry.insert("request_id".into(), "d".to_owned(), secs(60), secs(25));
+        assert!(!is_traced(&registry, "user", "c"));
+        assert!(registry.remove("request_id", "a"));
+        assert!(!registry.remove("request_id", "a"));
+        assert!(!registry.is_empty());
+        assert_eq!(registry.evictions(), 1);
+    }
+
+    #[test]
+    fn lruregistry() {
+        use slog::{Level, OwnedKVList, Record, RecordLocation, RecordStatic};
+
+        static LOCATION: RecordLocation = RecordLocation {
+            file: "",
+            line: 0,
+            column: 0,
+            function: "",
+            module: "",
+        };
+        let rs = RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level: Level::Info,
+        };
+        let context = OwnedKVList::from(o!());
+
+        let registry = CorrelationRegistry::default();
+        registry.set_per_key_capacity(2);
+        registry.insert("session_id".into(), "a".to_owned(), secs(60), secs(0));
+        registry.insert("session_id".into(), "b".to_owned(), secs(60), secs(0));
+        registry.insert("user".into(), "c".to_owned(), secs(60), secs(0));
+        // "a" used after "b" was traced
+        let kv = o!("session_id" => "a");
+        assert!(registry.is_match(&Record::new(&rs, &format_args!(""), ::slog::BorrowedKV(&kv)), &context, secs(1)));
+
+        registry.insert("session_id".into(), "d".to_owned(), secs(60), secs(1));
+        assert!(is_traced(&registry, "session_id", "a"));
+        assert!(!is_traced(&registry, "session_id", "b"));
+        assert!(is_traced(&registry, "session_id", "d"));
+        assert!(is_traced(&registry, "user", "c"));
+        assert_eq!(registry.evictions(), 1);
+    }
+}
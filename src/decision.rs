@@ -0,0 +1,109 @@
+//! Outcomes of filtering reported to the drains wrapping a filter, see `DecidingFilter`
+
+use std::panic::{RefUnwindSafe, UnwindSafe};
+
+use slog;
+
+use KVFilter;
+
+/// What a `DecidingFilter` did with a record
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision<T> {
+    /// forwarded to the filtered drain, with what it returned
+    Passed(T),
+    /// suppressed for its level, e.g. below the minimum level or that of its module
+    SuppressedByLevel,
+    /// suppressed by the key values, or by the sampling, caps and other limits of the spec
+    SuppressedByKV,
+    /// sent to a drain of `KVFilter::route_to` instead
+    Routed,
+    /// the filtered drain failed, the record was sent to `KVFilter::fallback_on_error`
+    FellBack,
+}
+
+impl<T> Decision<T> {
+    pub fn is_passed(&self) -> bool {
+        matches!(*self, Decision::Passed(_))
+    }
+
+    /// what the filtered drain returned, if the record was forwarded to it
+    pub fn passed(self) -> Option<T> {
+        match self {
+            Decision::Passed(ok) => Some(ok),
+            _ => None,
+        }
+    }
+}
+
+/// `KVFilter` returning the `Decision` on each record, e.g. for the drains wrapping it, or
+/// tests, to tell the outcomes apart without counting what reaches the filtered drain.
+/// See `KVFilter::deciding`.
+pub struct DecidingFilter<D: slog::Drain> {
+    filter: KVFilter<D>,
+}
+
+impl<D: slog::Drain> DecidingFilter<D> {
+    pub fn new(filter: KVFilter<D>) -> Self {
+        DecidingFilter { filter }
+    }
+
+    /// the wrapped filter
+    pub fn into_inner(self) -> KVFilter<D> {
+        self.filter
+    }
+}
+
+impl<D: slog::Drain + Clone> Clone for DecidingFilter<D> {
+    fn clone(&self) -> Self {
+        DecidingFilter {
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+impl<D: slog::Drain> UnwindSafe for DecidingFilter<D> {}
+impl<D: slog::Drain> RefUnwindSafe for DecidingFilter<D> {}
+
+impl<D: slog::Drain> slog::Drain for DecidingFilter<D> {
+    type Ok = Decision<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        self.filter.log_deciding(record, logger_values)
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        slog::Drain::is_enabled(&self.filter, level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decision;
+    use slog::{Drain, Level};
+    use testing::CountingDrain;
+    use {filter_list, KVFilter, Rule};
+
+    #[test]
+    fn decidingfilter() {
+        let filter = KVFilter::new(CountingDrain::new(), Level::Info)
+            .min_level(Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .route_to("audit", ::slog::Discard);
+        filter.handle().add_rule(Rule::pass("user", "admin", Level::Info).to("audit"), None);
+        let filter = filter.deciding();
+        let context = o!("region" => "eu").into();
+        macro_rules! decide {
+            ($level:expr, $($kv:tt)*) => {
+                filter.log(&record!($level, "", &format_args!(""), b!($($kv)*)), &context)
+            };
+        }
+
+        assert_eq!(decide!(Level::Info, "tenant" => "acme"), Ok(Decision::Passed(())));
+        assert_eq!(decide!(Level::Debug, "tenant" => "acme"), Ok(Decision::SuppressedByLevel));
+        assert_eq!(decide!(Level::Info, "tenant" => "globex"), Ok(Decision::SuppressedByKV));
+        assert_eq!(decide!(Level::Info, "user" => "admin"), Ok(Decision::Routed));
+        assert!(decide!(Level::Error,).unwrap().is_passed());
+        assert_eq!(decide!(Level::Debug,).unwrap().passed(), None);
+    }
+}
@@ -0,0 +1,312 @@
+//! Differences between specs, and merging of specs, e.g. to review configuration changes or
+//! to apply partial updates.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::prelude::v1::*;
+
+use slog;
+
+use small_str::SmallStr;
+use {FilterList, FilterSpec, KVFilterError};
+
+/// Value lists of a spec
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueList {
+    /// `KVFilter::only_pass_any_on_all_keys`
+    Pass,
+    /// `KVFilter::always_suppress_any`
+    Suppress,
+    /// `KVFilter::always_suppress_all`
+    SuppressAll,
+}
+
+/// Change from one spec to another, see `FilterSpec::diff`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpecChange {
+    KeyAdded { list: ValueList, key: String, values: Vec<String> },
+    KeyRemoved { list: ValueList, key: String },
+    /// values added to and removed from a key present in both specs
+    ValuesChanged {
+        list: ValueList,
+        key: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// level below which records pass unfiltered, as given to `KVFilter::new`
+    LevelChanged { from: slog::Level, to: slog::Level },
+    /// `KVFilter::only_pass_on_regex`, or with `suppress` `always_suppress_on_regex`
+    RegexChanged {
+        suppress: bool,
+        from: Option<String>,
+        to: Option<String>,
+    },
+}
+
+impl fmt::Display for SpecChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpecChange::KeyAdded { list, ref key, ref values } => {
+                write!(f, "+ {:?} {}: {}", list, key, values.join(", "))
+            }
+            SpecChange::KeyRemoved { list, ref key } => write!(f, "- {:?} {}", list, key),
+            SpecChange::ValuesChanged { list, ref key, ref added, ref removed } => write!(
+                f,
+                "~ {:?} {}: +[{}] -[{}]",
+                list,
+                key,
+                added.join(", "),
+                removed.join(", ")
+            ),
+            SpecChange::LevelChanged { from, to } => write!(f, "~ level: {} -> {}", from, to),
+            SpecChange::RegexChanged { suppress, ref from, ref to } => write!(
+                f,
+                "~ {} regex: {} -> {}",
+                if suppress { "suppressing" } else { "passing" },
+                from.as_deref().unwrap_or("none"),
+                to.as_deref().unwrap_or("none")
+            ),
+        }
+    }
+}
+
+/// How `FilterSpec::merge` resolves a key, or a setting like the level, differing
+/// between the specs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// keep the values of this spec
+    Ours,
+    /// take the values of the other spec, e.g. a partial update
+    Theirs,
+    /// unite the values of keys present in both, taking the other settings of the other
+    /// spec
+    Union,
+    /// fail with `KVFilterError::MergeConflict`
+    Fail,
+}
+
+impl FilterSpec {
+    /// changes turning this spec into `other`, covering the value lists, the level and the
+    /// regexes. Patterns, scopes and the other settings aren't compared.
+    pub fn diff(&self, other: &FilterSpec) -> Vec<SpecChange> {
+        let mut changes = Vec::new();
+        for &(list, ours, theirs) in &self.lists_with(other) {
+            diff_values(list, &ours.values, &theirs.values, &mut changes);
+        }
+
+        if self.level != other.level {
+            changes.push(SpecChange::LevelChanged {
+                from: self.level,
+                to: other.level,
+            });
+        }
+        for &(suppress, ours, theirs) in &[
+            (false, &self.regex, &other.regex),
+            (true, &self.neg_regex, &other.neg_regex),
+        ] {
+            let (from, to) = (
+                ours.as_ref().map(|regex| regex.as_str().to_owned()),
+                theirs.as_ref().map(|regex| regex.as_str().to_owned()),
+            );
+            if from != to {
+                changes.push(SpecChange::RegexChanged { suppress, from, to });
+            }
+        }
+        changes
+    }
+
+    /// this spec with the value lists, the level and the regexes of `other` merged in.
+    /// Keys of only one of the specs are kept, keys with different values in each, and
+    /// differing settings, are resolved by `policy`.
+    pub fn merge(&self, other: &FilterSpec, policy: MergePolicy) -> Result<FilterSpec, KVFilterError> {
+        let mut merged = self.clone();
+
+        let mut lists = Vec::new();
+        for &(list, ours, theirs) in &self.lists_with(other) {
+            let mut values = ours.values.clone();
+            for (key, their_values) in &theirs.values {
+                match values.get_mut(key) {
+                    Some(our_values) if our_values != their_values => match policy {
+                        MergePolicy::Ours => {}
+                        MergePolicy::Theirs => *our_values = their_values.clone(),
+                        MergePolicy::Union => our_values.extend(their_values.iter().cloned()),
+                        MergePolicy::Fail => {
+                            return Err(KVFilterError::MergeConflict(format!("{:?} {}", list, key)))
+                        }
+                    },
+                    Some(_) => {}
+                    None => {
+                        values.insert(key.clone(), their_values.clone());
+                    }
+                }
+            }
+            lists.push(values);
+        }
+        merged.neg_all_filters.values = lists.pop().unwrap_or_default();
+        merged.neg_filters.values = lists.pop().unwrap_or_default();
+        merged.filters.values = lists.pop().unwrap_or_default();
+
+        let theirs = match policy {
+            MergePolicy::Ours => false,
+            MergePolicy::Theirs | MergePolicy::Union => true,
+            MergePolicy::Fail => {
+                let regex = |spec: &FilterSpec| spec.regex.as_ref().map(|r| r.as_str().to_owned());
+                let neg_regex =
+                    |spec: &FilterSpec| spec.neg_regex.as_ref().map(|r| r.as_str().to_owned());
+                if self.level != other.level {
+                    return Err(KVFilterError::MergeConflict("level".to_owned()));
+                }
+                if regex(self) != regex(other) || neg_regex(self) != neg_regex(other) {
+                    return Err(KVFilterError::MergeConflict("regex".to_owned()));
+                }
+                false
+            }
+        };
+        if theirs {
+            merged.level = other.level;
+            merged.regex = other.regex.clone().or_else(|| self.regex.clone());
+            merged.neg_regex = other.neg_regex.clone().or_else(|| self.neg_regex.clone());
+        }
+
+        merged.compile();
+        Ok(merged)
+    }
+
+    fn lists_with<'a>(&'a self, other: &'a FilterSpec) -> [(ValueList, &'a FilterList, &'a FilterList); 3] {
+        [
+            (ValueList::Pass, &self.filters, &other.filters),
+            (ValueList::Suppress, &self.neg_filters, &other.neg_filters),
+            (ValueList::SuppressAll, &self.neg_all_filters, &other.neg_all_filters),
+        ]
+    }
+}
+
+fn sorted<'a, I: IntoIterator<Item = &'a SmallStr>>(values: I) -> Vec<String> {
+    values
+        .into_iter()
+        .map(|value| value.to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+fn diff_values(
+    list: ValueList,
+    ours: &HashMap<SmallStr, HashSet<SmallStr>>,
+    theirs: &HashMap<SmallStr, HashSet<SmallStr>>,
+    changes: &mut Vec<SpecChange>,
+) {
+    let keys: BTreeSet<&SmallStr> = ours.keys().chain(theirs.keys()).collect();
+    for key in keys {
+        match (ours.get(key), theirs.get(key)) {
+            (None, Some(values)) => changes.push(SpecChange::KeyAdded {
+                list,
+                key: key.to_string(),
+                values: sorted(values),
+            }),
+            (Some(_), None) => changes.push(SpecChange::KeyRemoved {
+                list,
+                key: key.to_string(),
+            }),
+            (Some(our_values), Some(their_values)) if our_values != their_values => {
+                changes.push(SpecChange::ValuesChanged {
+                    list,
+                    key: key.to_string(),
+                    added: sorted(their_values.difference(our_values)),
+                    removed: sorted(our_values.difference(their_values)),
+                })
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergePolicy, SpecChange, ValueList};
+    use regex::Regex;
+    use slog::Level;
+    use {filter_list, KVFilter, KVFilterError};
+
+    #[test]
+    fn specdiff() {
+        let ours = KVFilter::new(::slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![
+                ("thread", vec!["100", "200"]),
+                ("direction", vec!["send"]),
+            ])))
+            .spec();
+        let theirs = KVFilter::new(::slog::Discard, Level::Warning)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![
+                ("thread", vec!["100", "300"]),
+                ("tenant", vec!["acme"]),
+            ])))
+            .always_suppress_on_regex(Regex::new("noise").unwrap())
+            .spec();
+
+        let changes = ours.diff(&theirs);
+        assert_eq!(
+            changes,
+            vec![
+                SpecChange::KeyRemoved { list: ValueList::Pass, key: "direction".to_owned() },
+                SpecChange::KeyAdded {
+                    list: ValueList::Pass,
+                    key: "tenant".to_owned(),
+                    values: vec!["acme".to_owned()],
+                },
+                SpecChange::ValuesChanged {
+                    list: ValueList::Pass,
+                    key: "thread".to_owned(),
+                    added: vec!["300".to_owned()],
+                    removed: vec!["200".to_owned()],
+                },
+                SpecChange::LevelChanged { from: Level::Info, to: Level::Warning },
+                SpecChange::RegexChanged {
+                    suppress: true,
+                    from: None,
+                    to: Some("noise".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(changes[2].to_string(), "~ Pass thread: +[300] -[200]");
+        assert!(ours.diff(&ours).is_empty());
+    }
+
+    #[test]
+    fn specmerge() {
+        let ours = KVFilter::new(::slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![
+                ("thread", vec!["100"]),
+                ("direction", vec!["send"]),
+            ])))
+            .spec();
+        let theirs = KVFilter::new(::slog::Discard, Level::Warning)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["200"])])))
+            .spec();
+        let thread = |spec: &::FilterSpec| {
+            let mut values: Vec<String> =
+                spec.filters.values["thread"].iter().map(|v| v.to_string()).collect();
+            values.sort();
+            values
+        };
+
+        let merged = ours.merge(&theirs, MergePolicy::Ours).unwrap();
+        assert_eq!(thread(&merged), vec!["100"]);
+        assert_eq!(merged.level, Level::Info);
+        assert!(merged.filters.values.contains_key("direction"));
+
+        let merged = ours.merge(&theirs, MergePolicy::Theirs).unwrap();
+        assert_eq!(thread(&merged), vec!["200"]);
+        assert_eq!(merged.level, Level::Warning);
+        assert_eq!(merged.diff(&theirs).len(), 1);
+
+        let merged = ours.merge(&theirs, MergePolicy::Union).unwrap();
+        assert_eq!(thread(&merged), vec!["100", "200"]);
+
+        match ours.merge(&theirs, MergePolicy::Fail) {
+            Err(KVFilterError::MergeConflict(conflict)) => assert_eq!(conflict, "Pass thread"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(ours.merge(&ours, MergePolicy::Fail).is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+//! `RUST_LOG` style directives as understood by slog-envlogger, extended by key value clauses
+
+use std::prelude::v1::*;
+
+use slog;
+
+use rules::{Condition, Rule};
+use {FilterStr, KVFilterError};
+
+/// Directives parsed from e.g. `warn,hyper=error,my_app::db=debug,tenant=acme/timeout`
+#[derive(Debug, PartialEq)]
+pub struct Directives {
+    /// level of a bare `warn` directive
+    pub default: Option<slog::Level>,
+    pub modules: ModuleLevels,
+    /// `key=value` clauses, passing records with the key value at any level, if their
+    /// `?VAR` condition holds
+    pub rules: Vec<Rule>,
+    /// regex after a `/`, which messages must match
+    pub regex: Option<String>,
+}
+
+/// Minimum levels of records by the module logging them, None for `off`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleLevels {
+    /// longest modules first, so that the first matching is the most specific one
+    modules: Vec<(FilterStr, Option<slog::Level>)>,
+}
+
+impl ModuleLevels {
+    pub fn insert(&mut self, module: FilterStr, level: Option<slog::Level>) {
+        self.modules.retain(|(m, _)| *m != module);
+        let index = self
+            .modules
+            .iter()
+            .position(|(m, _)| m.len() < module.len())
+            .unwrap_or(self.modules.len());
+        self.modules.insert(index, (module, level));
+    }
+
+    pub fn extend(&mut self, other: ModuleLevels) {
+        for (module, level) in other.modules {
+            self.insert(module, level);
+        }
+    }
+
+    /// level of the most specific directive prefixing `module`, if any
+    pub fn level_for(&self, module: &str) -> Option<Option<slog::Level>> {
+        self.modules
+            .iter()
+            .find(|(m, _)| module.starts_with(&**m))
+            .map(|&(_, level)| level)
+    }
+
+    /// least important of the levels, if any
+    pub fn lowest(&self) -> Option<slog::Level> {
+        self.modules.iter().filter_map(|&(_, level)| level).max()
+    }
+}
+
+pub fn parse_level(level: &str) -> Option<Option<slog::Level>> {
+    if level.eq_ignore_ascii_case("off") {
+        Some(None)
+    } else {
+        level.parse().ok().map(Some)
+    }
+}
+
+pub fn parse(spec: &str) -> Result<Directives, KVFilterError> {
+    let (mut directives, spec) = split(spec);
+    for directive in directives_of(spec) {
+        parse_directive(directive, &mut directives)?;
+    }
+    Ok(directives)
+}
+
+/// the valid directives of `spec`, with the errors of the invalid ones left out
+#[cfg(feature = "remote")]
+pub fn parse_valid(spec: &str) -> (Directives, Vec<KVFilterError>) {
+    let (mut directives, spec) = split(spec);
+    let errors = directives_of(spec)
+        .filter_map(|directive| parse_directive(directive, &mut directives).err())
+        .collect();
+    (directives, errors)
+}
+
+/// no directives yet but the regex of `spec`, and the rest of it
+fn split(spec: &str) -> (Directives, &str) {
+    let (spec, regex) = match spec.split_once('/') {
+        Some((spec, regex)) => (spec, Some(regex.to_owned())),
+        None => (spec, None),
+    };
+    let directives = Directives {
+        default: None,
+        modules: ModuleLevels::default(),
+        rules: Vec::new(),
+        regex,
+    };
+    (directives, spec)
+}
+
+fn directives_of(spec: &str) -> impl Iterator<Item = &str> {
+    spec.split(',').map(str::trim).filter(|d| !d.is_empty())
+}
+
+/// add `directive` to `directives`
+fn parse_directive(directive: &str, directives: &mut Directives) -> Result<(), KVFilterError> {
+    match directive.split_once('=') {
+        Some((name, value)) => {
+            let (name, value) = (name.trim(), value.trim());
+            if name.is_empty() || value.is_empty() {
+                return Err(KVFilterError::InvalidDirective(directive.to_owned()));
+            }
+            let (value, condition) = match value.split_once('?') {
+                Some((_, "")) => return Err(KVFilterError::InvalidDirective(directive.to_owned())),
+                Some((value, "debug_assertions")) => (value, Some(Condition::DebugAssertions)),
+                Some((value, var)) => (value, Some(Condition::Env(var.to_owned().into()))),
+                None => (value, None),
+            };
+            match (parse_level(value), condition) {
+                (Some(level), None) => directives.modules.insert(name.to_owned().into(), level),
+                (Some(level), Some(condition)) => {
+                    if condition.holds() {
+                        directives.modules.insert(name.to_owned().into(), level);
+                    }
+                }
+                (None, condition) => {
+                    let rule = Rule::pass(name.to_owned(), value.to_owned(), slog::Level::Trace);
+                    directives.rules.push(match condition {
+                        Some(condition) => rule.when(condition),
+                        None => rule,
+                    });
+                }
+            }
+        }
+        None => match parse_level(directive) {
+            Some(Some(level)) => directives.default = Some(level),
+            // nothing is less important than `Critical`
+            Some(None) => return Err(KVFilterError::InvalidDirective(directive.to_owned())),
+            None => directives
+                .modules
+                .insert(directive.to_owned().into(), Some(slog::Level::Trace)),
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    #[cfg(feature = "remote")]
+    use super::parse_valid;
+    use rules::{Condition, Rule};
+    use slog::Level;
+    use KVFilterError;
+
+    #[test]
+    fn envloggerdirectives() {
+        let directives = parse("warn, hyper=error,my_app::db=debug,my_app=off,tokio").unwrap();
+
+        assert_eq!(directives.default, Some(Level::Warning));
+        assert_eq!(directives.modules.level_for("hyper::client"), Some(Some(Level::Error)));
+        assert_eq!(directives.modules.level_for("my_app::db::pool"), Some(Some(Level::Debug)));
+        assert_eq!(directives.modules.level_for("my_app::web"), Some(None));
+        assert_eq!(directives.modules.level_for("tokio"), Some(Some(Level::Trace)));
+        assert_eq!(directives.modules.level_for("serde"), None);
+        assert_eq!(directives.modules.lowest(), Some(Level::Trace));
+        assert!(directives.rules.is_empty());
+        assert_eq!(directives.regex, None);
+    }
+
+    #[test]
+    fn kvdirectives() {
+        let directives = parse("info,tenant=acme/time.*out").unwrap();
+
+        assert_eq!(directives.default, Some(Level::Info));
+        assert_eq!(directives.rules, vec![Rule::pass("tenant", "acme", Level::Trace)]);
+        assert_eq!(directives.regex.as_deref(), Some("time.*out"));
+
+        let directives =
+            parse("tenant=acme?ENABLE_ACME_TRACE,db=debug?debug_assertions,net=trace?KVFILTER_UNSET")
+                .unwrap();
+        assert_eq!(
+            directives.rules,
+            vec![Rule::pass("tenant", "acme", Level::Trace)
+                .when(Condition::Env("ENABLE_ACME_TRACE".into()))]
+        );
+        assert_eq!(
+            directives.modules.level_for("db"),
+            if cfg!(debug_assertions) { Some(Some(Level::Debug)) } else { None }
+        );
+        assert_eq!(directives.modules.level_for("net"), None);
+
+        match parse("info,=debug") {
+            Err(KVFilterError::InvalidDirective(directive)) => assert_eq!(directive, "=debug"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "remote")]
+    fn validdirectives() {
+        let (directives, errors) = parse_valid("info,=debug,tenant=acme,off,db=trace?");
+        assert_eq!(directives.default, Some(Level::Info));
+        assert_eq!(directives.rules, vec![Rule::pass("tenant", "acme", Level::Trace)]);
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                KVFilterError::InvalidDirective("=debug".to_owned()).to_string(),
+                KVFilterError::InvalidDirective("off".to_owned()).to_string(),
+                KVFilterError::InvalidDirective("db=trace?".to_owned()).to_string(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Decisions of a `KVFilter` for other logging frameworks and pipelines, see `FilterEngine`
+
+use std::prelude::v1::*;
+
+use slog;
+use slog::KV;
+
+use combinators::SerializedKV;
+use {KVFilter, KVFilterHandle, KVPredicate};
+
+/// Decides key values the way a `KVFilter` decides slog records, without a drain to forward
+/// them to, e.g. for the events of another logging framework to be filtered by the same
+/// configuration as the slog records.
+///
+/// ```
+/// # extern crate slog;
+/// # extern crate slog_kvfilter;
+/// use slog_kvfilter::{filter_list, FilterEngine, KVFilter};
+///
+/// # fn main() {
+/// let engine = FilterEngine::new(
+///     KVFilter::new(slog::Discard, slog::Level::Info)
+///         .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])]))),
+/// );
+/// assert!(engine.matches(slog::Level::Debug, vec![("tenant", "acme")]));
+/// assert!(!engine.matches(slog::Level::Debug, vec![("tenant", "globex")]));
+/// # }
+/// ```
+///
+/// Its decisions are those of records logged with an empty message, from no module, so
+/// that module levels and message regexes don't apply. The values are serialized as slog
+/// values, so that numbers and booleans match `ValuePattern::Number`s and
+/// `ValuePattern::Bool`s as they do on records. They aren't counted in
+/// `KVFilterHandle::stats`, but for `FilterStats::match_errors`.
+pub struct FilterEngine {
+    filter: KVFilter<slog::Discard>,
+}
+
+/// key values given to `FilterEngine::matches`, serialized straight into the pass
+struct Pairs<V>(Vec<(slog::Key, V)>);
+
+impl<V: slog::Value> KV for Pairs<V> {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        for &(key, ref value) in &self.0 {
+            value.serialize(record, key, serializer)?;
+        }
+        Ok(())
+    }
+}
+
+static LOCATION: slog::RecordLocation = slog::RecordLocation {
+    file: "",
+    line: 0,
+    column: 0,
+    function: "",
+    module: "",
+};
+
+impl FilterEngine {
+    /// engine deciding like `filter`
+    pub fn new(filter: KVFilter<slog::Discard>) -> Self {
+        FilterEngine { filter }
+    }
+
+    /// engine deciding like the filters controlled by `handle`, following its updates
+    pub fn controlled_by(handle: &KVFilterHandle) -> Self {
+        FilterEngine::new(KVFilter::new(slog::Discard, slog::Level::Trace).controlled_by(handle))
+    }
+
+    /// handle to control the engine, see `KVFilter::handle`
+    pub fn handle(&self) -> KVFilterHandle {
+        self.filter.handle()
+    }
+
+    /// whether anything of `level` may pass, to skip building the key values otherwise
+    pub fn enabled(&self, level: slog::Level) -> bool {
+        self.filter.enabled_level(level).is_some()
+    }
+
+    /// whether a record of `level` with the key values `kv` passes
+    pub fn matches<I, V>(&self, level: slog::Level, kv: I) -> bool
+    where
+        I: IntoIterator<Item = (slog::Key, V)>,
+        V: slog::Value,
+    {
+        self.matches_with_context(level, kv, Vec::<(slog::Key, V)>::new())
+    }
+
+    /// whether a record of `level` with the key values `kv` passes in a logger context of
+    /// the key values `context`, e.g. the fields of the enclosing spans, innermost first
+    pub fn matches_with_context<I, C, V, W>(&self, level: slog::Level, kv: I, context: C) -> bool
+    where
+        I: IntoIterator<Item = (slog::Key, V)>,
+        C: IntoIterator<Item = (slog::Key, W)>,
+        V: slog::Value,
+        W: slog::Value,
+    {
+        if !self.enabled(level) {
+            return false;
+        }
+        let kv = Pairs(kv.into_iter().collect());
+        let rs = slog::RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level,
+        };
+        self.matches_in_context(&slog::Record::new(&rs, &format_args!(""), slog::BorrowedKV(&kv)), context)
+    }
+
+    fn matches_in_context<C, W>(&self, record: &slog::Record, context: C) -> bool
+    where
+        C: IntoIterator<Item = (slog::Key, W)>,
+        W: slog::Value,
+    {
+        // the logger context is owned, so its values are copied, keeping their type
+        let mut serialized = SerializedKV(Vec::new());
+        for (key, value) in context {
+            let _ = value.serialize(record, key, &mut serialized);
+        }
+        self.matches_record(record, &slog::OwnedKVList::from(slog::OwnedKV(serialized)))
+    }
+
+    /// whether `record` passes, for the frameworks able to build slog records
+    pub fn matches_record(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool {
+        KVPredicate::is_match(&self.filter, record, logger_values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilterEngine;
+    use slog::{self, Level};
+    use std::collections::HashMap;
+    use {filter_list, KVFilter, Rule, ValuePattern};
+
+    #[test]
+    fn filterengine() {
+        let engine = FilterEngine::new(
+            KVFilter::new(slog::Discard, Level::Info)
+                .min_level(Level::Debug)
+                .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+                .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])]))),
+        );
+
+        assert!(engine.matches(Level::Debug, vec![("tenant", "acme")]));
+        assert!(!engine.matches(Level::Trace, vec![("tenant", "acme")]));
+        assert!(!engine.matches(Level::Debug, vec![("tenant", "globex")]));
+        assert!(!engine.matches(Level::Debug, vec![("tenant", "acme"), ("deepcomp", "1")]));
+        assert!(engine.matches(Level::Warning, Vec::<(&str, &str)>::new()));
+        assert!(engine.matches_with_context(Level::Debug, vec![("thread", 100)], vec![("tenant", "acme")]));
+        assert!(!engine.enabled(Level::Trace));
+
+        let mut patterns = HashMap::new();
+        patterns.insert("packet".to_owned(), vec![ValuePattern::Number(42u64.into())]);
+        let typed = FilterEngine::new(
+            KVFilter::new(slog::Discard, Level::Info).only_pass_any_pattern_on_all_keys(Some(patterns)),
+        );
+        assert!(typed.matches(Level::Info, vec![("packet", 42u64)]));
+        assert!(typed.matches_with_context(Level::Info, Vec::<(&str, u8)>::new(), vec![("packet", 42.0)]));
+        assert!(!typed.matches(Level::Info, vec![("packet", "42")]));
+
+        let other = FilterEngine::controlled_by(&engine.handle());
+        engine.handle().add_rule(Rule::pass("tenant", "globex", Level::Debug), None);
+        assert!(other.matches(Level::Debug, vec![("tenant", "globex")]));
+    }
+}
@@ -0,0 +1,133 @@
+//! Errors of filter configuration
+
+use std::error;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io;
+use std::prelude::v1::*;
+
+use regex;
+
+use FilterStr;
+
+/// Problem with a filter configuration, see `KVFilterBuilder::build`, or with setting up a
+/// filter. Filters never fail, nor panic, while filtering records.
+///
+/// New kinds of problems may be added, so matches need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum KVFilterError {
+    /// key given without any values or patterns, it could never match
+    EmptyValues(FilterStr),
+    /// key given more than once for the same filter
+    DuplicateKey(FilterStr),
+    /// value both passing and suppressing a key
+    Conflict { key: FilterStr, value: FilterStr },
+    /// regex that doesn't compile
+    InvalidRegex(regex::Error),
+    /// `RUST_LOG` style directive that doesn't parse, see `KVFilter::directives`
+    InvalidDirective(String),
+    /// key or setting differing between specs merged by `MergePolicy::Fail`, see
+    /// `FilterSpec::merge`
+    MergeConflict(String),
+    /// IP address range that doesn't parse, see `Cidr`
+    InvalidCidr(String),
+    /// version or version requirement that doesn't parse, see `VersionReq`
+    InvalidVersion(String),
+    /// duration or size comparison that doesn't parse, see `Threshold`
+    InvalidThreshold(String),
+    /// URL a `ConfigSource` can't fetch from
+    InvalidUrl(String),
+    /// configuration that couldn't be fetched from its `ConfigSource`
+    #[cfg(feature = "std")]
+    Fetch(io::Error),
+    /// typed configuration that doesn't convert into a spec, see `spec_from_config`
+    InvalidConfig(String),
+    /// key given more values and patterns than allowed, see
+    /// `KVFilterBuilder::max_values_per_key`
+    TooManyValues { key: FilterStr, values: usize, limit: usize },
+    /// more values and patterns given for all the keys than allowed, see
+    /// `KVFilterBuilder::max_values`
+    SpecTooLarge { values: usize, limit: usize },
+    /// placeholder of a configuration without a value, see `Placeholders`
+    UndefinedPlaceholder(String),
+    /// placeholder of a configuration that doesn't parse, e.g. without its closing brace
+    InvalidPlaceholder(String),
+    /// worker thread that couldn't be spawned, see `NonBlocking::new`
+    #[cfg(feature = "std")]
+    Spawn(io::Error),
+    /// socket of a `Registry` that couldn't be bound, or reached by a `RegistryClient`
+    #[cfg(feature = "std")]
+    Registry(io::Error),
+    /// request refused by the `Registry` of a process, e.g. for a filter it doesn't publish
+    Rejected(String),
+}
+
+impl fmt::Display for KVFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KVFilterError::EmptyValues(ref key) => write!(f, "no values given for key {}", key),
+            KVFilterError::DuplicateKey(ref key) => write!(f, "key {} given more than once", key),
+            KVFilterError::Conflict { ref key, ref value } => write!(
+                f,
+                "value {} of key {} both passes and suppresses",
+                value, key
+            ),
+            KVFilterError::InvalidRegex(ref err) => write!(f, "invalid regex: {}", err),
+            KVFilterError::InvalidDirective(ref directive) => {
+                write!(f, "invalid directive: {}", directive)
+            }
+            KVFilterError::MergeConflict(ref conflict) => {
+                write!(f, "merged specs differ in {}", conflict)
+            }
+            KVFilterError::InvalidCidr(ref cidr) => write!(f, "invalid CIDR range: {}", cidr),
+            KVFilterError::InvalidVersion(ref version) => write!(f, "invalid version: {}", version),
+            KVFilterError::InvalidThreshold(ref threshold) => {
+                write!(f, "invalid threshold: {}", threshold)
+            }
+            KVFilterError::InvalidUrl(ref url) => write!(f, "invalid URL: {}", url),
+            #[cfg(feature = "std")]
+            KVFilterError::Fetch(ref err) => write!(f, "fetching the configuration failed: {}", err),
+            KVFilterError::InvalidConfig(ref config) => write!(f, "invalid configuration: {}", config),
+            KVFilterError::TooManyValues { ref key, values, limit } => write!(
+                f,
+                "key {} given {} values, more than the limit of {}",
+                key, values, limit
+            ),
+            KVFilterError::SpecTooLarge { values, limit } => {
+                write!(f, "{} values given, more than the limit of {}", values, limit)
+            }
+            KVFilterError::UndefinedPlaceholder(ref name) => {
+                write!(f, "no value for placeholder ${{{}}}", name)
+            }
+            KVFilterError::InvalidPlaceholder(ref placeholder) => {
+                write!(f, "invalid placeholder: {}", placeholder)
+            }
+            #[cfg(feature = "std")]
+            KVFilterError::Spawn(ref err) => write!(f, "spawning the worker thread failed: {}", err),
+            #[cfg(feature = "std")]
+            KVFilterError::Registry(ref err) => write!(f, "registry socket failed: {}", err),
+            KVFilterError::Rejected(ref reason) => write!(f, "request rejected: {}", reason),
+        }
+    }
+}
+
+impl error::Error for KVFilterError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            #[cfg(feature = "std")]
+            KVFilterError::InvalidRegex(ref err) => Some(err),
+            #[cfg(feature = "std")]
+            KVFilterError::Fetch(ref err) | KVFilterError::Spawn(ref err) | KVFilterError::Registry(ref err) => {
+                Some(err)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<regex::Error> for KVFilterError {
+    fn from(err: regex::Error) -> Self {
+        KVFilterError::InvalidRegex(err)
+    }
+}
@@ -0,0 +1,168 @@
+//! Records deciding for themselves whether they pass, see `FORCE_KEY`
+
+use std::fmt;
+
+use slog;
+use slog::KV;
+
+use combinators::text;
+
+/// Reserved key of a record overriding the decision of filters of `KVFilter::honor_force_key`:
+/// `"pass"` forwards the record whatever the spec, the rules, the routes and the overrides,
+/// `"drop"` suppresses it. Libraries can thus make sure their critical records escape the
+/// filters configured by applications:
+///
+/// ```
+/// # #[macro_use] extern crate slog;
+/// # extern crate slog_kvfilter;
+/// use slog::Drain;
+/// use slog_kvfilter::{filter_list, KVFilter, FORCE_KEY};
+///
+/// # fn main() {
+/// let filter = KVFilter::new(slog::Discard, slog::Level::Info)
+///     .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+///     .honor_force_key();
+/// let log = slog::Logger::root(filter.fuse(), o!());
+/// info!(log, "passing, forced"; FORCE_KEY => "pass", "tenant" => "globex");
+/// # }
+/// ```
+///
+/// Only the key values of the record are looked at, not those of the logger context, and
+/// the key is left out of the records it decides. Other values of the key are ignored, and
+/// forwarded. Records below `KVFilter::min_level` aren't looked at for the key.
+pub const FORCE_KEY: &str = "kvfilter.force";
+
+/// Decision forced by `FORCE_KEY`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Force {
+    Pass,
+    Drop,
+}
+
+/// decision forced by the `FORCE_KEY` of `record`, the last one if there are several
+pub fn forced(record: &slog::Record) -> Option<Force> {
+    let mut finder = ForceFinder(None);
+    let _ = record.kv().serialize(record, &mut finder);
+    finder.0
+}
+
+struct ForceFinder(Option<Force>);
+
+impl slog::Serializer for ForceFinder {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if key == FORCE_KEY {
+            self.emit_str(key, &text(val))?;
+        }
+        Ok(())
+    }
+
+    fn emit_str(&mut self, key: slog::Key, val: &str) -> slog::Result {
+        if key == FORCE_KEY {
+            match val {
+                "pass" => self.0 = Some(Force::Pass),
+                "drop" => self.0 = Some(Force::Drop),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Key values of a record without its `FORCE_KEY`s, forwarded as they are otherwise
+pub struct WithoutForce<'a>(pub slog::BorrowedKV<'a>);
+
+impl<'a> KV for WithoutForce<'a> {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        self.0.serialize(record, &mut Stripping(serializer))
+    }
+}
+
+struct Stripping<'a>(&'a mut dyn slog::Serializer);
+
+macro_rules! strip {
+    ($($f:ident: $t:ty),*) => {
+        $(
+            fn $f(&mut self, key: slog::Key, val: $t) -> slog::Result {
+                if key == FORCE_KEY {
+                    return Ok(());
+                }
+                self.0.$f(key, val)
+            }
+        )*
+    };
+}
+
+impl<'a> slog::Serializer for Stripping<'a> {
+    strip!(
+        emit_usize: usize, emit_isize: isize, emit_bool: bool, emit_char: char, emit_u8: u8, emit_i8: i8,
+        emit_u16: u16, emit_i16: i16, emit_u32: u32, emit_i32: i32, emit_f32: f32, emit_u64: u64, emit_i64: i64,
+        emit_f64: f64, emit_u128: u128, emit_i128: i128, emit_str: &str, emit_arguments: &fmt::Arguments
+    );
+
+    fn emit_unit(&mut self, key: slog::Key) -> slog::Result {
+        if key == FORCE_KEY {
+            return Ok(());
+        }
+        self.0.emit_unit(key)
+    }
+
+    fn emit_none(&mut self, key: slog::Key) -> slog::Result {
+        if key == FORCE_KEY {
+            return Ok(());
+        }
+        self.0.emit_none(key)
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        if key == FORCE_KEY {
+            return Ok(());
+        }
+        self.0.emit_serde(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forced, Force, WithoutForce, FORCE_KEY};
+    use slog::{self, Level, Record, RecordLocation, RecordStatic, KV};
+    use std::fmt;
+
+    struct Keys(Vec<String>);
+
+    impl slog::Serializer for Keys {
+        fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+            self.0.push(format!("{}={}", key, val));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn forcekey() {
+        static LOCATION: RecordLocation = RecordLocation {
+            file: "",
+            line: 0,
+            column: 0,
+            function: "",
+            module: "",
+        };
+        let rs = RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level: Level::Info,
+        };
+        let force = |kv: &dyn KV| forced(&Record::new(&rs, &format_args!(""), slog::BorrowedKV(kv)));
+        let kv = kv!("tenant" => "acme", FORCE_KEY => "pass", "packet" => 1);
+        assert_eq!(force(&kv), Some(Force::Pass));
+
+        let mut keys = Keys(Vec::new());
+        WithoutForce(slog::BorrowedKV(&kv))
+            .serialize(&Record::new(&rs, &format_args!(""), slog::BorrowedKV(&kv)), &mut keys)
+            .unwrap();
+        keys.0.sort();
+        assert_eq!(keys.0, vec!["packet=1", "tenant=acme"]);
+
+        assert_eq!(force(&kv!(FORCE_KEY => "drop")), Some(Force::Drop));
+        assert_eq!(force(&kv!(FORCE_KEY => "maybe", "tenant" => "acme")), None);
+    }
+}
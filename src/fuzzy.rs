@@ -0,0 +1,64 @@
+//! Bounded edit distance of values, see `ValuePattern::Fuzzy`
+
+use std::prelude::v1::*;
+
+/// whether `value` turns into `target` by at most `max` insertions, deletions and
+/// substitutions of characters
+///
+/// Only the diagonal band of width `2 * max + 1` of the Levenshtein table is computed, and
+/// the computation stops as soon as no cell of a row is within `max`, so that values far
+/// off are rejected after their first few characters.
+pub fn within(value: &str, target: &str, max: usize) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let target: Vec<char> = target.chars().collect();
+    if value.len().abs_diff(target.len()) > max {
+        return false;
+    }
+    // no distance is larger, and `max + 1` can't overflow
+    let max = max.min(value.len().max(target.len()));
+
+    // cells outside the band are beyond `max` anyway
+    let beyond = max + 1;
+    let mut previous: Vec<usize> = (0..=target.len()).map(|j| j.min(beyond)).collect();
+    let mut current = vec![beyond; target.len() + 1];
+    for (i, &c) in value.iter().enumerate() {
+        let row = i + 1;
+        let first = row.saturating_sub(max).max(1);
+        let last = (row + max).min(target.len());
+
+        current.iter_mut().for_each(|cell| *cell = beyond);
+        current[0] = row.min(beyond);
+        let mut best = current[0];
+        for j in first..=last {
+            let substitution = previous[j - 1] + usize::from(c != target[j - 1]);
+            let cell = substitution.min(previous[j] + 1).min(current[j - 1] + 1).min(beyond);
+            current[j] = cell;
+            best = best.min(cell);
+        }
+        if best > max {
+            return false;
+        }
+        ::std::mem::swap(&mut previous, &mut current);
+    }
+    previous[target.len()] <= max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::within;
+
+    #[test]
+    fn editdistance() {
+        assert!(within("alice", "alice", 0));
+        assert!(within("alicr", "alice", 1));
+        assert!(within("alic", "alice", 1));
+        assert!(within("aalice", "alice", 1));
+        assert!(!within("alcie", "alice", 1));
+        assert!(within("alcie", "alice", 2));
+        assert!(!within("bob", "alice", 2));
+        assert!(within("db-01.exämple", "db-01.example", 1));
+        assert!(within("", "a", 1));
+        assert!(!within("", "ab", 1));
+        assert!(within("bob", "alice", usize::MAX));
+    }
+}
@@ -0,0 +1,544 @@
+//! Control of a `KVFilter` while it's in use by a `Logger`
+
+use std::collections::VecDeque;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog;
+
+use audit::{AuditEntry, AuditLog};
+use clock::{self, WallTime};
+use learn::{Learned, Learner};
+#[cfg(feature = "std")]
+use overrides::{self, OverrideGuard, ScopedOverride};
+use rules::{Rule, RuleId, RuntimeRules};
+use stats::{Counters, FilterStats};
+use small_str::SmallStr;
+use sync::{ArcSwap, Guard, Mutex, MutexGuard};
+use {FilterList, FilterSpec, FilterStr, KVFilter, ValueList};
+
+/// Level shared between a `KVFilter` and its handles, updated atomically
+#[derive(Clone)]
+pub struct SharedLevel(Arc<AtomicUsize>);
+
+impl SharedLevel {
+    pub fn new(level: slog::Level) -> Self {
+        SharedLevel(Arc::new(AtomicUsize::new(level.as_usize())))
+    }
+
+    pub fn get(&self) -> slog::Level {
+        slog::Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(slog::Level::Trace)
+    }
+
+    pub fn set(&self, level: slog::Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed)
+    }
+
+    /// identity of the filter sharing the level, for its scoped overrides
+    #[cfg(feature = "std")]
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+}
+
+/// number of specs kept for rollback by default
+const HISTORY_CAPACITY: usize = 16;
+
+/// Spec applied through a handle, see `KVFilterHandle::versions`
+#[derive(Clone, Debug)]
+pub struct SpecVersion {
+    /// increasing with each spec applied, the configured spec being version 0
+    pub version: u64,
+    /// wall time of the clock of the filter when applied, see `KVFilter::clock`, `None` for
+    /// the configured spec, so that creating filters doesn't read the clock
+    pub applied_at: Option<WallTime>,
+    /// see `KVFilterHandle::label_version`
+    pub label: Option<String>,
+}
+
+struct History {
+    /// the specs kept, the current one last
+    versions: VecDeque<(SpecVersion, Arc<FilterSpec>)>,
+    capacity: usize,
+    /// version of the next spec applied, versions rolled back aren't reused
+    next: u64,
+}
+
+struct SpecCell {
+    /// swapped atomically, so that records load it without locking
+    current: ArcSwap<FilterSpec>,
+    /// times `current` was replaced, see `SharedSpec::replaced`
+    replaced: AtomicUsize,
+    /// serializes updates, which copy the current spec and replace it once done
+    updating: Mutex<()>,
+    history: Mutex<History>,
+}
+
+/// spec replaced, and the one replacing it
+pub type Swap = (Arc<FilterSpec>, Arc<FilterSpec>);
+
+/// Spec shared between filters and their handles. Updates build a new spec and swap it in
+/// at once, so that records are always decided by either the old or the new one.
+#[derive(Clone)]
+pub struct SharedSpec(Arc<SpecCell>);
+
+impl SharedSpec {
+    pub fn new(spec: Arc<FilterSpec>) -> Self {
+        let initial = SpecVersion {
+            version: 0,
+            applied_at: None,
+            label: None,
+        };
+        SharedSpec(Arc::new(SpecCell {
+            current: ArcSwap::new(spec.clone()),
+            replaced: AtomicUsize::new(0),
+            updating: Mutex::new(()),
+            history: Mutex::new(History {
+                versions: vec![(initial, spec)].into(),
+                capacity: HISTORY_CAPACITY,
+                next: 1,
+            }),
+        }))
+    }
+
+    /// the current spec, without locking nor counting a reference, for deciding records
+    pub fn load(&self) -> Guard<Arc<FilterSpec>> {
+        self.0.current.load()
+    }
+
+    /// the current spec, to be kept
+    pub fn load_full(&self) -> Arc<FilterSpec> {
+        self.0.current.load_full()
+    }
+
+    /// times the spec was replaced, for drains holding on to what they made of a spec to
+    /// tell it's still current by a single load
+    pub fn replaced(&self) -> usize {
+        self.0.replaced.load(Ordering::Acquire)
+    }
+
+    fn swap(&self, spec: Arc<FilterSpec>) -> Arc<FilterSpec> {
+        let replaced = self.0.current.swap(spec);
+        self.0.replaced.fetch_add(1, Ordering::Release);
+        replaced
+    }
+
+    /// replace the spec by the one `f` builds from the current one
+    pub fn replace<F: FnOnce(Arc<FilterSpec>) -> Arc<FilterSpec>>(&self, f: F) {
+        let _updating = self.0.updating.lock();
+        let spec = f(self.load_full());
+        self.swap(spec);
+    }
+
+    /// replace the spec by the one `f` builds from the current one as a new version applied
+    /// `at`, keeping the replaced one for rollback
+    pub fn apply<F: FnOnce(Arc<FilterSpec>) -> Arc<FilterSpec>>(&self, at: WallTime, f: F) -> Swap {
+        let _updating = self.0.updating.lock();
+        let current = self.load_full();
+        let spec = f(current.clone());
+
+        let mut history = self.history();
+        if let Some(last) = history.versions.back_mut() {
+            // the filter may have been configured further since the version was recorded
+            last.1 = current;
+        }
+        let applied = SpecVersion {
+            version: history.next,
+            applied_at: Some(at),
+            label: None,
+        };
+        history.next += 1;
+        history.versions.push_back((applied, spec.clone()));
+        while history.versions.len() > history.capacity {
+            history.versions.pop_front();
+        }
+
+        let replaced = self.swap(spec.clone());
+        (replaced, spec)
+    }
+
+    /// switch back to `version`, dropping the versions applied after it
+    pub fn rollback_to(&self, version: u64) -> Option<Swap> {
+        let _updating = self.0.updating.lock();
+        let mut history = self.history();
+        let index = history.versions.iter().position(|v| v.0.version == version)?;
+        history.versions.truncate(index + 1);
+        let spec = history.versions[index].1.clone();
+        let replaced = self.swap(spec.clone());
+        Some((replaced, spec))
+    }
+
+    /// the version before the current one
+    pub fn previous_version(&self) -> Option<u64> {
+        let history = self.history();
+        let len = history.versions.len();
+        if len < 2 {
+            return None;
+        }
+        Some(history.versions[len - 2].0.version)
+    }
+
+    pub fn versions(&self) -> Vec<SpecVersion> {
+        self.history().versions.iter().map(|v| v.0.clone()).collect()
+    }
+
+    pub fn label(&self, label: String) {
+        if let Some(last) = self.history().versions.back_mut() {
+            last.0.label = Some(label);
+        }
+    }
+
+    pub fn history_capacity(&self) -> usize {
+        self.history().capacity
+    }
+
+    pub fn set_history_capacity(&self, capacity: usize) {
+        let mut history = self.history();
+        // the current spec is always kept
+        history.capacity = capacity.max(1);
+        while history.versions.len() > history.capacity {
+            history.versions.pop_front();
+        }
+    }
+
+    fn history(&self) -> MutexGuard<'_, History> {
+        self.0.history.lock()
+    }
+
+    /// replace the spec by a copy changed by `f`
+    pub fn update<F: FnOnce(&mut FilterSpec)>(&self, f: F) {
+        self.replace(|current| {
+            let mut spec = (*current).clone();
+            f(&mut spec);
+            Arc::new(spec)
+        })
+    }
+}
+
+/// Handle changing the behavior of a `KVFilter` after it has been moved into a `Logger`,
+/// obtained by `KVFilter::handle`.
+///
+/// Handles are cheap to clone and can be sent to other threads, e.g. to bump the
+/// verbosity of a live process on request. The changes made through them are recorded in
+/// the `audit_trail`.
+#[derive(Clone)]
+pub struct KVFilterHandle {
+    pub(crate) min_level: SharedLevel,
+    pub(crate) rules: Arc<RuntimeRules>,
+    pub(crate) spec: SharedSpec,
+    pub(crate) audit: Arc<AuditLog>,
+    pub(crate) actor: Option<Arc<str>>,
+    pub(crate) counters: Arc<Counters>,
+    pub(crate) learner: Arc<Learner>,
+}
+
+impl KVFilterHandle {
+    /// handle recording `actor` in the audit trail for the changes made through it, e.g.
+    /// `handle.with_actor("alice: INC-42")`
+    pub fn with_actor<A: Into<String>>(&self, actor: A) -> KVFilterHandle {
+        KVFilterHandle {
+            actor: Some(actor.into().into()),
+            ..self.clone()
+        }
+    }
+
+    /// changes made through the handles of the filter, the latest 1024 of them, oldest
+    /// first. See `KVFilter::log_audit_trail` to log them as they are made.
+    pub fn audit_trail(&self) -> Vec<AuditEntry> {
+        self.audit.entries()
+    }
+
+    /// wall time of the clock of the filter, see `KVFilter::clock`
+    fn wall_time(&self) -> WallTime {
+        clock::wall_time(self.rules.since_epoch())
+    }
+
+    fn audit(&self, action: String, swap: Option<Swap>) {
+        self.audit.record(AuditEntry {
+            at: self.wall_time(),
+            actor: self.actor.as_ref().map(|actor| actor.to_string()),
+            action,
+            changes: swap.map_or_else(Vec::new, |(replaced, applied)| replaced.diff(&applied)),
+        })
+    }
+
+    /// current spec, see `KVFilter::spec`
+    pub fn spec(&self) -> Arc<FilterSpec> {
+        self.spec.load_full()
+    }
+
+    /// replace the spec of the filters controlled by this handle at once
+    pub fn set_spec(&self, spec: Arc<FilterSpec>) {
+        let swap = self.spec.apply(self.wall_time(), |_| spec);
+        self.audit("set_spec".to_owned(), Some(swap));
+    }
+
+    /// replace the spec by a copy reconfigured by `configure`, e.g.
+    /// `handle.update_spec(|filter| filter.always_suppress_any(None))`. The filters
+    /// controlled by this handle switch to the new spec at once, records are never decided
+    /// by a partially updated one.
+    pub fn update_spec<F>(&self, configure: F)
+    where
+        F: FnOnce(KVFilter<slog::Discard>) -> KVFilter<slog::Discard>,
+    {
+        let swap = self.spec.apply(self.wall_time(), |current| {
+            let filter = KVFilter::new(slog::Discard, current.level).with_spec(current);
+            configure(filter).spec()
+        });
+        self.audit("update_spec".to_owned(), Some(swap));
+    }
+
+    /// start collecting changes of the spec to apply at once by `SpecUpdate::commit`, e.g.
+    /// to move a value from one key to another without any record decided in between
+    pub fn begin_update(&self) -> SpecUpdate {
+        SpecUpdate {
+            handle: self.clone(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// the specs kept for rollback, the current one last, see `KVFilter::spec_history`.
+    /// Each `set_spec` and `update_spec` applies a new version.
+    pub fn versions(&self) -> Vec<SpecVersion> {
+        self.spec.versions()
+    }
+
+    /// label the current version, e.g. with the ticket it was applied for
+    pub fn label_version<L: Into<String>>(&self, label: L) {
+        let label = label.into();
+        self.audit(format!("label_version({})", label), None);
+        self.spec.label(label)
+    }
+
+    /// switch back to the spec before the current one, returning its version, if kept. The
+    /// current version is dropped, so that repeated rollbacks go further back.
+    pub fn rollback(&self) -> Option<u64> {
+        let previous = self.spec.previous_version()?;
+        if self.rollback_to(previous) {
+            Some(previous)
+        } else {
+            None
+        }
+    }
+
+    /// switch back to `version`, dropping the versions applied after it, returning whether
+    /// it was still kept
+    pub fn rollback_to(&self, version: u64) -> bool {
+        let swap = self.spec.rollback_to(version);
+        let rolled_back = swap.is_some();
+        if rolled_back {
+            self.audit(format!("rollback_to({})", version), swap);
+        }
+        rolled_back
+    }
+
+    /// current minimum level, see `KVFilter::min_level`
+    pub fn min_level(&self) -> slog::Level {
+        self.min_level.get()
+    }
+
+    /// change the minimum level, taking effect for the records logged from now on
+    pub fn set_min_level(&self, level: slog::Level) {
+        self.audit(format!("set_min_level({})", level), None);
+        self.min_level.set(level)
+    }
+
+    /// rules with no effect, as another rule passes all the records they do for at least as
+    /// long, along with the other rule. Such rules can be removed; of equivalent rules, only
+    /// the later ones are reported.
+    pub fn shadowed_rules(&self) -> Vec<(RuleId, RuleId)> {
+        self.rules.shadowed()
+    }
+
+    /// add `rule`, expiring after `ttl` if given, e.g. to enable debug logs of one tenant
+    /// for the duration of an incident without anyone having to remember to revert it
+    pub fn add_rule(&self, rule: Rule, ttl: Option<Duration>) -> RuleId {
+        self.audit(format!("add_rule({:?}, {:?})", rule, ttl), None);
+        self.rules.add(rule, ttl)
+    }
+
+    /// remove a rule before it expires, returning whether it was still there
+    pub fn remove_rule(&self, id: RuleId) -> bool {
+        self.audit(format!("remove_rule({:?})", id), None);
+        self.rules.remove(id)
+    }
+
+    /// pass all the records carrying `value` of `key`, e.g. a request id, on the record or
+    /// in the logger context, for `ttl`, bypassing the filtering altogether. Once
+    /// `KVFilter::trace_capacity` values are traced, the one expiring first is dropped, and
+    /// once `KVFilter::trace_capacity_per_key` values of the key are, the least recently
+    /// used of them.
+    pub fn trace<K: Into<FilterStr>, V: Into<String>>(&self, key: K, value: V, ttl: Duration) {
+        let (key, value) = (key.into(), value.into());
+        self.audit(format!("trace({}={}, {:?})", key, value, ttl), None);
+        self.rules.trace(key, value, ttl)
+    }
+
+    /// stop tracing `value` of `key` before it expires, returning whether it was traced
+    pub fn untrace(&self, key: &str, value: &str) -> bool {
+        self.audit(format!("untrace({}={})", key, value), None);
+        self.rules.untrace(key, value)
+    }
+
+    /// rules added which haven't expired yet
+    pub fn rules(&self) -> Vec<(RuleId, Rule)> {
+        self.rules.active()
+    }
+
+    /// counts of the records decided by the filters controlled by this handle, and of the
+    /// records matched by each rule
+    pub fn stats(&self) -> FilterStats {
+        FilterStats {
+            trace_evictions: self.rules.trace_evictions(),
+            memo: self.spec.load().memo_stats(),
+            ..self.counters.get(self.rules.counts())
+        }
+    }
+
+    /// key values seen by the filters in learning mode, see `KVFilter::learn`
+    pub fn learned(&self) -> Learned {
+        self.learner.learned()
+    }
+
+    /// see `KVFilter::scoped_override`
+    #[cfg(feature = "std")]
+    pub fn scoped_override(&self, spec: ScopedOverride) -> OverrideGuard {
+        overrides::push(self.min_level.id(), spec)
+    }
+
+    /// run `future` with the filter overridden for records logged within it, even as the
+    /// task moves between worker threads. Task overrides take precedence over thread ones.
+    #[cfg(feature = "tokio")]
+    pub fn task_override<F: Future>(
+        &self,
+        spec: ScopedOverride,
+        future: F,
+    ) -> impl Future<Output = F::Output> {
+        overrides::scope(self.min_level.id(), spec, future)
+    }
+}
+
+type Configure = Box<dyn FnOnce(KVFilter<slog::Discard>) -> KVFilter<slog::Discard> + Send>;
+
+enum Change {
+    Add(ValueList, SmallStr, Vec<SmallStr>),
+    /// the values removed, or the whole key
+    Remove(ValueList, SmallStr, Option<Vec<SmallStr>>),
+    Configure(Configure),
+}
+
+/// Changes of the spec collected by `KVFilterHandle::begin_update`, applied in order as a
+/// single new version by `commit`. Records are decided by the spec before all of them or
+/// after all of them, never in between. Dropping the update discards the changes.
+#[must_use = "the changes are discarded unless committed"]
+pub struct SpecUpdate {
+    handle: KVFilterHandle,
+    changes: Vec<Change>,
+}
+
+impl SpecUpdate {
+    /// add `values` of `key` to `list`, adding the key if not there yet
+    pub fn add_values<K, I>(mut self, list: ValueList, key: K, values: I) -> Self
+    where
+        K: Into<FilterStr>,
+        I: IntoIterator,
+        I::Item: Into<FilterStr>,
+    {
+        let values = values.into_iter().map(|value| SmallStr::from(value.into())).collect();
+        self.changes.push(Change::Add(list, SmallStr::from(key.into()), values));
+        self
+    }
+
+    /// remove `values` of `key` from `list`, removing the key along with its last value
+    pub fn remove_values<K, I>(mut self, list: ValueList, key: K, values: I) -> Self
+    where
+        K: Into<FilterStr>,
+        I: IntoIterator,
+        I::Item: Into<FilterStr>,
+    {
+        let values = values.into_iter().map(|value| SmallStr::from(value.into())).collect();
+        self.changes.push(Change::Remove(list, SmallStr::from(key.into()), Some(values)));
+        self
+    }
+
+    /// remove `key` from `list` with all its values
+    pub fn remove_key<K: Into<FilterStr>>(mut self, list: ValueList, key: K) -> Self {
+        self.changes.push(Change::Remove(list, SmallStr::from(key.into()), None));
+        self
+    }
+
+    /// reconfigure the spec as by `KVFilterHandle::update_spec`, after the changes before
+    pub fn configure<F>(mut self, configure: F) -> Self
+    where
+        F: FnOnce(KVFilter<slog::Discard>) -> KVFilter<slog::Discard> + Send + 'static,
+    {
+        self.changes.push(Change::Configure(Box::new(configure)));
+        self
+    }
+
+    /// apply the changes to the current spec, as updated by others since `begin_update`,
+    /// switching the filters controlled by the handle to the result at once
+    pub fn commit(self) {
+        let SpecUpdate { handle, changes } = self;
+        let count = changes.len();
+        let swap = handle.spec.apply(handle.wall_time(), |current| {
+            let mut filter = KVFilter::new(slog::Discard, current.level).with_spec(current);
+            let mut values_changed = false;
+            for change in changes {
+                match change {
+                    Change::Configure(configure) => {
+                        if values_changed {
+                            filter.spec.update(|spec| spec.compile());
+                            values_changed = false;
+                        }
+                        filter = configure(filter);
+                    }
+                    Change::Add(list, key, values) => {
+                        filter.spec.update(|spec| {
+                            list_mut(spec, list).values.entry(key).or_default().extend(values)
+                        });
+                        values_changed = true;
+                    }
+                    Change::Remove(list, key, values) => {
+                        filter.spec.update(|spec| remove_values(list_mut(spec, list), &key, values));
+                        values_changed = true;
+                    }
+                }
+            }
+            if values_changed {
+                filter.spec.update(|spec| spec.compile());
+            }
+            filter.spec()
+        });
+        handle.audit(format!("commit({} changes)", count), Some(swap));
+    }
+}
+
+fn list_mut(spec: &mut FilterSpec, list: ValueList) -> &mut FilterList {
+    match list {
+        ValueList::Pass => &mut spec.filters,
+        ValueList::Suppress => &mut spec.neg_filters,
+        ValueList::SuppressAll => &mut spec.neg_all_filters,
+    }
+}
+
+/// remove `values` of `key`, or all of them if `None`, dropping the key once it has none
+fn remove_values(list: &mut FilterList, key: &str, values: Option<Vec<SmallStr>>) {
+    let emptied = match (list.values.get_mut(key), values) {
+        (Some(current), Some(values)) => {
+            for value in &values {
+                current.remove(value);
+            }
+            current.is_empty()
+        }
+        (current, None) => current.is_some(),
+        (None, Some(_)) => false,
+    };
+    if emptied {
+        list.values.remove(key);
+    }
+}
@@ -0,0 +1,135 @@
+//! Owned copy of the key values of a record and its logger context, see `KVMap`
+
+use std::ops::Range;
+use std::prelude::v1::*;
+
+use slog;
+use slog::KV;
+
+use combinators::SerializedKV;
+
+/// Key values of a record followed by those of its logger context, formatted to strings in
+/// the order a drain sees them, e.g. for mock drains, routing or redaction hooks to look at
+/// them without a serializer of their own.
+///
+/// ```
+/// # #[macro_use] extern crate slog;
+/// # extern crate slog_kvfilter;
+/// use slog_kvfilter::KVMap;
+///
+/// # fn main() {
+/// let context = slog::OwnedKVList::from(o!("tenant" => "acme"));
+/// let kv = b!("tenant" => "globex", "user" => 7);
+///
+/// let map = KVMap::new(&record!(slog::Level::Info, "", &format_args!(""), kv), &context);
+/// assert_eq!(map.get("tenant"), Some("globex"));
+/// assert_eq!(map.get_all("tenant").collect::<Vec<_>>(), vec!["globex", "acme"]);
+/// assert_eq!(map.get("user"), Some("7"));
+/// # }
+/// ```
+///
+/// Keys given several times keep all their values, the one closest to the record first.
+/// Values failing to serialize are left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KVMap {
+    entries: Vec<(slog::Key, String)>,
+    /// index of the first key value of the logger context
+    context: usize,
+}
+
+impl KVMap {
+    pub fn new(record: &slog::Record, logger_values: &slog::OwnedKVList) -> Self {
+        let mut kv = SerializedKV(Vec::new());
+        let _ = record.kv().serialize(record, &mut kv);
+        let context = kv.0.len();
+        let _ = logger_values.serialize(record, &mut kv);
+
+        KVMap {
+            entries: kv.0.into_iter().map(|(key, value)| (key, value.into_text())).collect(),
+            context,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// value of `key`, the one closest to the record if given several times
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    /// values of `key`, the one closest to the record first
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter().filter(move |&(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// all the key values, those of the record first
+    pub fn iter(&self) -> impl Iterator<Item = (slog::Key, &str)> {
+        self.entries.iter().map(|(key, value)| (*key, value.as_str()))
+    }
+
+    /// key values of the record
+    pub fn record_kv(&self) -> impl Iterator<Item = (slog::Key, &str)> {
+        self.range(0..self.context)
+    }
+
+    /// key values of the logger context
+    pub fn context_kv(&self) -> impl Iterator<Item = (slog::Key, &str)> {
+        self.range(self.context..self.entries.len())
+    }
+
+    fn range(&self, range: Range<usize>) -> impl Iterator<Item = (slog::Key, &str)> {
+        self.entries[range].iter().map(|(key, value)| (*key, value.as_str()))
+    }
+
+    /// the key values, those of the record first
+    pub fn into_vec(self) -> Vec<(slog::Key, String)> {
+        self.entries
+    }
+}
+
+impl KV for KVMap {
+    /// emits all the key values as strings, those of the logger context too
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        for (key, value) in self.iter() {
+            serializer.emit_str(key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KVMap;
+    use slog::{self, Level};
+
+    #[test]
+    fn kvmap() {
+        let context = slog::OwnedKVList::from(o!("tenant" => "acme", "version" => "1.0"));
+        let kv = b!("tenant" => "globex", "user" => 7, "user" => 8);
+        let map = KVMap::new(&record!(Level::Info, "", &format_args!(""), kv), &context);
+
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get("user"), Some("8"));
+        assert_eq!(map.get_all("user").collect::<Vec<_>>(), vec!["8", "7"]);
+        assert_eq!(map.get("version"), Some("1.0"));
+        assert!(!map.contains_key("thread"));
+        assert_eq!(
+            map.record_kv().map(|(key, _)| key).collect::<Vec<_>>(),
+            vec!["user", "user", "tenant"]
+        );
+        assert_eq!(
+            map.context_kv().collect::<Vec<_>>(),
+            vec![("version", "1.0"), ("tenant", "acme")]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Learning mode, recording the key values flowing through a filter instead of filtering,
+//! see `KVFilter::learn`
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use sync::Mutex;
+
+use slog;
+use slog::KV;
+
+use combinators::text;
+
+/// Key values seen by a filter in learning mode, see `KVFilterHandle::learned`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Learned {
+    pub records: u64,
+    pub keys: BTreeMap<String, LearnedKey>,
+}
+
+/// Values seen of a key, see `Learned`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LearnedKey {
+    /// records carrying the key, on the record or in the logger context
+    pub records: u64,
+    /// the first values seen, up to the limit of `KVFilter::learn`, with their records
+    pub values: BTreeMap<String, u64>,
+    /// records carrying other values
+    pub other: u64,
+}
+
+impl fmt::Display for Learned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "records: {}", self.records)?;
+        for (key, learned) in &self.keys {
+            let values: Vec<String> = learned
+                .values
+                .iter()
+                .map(|(value, records)| format!("{} ({})", value, records))
+                .collect();
+            write!(f, "  {}: {} records, {}", key, learned.records, values.join(", "))?;
+            if learned.other > 0 {
+                write!(f, ", other values ({})", learned.other)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Key values seen, shared by a filter and its handles
+#[derive(Default)]
+pub struct Learner {
+    /// values sampled of each key, 0 if not learning
+    max_values: AtomicUsize,
+    learned: Mutex<Learned>,
+}
+
+impl Learner {
+    pub fn start(&self, max_values: usize) {
+        self.max_values.store(max_values.max(1), Ordering::Relaxed);
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.max_values.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn observe(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) {
+        let mut ser = KeyCollector(Vec::new());
+        let _ = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+
+        let max_values = self.max_values.load(Ordering::Relaxed);
+        let mut learned = self.learned.lock();
+        learned.records += 1;
+        for (key, value) in ser.0 {
+            let key = learned.keys.entry(key.to_owned()).or_default();
+            key.records += 1;
+            if key.values.len() < max_values || key.values.contains_key(&value) {
+                *key.values.entry(value).or_insert(0) += 1;
+            } else {
+                key.other += 1;
+            }
+        }
+    }
+
+    pub fn learned(&self) -> Learned {
+        self.learned.lock().clone()
+    }
+}
+
+/// first value of each key, on the record before the logger context
+struct KeyCollector(Vec<(slog::Key, String)>);
+
+impl slog::Serializer for KeyCollector {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if !self.0.iter().any(|&(seen, _)| seen == key) {
+            self.0.push((key, text(val)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Levels of records translated by the values of their keys, see `KVFilter::remap_levels`
+
+use std::fmt;
+use std::prelude::v1::*;
+
+use slog;
+
+use compiled::{feed_record, Pass};
+use small_str::SmallStr;
+use FilterStr;
+
+/// Translation of the levels of records, e.g. of a dependency logging too loudly, see
+/// `KVFilter::remap_levels`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LevelMap {
+    /// level each level is forwarded at, by `slog::Level::as_usize`, `None` if suppressed
+    levels: [Option<slog::Level>; 6],
+}
+
+impl Default for LevelMap {
+    fn default() -> Self {
+        LevelMap {
+            levels: [
+                Some(slog::Level::Critical),
+                Some(slog::Level::Error),
+                Some(slog::Level::Warning),
+                Some(slog::Level::Info),
+                Some(slog::Level::Debug),
+                Some(slog::Level::Trace),
+            ],
+        }
+    }
+}
+
+impl LevelMap {
+    /// records keep their levels
+    pub fn new() -> Self {
+        LevelMap::default()
+    }
+
+    /// forward records of `from` at `to`
+    pub fn map(mut self, from: slog::Level, to: slog::Level) -> Self {
+        self.levels[from.as_usize() - 1] = Some(to);
+        self
+    }
+
+    /// suppress records of `level`
+    pub fn suppress(mut self, level: slog::Level) -> Self {
+        self.levels[level.as_usize() - 1] = None;
+        self
+    }
+
+    /// level records of `level` are forwarded at, `None` if they're suppressed
+    pub fn get(&self, level: slog::Level) -> Option<slog::Level> {
+        self.levels[level.as_usize() - 1]
+    }
+
+    /// least important level translated to a more important one, if any
+    fn lowest_raised(&self) -> Option<slog::Level> {
+        (1..=slog::Level::Trace.as_usize())
+            .rev()
+            .filter_map(slog::Level::from_usize)
+            .find(|&level| self.get(level).is_some_and(|to| to < level))
+    }
+}
+
+/// Level maps of the records carrying a value of a key, see `KVFilter::remap_levels`
+#[derive(Clone, Default)]
+pub struct LevelMaps {
+    maps: Vec<(FilterStr, SmallStr, LevelMap)>,
+}
+
+impl LevelMaps {
+    pub fn insert(&mut self, key: FilterStr, value: SmallStr, map: LevelMap) {
+        self.maps.retain(|(k, v, _)| *k != key || *v != value);
+        self.maps.push((key, value, map));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.maps.is_empty()
+    }
+
+    /// least important level any of the maps translates to a more important one, so that
+    /// such records are enabled
+    pub fn lowest(&self) -> Option<slog::Level> {
+        self.maps.iter().filter_map(|(_, _, map)| map.lowest_raised()).max()
+    }
+
+    /// levels the maps translate `level` to, some possibly more than once
+    pub fn targets(&self, level: slog::Level) -> impl Iterator<Item = slog::Level> + '_ {
+        self.maps.iter().filter_map(move |(_, _, map)| map.get(level))
+    }
+
+    /// map of the value of a key closest to `record`, on the record before the logger
+    /// context, if any
+    pub fn find(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Option<LevelMap> {
+        if self.maps.is_empty() {
+            return None;
+        }
+        let mut pass = self.pass();
+        feed_record(Some(&mut pass), record, logger_values);
+        pass.found
+    }
+
+    /// pass finding the map of a record, e.g. along matching its key values, see `find`
+    pub fn pass(&self) -> LevelMapPass<'_> {
+        LevelMapPass {
+            maps: self,
+            found: None,
+            tmp_str: String::new(),
+        }
+    }
+}
+
+/// `Pass` finding the map of the value of a key closest to a record, see `LevelMaps::find`
+pub struct LevelMapPass<'a> {
+    maps: &'a LevelMaps,
+    found: Option<LevelMap>,
+    tmp_str: String,
+}
+
+impl<'a> LevelMapPass<'a> {
+    /// map found so far, the one of the record once it's fed
+    pub fn found(&self) -> Option<LevelMap> {
+        self.found
+    }
+}
+
+impl<'a> Pass for LevelMapPass<'a> {
+    fn is_decided(&self) -> bool {
+        self.found.is_some()
+    }
+
+    fn enter_context(&mut self) {}
+
+    /// the values after the one failing are unseen, as the maps they hold
+    fn failed(&mut self) {}
+}
+
+impl<'a> slog::Serializer for LevelMapPass<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if !self.maps.maps.iter().any(|(k, _, _)| *k == key) {
+            return Ok(());
+        }
+        let value = match val.as_str() {
+            Some(value) => value,
+            None => {
+                self.tmp_str.clear();
+                if fmt::write(&mut self.tmp_str, *val).is_err() {
+                    return Ok(());
+                }
+                &self.tmp_str
+            }
+        };
+        let found = self.maps.maps.iter().find(|(k, v, _)| *k == key && *v == value);
+        match found {
+            Some(&(_, _, map)) => {
+                self.found = Some(map);
+                Err(slog::Error::Other)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LevelMap;
+    use slog::Level;
+
+    #[test]
+    fn levelmap() {
+        let map = LevelMap::new()
+            .suppress(Level::Trace)
+            .map(Level::Debug, Level::Trace)
+            .map(Level::Info, Level::Debug);
+        assert_eq!(map.get(Level::Trace), None);
+        assert_eq!(map.get(Level::Info), Some(Level::Debug));
+        assert_eq!(map.get(Level::Error), Some(Level::Error));
+        assert_eq!(map.lowest_raised(), None);
+        assert_eq!(map.map(Level::Debug, Level::Warning).lowest_raised(), Some(Level::Debug));
+    }
+}
@@ -3,6 +3,20 @@
 //! positive filter to allow sophisticated 'hole-punching' into a matching category. Ultimately,
 //! the resulting message (without keys and values) can be constrained by both presence of a regex
 //! or its absence.
+//!
+//! Without the default `std` feature the crate only needs `core` and `alloc`, see the README.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+extern crate hashbrown;
+#[cfg(not(feature = "std"))]
+extern crate portable_atomic;
+#[cfg(not(feature = "std"))]
+extern crate spin;
 
 #[cfg(test)]
 #[macro_use]
@@ -11,52 +25,647 @@ extern crate slog;
 #[cfg(not(test))]
 extern crate slog;
 
+/// used by the generated code of `static_kvfilter!`
+#[doc(hidden)]
+pub extern crate slog as __slog;
+
+/// used by the expansions of `kvfilter!` and `static_kvfilter!` rather than `std`, so that
+/// they expand in `no_std` crates too
+#[doc(hidden)]
+pub mod __private {
+    pub use std::fmt::Arguments;
+    pub use std::prelude::v1::{String, Vec};
+}
+
+extern crate aho_corasick;
+#[cfg(feature = "std")]
+extern crate arc_swap;
 extern crate regex;
+extern crate regex_syntax;
+
+#[cfg(feature = "ahash")]
+extern crate ahash;
+
+#[cfg(any(feature = "nested-values", feature = "config"))]
+extern crate serde;
+
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
+
+#[cfg(feature = "parking_lot")]
+extern crate parking_lot;
+#[cfg(feature = "prometheus")]
+extern crate prometheus;
+#[cfg(feature = "unicode-normalization")]
+extern crate unicode_normalization;
+
+#[cfg(feature = "libc")]
+extern crate libc;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+#[cfg(feature = "chrono")]
+extern crate chrono;
+
+#[cfg(feature = "tracing")]
+extern crate tracing_core;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
+#[cfg(all(test, feature = "tracing"))]
+extern crate tracing;
+
+#[cfg(all(test, any(feature = "nested-values", feature = "config")))]
+#[macro_use]
+extern crate serde_derive;
 
+use std::borrow::Cow;
+use std::cmp;
 use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::env;
 use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::ops::RangeInclusive;
 use std::option::Option;
 use std::panic::UnwindSafe;
 use std::panic::RefUnwindSafe;
-use std::fmt::format;
+use std::prelude::v1::*;
+use std::sync::Arc;
+use std::time::Duration;
 
-use slog::KV;
 use regex::Regex;
+use slog::KV;
+
+mod audit;
+mod auditor;
+mod batch;
+mod builder;
+#[cfg(feature = "std")]
+mod cache;
+mod caps;
+mod cidr;
+mod decision;
+mod clock;
+mod combinators;
+mod compiled;
+mod config;
+mod correlation;
+mod diff;
+mod directives;
+mod engine;
+mod error;
+mod force;
+#[cfg(feature = "fuzzy")]
+mod fuzzy;
+mod handle;
+mod kvmap;
+mod learn;
+mod level_map;
+mod lint;
+mod memo;
+#[cfg(feature = "std")]
+mod nonblocking;
+mod number;
+mod overload;
+mod owned;
+#[cfg(feature = "std")]
+mod overrides;
+mod oversize;
+mod prefilter;
+#[cfg(feature = "prometheus")]
+mod prometheus_collector;
+#[cfg(all(feature = "registry", unix))]
+mod registry;
+#[cfg(feature = "remote")]
+mod remote;
+mod rules;
+mod sampling;
+mod semver;
+mod small_str;
+mod stats;
+#[cfg(not(feature = "std"))]
+mod std;
+#[cfg(feature = "statsd")]
+mod statsd;
+#[doc(hidden)]
+#[macro_use]
+pub mod static_filter;
+mod summary;
+mod template;
+mod sync;
+mod threshold;
+mod topk;
+mod watchdog;
+pub mod testing;
+#[cfg(feature = "nested-values")]
+mod nested;
+#[cfg(feature = "chrono")]
+mod schedule;
+#[cfg(feature = "tracing")]
+mod tracing_filter;
 
-// @todo: must that be thread-safe?
-struct FilteringSerializer<'a> {
-    pending_matches: KVFilterListFlyWeight<'a>,
-    tmp_str: String,
+pub use audit::AuditEntry;
+pub use auditor::KVAuditor;
+pub use batch::{DecidedBy, KeyReport, Report, RuleReport};
+pub use builder::KVFilterBuilder;
+#[cfg(feature = "std")]
+pub use cache::{cached_context, CachedContext};
+pub use cidr::Cidr;
+pub use clock::{Clock, WallTime};
+#[cfg(feature = "std")]
+pub use clock::SystemClock;
+pub use combinators::{AllOf, AnyOf, KVFilterChain, KVPredicate, NotFilter, SerializedValue};
+pub use config::IntoFilterSpec;
+#[cfg(feature = "config")]
+pub use config::spec_from_config;
+pub use decision::{Decision, DecidingFilter};
+pub use diff::{MergePolicy, SpecChange, ValueList};
+pub use engine::FilterEngine;
+pub use error::KVFilterError;
+pub use force::FORCE_KEY;
+pub use handle::{KVFilterHandle, SpecUpdate, SpecVersion};
+pub use kvmap::KVMap;
+pub use learn::{Learned, LearnedKey};
+pub use level_map::LevelMap;
+pub use lint::LintWarning;
+pub use memo::{MemoPolicy, MemoStats};
+#[cfg(feature = "std")]
+pub use nonblocking::{NonBlocking, OverflowPolicy};
+pub use number::Number;
+pub use overload::Tightening;
+#[cfg(feature = "std")]
+pub use overrides::{OverrideGuard, ScopedOverride};
+pub use owned::OwnedRecord;
+pub use oversize::OversizePolicy;
+pub use prefilter::Prefiltered;
+#[cfg(feature = "prometheus")]
+pub use prometheus_collector::PrometheusCollector;
+#[cfg(all(feature = "registry", unix))]
+pub use registry::{Registry, RegistryClient};
+#[cfg(feature = "remote")]
+pub use remote::{ConfigPoller, ConfigSource, HttpSource, RemoteConfig};
+pub use rules::{Condition, Rule, RuleId, Trigger, Window};
+#[cfg(feature = "chrono")]
+pub use schedule::Schedule;
+pub use semver::{Version, VersionReq};
+pub use stats::{FilterStats, KeyCount, TopSuppressed};
+pub use template::Placeholders;
+#[cfg(feature = "statsd")]
+pub use statsd::{ReportingThread, StatsdReporter};
+pub use threshold::Threshold;
+pub use watchdog::{Starvation, StarvationAction};
+#[cfg(feature = "tracing")]
+pub use tracing_filter::TracingFilter;
+
+#[cfg(feature = "std")]
+use cache::ContextMatch;
+use caps::GroupCap;
+use compiled::{feed_record, Budget, CompiledFilter, DualSerializer, FilteringSerializer, Fork, KeySet,
+               LevelKeys, Pass, Polarity, Scratch};
+use directives::ModuleLevels;
+use force::{Force, WithoutForce};
+use audit::AuditLog;
+use learn::Learner;
+use level_map::LevelMaps;
+use overload::{Overload, Transition};
+use handle::{SharedLevel, SharedSpec};
+use rules::RuntimeRules;
+use sampling::Sampling;
+use small_str::SmallStr;
+use stats::Counters;
+use watchdog::Watchdog;
+use summary::{Summary, SummaryKV};
+
+/// Exact values and patterns of one polarity (positive or negative) as configured
+#[derive(Clone, Default)]
+struct FilterList {
+    values: HashMap<SmallStr, HashSet<SmallStr>>,
+    patterns: HashMap<SmallStr, Vec<ValuePattern>>,
 }
 
-impl<'a> slog::Serializer for FilteringSerializer<'a> {
-    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
-        if self.pending_matches.is_empty() {
-            return Ok(());
-        }
+impl FilterList {
+    fn set_values<K, V, S>(&mut self, filters: Option<KVFilterList<K, V, S>>)
+    where
+        K: Into<FilterStr> + Eq + Hash,
+        V: Into<FilterStr> + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.values = filters.map_or(HashMap::new(), |filters| {
+            filters
+                .into_iter()
+                .map(|(key, values)| {
+                    let values = values.into_iter().map(|value| SmallStr::from(value.into())).collect();
+                    (SmallStr::from(key.into()), values)
+                })
+                .collect()
+        });
+    }
 
-        let matched = if let Some(keyvalues) = self.pending_matches.get(&key) {
-            self.tmp_str.clear();
-            fmt::write(&mut self.tmp_str, *val)?;
+    fn set_patterns<K>(&mut self, patterns: Option<KVPatternList<K>>)
+    where
+        K: Into<FilterStr> + Eq + Hash,
+    {
+        self.patterns = patterns.map_or(HashMap::new(), |patterns| {
+            patterns
+                .into_iter()
+                .map(|(key, patterns)| (SmallStr::from(key.into()), patterns))
+                .collect()
+        });
+    }
 
-            keyvalues.contains(&self.tmp_str)
-        } else {
-            false
-        };
+    fn keys(&self) -> impl Iterator<Item = &SmallStr> {
+        self.values.keys().chain(self.patterns.keys())
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.values.contains_key(key) || self.patterns.contains_key(key)
+    }
+}
 
-        if matched {
-            self.pending_matches.remove(&key);
+/// Must be a hashmap since we do not rely on ordered keys
+///
+/// Keys and values can be anything convertible to `FilterStr`, e.g. `&'static str`, and
+/// the maps and sets hashed by anything, e.g. `ahash::RandomState`, for the `_with`
+/// variants of the methods taking the lists, e.g. `KVFilter::only_pass_any_on_all_keys_with`.
+/// The lists are only read when filters are configured, records are matched against the
+/// compiled tables.
+pub type KVFilterList<K = String, V = String, S = StdHasher> = HashMap<K, HashSet<V, S>, S>;
+
+/// hasher of `std::collections::HashMap`
+#[cfg(feature = "std")]
+type StdHasher = std::collections::hash_map::RandomState;
+#[cfg(not(feature = "std"))]
+type StdHasher = hashbrown::DefaultHashBuilder;
+
+/// Build a `KVFilterList` from keys with any iterable of their values, e.g.
+/// `filter_list(vec![("thread", vec!["100", "200"])])`, sparing the conversions to
+/// `String` and the `HashSet::from_iter`.
+pub fn filter_list<I, K, J, V>(filters: I) -> KVFilterList
+where
+    I: IntoIterator<Item = (K, J)>,
+    K: Into<String>,
+    J: IntoIterator<Item = V>,
+    V: Into<String>,
+{
+    filters
+        .into_iter()
+        .map(|(key, values)| (key.into(), values.into_iter().map(Into::into).collect()))
+        .collect()
+}
+
+/// Build a `KVFilterList` from keys with their values
+///
+/// ```
+/// # #[macro_use] extern crate slog_kvfilter;
+/// # fn main() {
+/// let filters = kvfilter!{ "subsystem" => ["db", "cache"], "tenant" => ["acme"] };
+/// assert_eq!(filters["subsystem"].len(), 2);
+/// # }
+/// ```
+///
+/// With each value preceded by `prefix`, `substring`, `threshold` or `version`, it builds a
+/// `KVPatternList` for `KVFilter::only_pass_any_pattern_on_all_keys` instead. Thresholds
+/// and versions are given as strings and parsed.
+///
+/// ```
+/// # #[macro_use] extern crate slog_kvfilter;
+/// # use slog_kvfilter::ValuePattern;
+/// # fn main() {
+/// let patterns = kvfilter!{
+///     "path" => [prefix "/api", substring "admin"],
+///     "latency" => [threshold "> 1.5s"],
+///     "client" => [version ">= 2.3.0, < 3"],
+/// };
+/// assert_eq!(patterns["path"][0], ValuePattern::Prefix("/api".into()));
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// When a threshold or a version doesn't parse.
+#[macro_export]
+macro_rules! kvfilter {
+    (@pattern prefix $value:expr) => {
+        $crate::ValuePattern::Prefix($crate::FilterStr::from($value))
+    };
+    (@pattern substring $value:expr) => {
+        $crate::ValuePattern::Substring($crate::FilterStr::from($value))
+    };
+    (@pattern threshold $value:expr) => {
+        $crate::ValuePattern::Threshold($crate::kvfilter!(@parse threshold $value))
+    };
+    (@pattern version $value:expr) => {
+        $crate::ValuePattern::Version($crate::kvfilter!(@parse version $value))
+    };
+    (@parse $kind:ident $value:expr) => {{
+        let text: &str = &$value;
+        text.parse()
+            .unwrap_or_else(|err| panic!("invalid {} {:?} in kvfilter!: {}", stringify!($kind), text, err))
+    }};
+    () => {{
+        let filters: $crate::KVFilterList = $crate::KVFilterList::new();
+        filters
+    }};
+    ($($key:expr => [$($kind:ident $value:expr),+ $(,)*]),+ $(,)*) => {{
+        let mut patterns: $crate::KVPatternList = $crate::KVPatternList::new();
+        $(
+            patterns.insert(
+                $crate::__private::String::from($key),
+                $crate::__private::Vec::from([$($crate::kvfilter!(@pattern $kind $value)),+]),
+            );
+        )+
+        patterns
+    }};
+    ($($key:expr => [$($value:expr),* $(,)*]),* $(,)*) => {{
+        #[allow(unused_mut)]
+        let mut filters: $crate::KVFilterList = $crate::KVFilterList::new();
+        $(
+            filters.insert(
+                $crate::__private::String::from($key),
+                $crate::__private::Vec::from([$($crate::__private::String::from($value)),*])
+                    .into_iter()
+                    .collect(),
+            );
+        )*
+        filters
+    }};
+}
+
+/// Hasher of the maps consulted for every record, e.g. the specs of `select_by_key` or the
+/// memos of `memoize_matches`, and of the values checked against bloom filters. SipHash is
+/// slow for short values, so the `ahash` feature replaces it at the expense of weaker
+/// HashDoS guarantees.
+#[cfg(feature = "ahash")]
+type FilterHasher = ahash::RandomState;
+#[cfg(not(feature = "ahash"))]
+type FilterHasher = StdHasher;
+
+/// map consulted for every record, hashed by `FilterHasher`
+type FilterMap<K, V> = HashMap<K, V, FilterHasher>;
+/// set consulted for every record, hashed by `FilterHasher`
+type FilterSet<T> = HashSet<T, FilterHasher>;
+
+/// Key or value of a filter, borrowed when it's known upfront so no copy is needed.
+///
+/// Keys are still looked up by their text, in the key table of the compiled filter.
+pub type FilterStr = Cow<'static, str>;
+
+/// Pattern a value can match instead of being equal to one of the values of a key.
+///
+/// All the prefixes and substrings of a key are compiled into a single automaton each, so
+/// that even hundreds of them are matched in one go.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ValuePattern {
+    /// value starts with the given string
+    Prefix(FilterStr),
+    /// value contains the given string
+    Substring(FilterStr),
+    /// value is an IP address in the range, possibly with a port, e.g.
+    /// `Cidr("10.0.0.0/8".parse()?)`
+    Cidr(Cidr),
+    /// value is a semantic version matching the requirement, e.g.
+    /// `Version(">= 2.3.0, < 3".parse()?)`
+    Version(VersionReq),
+    /// value is a duration or a size comparing with the threshold as required, e.g.
+    /// `Threshold("> 1.5s".parse()?)` or `Threshold(">= 10MiB".parse()?)`
+    Threshold(Threshold),
+    /// value is the number, whatever its type, e.g. `Number(7.into())` matching both
+    /// `emit_u64(7)` and `emit_f64(7.0)`, but not the string `"7"`
+    Number(Number),
+    /// value is the boolean emitted by `emit_bool`, not the string `"true"` or `"false"`
+    Bool(bool),
+    /// value is a JSON document with the given string at the JSON pointer, e.g.
+    /// `JsonPointer("/status".into(), "failed".into())`. Strings are compared without
+    /// quotes, anything else by its JSON representation.
+    #[cfg(feature = "serde_json")]
+    JsonPointer(FilterStr, FilterStr),
+    /// value is within the given edit distance of the string, counting the characters
+    /// inserted, deleted or substituted, e.g. `Fuzzy("alice".into(), 1)` matching `alicr`,
+    /// for values typed by humans. Costly, so each value of the key is compared with each
+    /// fuzzy pattern only once the other patterns and values didn't match.
+    #[cfg(feature = "fuzzy")]
+    Fuzzy(FilterStr, usize),
+}
+
+/// Which of the occurrences of a key are matched, see `KVFilter::filter_key_at`
+///
+/// slog doesn't tell which logger of the hierarchy gave a value, but serializes the key
+/// values of the record first, then those of the logger context from the child logging
+/// the record up to the root, so that the first occurrence of a key is the one overriding
+/// the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyPosition {
+    #[default]
+    Anywhere,
+    /// on the record only
+    Record,
+    /// in the logger context only
+    Context,
+    /// the occurrence overriding the others only, e.g. `component=server` of the root
+    /// logger isn't matched once a child logger, or the record, gives `component=client`
+    Nearest,
+}
+
+/// Cleanup applied to the values of a key before matching them, see `KVFilter::normalize_key`.
+/// The values given to the filters for the key are normalized alike.
+#[derive(Clone)]
+pub enum Normalizer {
+    /// strip leading and trailing whitespace
+    Trim,
+    Lowercase,
+    /// strip a pair of surrounding `"` or `'` quotes
+    StripQuotes,
+    /// write UUIDs in the canonical lowercase hyphenated form, e.g.
+    /// `{0E9C3F4A6B2D4C1E9F8A7B6C5D4E3F2A}` or `urn:uuid:0E9C3F4A-...` as
+    /// `0e9c3f4a-6b2d-4c1e-9f8a-7b6c5d4e3f2a`, leaving other values as they are
+    Uuid,
+    /// fold the case for caseless comparison, lowercasing and also folding `ß` to `ss`,
+    /// `ſ` to `s` and the final `ς` to `σ`. Mixed normalization forms are unified by `Nfc`
+    /// or `Nfkc` given before it.
+    CaseFold,
+    /// compose the value to Unicode Normalization Form C, e.g. `e` followed by the combining
+    /// acute accent to `é`
+    #[cfg(feature = "unicode-normalization")]
+    Nfc,
+    /// compose the value to Unicode Normalization Form KC, also replacing compatibility
+    /// characters, e.g. the ligature `ﬁ` by `fi` or the fullwidth `Ａ` by `A`
+    #[cfg(feature = "unicode-normalization")]
+    Nfkc,
+    /// rewrite the value in place
+    Custom(Arc<dyn Fn(&mut String) + Send + Sync>),
+}
+
+impl fmt::Debug for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Normalizer::Trim => f.write_str("Trim"),
+            Normalizer::Lowercase => f.write_str("Lowercase"),
+            Normalizer::StripQuotes => f.write_str("StripQuotes"),
+            Normalizer::Uuid => f.write_str("Uuid"),
+            Normalizer::CaseFold => f.write_str("CaseFold"),
+            #[cfg(feature = "unicode-normalization")]
+            Normalizer::Nfc => f.write_str("Nfc"),
+            #[cfg(feature = "unicode-normalization")]
+            Normalizer::Nfkc => f.write_str("Nfkc"),
+            Normalizer::Custom(_) => f.write_str("Custom(..)"),
         }
+    }
+}
 
-        Ok(())
+impl Normalizer {
+    fn apply(&self, value: &mut String) {
+        match *self {
+            Normalizer::Trim => {
+                let end = value.trim_end().len();
+                value.truncate(end);
+                let start = value.len() - value.trim_start().len();
+                value.drain(..start);
+            }
+            Normalizer::Lowercase => {
+                if value.is_ascii() {
+                    value.make_ascii_lowercase();
+                } else {
+                    *value = value.to_lowercase();
+                }
+            }
+            Normalizer::StripQuotes => {
+                let quoted = value.len() >= 2
+                    && (value.starts_with('"') && value.ends_with('"')
+                        || value.starts_with('\'') && value.ends_with('\''));
+                if quoted {
+                    value.pop();
+                    value.remove(0);
+                }
+            }
+            Normalizer::Uuid => {
+                let trimmed = value.trim();
+                let trimmed = trimmed.strip_prefix("urn:uuid:").unwrap_or(trimmed);
+                let trimmed = trimmed
+                    .strip_prefix('{')
+                    .and_then(|braced| braced.strip_suffix('}'))
+                    .unwrap_or(trimmed);
+                let digits: Vec<u8> = trimmed.bytes().filter(|&b| b != b'-').collect();
+                if digits.len() != 32 || !digits.iter().all(u8::is_ascii_hexdigit) {
+                    return;
+                }
+                let mut uuid = String::with_capacity(36);
+                for (i, &digit) in digits.iter().enumerate() {
+                    if i == 8 || i == 12 || i == 16 || i == 20 {
+                        uuid.push('-');
+                    }
+                    uuid.push(digit.to_ascii_lowercase() as char);
+                }
+                *value = uuid;
+            }
+            Normalizer::CaseFold => {
+                Normalizer::Lowercase.apply(value);
+                if !value.is_ascii() {
+                    *value = value
+                        .chars()
+                        .fold(String::with_capacity(value.len()), |mut folded, c| {
+                            match c {
+                                'ß' => folded.push_str("ss"),
+                                'ſ' => folded.push('s'),
+                                'ς' => folded.push('σ'),
+                                c => folded.push(c),
+                            }
+                            folded
+                        });
+                }
+            }
+            #[cfg(feature = "unicode-normalization")]
+            Normalizer::Nfc => {
+                use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+                if is_nfc_quick(value.chars()) != IsNormalized::Yes {
+                    *value = value.nfc().collect();
+                }
+            }
+            #[cfg(feature = "unicode-normalization")]
+            Normalizer::Nfkc => {
+                use unicode_normalization::{is_nfkc_quick, IsNormalized, UnicodeNormalization};
+                if is_nfkc_quick(value.chars()) != IsNormalized::Yes {
+                    *value = value.nfkc().collect();
+                }
+            }
+            Normalizer::Custom(ref normalize) => normalize(value),
+        }
     }
 }
 
-/// Must be a hashmap since we do not rely on ordered keys
-pub type KVFilterList = HashMap<String, HashSet<String>>;
+/// apply `normalizers` to `value` in order
+fn normalize(normalizers: &[Normalizer], value: &mut String) {
+    for normalizer in normalizers {
+        normalizer.apply(value);
+    }
+}
+
+/// Patterns to match values of given keys against, see `ValuePattern`
+pub type KVPatternList<K = String> = HashMap<K, Vec<ValuePattern>>;
+
+/// Whether the minimum level is checked before or after matching key values, see
+/// `KVFilter::level_order`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelOrder {
+    /// records below the minimum level are suppressed without looking at their key values
+    LevelFirst,
+    /// records below the minimum level still pass if they match the key value filters
+    KVFirst,
+}
+
+/// Decision taken on records which can't be decided as configured, e.g. as they exceed
+/// the `KVFilter::evaluation_budget`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fallback {
+    Pass,
+    Suppress,
+}
+
+/// How the conditions whose matchers can't evaluate a value are decided, e.g. a value which
+/// isn't an address for a `ValuePattern::Cidr`, or isn't JSON for a
+/// `ValuePattern::JsonPointer`, see `KVFilter::on_match_error`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchErrorPolicy {
+    /// the condition holds, so that a positive key matches and a negative one suppresses
+    Matched,
+    /// the condition doesn't hold, the default
+    #[default]
+    Unmatched,
+    /// the record is suppressed
+    Suppress,
+}
+
+/// Pseudo-keys under which `KVFilter::log_compat` exposes the target (`slog::Record::tag`)
+/// and message of records bridged from the `log` crate, e.g. by `slog-stdlog`, which carry
+/// no key values of their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogCompat {
+    pub target_key: &'static str,
+    pub message_key: &'static str,
+}
+
+impl Default for LogCompat {
+    fn default() -> Self {
+        LogCompat {
+            target_key: "log.target",
+            message_key: "log.message",
+        }
+    }
+}
+
+/// Key values of a record followed by the `LogCompat` pseudo-keys
+struct LogCompatKV<'a> {
+    compat: LogCompat,
+    record: &'a slog::Record<'a>,
+}
 
-/// flyweight copy that is created upfront and given to every serializer
-type KVFilterListFlyWeight<'a> = HashMap<&'a str, &'a HashSet<String>>;
+impl<'a> slog::KV for LogCompatKV<'a> {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        self.record.kv().serialize(record, serializer)?;
+        serializer.emit_str(self.compat.target_key, self.record.tag())?;
+        serializer.emit_arguments(self.compat.message_key, self.record.msg())
+    }
+}
 
 /// `Drain` filtering records using list of keys and values they
 /// must have unless they are of a higher level than filtering applied.
@@ -87,12 +696,25 @@ type KVFilterListFlyWeight<'a> = HashMap<&'a str, &'a HashSet<String>>;
 ///     for the key to pass the filter.
 ///   * an entry that hits any value of any negative filter key is filtered, this
 ///     takes precedence over `filters`
+///   * an entry that hits all keys of `always_suppress_all` is filtered as well
+///   * instead of being equal to one of the values, a value can match one of the
+///     `ValuePattern`s given for the key
 ///   * Behavior of empty `KVFilterList` is undefined but normally anything should pass.
 ///   * Behavior of `KVFilter` that has same key in both the matching and the suppressing
 ///     section is undefined even if we have different values there. Logically, it should
 ///     be matching the positive and pass and only suppress negative if it finds matching
 ///     value but it's untested.
 ///
+/// Lazy values, e.g. `slog::FnValue`, are computed by slog whenever the key values holding
+/// them are serialized, whatever their key. The filter serializes the key values of a
+/// record, then its logger context, only until the outcome is decided, e.g. by a value of
+/// a suppressing key, or once all the passing keys matched if there are no suppressing
+/// ones, and formats only the values of the keys it matches, so that values formatting
+/// lazily, e.g. by `%`, are never formatted for other keys. The key values of records are
+/// serialized once more for `FORCE_KEY` by filters of `honor_force_key`, and by the
+/// features looking for keys of their own, e.g. `count_by_key`, `track_top_suppressed` or
+/// `guard_oversized`.
+///
 /// Additionally, the resulting message (without keys and values) can be constrained
 /// by both presence of a regex or its absence by applying the `only_pass_on_regex`
 /// and `always_suppress_on_regex` API calls. As the names suggest, suppression wins
@@ -106,17 +728,293 @@ type KVFilterListFlyWeight<'a> = HashMap<&'a str, &'a HashSet<String>>;
 /// modules, such as e.g. "sending packet" or "running FSM".
 pub struct KVFilter<D: slog::Drain> {
     drain: D,
-    filters: Option<KVFilterList>,
-    neg_filters: Option<KVFilterList>,
+    spec: SharedSpec,
+    min_level: SharedLevel,
+    rules: Arc<RuntimeRules>,
+    summary: Option<Arc<Summary>>,
+    audit: Arc<AuditLog>,
+    injected: Option<slog::OwnedKVList>,
+    /// bytes of key values above which records are oversized
+    oversize: Option<(usize, OversizePolicy)>,
+    /// drains records matching the rules of `Rule::to` go to, by name
+    routes: FilterMap<FilterStr, Arc<RouteDrain>>,
+    /// drain records go to once the filtered drain fails to log them
+    error_fallback: Option<Arc<RouteDrain>>,
+    counters: Arc<Counters>,
+    watchdog: Option<Arc<Watchdog>>,
+    learner: Arc<Learner>,
+    overload: Option<Arc<Overload>>,
+    /// whether records are looked at for `FORCE_KEY`, see `honor_force_key`
+    force_key: bool,
+    /// whether logger contexts are matched once, see `cache_context`
+    #[cfg(feature = "std")]
+    context_cache: bool,
+}
+
+/// drain registered by `KVFilter::route_to`
+type RouteDrain = dyn slog::SendSyncRefUnwindSafeDrain<Ok = (), Err = slog::Never>;
+
+/// Configuration of what a `KVFilter` passes, immutable once in use.
+///
+/// Get it from a configured filter by `KVFilter::spec`, and use it for other filters with
+/// `KVFilter::with_spec`. Filters controlled by the same `KVFilterHandle` share their spec,
+/// which is replaced as a whole by its copy on updates, see `KVFilterHandle::update_spec`.
+#[derive(Clone)]
+pub struct FilterSpec {
+    filters: FilterList,
+    neg_filters: FilterList,
+    neg_all_filters: FilterList,
+    scopes: HashMap<FilterStr, RangeInclusive<slog::Level>>,
+    positions: HashMap<FilterStr, KeyPosition>,
+    bloom_threshold: Option<usize>,
+    /// capacity and policy of the memos of the costly matchers, see `KVFilter::memoize_matches`
+    memo: Option<(usize, MemoPolicy)>,
+    compiled: CompiledFilter,
+    /// `neg_all_filters` compiled as positive ones, as all their keys have to match
+    compiled_all: CompiledFilter,
     level: slog::Level,
+    level_keys: LevelKeys,
+    /// level maps of the records carrying the values, see `KVFilter::remap_levels`
+    level_maps: LevelMaps,
+    modules: ModuleLevels,
+    level_order: LevelOrder,
+    boost_level: Option<slog::Level>,
+    log_compat: Option<LogCompat>,
     regex: Option<Regex>,
     neg_regex: Option<Regex>,
+    selector: Option<Selector>,
+    /// combinations of values of keys passed jointly, all of them have to match
+    combinations: Vec<Combinations>,
+    neg_combinations: Vec<Combinations>,
+    /// keys whose values are traced for a while once a record carrying them passes
+    sticky: Vec<(FilterStr, Duration)>,
+    triggers: Vec<Trigger>,
+    /// level of the records opening the pass-through window, and the window
+    pass_through: Option<(slog::Level, Window)>,
+    caps: Vec<GroupCap>,
+    sampling: Option<Sampling>,
+    normalizers: HashMap<FilterStr, Vec<Normalizer>>,
+    budget: Option<(Budget, Fallback)>,
+    match_errors: MatchErrorPolicy,
+}
+
+/// Specs to decide records by instead, selected by the value of a key, see
+/// `KVFilter::select_by_key`
+#[derive(Clone)]
+struct Selector {
+    key: FilterStr,
+    specs: FilterMap<FilterStr, Arc<FilterSpec>>,
+}
+
+/// Values of several keys matched jointly, see `KVFilter::only_pass_any_combination`
+#[derive(Clone)]
+struct Combinations {
+    keys: Vec<FilterStr>,
+    values: FilterSet<Vec<String>>,
+}
+
+impl Combinations {
+    fn new<K, V, C>(keys: Vec<K>, combinations: C) -> Self
+    where
+        K: Into<FilterStr>,
+        V: Into<String>,
+        C: IntoIterator<Item = Vec<V>>,
+    {
+        Combinations {
+            keys: keys.into_iter().map(Into::into).collect(),
+            values: combinations
+                .into_iter()
+                .map(|values| values.into_iter().map(Into::into).collect())
+                .collect(),
+        }
+    }
+
+    /// whether `record` has all the keys, with one of the combinations of values
+    fn is_match(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool {
+        let mut ser = FirstValuesSerializer {
+            keys: &self.keys,
+            values: vec![None; self.keys.len()],
+            missing: self.keys.len(),
+        };
+        let _ = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+        let values: Option<Vec<String>> = ser.values.into_iter().collect();
+        values.is_some_and(|values| self.values.contains(&values))
+    }
+}
+
+/// sets `combinations` of the same keys as `combination` to it
+fn replace_combinations(combinations: &mut Vec<Combinations>, combination: Combinations) {
+    combinations.retain(|other| other.keys != combination.keys);
+    combinations.push(combination);
+}
+
+/// Finds the first values of keys, on the record before the logger context
+struct FirstValuesSerializer<'a> {
+    keys: &'a [FilterStr],
+    values: Vec<Option<String>>,
+    /// number of keys not found yet
+    missing: usize,
+}
+
+impl<'a> slog::Serializer for FirstValuesSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if let Some(index) = self.keys.iter().position(|k| *k == key) {
+            if self.values[index].is_none() {
+                self.values[index] = Some(val.to_string());
+                self.missing -= 1;
+                if self.missing == 0 {
+                    // all found, stop serializing
+                    return Err(slog::Error::Other);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the first value of a key, on the record before the logger context
+struct FirstValueSerializer<'a> {
+    key: &'a str,
+    value: Option<String>,
+}
+
+impl<'a> FirstValueSerializer<'a> {
+    fn find(key: &str, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Option<String> {
+        let mut ser = FirstValueSerializer { key, value: None };
+        let _ = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+        ser.value
+    }
+}
+
+impl<'a> slog::Serializer for FirstValueSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if key == self.key {
+            self.value = Some(val.to_string());
+            // found, stop serializing
+            return Err(slog::Error::Other);
+        }
+        Ok(())
+    }
+}
+
+impl FilterSpec {
+    fn new(level: slog::Level) -> Self {
+        FilterSpec {
+            level,
+            level_keys: LevelKeys::default(),
+            level_maps: LevelMaps::default(),
+            modules: ModuleLevels::default(),
+            level_order: LevelOrder::LevelFirst,
+            log_compat: None,
+            boost_level: None,
+            filters: FilterList::default(),
+            neg_filters: FilterList::default(),
+            neg_all_filters: FilterList::default(),
+            scopes: HashMap::new(),
+            positions: HashMap::new(),
+            bloom_threshold: None,
+            memo: None,
+            compiled: CompiledFilter::default(),
+            compiled_all: CompiledFilter::default(),
+            regex: None,
+            neg_regex: None,
+            selector: None,
+            combinations: Vec::new(),
+            neg_combinations: Vec::new(),
+            sticky: Vec::new(),
+            triggers: Vec::new(),
+            pass_through: None,
+            caps: Vec::new(),
+            sampling: None,
+            normalizers: HashMap::new(),
+            budget: None,
+            match_errors: MatchErrorPolicy::Unmatched,
+        }
+    }
+
+    /// the spec selected by the value of the selector key of `record`, or this one
+    fn select(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> &FilterSpec {
+        let selector = match self.selector {
+            Some(ref selector) => selector,
+            None => return self,
+        };
+
+        FirstValueSerializer::find(&selector.key, record, logger_values)
+            .and_then(|value| selector.specs.get(value.as_str()))
+            .map_or(self, |spec| spec)
+    }
+
+    /// whether the key values of records can be matched against this spec in a pass shared
+    /// with other filters, as it doesn't change how they're matched by the record
+    fn shares_pass(&self) -> bool {
+        self.selector.is_none() && self.log_compat.is_none() && self.level_maps.is_empty() && self.budget.is_none()
+    }
+
+    fn compile(&mut self) {
+        self.compiled = CompiledFilter::new(
+            &self.filters,
+            &self.neg_filters,
+            &self.scopes,
+            &self.positions,
+            &self.normalizers,
+            self.bloom_threshold,
+            self.memo,
+        );
+        self.compiled_all = CompiledFilter::new(
+            &self.neg_all_filters,
+            &FilterList::default(),
+            &self.scopes,
+            &self.positions,
+            &self.normalizers,
+            self.bloom_threshold,
+            self.memo,
+        );
+    }
+
+    fn memo_stats(&self) -> MemoStats {
+        let mut stats = self.compiled.memo_stats();
+        stats.add(self.compiled_all.memo_stats());
+        stats
+    }
+}
+
+/// Clones share the spec and the state controlled by `handle`, e.g. the minimum level and
+/// rules, so that a single runtime update, or further configuration, affects all of them.
+/// The count of `summarize_suppressed` is shared.
+impl<D: slog::Drain + Clone> Clone for KVFilter<D> {
+    fn clone(&self) -> Self {
+        KVFilter {
+            drain: self.drain.clone(),
+            spec: self.spec.clone(),
+            min_level: self.min_level.clone(),
+            rules: self.rules.clone(),
+            summary: self.summary.clone(),
+            injected: self.injected.clone(),
+            audit: self.audit.clone(),
+            oversize: self.oversize,
+            routes: self.routes.clone(),
+            error_fallback: self.error_fallback.clone(),
+            counters: self.counters.clone(),
+            watchdog: self.watchdog.clone(),
+            learner: self.learner.clone(),
+            overload: self.overload.clone(),
+            force_key: self.force_key,
+            #[cfg(feature = "std")]
+            context_cache: self.context_cache,
+        }
+    }
 }
 
 impl<D: slog::Drain> UnwindSafe for KVFilter<D> {}
 impl<D: slog::Drain> RefUnwindSafe for KVFilter<D> {}
 
-impl<'a, D: slog::Drain> KVFilter<D> {
+impl<D: slog::Drain> KVFilter<D> {
     /// Create `KVFilter` letting e'thing pass unless filters are set. Anything more
     /// important than `level` will pass in any case.
     ///
@@ -124,270 +1022,4146 @@ impl<'a, D: slog::Drain> KVFilter<D> {
     /// * `level` - maximum level filtered, higher levels pass by without filtering
     pub fn new(drain: D, level: slog::Level) -> Self {
         KVFilter {
-            drain: drain,
-            level: level,
-            filters: None,
-            neg_filters: None,
-            regex: None,
-            neg_regex: None,
+            drain,
+            spec: SharedSpec::new(Arc::new(FilterSpec::new(level))),
+            min_level: SharedLevel::new(slog::Level::Trace),
+            rules: Arc::new(RuntimeRules::default()),
+            summary: None,
+            injected: None,
+            audit: Arc::new(AuditLog::default()),
+            oversize: None,
+            routes: FilterMap::default(),
+            error_fallback: None,
+            counters: Arc::new(Counters::default()),
+            watchdog: None,
+            learner: Arc::new(Learner::default()),
+            overload: None,
+            force_key: false,
+            #[cfg(feature = "std")]
+            context_cache: false,
         }
     }
 
-    /// pass through entries with all keys with _any_ of the matching values in its entries
-    /// or ignore condition if None
-    pub fn only_pass_any_on_all_keys(mut self, filters: Option<KVFilterList>) -> Self {
-        self.filters = filters;
-        self
+    /// Create `KVFilter` configured by the `RUST_LOG` environment variable like
+    /// `slog_envlogger::new` would, see `directives`. Only errors pass if it's not set.
+    #[cfg(feature = "std")]
+    pub fn new_from_env(drain: D) -> Result<Self, KVFilterError> {
+        let spec = env::var("RUST_LOG").unwrap_or_else(|_| "error".to_owned());
+        KVFilter::new(drain, slog::Level::Critical).directives(&spec)
     }
 
-    /// suppress _any_ key with _any_ of the matching values in its entries or ignore
-    /// condition if None.
-    /// @note: This takes precedence over `only_pass_any`
-    pub fn always_suppress_any(mut self, filters: Option<KVFilterList>) -> Self {
-        self.neg_filters = filters;
-        self
+    /// apply `RUST_LOG` style directives as understood by slog-envlogger, extended with key
+    /// value clauses, e.g. `warn,hyper=error,my_app::db=debug,tenant=acme/timeout`:
+    ///
+    /// * `warn` sets `min_level`
+    /// * `hyper=error` sets the minimum level of records logged from modules starting with
+    ///   `hyper`, `off` suppressing them all; a bare module name lets all of its records pass
+    /// * `tenant=acme`, whose value isn't a level, passes records having the key value on
+    ///   the record or in the logger context at any level, as a `Rule` seen by `handle`
+    /// * a `?VAR` suffix applies a clause only if the environment variable `VAR` is set, or
+    ///   `?debug_assertions` only in debug builds, e.g. `db=trace?ENABLE_DB_TRACE`, see
+    ///   `Condition`
+    /// * messages must match the regex after `/`, see `only_pass_on_regex`
+    pub fn directives(self, spec: &str) -> Result<Self, KVFilterError> {
+        let directives = directives::parse(spec)?;
+
+        if let Some(level) = directives.default {
+            self.min_level.set(level);
+        }
+        let modules = directives.modules;
+        self.spec.update(|spec| spec.modules.extend(modules));
+        for rule in directives.rules {
+            self.rules.add(rule, None);
+        }
+        if let Some(regex) = directives.regex {
+            let regex = Regex::new(&regex)?;
+            self.spec.update(|spec| spec.regex = Some(regex));
+        }
+        Ok(self)
     }
 
-    /// only pass when this regex is found in the log message output.
-    pub fn only_pass_on_regex(mut self, regex: Regex) -> Self {
-        self.regex = Some(regex);
-        self
+    /// apply `directives` after resolving their `${NAME}` placeholders, e.g.
+    /// `warn,deploy_env=${DEPLOY_ENV}`, see `Placeholders`
+    pub fn directives_with(self, spec: &str, placeholders: &Placeholders) -> Result<Self, KVFilterError> {
+        self.directives(&placeholders.resolve(spec)?)
     }
 
-    /// suppress output if this regex if found in the log message output.
-    pub fn always_suppress_on_regex(mut self, regex: Regex) -> Self {
-        self.neg_regex = Some(regex);
+    /// always suppress records less important than `level`, regardless of key values.
+    ///
+    /// Such records are reported as disabled by `Drain::is_enabled`, so that callers can
+    /// skip building them altogether. Defaults to `Level::Trace`, i.e. nothing is suppressed.
+    ///
+    /// The minimum level can be changed later on through `handle`.
+    pub fn min_level(self, level: slog::Level) -> Self {
+        self.min_level.set(level);
         self
     }
 
-    fn is_match(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool {
-        // Can't use chaining here, as it's not possible to cast
-        // SyncSerialize to Serialize
-        let mut ser = FilteringSerializer {
-            pending_matches: self.filters.as_ref().map_or(HashMap::new(), |f| {
-                f.iter().map(|(k, v)| (k.as_str(), v)).collect()
-            }),
-            tmp_str: String::new(),
-        };
+    /// change how records logged on the current thread are decided until the returned
+    /// guard is dropped, e.g. `ScopedOverride::Pass` to log everything within a request
+    /// handler without touching the configuration. Of several overrides, the one pushed
+    /// last applies.
+    ///
+    /// Once the filter is moved into a `Logger`, use `KVFilterHandle::scoped_override`.
+    #[cfg(feature = "std")]
+    pub fn scoped_override(&self, spec: ScopedOverride) -> OverrideGuard {
+        overrides::push(self.min_level.id(), spec)
+    }
 
-        let mut negser = FilteringSerializer {
-            pending_matches: self.neg_filters.as_ref().map_or(HashMap::new(), |ref f| {
-                f.iter().map(|(k, v)| (k.as_str(), v)).collect()
-            }),
-            tmp_str: String::new(),
-        };
+    /// read the time from `clock` for rules expiring after their TTL or scheduled, caps and
+    /// traced values, e.g. one returning `performance.now()` on `wasm32-unknown-unknown`
+    /// where `std::time::Instant` panics, or `testing::ManualClock` in tests. Defaults to
+    /// `SystemClock`, or without `std` to a clock standing still.
+    pub fn clock(self, clock: Arc<dyn Clock>) -> Self {
+        self.rules.set_clock(clock);
+        self
+    }
 
-        record.kv().serialize(record, &mut ser).unwrap();
+    /// filter of `level` configured by `config`, see `IntoFilterSpec`
+    pub fn from_config<C: IntoFilterSpec + ?Sized>(
+        drain: D,
+        level: slog::Level,
+        config: &C,
+    ) -> Result<Self, KVFilterError> {
+        let spec = config.to_filter_spec(level)?;
+        Ok(KVFilter::new(drain, level).with_spec(spec))
+    }
 
-        // negative we have to go all way down to check for _any_ key match
-        record.kv().serialize(record, &mut negser).unwrap();
-        logger_values.serialize(record, &mut negser).unwrap();
+    /// configuration of this filter, for other filters to start from by `with_spec`
+    pub fn spec(&self) -> Arc<FilterSpec> {
+        self.spec.load_full()
+    }
 
-        let anynegativematch = ||
-            negser.pending_matches.len() == self.neg_filters.as_ref()
-                .map_or(0,
-                        |m| m.keys().len());
+    /// filter by `spec` in place of the configuration done so far. Configuring the filter
+    /// further copies the spec first, leaving other filters using it unaffected.
+    pub fn with_spec(mut self, spec: Arc<FilterSpec>) -> Self {
+        let capacity = self.spec.history_capacity();
+        self.spec = SharedSpec::new(spec);
+        self.spec.set_history_capacity(capacity);
+        self
+    }
 
-        let mut pass = if ser.pending_matches.is_empty() {
-            // if e'thing matched on the positive make sure _nothing_ matched on negative
-            anynegativematch()
-        } else {
-            // check inside whether we find more matches
-            logger_values.serialize(record, &mut ser).unwrap();
+    /// share the spec, the minimum level and the rules controlled by `handle`, so that
+    /// updates through it affect this filter too, e.g. the drains of several logger trees
+    /// which should filter alike. Their records are counted together in `handle.stats()`.
+    pub fn controlled_by(mut self, handle: &KVFilterHandle) -> Self {
+        self.spec = handle.spec.clone();
+        self.min_level = handle.min_level.clone();
+        self.rules = handle.rules.clone();
+        self.audit = handle.audit.clone();
+        self.counters = handle.counters.clone();
+        self.learner = handle.learner.clone();
+        self
+    }
 
-            if ser.pending_matches.is_empty() {
-                anynegativematch()
-            } else {
-                false
-            }
-        };
+    /// once a record carrying `key` passes, e.g. an error with a `session_id`, pass all the
+    /// records carrying the same value of the key for `duration`, like
+    /// `KVFilterHandle::trace` does. This gives full traces of the sessions an anomaly
+    /// occurred in. Records passing thanks to a traced value don't prolong the tracing.
+    pub fn sticky_on_key<K: Into<FilterStr>>(self, key: K, duration: Duration) -> Self {
+        let key = key.into();
+        self.spec.update(|spec| spec.sticky.push((key, duration)));
+        self
+    }
 
-        if pass && (self.regex.is_some() || self.neg_regex.is_some()) {
-            let res = format(*record.msg());
+    /// enable the rules of `trigger` for a while once a record fires it, see `Trigger`
+    pub fn trigger(self, trigger: Trigger) -> Self {
+        self.spec.update(|spec| spec.triggers.push(trigger));
+        self
+    }
 
-            if let Some(ref posmatch) = self.regex {
-                pass = posmatch.is_match(&res);
-            };
+    /// once a record at least as important as `level` passes, e.g. an error, pass all the
+    /// records of a sufficient level regardless of their key values for the `window`. This
+    /// gives the context following an anomaly. Records passing thanks to the window don't
+    /// renew it.
+    pub fn pass_through_after(self, level: slog::Level, window: Window) -> Self {
+        self.spec.update(|spec| spec.pass_through = Some((level, window)));
+        self
+    }
 
-            if pass {
-                if let Some(ref negmatch) = self.neg_regex {
-                    pass = !negmatch.is_match(&res);
-                }
-            }
-        }
+    /// pass only a `ratio` (0.0 to 1.0) of the values of `key`, e.g. of `trace_id`s, so that
+    /// either all or none of the records carrying a value pass. The values are hashed by a
+    /// stable hash, so processes sampling by the same ratio keep the same values. Records
+    /// without the key aren't sampled.
+    pub fn sample_by_key<K: Into<FilterStr>>(self, key: K, ratio: f64) -> Self {
+        let sampling = Sampling::new(key.into(), ratio);
+        self.spec.update(|spec| spec.sampling = Some(sampling));
+        self
+    }
 
-        pass
+    /// pass at most `records` records per value of `key` per `window`, e.g. at most 5 per
+    /// `client_ip` per minute, suppressing the rest. The window of a value starts with its
+    /// first record. Records without the key aren't capped, a later cap on the same key
+    /// replaces the earlier one. See `summarize_suppressed` to keep track of the suppressed
+    /// records.
+    pub fn cap_per_value<K: Into<FilterStr>>(self, key: K, records: usize, window: Duration) -> Self {
+        let cap = GroupCap {
+            key: key.into(),
+            records,
+            window,
+        };
+        self.spec.update(|spec| {
+            spec.caps.retain(|other| other.key != cap.key);
+            spec.caps.push(cap);
+        });
+        self
     }
-}
 
-impl<'a, D: slog::Drain> slog::Drain for KVFilter<D> {
-    type Err = D::Err;
-    type Ok = Option<D::Ok>;
+    /// add key values to every record passed on, e.g.
+    /// `inject(o!("pipeline" => "filtered"))`, replacing the ones injected before. They come
+    /// after the key values of the record, and aren't matched by the filter.
+    pub fn inject<T>(mut self, kv: slog::OwnedKV<T>) -> Self
+    where
+        T: slog::SendSyncRefUnwindSafeKV + 'static,
+    {
+        self.injected = Some(kv.into());
+        self
+    }
 
-    fn log(
-        &self,
-        info: &slog::Record,
-        logger_values: &slog::OwnedKVList,
-    ) -> Result<Self::Ok, Self::Err> {
-        // println!("{:#?}", info.msg());
+    /// deal with passing records by `policy` if the keys and formatted values of the record
+    /// and its logger context take more than `max_bytes`, e.g. to protect a log shipper
+    /// choking on huge records. The size is counted by formatting each value of each passing
+    /// record once more. Oversized records are forwarded without the key values of
+    /// `inject` and `summarize_suppressed`.
+    pub fn guard_oversized(mut self, max_bytes: usize, policy: OversizePolicy) -> Self {
+        self.oversize = Some((max_bytes, policy));
+        self
+    }
 
-        if info.level() < self.level || self.is_match(info, logger_values) {
-            self.drain.log(info, logger_values).map(Some)
-        } else {
-            Ok(None)
-        }
+    /// register `drain` as the destination `name` of the rules routing records to it by
+    /// `Rule::to`, e.g. `route_to("audit", audit_file_drain)`, making the filter a simple
+    /// router by key values. Records routed to a destination are not forwarded to the
+    /// filtered drain, nor decided by the other filters.
+    pub fn route_to<S, R>(mut self, name: S, drain: R) -> Self
+    where
+        S: Into<FilterStr>,
+        R: slog::Drain<Err = slog::Never> + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.routes.insert(name.into(), Arc::new(drain.ignore_res()));
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::KVFilter;
-    use slog::{Drain, Level, Logger, OwnedKVList, Record};
-    use regex::Regex;
-    use std::collections::HashSet;
-    use std::iter::FromIterator;
-    use std::sync::Mutex;
-    use std::fmt::Display;
-    use std::fmt::Formatter;
-    use std::fmt::Result as FmtResult;
-    use std::io;
-    use std::sync::Arc;
+    /// log the records the filtered drain fails to log to `drain` instead, and return
+    /// `Ok(None)` rather than the error, e.g. so that a network drain failing now and then
+    /// doesn't take the application down through `fuse`. The reports of the filter itself,
+    /// e.g. the audit trail, failing to log are only skipped, the record being filtered
+    /// regardless. The failures are counted in `FilterStats::drain_errors`.
+    pub fn fallback_on_error<R>(mut self, drain: R) -> Self
+    where
+        R: slog::Drain<Err = slog::Never> + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.error_fallback = Some(Arc::new(drain.ignore_res()));
+        self
+    }
 
-    const YES: &'static str = "YES";
-    const NO: &'static str = "NO";
+    /// don't filter, but pass all the records and learn which keys they carry, and the
+    /// first `max_values` values of each key, for `KVFilterHandle::learned` to report, e.g.
+    /// to find out what to filter on in an unfamiliar code base
+    pub fn learn(self, max_values: usize) -> Self {
+        self.learner.start(max_values);
+        self
+    }
 
-    #[derive(Debug)]
-    struct StringDrain {
-        output: Arc<Mutex<Vec<String>>>,
+    /// tighten the filter by `tightening` while the configuration passes more than
+    /// `max_per_sec` records a second, e.g. raising the minimum level to `Warning` during a
+    /// log storm, and relax it once the rate drops below. The rate is measured over
+    /// windows of a second by the `clock` of the filter, including the records the
+    /// tightening suppresses. Each transition is noticed by a record logged unfiltered,
+    /// with the `kvfilter_pass_rate` of the window causing it.
+    pub fn protect_overload(mut self, max_per_sec: u64, tightening: Tightening) -> Self {
+        self.overload = Some(Arc::new(Overload::new(max_per_sec, tightening)));
+        self
     }
 
-    /// seriously hacked logger drain that just counts messages to make
-    /// sure we have tests behaving correcly
-    impl<'a> Drain for StringDrain {
-        type Err = io::Error;
-        type Ok = ();
+    /// act by `action` once no record passed for `after` while records were suppressed,
+    /// e.g. to be warned of an allowlist so strict that nothing gets logged. Starvation is
+    /// only noticed as records are suppressed, and reported once until a record passes.
+    /// The time is read from the `clock` of the filter for each record.
+    pub fn watch_starvation(mut self, after: Duration, action: StarvationAction) -> Self {
+        self.watchdog = Some(Arc::new(Watchdog::new(after, action)));
+        self
+    }
+
+    /// count the records passed and suppressed by the value of `key` they carry, on the
+    /// record or in the logger context, in `KVFilterHandle::stats`, e.g. to see which
+    /// tenants the filter drops the most of. Each key counted by costs serializing the key
+    /// values of each record once more.
+    pub fn count_by_key<K: Into<FilterStr>>(self, key: K) -> Self {
+        self.counters.count_by_key(key.into());
+        self
+    }
+
+    /// keep track of the `k` key values carried by the most suppressed records, in
+    /// `FilterStats::top_suppressed`, e.g. to see at a glance that most of them are of
+    /// `tenant=bigcorp` when the volume of logs drops. The key values are counted in
+    /// bounded space, so the counts are estimates, at the cost of serializing the key
+    /// values of each suppressed record once more.
+    pub fn track_top_suppressed(self, k: usize) -> Self {
+        self.counters.track_top_suppressed(k);
+        self
+    }
+
+    /// count the records suppressed since the last record passed, and attach the count to
+    /// the next passing record as `kvfilter_suppressed_since_last`. With `top_keys` above
+    /// zero, the most frequent keys of the suppressed records are attached as well, as
+    /// `kvfilter_top_suppressed_keys => "key: count, ..."`, at the cost of serializing the
+    /// key values of each suppressed record once more.
+    pub fn summarize_suppressed(mut self, top_keys: usize) -> Self {
+        self.summary = Some(Arc::new(Summary::new(top_keys)));
+        self
+    }
+
+    /// trace at most `capacity` values at once by `KVFilterHandle::trace`, 1024 by default
+    pub fn trace_capacity(self, capacity: usize) -> Self {
+        self.rules.set_trace_capacity(capacity);
+        self
+    }
+
+    /// trace at most `capacity` values of each key at once, dropping the least recently
+    /// traced or matched one to make room, so that a system tracing session ids as they
+    /// come can't crowd out the others. Unbounded by default, within `trace_capacity`.
+    pub fn trace_capacity_per_key(self, capacity: usize) -> Self {
+        self.rules.set_trace_capacity_per_key(capacity);
+        self
+    }
+
+    /// keep the last `versions` specs applied through `KVFilterHandle`s, including the
+    /// current one, to roll back to by `KVFilterHandle::rollback`, 16 by default
+    pub fn spec_history(self, versions: usize) -> Self {
+        self.spec.set_history_capacity(versions);
+        self
+    }
+
+    /// log the changes made through the handles of this filter to its drain, as `Info`
+    /// records with the `kvfilter_audit_actor`, `kvfilter_audit_action` and
+    /// `kvfilter_audit_changes` keys. They are logged unfiltered, ahead of the next record
+    /// the filter decides, by whichever filter sharing the handles decides it.
+    pub fn log_audit_trail(self) -> Self {
+        self.audit.set_emit(true);
+        self
+    }
+
+    /// this filter returning the `Decision` on each record, for the drains wrapping it to
+    /// tell the outcomes apart
+    pub fn deciding(self) -> DecidingFilter<D> {
+        DecidingFilter::new(self)
+    }
+
+    /// drain for a logger of `logger_values`, the context of a child of a logger of this
+    /// filter, suppressing the records the spec decides by their key values without looking
+    /// at them if its context alone suppresses them, e.g. for the loggers of connections of a
+    /// tenant always suppressed. Wrap it like the drain of the parent logger is wrapped:
+    ///
+    /// ```
+    /// # #[macro_use] extern crate slog;
+    /// # extern crate slog_kvfilter;
+    /// use slog::Drain;
+    /// use slog_kvfilter::{filter_list, KVFilter};
+    ///
+    /// # fn main() {
+    /// let filter = KVFilter::new(slog::Discard, slog::Level::Info)
+    ///     .always_suppress_any(Some(filter_list(vec![("tenant", vec!["globex"])])));
+    /// let log = slog::Logger::root(filter.clone().fuse(), o!());
+    ///
+    /// let child = log.new(o!("tenant" => "globex"));
+    /// let drain = filter.child_drain(child.list());
+    /// let child = slog::Logger::root(drain.fuse(), slog::OwnedKV(child.list().clone()));
+    /// info!(child, "not passing, without looking at it");
+    /// # }
+    /// ```
+    ///
+    /// See `Prefiltered`.
+    pub fn child_drain(&self, logger_values: &slog::OwnedKVList) -> Prefiltered<D>
+    where
+        D: Clone,
+    {
+        Prefiltered::new(self.clone(), logger_values)
+    }
+
+    /// match the logger contexts of the loggers given `cached_context` key values once rather
+    /// than for each of their records. The key values of a logger don't change, and neither do
+    /// those of its parents, so each record only has its own key values matched:
+    ///
+    /// ```
+    /// # #[macro_use] extern crate slog;
+    /// # extern crate slog_kvfilter;
+    /// use slog::Drain;
+    /// use slog_kvfilter::{cached_context, filter_list, KVFilter};
+    ///
+    /// # fn main() {
+    /// let filter = KVFilter::new(slog::Discard, slog::Level::Info)
+    ///     .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+    ///     .cache_context();
+    /// let log = slog::Logger::root(filter.fuse(), o!());
+    /// let acme = log.new(cached_context(o!("tenant" => "acme")));
+    /// info!(acme, "passing, by the context matched once");
+    /// # }
+    /// ```
+    ///
+    /// The outcome is kept by the `cached_context` key values, along with the spec it was
+    /// matched by, so they have to be the innermost ones of a logger: the contexts of its
+    /// children are matched for each record unless they're given `cached_context` key values
+    /// too. A context is matched again once the spec or `on_match_error` changes. Values
+    /// computed by `slog::FnValue` in the contexts are computed once, for the first record, as
+    /// are the errors of matching them counted. Specs whose matching of the context depends on
+    /// the record, by keys scoped to levels or `KeyPosition::Nearest`, by
+    /// `always_suppress_all`, `evaluation_budget` or `MatchErrorPolicy::Suppress`, match the
+    /// contexts for each record.
+    #[cfg(feature = "std")]
+    pub fn cache_context(mut self) -> Self {
+        self.context_cache = true;
+        self
+    }
+
+    /// handle to control this filter once it has been moved into a `Logger`
+    pub fn handle(&self) -> KVFilterHandle {
+        KVFilterHandle {
+            min_level: self.min_level.clone(),
+            rules: self.rules.clone(),
+            spec: self.spec.clone(),
+            audit: self.audit.clone(),
+            actor: None,
+            counters: self.counters.clone(),
+            learner: self.learner.clone(),
+        }
+    }
+
+    /// lower the minimum level to `level` for records carrying `key`, on the record itself
+    /// or in the logger context, e.g. log down to `Trace` whenever a `request_id` is
+    /// present and down to `min_level` otherwise. The value of the key doesn't matter.
+    ///
+    /// If several such keys are present, the least important of their levels applies.
+    pub fn min_level_on_key<K: Into<FilterStr>>(self, key: K, level: slog::Level) -> Self {
+        self.spec.update(|spec| spec.level_keys.insert(key.into(), level));
+        self
+    }
+
+    /// forward the records carrying `value` of `key` at the levels `map` translates theirs
+    /// to, or suppress them, e.g. all the records of `subsystem=chatty_dep` with `Trace`
+    /// suppressed, `Debug` forwarded as `Trace` and `Info` as `Debug`:
+    ///
+    /// ```
+    /// # extern crate slog;
+    /// # extern crate slog_kvfilter;
+    /// use slog::Level;
+    /// use slog_kvfilter::{KVFilter, LevelMap};
+    ///
+    /// # fn main() {
+    /// let map = LevelMap::new()
+    ///     .suppress(Level::Trace)
+    ///     .map(Level::Debug, Level::Trace)
+    ///     .map(Level::Info, Level::Debug);
+    /// let _filter = KVFilter::new(slog::Discard, Level::Info).remap_levels("subsystem", "chatty_dep", map);
+    /// # }
+    /// ```
+    ///
+    /// Records are then filtered as if logged at their translated levels, by the minimum
+    /// level too. The value closest to the record of the keys of all the maps decides,
+    /// on the record before the logger context, and records carrying none keep their levels.
+    /// The map is found in the same pass over the key values as they are matched in, unless
+    /// by `log_compat`, `select_by_key` or `evaluation_budget`.
+    pub fn remap_levels<K: Into<FilterStr>, V: Into<FilterStr>>(self, key: K, value: V, map: LevelMap) -> Self {
+        let (key, value) = (key.into(), SmallStr::from(value.into()));
+        self.spec.update(|spec| spec.level_maps.insert(key, value, map));
+        self
+    }
+
+    /// choose whether records below `min_level` are suppressed right away
+    /// (`LevelOrder::LevelFirst`, the default), or whether matching key values can rescue
+    /// them (`LevelOrder::KVFirst`).
+    ///
+    /// Rescuing only makes sense with positive filters set, as without them every record
+    /// matches. Since any record may be rescued, `Drain::is_enabled` then only consults the
+    /// inner drain.
+    pub fn level_order(self, order: LevelOrder) -> Self {
+        self.spec.update(|spec| spec.level_order = order);
+        self
+    }
+
+    /// forward records passing thanks to matching key values at `level` at least, e.g.
+    /// promote matching `Trace` records to `Debug` so that level based drains downstream
+    /// don't drop them, or don't if None.
+    ///
+    /// Records more important than the filtered `level` are forwarded as they are.
+    pub fn boost_level(self, level: Option<slog::Level>) -> Self {
+        self.spec.update(|spec| spec.boost_level = level);
+        self
+    }
+
+    /// match the target and message of records as if they were key values, under the
+    /// pseudo-keys of `compat`, e.g. `"log.target" => ["hyper::client"]`, so that records
+    /// bridged from the `log` crate can be filtered by key values too; or don't if None.
+    pub fn log_compat(self, compat: Option<LogCompat>) -> Self {
+        self.spec.update(|spec| spec.log_compat = compat);
+        self
+    }
+
+    /// decide records having `key` of one of the values of `specs`, on the record or in the
+    /// logger context, by the spec of that value instead, e.g. a spec per tenant with its
+    /// own filters and levels. Records of other values or without the key are decided by
+    /// this filter's own configuration, the default set.
+    ///
+    /// Selection is done once, the selectors of the selected specs don't apply. The
+    /// minimum level and rules are those of this filter, shared by all the specs.
+    pub fn select_by_key<K, I, V>(self, key: K, specs: I) -> Self
+    where
+        K: Into<FilterStr>,
+        I: IntoIterator<Item = (V, Arc<FilterSpec>)>,
+        V: Into<FilterStr>,
+    {
+        let selector = Selector {
+            key: key.into(),
+            specs: specs
+                .into_iter()
+                .map(|(value, spec)| (value.into(), spec))
+                .collect(),
+        };
+        self.spec.update(|spec| spec.selector = Some(selector));
+        self
+    }
+
+    /// normalize the values of `key` by `normalizer` before matching them, after the
+    /// normalizers given for the key before, e.g. `Normalizer::Trim` then
+    /// `Normalizer::Lowercase` to match ` Send` as `send`
+    pub fn normalize_key<K: Into<FilterStr>>(self, key: K, normalizer: Normalizer) -> Self {
+        let key = key.into();
+        self.spec.update(|spec| {
+            spec.normalizers.entry(key).or_default().push(normalizer);
+            spec.compile();
+        });
+        self
+    }
+
+    /// decide records by `fallback` once matching them has inspected `max_pairs` key values,
+    /// on the record and in the logger context, or formatted `max_value_bytes` bytes of the
+    /// values of the filtered keys, e.g. to bound the cost of buggy callers logging
+    /// thousands of key values.
+    pub fn evaluation_budget(self, max_pairs: usize, max_value_bytes: usize, fallback: Fallback) -> Self {
+        let budget = Budget {
+            max_pairs,
+            max_value_bytes,
+        };
+        self.spec.update(|spec| spec.budget = Some((budget, fallback)));
+        self
+    }
+
+    /// decide the conditions whose matchers can't evaluate a value by `policy`, e.g. to
+    /// suppress the records with a malformed address rather than let them pass a
+    /// `ValuePattern::Cidr` negative key. Such values, and the values failing to format,
+    /// are counted in `FilterStats::match_errors`.
+    pub fn on_match_error(self, policy: MatchErrorPolicy) -> Self {
+        self.spec.update(|spec| spec.match_errors = policy);
+        self
+    }
+
+    /// pass through entries with all keys with _any_ of the matching values in its entries
+    /// or ignore condition if None
+    pub fn only_pass_any_on_all_keys(self, filters: Option<KVFilterList>) -> Self {
+        self.only_pass_any_on_all_keys_with(filters)
+    }
+
+    /// `only_pass_any_on_all_keys` of keys and values of any type convertible to
+    /// `FilterStr`, e.g. `&'static str`, sparing the copies to `String`
+    pub fn only_pass_any_on_all_keys_with<K, V, S>(self, filters: Option<KVFilterList<K, V, S>>) -> Self
+    where
+        K: Into<FilterStr> + Eq + Hash,
+        V: Into<FilterStr> + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.spec.update(|spec| {
+            spec.filters.set_values(filters);
+            spec.compile();
+        });
+        self
+    }
+
+    /// pass through entries with all keys with a value matching _any_ of the patterns,
+    /// in addition to the values given by `only_pass_any_on_all_keys`, or ignore condition
+    /// if None
+    pub fn only_pass_any_pattern_on_all_keys<K>(self, patterns: Option<KVPatternList<K>>) -> Self
+    where
+        K: Into<FilterStr> + Eq + Hash,
+    {
+        self.spec.update(|spec| {
+            spec.filters.set_patterns(patterns);
+            spec.compile();
+        });
+        self
+    }
+
+    /// suppress _any_ key with _any_ of the matching values in its entries or ignore
+    /// condition if None.
+    /// @note: This takes precedence over `only_pass_any`
+    pub fn always_suppress_any(self, filters: Option<KVFilterList>) -> Self {
+        self.always_suppress_any_with(filters)
+    }
+
+    /// `always_suppress_any` of keys and values of any type convertible to `FilterStr`
+    pub fn always_suppress_any_with<K, V, S>(self, filters: Option<KVFilterList<K, V, S>>) -> Self
+    where
+        K: Into<FilterStr> + Eq + Hash,
+        V: Into<FilterStr> + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.spec.update(|spec| {
+            spec.neg_filters.set_values(filters);
+            spec.compile();
+        });
+        self
+    }
+
+    /// suppress entries with all keys with _any_ of the matching values in its entries,
+    /// or ignore condition if None.
+    ///
+    /// Unlike `always_suppress_any`, a single matching key isn't enough. Both can be set,
+    /// an entry is then suppressed if either of them matches.
+    ///
+    /// A filter has one such group of keys. Several groups, each suppressing on its own,
+    /// mixed with keys suppressing by any of them, are the filters of each combined in an
+    /// `AllOf`, the records passing all of them passing:
+    ///
+    /// ```
+    /// # extern crate slog;
+    /// # extern crate slog_kvfilter;
+    /// use slog::Level;
+    /// use slog_kvfilter::{filter_list, AllOf, KVFilter};
+    ///
+    /// # fn main() {
+    /// // suppressed if deepcomp=1 and direction=send, or thread=300 and direction=receive,
+    /// // or user=bot
+    /// let group = |filters| KVFilter::new(slog::Discard, Level::Info).always_suppress_all(Some(filters));
+    /// let _drain = AllOf::new(slog::Discard)
+    ///     .with(group(filter_list(vec![("deepcomp", vec!["1"]), ("direction", vec!["send"])])))
+    ///     .with(group(filter_list(vec![("thread", vec!["300"]), ("direction", vec!["receive"])])))
+    ///     .with(KVFilter::new(slog::Discard, Level::Info)
+    ///         .always_suppress_any(Some(filter_list(vec![("user", vec!["bot"])]))));
+    /// # }
+    /// ```
+    pub fn always_suppress_all(self, filters: Option<KVFilterList>) -> Self {
+        self.always_suppress_all_with(filters)
+    }
+
+    /// `always_suppress_all` of keys and values of any type convertible to `FilterStr`
+    pub fn always_suppress_all_with<K, V, S>(self, filters: Option<KVFilterList<K, V, S>>) -> Self
+    where
+        K: Into<FilterStr> + Eq + Hash,
+        V: Into<FilterStr> + Eq + Hash,
+        S: BuildHasher,
+    {
+        self.spec.update(|spec| {
+            spec.neg_all_filters.set_values(filters);
+            spec.compile();
+        });
+        self
+    }
+
+    /// suppress _any_ key with a value matching _any_ of the patterns, in addition to
+    /// the values given by `always_suppress_any`, or ignore condition if None.
+    pub fn always_suppress_any_pattern<K>(self, patterns: Option<KVPatternList<K>>) -> Self
+    where
+        K: Into<FilterStr> + Eq + Hash,
+    {
+        self.spec.update(|spec| {
+            spec.neg_filters.set_patterns(patterns);
+            spec.compile();
+        });
+        self
+    }
+
+    /// only match the filters of `key`, positive and negative, on records within `levels`,
+    /// e.g. `Level::Debug..=Level::Trace`; on other levels the key is ignored as if it
+    /// wasn't filtered at all. The bounds may be given in either order, e.g.
+    /// `Level::Trace..=Level::Debug` is the same range.
+    pub fn filter_key_on_levels<K: Into<FilterStr>>(
+        self,
+        key: K,
+        levels: RangeInclusive<slog::Level>,
+    ) -> Self {
+        let (from, to) = levels.into_inner();
+        let levels = cmp::min(from, to)..=cmp::max(from, to);
+        self.spec.update(|spec| {
+            spec.scopes.insert(key.into(), levels);
+            spec.compile();
+        });
+        self
+    }
+
+    /// only match the filters of `key`, positive and negative, on the occurrences of the key
+    /// at `position`, e.g. `KeyPosition::Nearest` to match a value of the root logger
+    /// only as long as no child logger overrides it. Other occurrences are ignored as if
+    /// the key wasn't given there.
+    pub fn filter_key_at<K: Into<FilterStr>>(self, key: K, position: KeyPosition) -> Self {
+        self.spec.update(|spec| {
+            spec.positions.insert(key.into(), position);
+            spec.compile();
+        });
+        self
+    }
+
+    /// only pass records whose values of `keys` are one of `combinations`, taken jointly
+    /// rather than by each key, e.g. the `method` and `status` of `POST` and `500` or `GET`
+    /// and `403`, without passing `GET` and `500`. Records lacking any of the keys don't
+    /// pass. The values closest to the record are compared, a later call for the same keys
+    /// replaces the earlier one.
+    pub fn only_pass_any_combination<K, V, C>(self, keys: Vec<K>, combinations: C) -> Self
+    where
+        K: Into<FilterStr>,
+        V: Into<String>,
+        C: IntoIterator<Item = Vec<V>>,
+    {
+        let combination = Combinations::new(keys, combinations);
+        self.spec.update(|spec| replace_combinations(&mut spec.combinations, combination));
+        self
+    }
+
+    /// suppress records whose values of `keys` are one of `combinations`, taken jointly, see
+    /// `only_pass_any_combination`
+    pub fn always_suppress_any_combination<K, V, C>(self, keys: Vec<K>, combinations: C) -> Self
+    where
+        K: Into<FilterStr>,
+        V: Into<String>,
+        C: IntoIterator<Item = Vec<V>>,
+    {
+        let combination = Combinations::new(keys, combinations);
+        self.spec.update(|spec| replace_combinations(&mut spec.neg_combinations, combination));
+        self
+    }
+
+    /// only pass when this regex is found in the log message output.
+    pub fn only_pass_on_regex(self, regex: Regex) -> Self {
+        self.spec.update(|spec| spec.regex = Some(regex));
+        self
+    }
+
+    /// suppress output if this regex if found in the log message output.
+    pub fn always_suppress_on_regex(self, regex: Regex) -> Self {
+        self.spec.update(|spec| spec.neg_regex = Some(regex));
+        self
+    }
+
+    /// pre-check values of keys having at least `threshold` values with a bloom filter,
+    /// or don't if None.
+    ///
+    /// For large value sets, most of the values not in the set are then rejected without
+    /// the exact lookup. The outcome of filtering is the same either way.
+    pub fn bloom_filter_threshold(self, threshold: Option<usize>) -> Self {
+        self.spec.update(|spec| {
+            spec.bloom_threshold = threshold;
+            spec.compile();
+        });
+        self
+    }
+
+    /// memoize the outcomes of the patterns parsing or comparing values, e.g.
+    /// `ValuePattern::JsonPointer` or `ValuePattern::Fuzzy`, for up to `capacity` values of
+    /// each key, kept by `policy`, so that hot values aren't parsed again on each record.
+    /// Values are memoized after normalization, a `capacity` of 0 doesn't memoize.
+    ///
+    /// The lookups are counted in `FilterStats::memo`. The memos start empty whenever the
+    /// values or the patterns of the spec change.
+    pub fn memoize_matches(self, capacity: usize, policy: MemoPolicy) -> Self {
+        self.spec.update(|spec| {
+            spec.memo = if capacity > 0 { Some((capacity, policy)) } else { None };
+            spec.compile();
+        });
+        self
+    }
+
+    /// let records decide for themselves by `FORCE_KEY`. Their key values are serialized
+    /// once more for it, before the filter decides, so that values of the keys the spec
+    /// doesn't name, e.g. computed by `slog::FnValue`, are computed for it too.
+    pub fn honor_force_key(mut self) -> Self {
+        self.force_key = true;
+        self
+    }
+
+    /// whether a negative filter hits in the logger context, see `Prefiltered`
+    fn is_context_suppressed(
+        &self,
+        spec: &FilterSpec,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+    ) -> bool {
+        let mut tmp_str = String::new();
+        let mut matched = KeySet::default();
+        let mut ser = DualSerializer::new(&spec.compiled, record.level(), &mut matched, &mut tmp_str)
+            .on_error(spec.match_errors);
+        ser.serialize_context(logger_values, record);
+        self.counters.match_errors(ser.take_errors());
+        ser.suppressed
+    }
+
+    /// whether all the keys of `always_suppress_all` match
+    fn is_suppressed_by_all(
+        &self,
+        spec: &FilterSpec,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        matched: &mut KeySet,
+        tmp_str: &mut String,
+    ) -> bool {
+        if spec.compiled_all.keys_of(Polarity::Positive, record.level()) == 0 {
+            return false;
+        }
+
+        let mut ser = FilteringSerializer::new(
+            &spec.compiled_all,
+            Polarity::Positive,
+            record.level(),
+            matched,
+            tmp_str,
+        ).on_error(spec.match_errors);
+        ser.serialize(&record.kv(), record);
+        ser.serialize_context(logger_values, record);
+        self.counters.match_errors(ser.take_errors());
+        ser.all_matched()
+    }
+
+    /// whether records of `level` may pass, and if so at which level they are forwarded,
+    /// regardless of the inner drain
+    fn enabled_level(&self, level: slog::Level) -> Option<slog::Level> {
+        if self.learner.is_learning() {
+            return Some(level);
+        }
+        let spec = self.spec.load();
+        let forwarded = spec.boost_level.map_or(level, |boost| cmp::min(level, boost));
+
+        let min_level = self.min_level.get();
+
+        #[cfg(feature = "std")]
+        let min_level = match overrides::current(self.min_level.id()).as_deref() {
+            Some(&ScopedOverride::Pass) => return Some(level),
+            Some(&ScopedOverride::Suppress) => return None,
+            Some(&ScopedOverride::MinLevel(level)) => level,
+            _ => min_level,
+        };
+
+        let min_level = cmp::max(min_level, spec.level_keys.lowest().unwrap_or(min_level));
+        let min_level = cmp::max(min_level, spec.level_maps.lowest().unwrap_or(min_level));
+        let min_level = cmp::max(min_level, self.rules.lowest().unwrap_or(min_level));
+        let min_level = cmp::max(min_level, spec.modules.lowest().unwrap_or(min_level));
+
+        if spec.level_order == LevelOrder::KVFirst || level.is_at_least(min_level) {
+            Some(forwarded)
+        } else {
+            None
+        }
+    }
+
+    /// forward a record exceeding `limit` by `size` bytes, see `guard_oversized`
+    fn log_oversized(
+        &self,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        level: slog::Level,
+        limit: usize,
+        policy: OversizePolicy,
+        size: usize,
+    ) -> Result<D::Ok, D::Err> {
+        let rs = slog::RecordStatic {
+            location: info.location(),
+            tag: info.tag(),
+            level,
+        };
+        // the logger context is part of the size, so it's left out or truncated too
+        let no_context = slog::OwnedKVList::from(slog::OwnedKV(()));
+        match policy {
+            OversizePolicy::Suppress => self.drain.log(
+                &slog::Record::new(&rs, info.msg(), slog::BorrowedKV(&oversize::OversizedKV(size))),
+                &no_context,
+            ),
+            OversizePolicy::Truncate => {
+                let kv = oversize::TruncatedKV {
+                    record: info.kv(),
+                    logger_values,
+                    limit,
+                    size,
+                };
+                self.drain
+                    .log(&slog::Record::new(&rs, info.msg(), slog::BorrowedKV(&kv)), &no_context)
+            }
+        }
+    }
+
+    /// log a change made through the handles, see `log_audit_trail`
+    fn log_audit_entry(&self, entry: &AuditEntry) -> Result<(), D::Err> {
+        static LOCATION: slog::RecordLocation = slog::RecordLocation {
+            file: file!(),
+            line: line!(),
+            column: column!(),
+            function: "",
+            module: module_path!(),
+        };
+        let rs = slog::RecordStatic {
+            location: &LOCATION,
+            tag: "kvfilter_audit",
+            level: slog::Level::Info,
+        };
+        self.drain
+            .log(
+                &slog::Record::new(&rs, &format_args!("filter configuration changed"), slog::BorrowedKV(entry)),
+                &slog::OwnedKVList::from(slog::OwnedKV(())),
+            )
+            .map(|_| ())
+    }
+
+    /// decision on a record passed by the configuration at `level`, tightened if overloaded,
+    /// see `protect_overload`
+    fn protect(
+        &self,
+        overload: &Overload,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        level: slog::Level,
+        by_level: &mut bool,
+    ) -> Result<Option<slog::Level>, D::Err> {
+        static LOCATION: slog::RecordLocation = slog::RecordLocation {
+            file: file!(),
+            line: line!(),
+            column: column!(),
+            function: "",
+            module: module_path!(),
+        };
+        if let Some(transition) = overload.passed(self.rules.now()) {
+            let (level, message, rate) = match transition {
+                Transition::Tightened(rate) => (slog::Level::Warning, "log volume above the limit, filter tightened", rate),
+                Transition::Relaxed(rate) => (slog::Level::Info, "log volume back within the limit, filter relaxed", rate),
+            };
+            let rs = slog::RecordStatic {
+                location: &LOCATION,
+                tag: "kvfilter_overload",
+                level,
+            };
+            let res = self.drain.log(
+                &slog::Record::new(&rs, &format_args!("{}", message), slog::BorrowedKV(&overload.transition_kv(rate))),
+                &slog::OwnedKVList::from(slog::OwnedKV(())),
+            );
+            self.reported(res.map(|_| ()))?;
+        }
+
+        if !overload.is_tightened() {
+            return Ok(Some(level));
+        }
+        Ok(match overload.tightening {
+            Tightening::MinLevel(min_level) if info.level().is_at_least(min_level) => Some(level),
+            Tightening::MinLevel(_) => {
+                *by_level = true;
+                None
+            }
+            Tightening::Spec(ref spec) => self
+                .decide_selected(spec, info, logger_values, None, &mut false, by_level)
+                .map(|_| level),
+        })
+    }
+
+    /// report `starvation`, see `watch_starvation`
+    fn starving(&self, action: &StarvationAction, starvation: &Starvation) -> Result<(), D::Err> {
+        static LOCATION: slog::RecordLocation = slog::RecordLocation {
+            file: file!(),
+            line: line!(),
+            column: column!(),
+            function: "",
+            module: module_path!(),
+        };
+        match *action {
+            StarvationAction::Callback(ref callback) => {
+                callback(starvation);
+                Ok(())
+            }
+            StarvationAction::Warn => {
+                let rs = slog::RecordStatic {
+                    location: &LOCATION,
+                    tag: "kvfilter_watchdog",
+                    level: slog::Level::Warning,
+                };
+                self.drain
+                    .log(
+                        &slog::Record::new(
+                            &rs,
+                            &format_args!("no record passed the filter for a while, it may be misconfigured"),
+                            slog::BorrowedKV(starvation),
+                        ),
+                        &slog::OwnedKVList::from(slog::OwnedKV(())),
+                    )
+                    .map(|_| ())
+            }
+        }
+    }
+
+    /// what forwarding `record` to the filtered drain returned, or, if that failed, the
+    /// record sent to the fallback of `fallback_on_error`
+    fn forwarded(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        res: Result<D::Ok, D::Err>,
+    ) -> Result<Decision<D::Ok>, D::Err> {
+        match (res, self.error_fallback.as_ref()) {
+            (Err(_), Some(fallback)) => {
+                self.counters.drain_error();
+                let _ = fallback.log(record, logger_values);
+                Ok(Decision::FellBack)
+            }
+            (res, _) => res.map(Decision::Passed),
+        }
+    }
+
+    /// what logging a report of the filter itself, e.g. an audit entry, returned, the error
+    /// only counted if `fallback_on_error` is set, as the report isn't a record to fall back
+    fn reported(&self, res: Result<(), D::Err>) -> Result<(), D::Err> {
+        match res {
+            Err(_) if self.error_fallback.is_some() => {
+                self.counters.drain_error();
+                Ok(())
+            }
+            res => res,
+        }
+    }
+
+    /// filter `info`, forwarding it to the filtered drain if it passes
+    fn log_deciding(&self, info: &slog::Record, logger_values: &slog::OwnedKVList) -> Result<Decision<D::Ok>, D::Err> {
+        let force = match self.forced(info) {
+            Some(force) => force,
+            None => return self.filter_forced(info, logger_values, None),
+        };
+        let kv = WithoutForce(info.kv());
+        let rs = slog::RecordStatic {
+            location: info.location(),
+            tag: info.tag(),
+            level: info.level(),
+        };
+        self.filter_forced(
+            &slog::Record::new(&rs, info.msg(), slog::BorrowedKV(&kv)),
+            logger_values,
+            Some(force),
+        )
+    }
+
+    /// `log_deciding` of a record without its `FORCE_KEY`, deciding it by `force` if given,
+    /// rather than by the spec or by routing it
+    fn filter_forced(
+        &self,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        force: Option<Force>,
+    ) -> Result<Decision<D::Ok>, D::Err> {
+        for entry in self.audit.take_unlogged() {
+            self.reported(self.log_audit_entry(&entry))?;
+        }
+
+        if self.learner.is_learning() {
+            self.learner.observe(info, logger_values);
+            return self.forwarded(info, logger_values, self.drain.log(info, logger_values));
+        }
+
+        if force.is_none() && !self.routes.is_empty() {
+            if let Some(drain) = self
+                .rules
+                .route(info, logger_values)
+                .and_then(|destination| self.routes.get(&destination))
+            {
+                self.counters.routed();
+                let _ = drain.log(info, logger_values);
+                return Ok(Decision::Routed);
+            }
+        }
+
+        let mut by_level = false;
+        let decided = match (force, self.overload.as_ref()) {
+            (Some(Force::Pass), _) => Some(info.level()),
+            (Some(Force::Drop), _) => None,
+            (None, overload) => match (self.decide_noting(info, logger_values, &mut by_level), overload) {
+                (Some(level), Some(overload)) => self.protect(overload, info, logger_values, level, &mut by_level)?,
+                (decided, _) => decided,
+            },
+        };
+        let level = match decided {
+            Some(level) => level,
+            None => {
+                self.counters.suppressed();
+                self.counters
+                    .by_key(false, |key| FirstValueSerializer::find(key, info, logger_values));
+                self.counters.top_suppressed(info, logger_values);
+                if let Some(ref watchdog) = self.watchdog {
+                    if let Some(starvation) = watchdog.suppressed(self.rules.now()) {
+                        self.reported(self.starving(&watchdog.action, &starvation))?;
+                    }
+                }
+                if let Some(ref summary) = self.summary {
+                    summary.suppressed(info);
+                }
+                return Ok(if by_level {
+                    Decision::SuppressedByLevel
+                } else {
+                    Decision::SuppressedByKV
+                });
+            }
+        };
+        self.counters.passed();
+        self.counters
+            .by_key(true, |key| FirstValueSerializer::find(key, info, logger_values));
+        if let Some(ref watchdog) = self.watchdog {
+            watchdog.passed(self.rules.now());
+        }
+
+        if let Some((limit, policy)) = self.oversize {
+            if let Some(size) = oversize::oversized(info, logger_values, limit) {
+                let res = self.log_oversized(info, logger_values, level, limit, policy, size);
+                return self.forwarded(info, logger_values, res);
+            }
+        }
+
+        let summary = self.summary.as_ref().and_then(|summary| summary.take());
+        if summary.is_none() && self.injected.is_none() {
+            return if level != info.level() {
+                let boosted = slog::RecordStatic {
+                    location: info.location(),
+                    tag: info.tag(),
+                    level,
+                };
+                let boosted = slog::Record::new(&boosted, info.msg(), info.kv());
+                self.forwarded(&boosted, logger_values, self.drain.log(&boosted, logger_values))
+            } else {
+                self.forwarded(info, logger_values, self.drain.log(info, logger_values))
+            };
+        }
+
+        let rs = slog::RecordStatic {
+            location: info.location(),
+            tag: info.tag(),
+            level,
+        };
+        let kv = (
+            info.kv(),
+            Enrichment {
+                summary,
+                injected: self.injected.as_ref(),
+            },
+        );
+        let record = slog::Record::new(&rs, info.msg(), slog::BorrowedKV(&kv));
+        self.forwarded(&record, logger_values, self.drain.log(&record, logger_values))
+    }
+
+    /// decision forced by the `FORCE_KEY` of `info`, unless ignored or below `min_level`
+    fn forced(&self, info: &slog::Record) -> Option<Force> {
+        if self.force_key && info.level().is_at_least(self.min_level.get()) {
+            force::forced(info)
+        } else {
+            None
+        }
+    }
+
+    /// whether the key values of a record match `spec` at `level`, matched in the pass `feed`
+    /// is given to feed them, joined by `pass` if any
+    fn match_in_pass(
+        &self,
+        spec: &FilterSpec,
+        level: slog::Level,
+        pass: Option<&mut dyn Pass>,
+        feed: &mut dyn FnMut(Option<&mut dyn Pass>),
+    ) -> bool {
+        let (mut value, mut value_all) = (Scratch::take_value(), Scratch::take_value());
+        let (mut matched, mut suppress_all) = (KeySet::default(), KeySet::default());
+        let kv_matched = {
+            let mut ser = DualSerializer::new(&spec.compiled, level, &mut matched, &mut value)
+                .on_error(spec.match_errors);
+            let mut ser_all = FilteringSerializer::new(
+                &spec.compiled_all,
+                Polarity::Positive,
+                level,
+                &mut suppress_all,
+                &mut value_all,
+            ).on_error(spec.match_errors);
+            // without keys to match, `always_suppress_all` would suppress all the records
+            let by_all = spec.compiled_all.keys_of(Polarity::Positive, level) != 0;
+            {
+                let mut both;
+                let own: &mut dyn Pass = if by_all {
+                    both = Fork(&mut ser, &mut ser_all);
+                    &mut both
+                } else {
+                    &mut ser
+                };
+                match pass {
+                    Some(pass) => feed(Some(&mut Fork(own, pass))),
+                    None => feed(Some(own)),
+                }
+            }
+            self.counters.match_errors(ser.take_errors() + ser_all.take_errors());
+            !ser.suppressed && ser.all_matched() && !(by_all && ser_all.all_matched())
+        };
+        Scratch::give_value(value);
+        Scratch::give_value(value_all);
+        kv_matched
+    }
+
+    /// `match_in_pass` at each of `levels` at once, into `matched`
+    fn match_at_levels(
+        &self,
+        spec: &FilterSpec,
+        levels: &[slog::Level],
+        pass: Option<&mut dyn Pass>,
+        feed: &mut dyn FnMut(Option<&mut dyn Pass>),
+        matched: &mut [bool],
+    ) {
+        match (levels.split_first(), matched.split_first_mut()) {
+            (Some((&level, levels)), Some((first, matched))) => {
+                *first = self.match_in_pass(spec, level, pass, &mut |pass| {
+                    self.match_at_levels(spec, levels, pass, &mut *feed, &mut *matched)
+                });
+            }
+            _ => feed(pass),
+        }
+    }
+
+    /// `decide` by `spec`, one of `FilterSpec::shares_pass`, of `info` whose key values
+    /// matched the spec if `kv_matched`
+    fn decide_matched(
+        &self,
+        spec: &FilterSpec,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        kv_matched: bool,
+    ) -> Option<slog::Level> {
+        match self.forced(info) {
+            Some(Force::Pass) => Some(info.level()),
+            Some(Force::Drop) => None,
+            None => self.decide_record(spec, info, logger_values, Some(kv_matched), &mut false),
+        }
+    }
+
+    /// whether `info` passes, and if so at which level it is forwarded
+    fn decide(&self, info: &slog::Record, logger_values: &slog::OwnedKVList) -> Option<slog::Level> {
+        match self.forced(info) {
+            Some(Force::Pass) => Some(info.level()),
+            Some(Force::Drop) => None,
+            None => self.decide_noting(info, logger_values, &mut false),
+        }
+    }
+
+    /// `decide`, setting `by_level` if `info` is suppressed for its level rather than its
+    /// key values
+    fn decide_noting(
+        &self,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        by_level: &mut bool,
+    ) -> Option<slog::Level> {
+        let spec = self.spec.load();
+        if spec.level_maps.is_empty() {
+            return self.decide_compat(&spec, info, logger_values, by_level);
+        }
+        if spec.log_compat.is_none() && spec.selector.is_none() && spec.budget.is_none() {
+            return self.decide_mapped(&spec, info, logger_values, by_level);
+        }
+
+        match spec.level_maps.find(info, logger_values).map(|map| map.get(info.level())) {
+            Some(None) => {
+                *by_level = true;
+                None
+            }
+            Some(Some(level)) if level != info.level() => {
+                let rs = slog::RecordStatic {
+                    location: info.location(),
+                    tag: info.tag(),
+                    level,
+                };
+                self.decide_compat(&spec, &slog::Record::new(&rs, info.msg(), info.kv()), logger_values, by_level)
+            }
+            _ => self.decide_compat(&spec, info, logger_values, by_level),
+        }
+    }
+
+    /// `decide_noting` by a spec of `FilterSpec::level_maps`, finding the map of `info` in the
+    /// pass matching its key values, at each of the levels it may be translated to if the
+    /// outcome depends on the level
+    fn decide_mapped(
+        &self,
+        spec: &FilterSpec,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        by_level: &mut bool,
+    ) -> Option<slog::Level> {
+        let mut levels = [info.level(); 6];
+        let mut candidates = 1;
+        if spec.compiled.is_scoped() || spec.compiled_all.is_scoped() {
+            for level in spec.level_maps.targets(info.level()) {
+                if !levels[..candidates].contains(&level) {
+                    levels[candidates] = level;
+                    candidates += 1;
+                }
+            }
+        }
+
+        let mut map = spec.level_maps.pass();
+        let mut matched = [false; 6];
+        self.match_at_levels(
+            spec,
+            &levels[..candidates],
+            Some(&mut map),
+            &mut |pass| feed_record(pass, info, logger_values),
+            &mut matched[..candidates],
+        );
+
+        let level = match map.found().map_or(Some(info.level()), |map| map.get(info.level())) {
+            Some(level) => level,
+            None => {
+                *by_level = true;
+                return None;
+            }
+        };
+        // the only candidate if the outcome doesn't depend on the level
+        let kv_matched = levels[..candidates]
+            .iter()
+            .position(|&candidate| candidate == level)
+            .map_or(matched[0], |candidate| matched[candidate]);
+        if level == info.level() {
+            return self.decide_record(spec, info, logger_values, Some(kv_matched), by_level);
+        }
+        let rs = slog::RecordStatic {
+            location: info.location(),
+            tag: info.tag(),
+            level,
+        };
+        self.decide_record(
+            spec,
+            &slog::Record::new(&rs, info.msg(), info.kv()),
+            logger_values,
+            Some(kv_matched),
+            by_level,
+        )
+    }
+
+    /// `decide_record` of `info` with the key values `FilterSpec::log_compat` makes of it
+    fn decide_compat(
+        &self,
+        spec: &FilterSpec,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        by_level: &mut bool,
+    ) -> Option<slog::Level> {
+        match spec.log_compat {
+            Some(compat) => {
+                let kv = LogCompatKV {
+                    compat,
+                    record: info,
+                };
+                let rs = slog::RecordStatic {
+                    location: info.location(),
+                    tag: info.tag(),
+                    level: info.level(),
+                };
+                self.decide_record(
+                    spec,
+                    &slog::Record::new(&rs, info.msg(), slog::BorrowedKV(&kv)),
+                    logger_values,
+                    None,
+                    by_level,
+                )
+            }
+            None => self.decide_record(spec, info, logger_values, None, by_level),
+        }
+    }
+
+    /// `kv_matched` is whether the key values of `info` match, if they were matched already
+    /// in a pass shared with other filters, see `shares_pass`
+    fn decide_record(
+        &self,
+        spec: &FilterSpec,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        kv_matched: Option<bool>,
+        by_level: &mut bool,
+    ) -> Option<slog::Level> {
+        let spec = spec.select(info, logger_values);
+        let mut windowed = false;
+        let decided = self.decide_selected(spec, info, logger_values, kv_matched, &mut windowed, by_level);
+
+        if decided.is_some() {
+            if let Some(ref sampling) = spec.sampling {
+                if let Some(value) = FirstValueSerializer::find(&sampling.key, info, logger_values) {
+                    if !sampling.keeps(&value) {
+                        return None;
+                    }
+                }
+            }
+            for cap in &spec.caps {
+                if let Some(value) = FirstValueSerializer::find(&cap.key, info, logger_values) {
+                    if !self.rules.admit(cap, value) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        // records passing thanks to the window don't renew it
+        if let Some((level, window)) = spec.pass_through {
+            if decided.is_some() && !windowed && info.level().is_at_least(level) {
+                self.rules.open_window(window);
+            }
+        }
+        decided
+    }
+
+    /// `windowed` is set if the record passes thanks to the pass-through window, `by_level`
+    /// if it's suppressed for its level
+    fn decide_selected(
+        &self,
+        spec: &FilterSpec,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        kv_matched: Option<bool>,
+        windowed: &mut bool,
+        by_level: &mut bool,
+    ) -> Option<slog::Level> {
+        let mut min_level = self.min_level.get();
+        let mut module_off = false;
+
+        match spec.modules.level_for(info.module()) {
+            Some(Some(level)) => min_level = level,
+            Some(None) => module_off = true,
+            None => {}
+        }
+
+        #[cfg(feature = "std")]
+        if let Some(current) = overrides::current(self.min_level.id()) {
+            match *current {
+                ScopedOverride::Pass => return Some(info.level()),
+                ScopedOverride::Suppress => return None,
+                ScopedOverride::MinLevel(level) => {
+                    min_level = level;
+                    module_off = false;
+                }
+                ScopedOverride::Require(ref predicate) => {
+                    if !predicate.is_match(info, logger_values) {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        let ruled = self.rules.passed_at(info, logger_values);
+
+        // the record firing a trigger isn't decided by the rules it enables
+        for trigger in &spec.triggers {
+            if let Some(value) = FirstValueSerializer::find(trigger.key(), info, logger_values) {
+                if trigger.fires(info.level(), &value) {
+                    self.rules.fire(trigger);
+                }
+            }
+        }
+
+        if ruled.is_some() {
+            return ruled;
+        }
+
+        if module_off {
+            *by_level = true;
+            return None;
+        }
+
+        let level_ok = info.level().is_at_least(min_level)
+            || spec.level_keys.lowest().is_some_and(|lowest| {
+                info.level().is_at_least(lowest)
+                    && info
+                        .level()
+                        .is_at_least(spec.level_keys.min_level(info, logger_values, min_level))
+            });
+
+        if level_ok && info.level() < spec.level {
+            self.stick(spec, info, logger_values);
+            return Some(info.level());
+        }
+
+        if level_ok && spec.pass_through.is_some() && self.rules.in_window() {
+            *windowed = true;
+            return Some(info.level());
+        }
+
+        if !(level_ok || spec.level_order == LevelOrder::KVFirst) {
+            *by_level = true;
+            return None;
+        }
+        let matched = match kv_matched {
+            Some(matched) => {
+                matched && Scratch::with(|scratch| self.is_rest_match(spec, info, logger_values, &mut scratch.message))
+            }
+            None => self.is_match(spec, info, logger_values),
+        };
+        if !matched {
+            return None;
+        }
+
+        self.stick(spec, info, logger_values);
+        match spec.boost_level {
+            Some(level) if level < info.level() => Some(level),
+            _ => Some(info.level()),
+        }
+    }
+
+    /// trace the values of the sticky keys of a record which passed on its own merit
+    fn stick(&self, spec: &FilterSpec, info: &slog::Record, logger_values: &slog::OwnedKVList) {
+        for (key, duration) in &spec.sticky {
+            if let Some(value) = FirstValueSerializer::find(key, info, logger_values) {
+                self.rules.trace(key.clone(), value, *duration);
+            }
+        }
+    }
+
+    fn is_match(&self, spec: &FilterSpec, record: &slog::Record, logger_values: &slog::OwnedKVList) -> bool {
+        Scratch::with(|scratch| self.is_match_with(spec, record, logger_values, scratch))
+    }
+
+    fn is_match_with(
+        &self,
+        spec: &FilterSpec,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        scratch: &mut Scratch,
+    ) -> bool {
+        let Scratch {
+            ref mut value,
+            ref mut message,
+            ref mut positive,
+            ref mut suppress_all,
+        } = *scratch;
+
+        // the most common spec, matched by a serializer of its own, see `SingleKey`
+        if let Some(single) = spec.compiled.single_key() {
+            if spec.compiled_all.len() == 0 && spec.budget.is_none() {
+                let mut errors = 0;
+                let matched = single.is_match(record, logger_values, spec.match_errors, value, &mut errors);
+                self.counters.match_errors(errors);
+                return matched == Some(true) && self.is_rest_match(spec, record, logger_values, message);
+            }
+        }
+
+        let (budget, fallback) = match spec.budget {
+            Some((budget, fallback)) => (Some(budget), fallback == Fallback::Pass),
+            None => (None, false),
+        };
+
+        // a negative hit on the record decides right away, before looking at the context
+        let mut ser = DualSerializer::new(&spec.compiled, record.level(), positive, value)
+            .budgeted(budget)
+            .on_error(spec.match_errors);
+        ser.serialize(&record.kv(), record);
+        self.counters.match_errors(ser.take_errors());
+
+        if ser.over_budget {
+            return fallback;
+        }
+        if ser.suppressed {
+            return false;
+        }
+
+        if self.is_suppressed_by_all(spec, record, logger_values, suppress_all, ser.tmp_str) {
+            return false;
+        }
+
+        #[cfg(feature = "std")]
+        match self.cached_context(spec, record, logger_values) {
+            Some(context) => ser.matched_context(&context.matched, context.suppressed),
+            // the context is matched in the same pass, and all of it has to be looked at for
+            // negative keys
+            None => ser.serialize_context(logger_values, record),
+        }
+        #[cfg(not(feature = "std"))]
+        ser.serialize_context(logger_values, record);
+        self.counters.match_errors(ser.take_errors());
+        if ser.over_budget {
+            return fallback;
+        }
+
+        !ser.suppressed && ser.all_matched() && self.is_rest_match(spec, record, logger_values, message)
+    }
+
+    /// the outcome of matching the logger context of `record` alone, if cached by
+    /// `cache_context`
+    #[cfg(feature = "std")]
+    fn cached_context(
+        &self,
+        spec: &FilterSpec,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+    ) -> Option<Arc<ContextMatch>> {
+        if !self.context_cache
+            || spec.budget.is_some()
+            || spec.match_errors == MatchErrorPolicy::Suppress
+            || spec.compiled.is_scoped()
+            || spec.compiled.has_nearest()
+            || spec.compiled_all.len() != 0
+        {
+            return None;
+        }
+        let slot = cache::probe(logger_values, record)?;
+        Some(slot.get(spec.compiled.generation(), spec.match_errors, || {
+            let (mut matched, mut tmp_str) = (KeySet::default(), String::new());
+            let suppressed = {
+                let mut ser = DualSerializer::new(&spec.compiled, record.level(), &mut matched, &mut tmp_str)
+                    .on_error(spec.match_errors);
+                ser.serialize_context(logger_values, record);
+                self.counters.match_errors(ser.take_errors());
+                ser.suppressed
+            };
+            ContextMatch::new(matched, suppressed)
+        }))
+    }
+
+    /// whether a record passing by the values of its keys passes by the message and the
+    /// combinations of values too
+    fn is_rest_match(
+        &self,
+        spec: &FilterSpec,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        message: &mut String,
+    ) -> bool {
+        let mut pass = true;
+        if spec.regex.is_some() || spec.neg_regex.is_some() {
+            message.clear();
+            // a message failing to format is matched by the part formatted before, or
+            // suppressed by `MatchErrorPolicy::Suppress`
+            if fmt::write(message, *record.msg()).is_err() {
+                self.counters.match_errors(1);
+                if spec.match_errors == MatchErrorPolicy::Suppress {
+                    return false;
+                }
+            }
+
+            if let Some(ref posmatch) = spec.regex {
+                pass = posmatch.is_match(message);
+            };
+
+            if pass {
+                if let Some(ref negmatch) = spec.neg_regex {
+                    pass = !negmatch.is_match(message);
+                }
+            }
+        }
+
+        pass
+            && spec.combinations.iter().all(|c| c.is_match(record, logger_values))
+            && !spec.neg_combinations.iter().any(|c| c.is_match(record, logger_values))
+    }
+}
+
+/// Key values added to the forwarded records, see `KVFilter::inject` and
+/// `KVFilter::summarize_suppressed`
+struct Enrichment<'a> {
+    summary: Option<SummaryKV>,
+    injected: Option<&'a slog::OwnedKVList>,
+}
+
+impl<'a> KV for Enrichment<'a> {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        if let Some(ref summary) = self.summary {
+            summary.serialize(record, serializer)?;
+        }
+        if let Some(injected) = self.injected {
+            injected.serialize(record, serializer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<D: slog::Drain> slog::Drain for KVFilter<D> {
+    type Err = D::Err;
+    type Ok = Option<D::Ok>;
+
+    fn log(
+        &self,
+        info: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        // println!("{:#?}", info.msg());
+
+        self.log_deciding(info, logger_values).map(Decision::passed)
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        self.enabled_level(level)
+            .is_some_and(|forwarded| self.drain.is_enabled(forwarded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cached_context, filter_list, AllOf, AnyOf, KVFilter, KVFilterBuilder, Decision, KVFilterChain, KVFilterError, KVFilterList, Fallback, LevelOrder, OversizePolicy, FORCE_KEY,
+                Condition, KeyPosition, LogCompat, MatchErrorPolicy, MemoPolicy, MemoStats, Normalizer, NotFilter, Rule, ScopedOverride, StarvationAction, Tightening, Trigger, ValueList, ValuePattern,
+                LevelMap, Window};
+    use slog::{Drain, Level, Logger, OwnedKVList, Record};
+    use regex::Regex;
+    use std::borrow::Cow;
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+    use std::sync::Mutex;
+    use std::fmt::Display;
+    use std::fmt::Formatter;
+    use std::fmt::Result as FmtResult;
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+    use testing::{CapturingDrain, CountingDrain, ManualClock};
+
+    const YES: &str = "YES";
+    const NO: &str = "NO";
+
+    #[derive(Debug)]
+    struct StringDrain {
+        output: Arc<Mutex<Vec<String>>>,
+    }
+
+    /// seriously hacked logger drain that just counts messages to make
+    /// sure we have tests behaving correcly
+    impl Drain for StringDrain {
+        type Err = io::Error;
+        type Ok = ();
 
         fn log(&self, info: &Record, _: &OwnedKVList) -> io::Result<()> {
             let mut lo = self.output.lock().unwrap();
             let fmt = format!("{:?}", info.msg());
 
-            if !fmt.contains(YES) && !fmt.contains(NO) {
-                panic!(fmt);
-            }
+            if !fmt.contains(YES) && !fmt.contains(NO) {
+                panic!("{}", fmt);
+            }
+
+            (*lo).push(fmt);
+
+            Ok(())
+        }
+    }
+
+    impl Display for StringDrain {
+        fn fmt(&self, f: &mut Formatter) -> FmtResult {
+            write!(f, "none")
+        }
+    }
+
+    fn testkvfilter<D: Drain>(d: D) -> KVFilter<D> {
+        KVFilter::new(d, Level::Info).only_pass_any_on_all_keys(Some(
+            vec![
+                (
+                    "thread".to_string(),
+                    HashSet::from_iter(vec!["100".to_string(), "200".to_string()]),
+                ),
+                (
+                    "direction".to_string(),
+                    HashSet::from_iter(vec!["send".to_string(), "receive".to_string()]),
+                ),
+            ].into_iter()
+                .collect(),
+        ))
+    }
+
+    fn testnegkvfilter<D: Drain>(f: KVFilter<D>) -> KVFilter<D> {
+        f.always_suppress_any(Some(
+            vec![
+                (
+                    "deepcomp".to_string(),
+                    HashSet::from_iter(vec!["1".to_string(), "2".to_string()]),
+                ),
+                (
+                    "deepercomp".to_string(),
+                    HashSet::from_iter(vec!["4".to_string(), "5".to_string()]),
+                ),
+            ].into_iter()
+                .collect(),
+        ))
+    }
+
+    #[test]
+    /// get an asserting Drain, get a couple of loggers that
+    /// have different nodes, components and see whether filtering
+    /// is applied properly on the derived `Logger` copies
+    fn nodecomponentlogfilter() {
+        assert!(Level::Critical < Level::Warning);
+
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        // build some small filter
+        let filter = testkvfilter(drain);
+
+        // Get a root logger that will log into a given drain.
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "200", "sub" => "sub"));
+        let subsublog = sublog.new(o!("direction" => "send"));
+        let subsubsublog = subsublog.new(o!());
+
+        let wrongthread = mainlog.new(o!("thread" => "400", "sub" => "sub"));
+
+        info!(mainlog, "NO: filtered, main, no keys");
+        info!(mainlog, "YES: unfiltered, on of thread matches, direction matches";
+        "thread" => "100", "direction" => "send");
+        info!(mainlog,
+              "YES: unfiltered, on of thread matches, direction matches, different key order";
+        "direction" => "send", "thread" => "100");
+
+        warn!(mainlog, "YES: unfiltered, higher level"); // level high enough to pass anyway
+
+        debug!(mainlog, "NO: filtered, level to low, no keys"); // level low
+
+        info!(mainlog, "NO: filtered, wrong thread on record";
+        "thread" => "300", "direction" => "send");
+
+        info!(wrongthread, "NO: filtered, wrong thread on sublog");
+
+        info!(sublog, "NO: filtered sublog, missing dirction ");
+
+        info!(sublog, "YES: unfiltered sublog with added directoin";
+        "direction" => "receive");
+
+        info!(
+            subsubsublog,
+            "YES: unfiltered subsubsublog, direction on subsublog, thread on sublog"
+        );
+
+        // test twice same keyword with right value will give filter match
+        let stackedthreadslog = wrongthread.new(o!("thread" => "200"));
+
+        info!(stackedthreadslog,
+              "YES: unfiltered since one of the threads matches from inherited";
+        "direction" => "send");
+
+        println!("resulting output: {:#?}", *out.lock().unwrap());
+
+        assert_eq!(out.lock().unwrap().len(), 6);
+    }
+
+    #[test]
+    /// get an asserting Drain, get a couple of loggers that
+    /// have different nodes, components and deep/deeper components and see whether filtering
+    /// is applied properly on the derived `Logger` copies while punching holes for the disallowed
+    /// values
+    fn negnodecomponentlogfilter() {
+        assert!(Level::Critical < Level::Warning);
+
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        // build some small filter
+        let filter = testnegkvfilter(testkvfilter(drain.fuse()));
+
+        // Get a root logger that will log into a given drain.
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "200", "sub" => "sub"));
+        let subsublog = sublog.new(o!("direction" => "send"));
+        // deep match won't match
+        let subsubsublog = subsublog.new(o!("deepcomp" => "0"));
+        // deep match will filter
+        let negsubsubsublog = subsublog.new(o!("deepcomp" => "1"));
+
+        info!(mainlog, "NO: filtered, main, no keys");
+        info!(mainlog, "YES: unfiltered, on of thread matches, direction matches";
+        "thread" => "100", "direction" => "send");
+        info!(subsubsublog, "YES: unfiltered, on of thread matches, direction matches, deep doesn't apply";
+        "thread" => "100", "direction" => "send");
+        info!(negsubsubsublog, "NO: filtered, on of thread matches, direction matches, deep negative applies";
+        "thread" => "100", "direction" => "send");
+        info!(subsubsublog, "NO: filtered, on of thread matches, direction matches, deep doesn't apply but deeper does";
+        "thread" => "100", "direction" => "send", "deepercomp" => "4");
+        info!(subsubsublog, "YES: unfiltered, on of thread matches, direction matches, deep doesn't apply and deeper doesn't";
+        "thread" => "100", "direction" => "send", "deepercomp" => "7");
+
+        println!("resulting output: {:#?}", *out.lock().unwrap());
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    /// test negative and positive
+    fn regextest() {
+        assert!(Level::Critical < Level::Warning);
+
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        // build some small filter
+        let filter = KVFilter::new(drain.fuse(), Level::Info)
+            .only_pass_on_regex(Regex::new(r"PASS\d:").unwrap())
+            .always_suppress_on_regex(Regex::new(r"NOPE\d:").unwrap());
+
+        // Get a root logger that will log into a given drain.
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        info!(mainlog, "NO: filtered, no positive");
+        info!(mainlog, "NO: NOPE2 PASS0 filtered, negative");
+        info!(mainlog, "NO: filtered, no positive");
+        info!(mainlog, "YES: PASS2: not filtered, positive");
+        info!(mainlog, "YES: {}: not filtered, positive", "PASS4");
+
+        println!("resulting output: {:#?}", *out.lock().unwrap());
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    /// context value counting how many times it has been serialized
+    struct CountingValue {
+        value: &'static str,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl ::slog::Value for CountingValue {
+        fn serialize(
+            &self,
+            record: &Record,
+            key: ::slog::Key,
+            serializer: &mut dyn (::slog::Serializer),
+        ) -> ::slog::Result {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.value.serialize(record, key, serializer)
+        }
+    }
+
+    #[test]
+    /// the logger contexts given `cached_context` key values are matched only once per logger,
+    /// while the filtering outcome stays the same
+    fn cachedcontextlogfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testnegkvfilter(testkvfilter(drain.fuse())).cache_context();
+        let handle = filter.handle();
+
+        let thread_count = Arc::new(AtomicUsize::new(0));
+        let deep_count = Arc::new(AtomicUsize::new(0));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(cached_context(o!("thread" => CountingValue {
+            value: "200",
+            count: thread_count.clone(),
+        })));
+        let negsublog = sublog.new(cached_context(o!("deepcomp" => CountingValue {
+            value: "1",
+            count: deep_count.clone(),
+        })));
+
+        info!(sublog, "NO: filtered, missing direction");
+        info!(negsublog, "NO: filtered, negative in context"; "direction" => "send");
+
+        let thread_evaluations = thread_count.load(Ordering::SeqCst);
+        let deep_evaluations = deep_count.load(Ordering::SeqCst);
+
+        info!(sublog, "YES: unfiltered, direction on record"; "direction" => "send");
+        info!(sublog, "YES: unfiltered, direction on record"; "direction" => "receive");
+        info!(sublog, "NO: filtered, negative on record";
+        "direction" => "receive", "deepercomp" => "5");
+        info!(negsublog, "NO: filtered, negative in context"; "direction" => "send");
+        info!(mainlog, "YES: unfiltered, everything on record";
+        "thread" => "100", "direction" => "send");
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+        // contexts seen before are not matched again
+        assert_eq!(thread_count.load(Ordering::SeqCst), thread_evaluations);
+        assert_eq!(deep_count.load(Ordering::SeqCst), deep_evaluations);
+
+        // but again once the spec is updated
+        handle.update_spec(|filter| filter.always_suppress_any(None));
+        info!(negsublog, "YES: unfiltered, negative no more"; "direction" => "send");
+        assert_eq!(out.lock().unwrap().len(), 4);
+        assert!(deep_count.load(Ordering::SeqCst) > deep_evaluations);
+    }
+
+    #[test]
+    /// key values are only looked at until the filtering outcome is known
+    fn earlyexitlogfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let poslog = Logger::root(
+            testkvfilter(drain.fuse()).fuse(),
+            o!("version" => CountingValue {
+                value: env!("CARGO_PKG_VERSION"),
+                count: count.clone(),
+            }),
+        );
+
+        info!(poslog, "YES: unfiltered, everything matched on record";
+        "thread" => "100", "direction" => "send");
+
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let neglog = Logger::root(
+            testnegkvfilter(KVFilter::new(drain.fuse(), Level::Info)).fuse(),
+            o!("version" => CountingValue {
+                value: env!("CARGO_PKG_VERSION"),
+                count: count.clone(),
+            }),
+        );
+        let negsublog = neglog.new(o!("deepcomp" => "1"));
+
+        info!(negsublog, "NO: filtered, negative hit before the version in context");
+
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        info!(neglog, "YES: unfiltered, no negative hit");
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// filters built from static strings and `Cow`s behave just like the `String` ones
+    fn borrowedkvfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = KVFilter::new(drain.fuse(), Level::Info)
+            .only_pass_any_on_all_keys_with(Some(
+                vec![
+                    ("thread", HashSet::from_iter(vec!["100", "200"])),
+                    ("direction", HashSet::from_iter(vec!["send", "receive"])),
+                ].into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ))
+            .always_suppress_any_with(Some(
+                vec![(
+                    Cow::Borrowed("deepcomp"),
+                    HashSet::from_iter(vec![Cow::Borrowed("1"), Cow::Owned("2".to_string())]),
+                )].into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "200"));
+
+        info!(mainlog, "NO: filtered, main, no keys");
+        info!(sublog, "YES: unfiltered, direction on record"; "direction" => "send");
+        info!(sublog, "NO: filtered, negative on record"; "direction" => "send", "deepcomp" => 2);
+        info!(mainlog, "YES: unfiltered, everything on record";
+        "thread" => "100", "direction" => "receive");
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// values matched against prefix and substring patterns, combined with exact values
+    fn patternkvfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let prefixes = (0..300)
+            .map(|i| ValuePattern::Prefix(format!("/api/v{}/", i).into()))
+            .collect();
+
+        let filter = testkvfilter(drain.fuse())
+            .only_pass_any_pattern_on_all_keys(Some(
+                vec![("path", prefixes), ("thread", vec![ValuePattern::Prefix("3".into())])]
+                    .into_iter()
+                    .collect(),
+            ))
+            .always_suppress_any_pattern(Some(
+                vec![("path", vec![ValuePattern::Substring("secret".into())])]
+                    .into_iter()
+                    .collect(),
+            ));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "200", "direction" => "send"));
+
+        info!(sublog, "NO: filtered, no path");
+        info!(sublog, "YES: unfiltered, path prefix matches"; "path" => "/api/v42/users");
+        info!(sublog, "NO: filtered, no path prefix matches"; "path" => "/api/v300/users");
+        info!(sublog, "NO: filtered, prefix not at start"; "path" => "/static/api/v1/");
+        info!(sublog, "NO: filtered, negative substring"; "path" => "/api/v1/secrets");
+        info!(mainlog, "YES: unfiltered, thread prefix matches";
+        "thread" => "300", "direction" => "receive", "path" => "/api/v299/");
+        info!(mainlog, "NO: filtered, thread neither value nor prefix";
+        "thread" => "400", "direction" => "receive", "path" => "/api/v299/");
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// IP addresses matched against CIDR ranges, with suppressed ranges carved out
+    fn cidrfilter() {
+        let drain = CountingDrain::new();
+        let cidrs = |ranges: &[&str]| -> Vec<ValuePattern> {
+            ranges.iter().map(|range| ValuePattern::Cidr(range.parse().unwrap())).collect()
+        };
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_pattern_on_all_keys(Some(
+                vec![("client_ip", cidrs(&["10.0.0.0/8", "192.168.0.0/16", "fd00::/8"]))]
+                    .into_iter()
+                    .collect(),
+            ))
+            .always_suppress_any_pattern(Some(
+                vec![("client_ip", cidrs(&["10.99.0.0/16"]))].into_iter().collect(),
+            ));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "client_ip" => "10.1.2.3");
+        info!(log, "passing, with a port"; "client_ip" => "192.168.7.1:51234");
+        info!(log, "passing"; "client_ip" => "fd12::1");
+        info!(log, "not passing, public"; "client_ip" => "8.8.8.8");
+        info!(log, "not passing, suppressed range"; "client_ip" => "10.99.1.1");
+        info!(log, "not passing, not an address"; "client_ip" => "localhost");
+
+        assert_eq!(drain.count(), 3);
+    }
+
+    #[cfg(feature = "fuzzy")]
+    #[test]
+    /// values typed by humans matched despite a typo
+    fn fuzzyfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info).only_pass_any_pattern_on_all_keys(Some(
+            vec![("user", vec![ValuePattern::Fuzzy("alice".into(), 1)])]
+                .into_iter()
+                .collect(),
+        ));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "user" => "alice");
+        info!(log, "passing, a typo"; "user" => "alicr");
+        info!(log, "passing, a letter missing"; "user" => "alce");
+        info!(log, "not passing, two typos"; "user" => "alcie");
+        info!(log, "not passing"; "user" => "bob");
+
+        assert_eq!(drain.count(), 3);
+    }
+
+    #[test]
+    /// client versions matched against a requirement instead of listing every release
+    fn versionfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info).only_pass_any_pattern_on_all_keys(Some(
+            vec![("client_version", vec![ValuePattern::Version(">= 2.3.0, < 3".parse().unwrap())])]
+                .into_iter()
+                .collect(),
+        ));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "client_version" => "2.3.0");
+        info!(log, "passing"; "client_version" => "v2.17.4");
+        info!(log, "not passing, too old"; "client_version" => "2.2.9");
+        info!(log, "not passing, too new"; "client_version" => "3.0.0");
+        info!(log, "not passing, not a version"; "client_version" => "latest");
+
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// durations and sizes compared with thresholds written with units
+    fn thresholdfilter() {
+        let drain = CountingDrain::new();
+        let threshold = |threshold: &str| vec![ValuePattern::Threshold(threshold.parse().unwrap())];
+
+        let filter = KVFilter::new(drain.clone(), Level::Info).only_pass_any_pattern_on_all_keys(Some(
+            vec![("elapsed", threshold("> 1.5s")), ("payload_size", threshold(">= 10MiB"))]
+                .into_iter()
+                .collect(),
+        ));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "elapsed" => ?Duration::from_millis(1700), "payload_size" => 20u64 << 20);
+        info!(log, "passing"; "elapsed" => "2s", "payload_size" => "1GB");
+        info!(log, "not passing, fast"; "elapsed" => "250ms", "payload_size" => "1GB");
+        info!(log, "not passing, small"; "elapsed" => "2s", "payload_size" => "10MB");
+
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// filtering outcome is the same with bloom filters enabled
+    fn bloomkvfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testnegkvfilter(testkvfilter(drain.fuse()))
+            .only_pass_any_pattern_on_all_keys(Some(
+                vec![("thread", vec![ValuePattern::Prefix("3".into())])]
+                    .into_iter()
+                    .collect(),
+            ))
+            .bloom_filter_threshold(Some(1));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "200"));
+
+        info!(sublog, "NO: filtered, missing direction");
+        info!(sublog, "YES: unfiltered, direction on record"; "direction" => "send");
+        info!(sublog, "NO: filtered, wrong direction"; "direction" => "sent");
+        info!(sublog, "NO: filtered, negative on record"; "direction" => "send", "deepcomp" => 2);
+        info!(mainlog, "YES: unfiltered, thread prefix"; "direction" => "send", "thread" => "300");
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// records below the minimum level are disabled and suppressed, others are up to
+    /// the inner drain
+    fn minlevelfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testkvfilter(drain.filter_level(Level::Info).fuse()).min_level(Level::Debug);
+
+        assert!(filter.is_enabled(Level::Critical));
+        assert!(filter.is_enabled(Level::Info));
+        // disabled by the inner drain
+        assert!(!filter.is_enabled(Level::Debug));
+        // disabled by the filter itself
+        assert!(!filter.is_enabled(Level::Trace));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testkvfilter(drain).min_level(Level::Debug);
+
+        assert!(filter.is_enabled(Level::Debug));
+        assert!(!filter.is_enabled(Level::Trace));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        debug!(mainlog, "YES: unfiltered, debug with matching keys";
+        "thread" => "100", "direction" => "send");
+        trace!(mainlog, "NO: filtered, trace below the minimum level";
+        "thread" => "100", "direction" => "send");
+        warn!(mainlog, "YES: unfiltered, higher level");
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    /// with the key values matched first, they can rescue records below the minimum level
+    fn kvfirstlevelfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testkvfilter(drain)
+            .min_level(Level::Info)
+            .level_order(LevelOrder::KVFirst);
+
+        assert!(filter.is_enabled(Level::Debug));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        debug!(mainlog, "YES: unfiltered, debug rescued by matching keys";
+        "thread" => "100", "direction" => "send");
+        debug!(mainlog, "NO: filtered, debug without matching keys"; "thread" => "100");
+        info!(mainlog, "YES: unfiltered, info with matching keys";
+        "thread" => "100", "direction" => "send");
+        info!(mainlog, "NO: filtered, info without matching keys");
+        warn!(mainlog, "YES: unfiltered, higher level");
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    /// keys limited to some levels are ignored on the others
+    fn levelscopedfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testnegkvfilter(testkvfilter(drain))
+            .filter_key_on_levels("direction", Level::Debug..=Level::Trace)
+            .filter_key_on_levels("deepcomp", Level::Trace..=Level::Debug);
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "100"));
+
+        info!(sublog, "YES: unfiltered, direction not needed on info");
+        debug!(sublog, "NO: filtered, direction needed on debug");
+        debug!(sublog, "YES: unfiltered, direction present"; "direction" => "send");
+        info!(sublog, "YES: unfiltered, deepcomp ignored on info"; "deepcomp" => "1");
+        debug!(sublog, "NO: filtered, deepcomp suppresses on debug";
+        "direction" => "send", "deepcomp" => "1");
+        info!(mainlog, "NO: filtered, thread still needed on info");
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    /// matching records are promoted past a level filter downstream
+    fn boostlevelfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = slog::LevelFilter::new(
+            StringDrain {
+                output: out.clone(),
+            },
+            Level::Info,
+        );
+
+        let filter = testkvfilter(drain).boost_level(Some(Level::Info));
+
+        assert!(filter.is_enabled(Level::Debug));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "100"));
+
+        debug!(sublog, "YES: unfiltered, debug promoted to info"; "direction" => "send");
+        debug!(sublog, "NO: filtered, debug without matching keys");
+        info!(sublog, "YES: unfiltered, info with matching keys"; "direction" => "send");
+        warn!(mainlog, "YES: unfiltered, higher level");
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    /// records matching a demoting rule are forwarded at the lower level
+    fn demotelevelfilter() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info);
+        let handle = filter.handle();
+        handle.add_rule(Rule::pass("component", "hyper", Level::Debug).demote_to(Level::Info), None);
+        // passing the same records, but at their level
+        handle.add_rule(Rule::pass("component", "hyper", Level::Info), None);
+        assert!(handle.shadowed_rules().is_empty());
+
+        let log = Logger::root(filter.fuse(), o!());
+        let hyper = log.new(o!("component" => "hyper"));
+        error!(hyper, "demoted");
+        debug!(hyper, "less important already");
+        error!(log, "not demoted, other component"; "component" => "app");
+
+        let levels: Vec<_> = drain.records().iter().map(|record| record.level).collect();
+        assert_eq!(levels, vec![Level::Info, Level::Debug, Level::Error]);
+    }
+
+    #[test]
+    /// keys matched only at some of their occurrences in the logger hierarchy
+    fn keypositionfilter() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("component", vec!["server"])])))
+            .always_suppress_any(Some(filter_list(vec![("user", vec!["bot"])])))
+            .filter_key_at("component", KeyPosition::Nearest)
+            .filter_key_at("user", KeyPosition::Record);
+
+        let log = Logger::root(filter.fuse(), o!("component" => "server"));
+        let client = log.new(o!("component" => "client"));
+        let bot = log.new(o!("user" => "bot"));
+
+        info!(log, "passing, root");
+        info!(client, "not passing, overridden by the child");
+        info!(client, "passing, overridden by the record"; "component" => "server");
+        info!(log, "not passing, overridden by the record"; "component" => "client");
+        info!(bot, "passing, suppressed on the record only");
+        info!(bot, "not passing, suppressed"; "user" => "bot");
+
+        assert_eq!(
+            drain.messages(),
+            vec![
+                "passing, root",
+                "passing, overridden by the record",
+                "passing, suppressed on the record only"
+            ]
+        );
+    }
+
+    #[test]
+    /// records carrying a key are logged down to a lower level
+    fn minlevelonkeyfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = KVFilter::new(drain, Level::Critical)
+            .min_level(Level::Info)
+            .min_level_on_key("request_id", Level::Debug);
+
+        assert!(filter.is_enabled(Level::Debug));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let requestlog = mainlog.new(o!("request_id" => 42));
+
+        debug!(mainlog, "NO: filtered, no request");
+        info!(mainlog, "YES: unfiltered, info without request");
+        debug!(requestlog, "YES: unfiltered, request in the context");
+        debug!(mainlog, "YES: unfiltered, request on the record"; "request_id" => 43);
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    /// the minimum level changes through the handle while logging
+    fn handleminlevelfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = KVFilter::new(drain, Level::Critical).min_level(Level::Info);
+        let handle = filter.handle();
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        debug!(mainlog, "NO: filtered, below the minimum level");
+        handle.set_min_level(Level::Debug);
+        assert_eq!(handle.min_level(), Level::Debug);
+        debug!(mainlog, "YES: unfiltered, minimum level lowered");
+        handle.set_min_level(Level::Warning);
+        info!(mainlog, "NO: filtered, minimum level raised");
+
+        assert_eq!(out.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    /// rules added at runtime, expiring after their TTL
+    fn rulefilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let clock = ManualClock::new();
+        let filter = KVFilter::new(drain, Level::Info)
+            .min_level(Level::Info)
+            .clock(Arc::new(clock.clone()));
+        let handle = filter.handle();
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let acmelog = mainlog.new(o!("tenant" => "acme"));
+
+        debug!(acmelog, "NO: filtered, no rule yet");
+        let id = handle.add_rule(Rule::pass("tenant", "acme", Level::Debug), None);
+        debug!(acmelog, "YES: rule matching the context");
+        debug!(mainlog, "YES: rule matching the record"; "tenant" => "acme");
+        debug!(mainlog, "NO: filtered, rule not matching"; "tenant" => "other");
+        assert!(handle.remove_rule(id));
+        assert!(!handle.remove_rule(id));
+        debug!(acmelog, "NO: filtered, rule removed");
+
+        handle.add_rule(
+            Rule::pass("tenant", "acme", Level::Debug),
+            Some(Duration::from_millis(50)),
+        );
+        debug!(acmelog, "YES: rule not expired yet");
+        assert_eq!(handle.rules().len(), 1);
+        clock.advance(Duration::from_millis(100));
+        debug!(acmelog, "NO: filtered, rule expired");
+        assert!(handle.rules().is_empty());
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    /// targets and messages of bridged log records matched as pseudo-keys
+    fn logcompatfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = KVFilter::new(drain, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("log.target", vec!["hyper::client"])])))
+            .always_suppress_any(Some(filter_list(vec![("log.message", vec!["NO: keepalive"])])))
+            .log_compat(Some(LogCompat::default()));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        info!(mainlog, #"hyper::client", "YES: matching target");
+        info!(mainlog, #"hyper::server", "NO: other target");
+        info!(mainlog, #"hyper::client", "NO: keepalive");
+        info!(mainlog, "NO: no target");
+
+        assert_eq!(out.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    /// envlogger directives with key value clauses
+    fn directivesfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = KVFilter::new(drain, Level::Critical)
+            .directives("warn,slog_kvfilter::tests=info,slog_kvfilter::tests::quiet=off,tenant=acme")
+            .unwrap();
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        info!(mainlog, "YES: module level");
+        debug!(mainlog, "NO: below the module level");
+        debug!(mainlog, "YES: key value clause"; "tenant" => "acme");
+        quiet::log(&mainlog);
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+
+        match KVFilter::new(slog::Discard, Level::Critical).directives("info/(") {
+            Err(KVFilterError::InvalidRegex(_)) => {}
+            _ => panic!("invalid regex accepted"),
+        }
+    }
+
+    mod quiet {
+        use slog::Logger;
+
+        pub fn log(log: &Logger) {
+            warn!(log, "NO: module off");
+        }
+    }
+
+    #[test]
+    /// clones and filters sharing a spec filter alike, clones share the handle state
+    fn sharedspecfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+        let clone = filter.clone();
+        let shared = KVFilter::new(drain.clone(), Level::Info).with_spec(filter.spec());
+        // configuring further copies the spec
+        let wider = KVFilter::new(drain.clone(), Level::Info)
+            .with_spec(filter.spec())
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100", "200"])])));
+        filter.handle().set_min_level(Level::Info);
+
+        for filter in [filter, clone, shared, wider] {
+            let log = Logger::root(filter.fuse(), o!());
+            info!(log, "passing"; "thread" => "100");
+            info!(log, "passing in wider only"; "thread" => "200");
+            debug!(log, "passing in shared, wider only"; "thread" => "100");
+        }
+
+        assert_eq!(drain.count(), 1 + 1 + 2 + 3);
+    }
+
+    #[test]
+    /// spec updates through the handle reach the filters it controls
+    fn updatespecfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+        let handle = filter.handle();
+        let other = KVFilter::new(drain.clone(), Level::Info).controlled_by(&handle);
+
+        let log = Logger::root(filter.fuse(), o!("thread" => "200"));
+        let otherlog = Logger::root(other.fuse(), o!("thread" => "200"));
+
+        info!(log, "not passing");
+        info!(otherlog, "not passing");
+        let before = handle.spec();
+        handle.update_spec(|filter| {
+            filter.only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100", "200"])])))
+        });
+        info!(log, "passing");
+        info!(otherlog, "passing");
+        handle.set_spec(before);
+        info!(log, "not passing");
+
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// changes collected by `begin_update` are applied as one version, on top of updates
+    /// made meanwhile
+    fn specupdatefilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1", "2"])])));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        let update = handle
+            .begin_update()
+            .add_values(ValueList::Pass, "thread", vec!["200"])
+            .remove_values(ValueList::Suppress, "deepcomp", vec!["1"])
+            .add_values(ValueList::Suppress, "deepercomp", vec!["1"]);
+        drop(handle.begin_update().add_values(ValueList::Pass, "thread", vec!["300"]));
+        info!(log, "not passing, not committed yet"; "thread" => "200");
+        handle.set_min_level(Level::Info);
+        update
+            .configure(|filter| filter.always_suppress_on_regex(Regex::new("negative").unwrap()))
+            .commit();
+
+        info!(log, "passing"; "thread" => "200", "deepcomp" => "1");
+        info!(log, "not passing, dropped update"; "thread" => "300");
+        info!(log, "not passing"; "thread" => "100", "deepercomp" => "1");
+        info!(log, "not passing, negative"; "thread" => "100");
+        assert_eq!(drain.count(), 1);
+        assert_eq!(handle.versions().len(), 2);
+
+        let entry = handle.audit_trail().pop().unwrap();
+        assert_eq!(entry.action, "commit(4 changes)");
+        assert_eq!(entry.changes.len(), 4);
+
+        handle
+            .begin_update()
+            .remove_values(ValueList::Suppress, "deepcomp", vec!["2"])
+            .remove_key(ValueList::Suppress, "deepercomp")
+            .commit();
+        info!(log, "passing"; "thread" => "100", "deepcomp" => "2", "deepercomp" => "1");
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// specs applied through the handle are rolled back one by one, or to a version
+    fn rollbackfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .spec_history(3)
+            .clock(Arc::new(ManualClock::starting_at(Duration::from_secs(1_000_000))));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+        let widen = |threads: Vec<&'static str>| {
+            handle.update_spec(|filter| {
+                filter.only_pass_any_on_all_keys(Some(filter_list(vec![("thread", threads)])))
+            })
+        };
+
+        assert_eq!(handle.rollback(), None);
+        widen(vec!["100", "200"]);
+        handle.label_version("INC-42");
+        handle.update_spec(|filter| filter.only_pass_on_regex(Regex::new("never").unwrap()));
+        info!(log, "not passing, blacked out"; "thread" => "100");
+
+        let versions = handle.versions();
+        assert_eq!(versions.iter().map(|v| v.version).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(versions[1].label.as_deref(), Some("INC-42"));
+        assert_eq!(versions[0].applied_at, None);
+        assert_eq!(versions[1].applied_at, Some(UNIX_EPOCH + Duration::from_secs(1_000_000)));
+
+        assert_eq!(handle.rollback(), Some(1));
+        info!(log, "passing"; "thread" => "200");
+        assert!(handle.rollback_to(0));
+        info!(log, "not passing"; "thread" => "200");
+        info!(log, "passing"; "thread" => "100");
+        assert!(!handle.rollback_to(1));
+
+        widen(vec!["300"]);
+        widen(vec!["400"]);
+        widen(vec!["500"]);
+        let versions = handle.versions();
+        assert_eq!(versions.iter().map(|v| v.version).collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert!(!handle.rollback_to(0));
+
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// changes made through handles are recorded with their actors, and logged if asked to
+    fn auditfilter() {
+        let drain = CapturingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .log_audit_trail()
+            .clock(Arc::new(ManualClock::starting_at(Duration::from_secs(1_000_000))));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        handle.with_actor("alice: INC-42").set_min_level(Level::Debug);
+        handle.update_spec(|filter| {
+            filter.only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100", "200"])])))
+        });
+        info!(log, "passing"; "thread" => "200");
+
+        let trail = handle.audit_trail();
+        assert_eq!(trail.len(), 2);
+        assert_eq!(trail[0].actor.as_deref(), Some("alice: INC-42"));
+        assert_eq!(trail[0].action, "set_min_level(DEBG)");
+        assert_eq!(trail[0].at, UNIX_EPOCH + Duration::from_secs(1_000_000));
+        assert!(trail[0].changes.is_empty());
+        assert_eq!(trail[1].actor, None);
+        assert_eq!(trail[1].changes.len(), 1);
+
+        let records = drain.records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].message, "filter configuration changed");
+        assert_eq!(records[0].get("kvfilter_audit_actor"), Some("alice: INC-42"));
+        assert_eq!(records[1].get("kvfilter_audit_changes"), Some("~ Pass thread: +[200] -[]"));
+        assert_eq!(records[2].message, "passing");
+    }
+
+    #[test]
+    /// per tenant specs selected by the tenant key, the filter's own for other tenants
+    fn selectorfilter() {
+        let drain = CountingDrain::new();
+
+        let acme = KVFilter::new(slog::Discard, Level::Debug)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100", "200"])])))
+            .spec();
+        let quiet = KVFilter::new(slog::Discard, Level::Critical)
+            .always_suppress_any(Some(filter_list(vec![("thread", vec!["100"])])))
+            .spec();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .select_by_key("tenant", vec![("acme", acme), ("quiet", quiet)]);
+
+        let log = Logger::root(filter.fuse(), o!());
+        let acmelog = log.new(o!("tenant" => "acme"));
+
+        info!(acmelog, "passing"; "thread" => "200");
+        info!(acmelog, "unfiltered at the level of the selected spec"; "thread" => "300");
+        info!(log, "not passing"; "tenant" => "quiet", "thread" => "100");
+        info!(log, "passing"; "tenant" => "other", "thread" => "100");
+        info!(log, "not passing"; "tenant" => "other", "thread" => "200");
+        info!(log, "passing"; "thread" => "100");
+
+        assert_eq!(drain.count(), 4);
+    }
+
+    #[test]
+    /// traced correlation ids bypassing the filtering until they expire
+    fn tracedfilter() {
+        let drain = CountingDrain::new();
+        let clock = ManualClock::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Warning)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .clock(Arc::new(clock.clone()));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        handle.trace("request_id", "abc123", Duration::from_secs(3600));
+        debug!(log, "passing"; "request_id" => "abc123");
+        debug!(log.new(o!("request_id" => "abc123")), "passing from the context");
+        debug!(log, "not passing"; "request_id" => "def456");
+        clock.advance(Duration::from_secs(3600));
+        debug!(log, "not passing, expired"; "request_id" => "abc123");
+
+        handle.trace("request_id", "def456", Duration::from_secs(60));
+        assert!(handle.untrace("request_id", "def456"));
+        debug!(log, "not passing, untraced"; "request_id" => "def456");
+
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// sessions traced once a record of theirs passes
+    fn stickyfilter() {
+        let drain = CountingDrain::new();
+        let clock = ManualClock::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Debug)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .sticky_on_key("session_id", Duration::from_secs(60))
+            .clock(Arc::new(clock.clone()));
+        let log = Logger::root(filter.fuse(), o!());
+        let session = log.new(o!("session_id" => "s1"));
+
+        debug!(session, "not passing, session not traced yet");
+        error!(session, "passing unfiltered, traces the session");
+        debug!(session, "passing");
+        clock.advance(Duration::from_secs(30));
+        debug!(session, "passing, doesn't prolong the tracing");
+        debug!(log, "not passing, other session"; "session_id" => "s2");
+        clock.advance(Duration::from_secs(30));
+        debug!(session, "not passing, expired");
+        debug!(session, "passing, traces the session again"; "thread" => "100");
+        debug!(session, "passing");
+
+        assert_eq!(drain.count(), 5);
+    }
+
+    #[test]
+    /// rules enabled for a while, or for a number of records, once a trigger fires
+    fn triggerfilter() {
+        let drain = CountingDrain::new();
+        let clock = ManualClock::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .trigger(
+                Trigger::on("subsystem", "db", Level::Error)
+                    .enable(Rule::pass("subsystem", "db", Level::Debug))
+                    .for_duration(Duration::from_secs(120)),
+            )
+            .trigger(
+                Trigger::on("subsystem", "net", Level::Warning)
+                    .enable(Rule::pass("subsystem", "net", Level::Debug))
+                    .for_records(2),
+            )
+            .clock(Arc::new(clock.clone()));
+        let log = Logger::root(filter.fuse(), o!());
+        let db = log.new(o!("subsystem" => "db"));
+        let net = log.new(o!("subsystem" => "net"));
+
+        debug!(db, "not passing, not triggered yet");
+        info!(db, "not passing, below the trigger level");
+        error!(db, "passing unfiltered, triggers");
+        debug!(db, "passing");
+        debug!(net, "not passing, other subsystem");
+        clock.advance(Duration::from_secs(60));
+        error!(db, "passing unfiltered, doesn't prolong the rules");
+        clock.advance(Duration::from_secs(60));
+        debug!(db, "not passing, expired");
+
+        warn!(net, "passing unfiltered, triggers");
+        debug!(net, "passing");
+        debug!(net, "passing");
+        debug!(net, "not passing, used up");
+
+        assert_eq!(drain.count(), 6);
+    }
+
+    #[test]
+    /// suppression off for a while, or for a number of records, after an error passes
+    fn passthroughfilter() {
+        let drain = CountingDrain::new();
+        let clock = ManualClock::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Debug)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .pass_through_after(Level::Error, Window::Duration(Duration::from_secs(10)))
+            .clock(Arc::new(clock.clone()));
+        let log = Logger::root(filter.fuse(), o!());
+
+        debug!(log, "not passing, window closed");
+        warn!(log, "passing unfiltered, below the window level");
+        debug!(log, "not passing, window closed");
+        error!(log, "passing unfiltered, opens the window");
+        debug!(log, "passing, doesn't renew the window");
+        clock.advance(Duration::from_secs(10));
+        debug!(log, "not passing, window closed");
+
+        assert_eq!(drain.count(), 3);
+
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .pass_through_after(Level::Info, Window::Records(2));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing, opens the window"; "thread" => "100");
+        info!(log, "passing");
+        debug!(log, "not passing, below the minimum level");
+        info!(log, "passing");
+        info!(log, "not passing, window closed");
+        warn!(log, "passing unfiltered, opens the window");
+        info!(log, "passing");
+
+        assert_eq!(drain.count(), 5);
+    }
+
+    #[test]
+    /// the number of suppressed records, and their most frequent keys, attached to the
+    /// next passing record
+    fn summaryfilter() {
+        let drain = CapturingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .summarize_suppressed(2);
+        let log = Logger::root(filter.fuse(), o!());
+
+        warn!(log, "passing unfiltered, nothing suppressed");
+        info!(log, "not passing"; "thread" => "200", "direction" => "send");
+        info!(log, "not passing"; "thread" => "300");
+        info!(log, "not passing"; "thread" => "300", "packet" => 1);
+        info!(log, "passing"; "thread" => "100");
+        info!(log, "passing, nothing suppressed"; "thread" => "100");
+
+        let records = drain.records();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].get("kvfilter_suppressed_since_last"), None);
+        assert_eq!(records[1].get("kvfilter_suppressed_since_last"), Some("3"));
+        assert_eq!(
+            records[1].get("kvfilter_top_suppressed_keys"),
+            Some("thread: 3, direction: 1")
+        );
+        assert_eq!(records[2].get("kvfilter_suppressed_since_last"), None);
+    }
+
+    #[test]
+    /// records capped per value of a key per window
+    fn capfilter() {
+        let drain = CountingDrain::new();
+        let clock = ManualClock::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .cap_per_value("client_ip", 2, Duration::from_secs(60))
+            .clock(Arc::new(clock.clone()));
+        let log = Logger::root(filter.fuse(), o!());
+        let client = log.new(o!("client_ip" => "10.0.0.1"));
+
+        warn!(client, "passing");
+        warn!(client, "passing");
+        warn!(client, "not passing, capped");
+        warn!(log, "passing, other client"; "client_ip" => "10.0.0.2");
+        warn!(log, "passing, not capped");
+        clock.advance(Duration::from_secs(60));
+        warn!(client, "passing, next window");
+
+        assert_eq!(drain.count(), 5);
+    }
+
+    #[test]
+    /// all or none of the records of a trace kept
+    fn samplingfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info).sample_by_key("trace_id", 0.5);
+        let log = Logger::root(filter.fuse(), o!());
+
+        let mut kept = 0;
+        for id in 0..100 {
+            let trace = log.new(o!("trace_id" => format!("trace-{}", id)));
+            let before = drain.count();
+            warn!(trace, "first");
+            warn!(trace, "second");
+            let passed = drain.count() - before;
+            assert!(passed == 0 || passed == 2);
+            kept += passed / 2;
+        }
+        assert!(kept > 25 && kept < 75, "kept {}", kept);
+
+        let before = drain.count();
+        warn!(log, "passing, not sampled");
+        assert_eq!(drain.count(), before + 1);
+    }
+
+    #[test]
+    /// values cleaned up before matching
+    fn normalizedfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("direction", vec!["send"])])))
+            .always_suppress_any(Some(filter_list(vec![("status", vec!["ignored"])])))
+            .normalize_key("direction", Normalizer::StripQuotes)
+            .normalize_key("direction", Normalizer::Trim)
+            .normalize_key("direction", Normalizer::Lowercase)
+            .normalize_key(
+                "status",
+                Normalizer::Custom(Arc::new(|value: &mut String| value.retain(|c| c != '_'))),
+            );
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "direction" => "send");
+        info!(log, "passing"; "direction" => " SEND ");
+        info!(log, "passing"; "direction" => "\" Send\"");
+        info!(log, "not passing"; "direction" => "receive");
+        info!(log, "not passing"; "direction" => "send", "status" => "IGN_ORED".to_lowercase());
+
+        assert_eq!(drain.count(), 3);
+    }
+
+    #[test]
+    /// values compared caselessly, beyond ASCII
+    fn casefoldfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("street", vec!["Hauptstraße", "Σίσυφος"])])))
+            .normalize_key("street", Normalizer::CaseFold);
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "street" => "HAUPTSTRASSE");
+        info!(log, "passing"; "street" => "hauptstrasse");
+        info!(log, "passing"; "street" => "ΣΊΣΥΦΟΣ");
+        info!(log, "not passing"; "street" => "Nebenstraße");
+
+        assert_eq!(drain.count(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-normalization")]
+    /// values compared in a Unicode normalization form, however they're composed
+    fn unicodenormalizedfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![
+                ("city", vec!["Z\u{fc}rich"]),
+                ("file", vec!["profile"]),
+            ])))
+            .normalize_key("city", Normalizer::Nfc)
+            .normalize_key("city", Normalizer::CaseFold)
+            .normalize_key("file", Normalizer::Nfkc);
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing, precomposed"; "city" => "Z\u{fc}rich", "file" => "profile");
+        info!(log, "passing, decomposed"; "city" => "Zu\u{308}rich", "file" => "profile");
+        info!(log, "passing, decomposed and uppercase"; "city" => "ZU\u{308}RICH", "file" => "profile");
+        info!(log, "passing, ligature"; "city" => "Z\u{fc}rich", "file" => "pro\u{fb01}le");
+        info!(log, "not passing"; "city" => "Zurich", "file" => "profile");
+        info!(log, "not passing, not compatibility composed"; "city" => "Zu\u{308}rich", "file" => "pro\u{fb02}le");
+
+        assert_eq!(drain.count(), 4);
+        assert_eq!(format!("{:?}", Normalizer::Nfkc), "Nfkc");
+    }
+
+    #[test]
+    /// records with too many key values, or too long values, are decided by the fallback
+    fn budgetfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .evaluation_budget(4, 16, Fallback::Suppress);
+        let log = Logger::root(filter.fuse(), o!("version" => "1.0"));
+
+        info!(log, "passing"; "a" => 1, "b" => 2, "thread" => "100");
+        info!(log, "not passing, over the pairs budget"; "a" => 1, "b" => 2, "c" => 3, "d" => 4, "thread" => "100");
+        info!(log, "not passing, over the bytes budget"; "thread" => "x".repeat(17));
+        info!(log, "passing, matched before the budget ran out"; "thread" => "100", "a" => 1, "b" => 2, "c" => 3, "d" => 4);
+
+        let passing = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .evaluation_budget(2, 1024, Fallback::Pass);
+        let log = Logger::root(passing.fuse(), o!());
+        info!(log, "passing, over the pairs budget"; "a" => 1, "b" => 2, "thread" => "200");
+        info!(log, "not passing"; "thread" => "200");
+
+        assert_eq!(drain.count(), 3);
+    }
+
+    #[test]
+    /// the outcomes of the patterns parsing values are memoized by value
+    fn memoizedfilter() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_pattern_on_all_keys(Some(
+                vec![("client_version", vec![ValuePattern::Version(">= 2.3.0".parse().unwrap())])]
+                    .into_iter()
+                    .collect(),
+            ))
+            .memoize_matches(64, MemoPolicy::Lru);
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        for _ in 0..3 {
+            info!(log, "passing"; "client_version" => "2.4.0");
+            info!(log, "not passing"; "client_version" => "2.2.9");
+        }
+        info!(log, "not passing, not a version"; "client_version" => "unknown");
+        info!(log, "not passing, not a version"; "client_version" => "unknown");
+        assert_eq!(drain.count(), 3);
+        let memo = handle.stats().memo;
+        assert_eq!((memo.hits, memo.misses, memo.entries), (5, 3, 3));
+        assert_eq!(handle.stats().match_errors, 2);
+
+        handle.update_spec(|filter| {
+            filter.only_pass_any_pattern_on_all_keys(Some(
+                vec![("client_version", vec![ValuePattern::Version(">= 2.0.0".parse().unwrap())])]
+                    .into_iter()
+                    .collect(),
+            ))
+        });
+        assert_eq!(handle.stats().memo, MemoStats::default());
+        info!(log, "passing"; "client_version" => "2.2.9");
+        assert_eq!(drain.count(), 4);
+    }
+
+    #[test]
+    /// records of the remapped values are decided and forwarded at their translated levels
+    fn levelmapfilter() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Debug)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("subsystem", vec!["chatty_dep", "app", "db", "metrics"])])))
+            .remap_levels(
+                "subsystem",
+                "chatty_dep",
+                LevelMap::new()
+                    .suppress(Level::Trace)
+                    .map(Level::Debug, Level::Trace)
+                    .map(Level::Info, Level::Debug),
+            )
+            .remap_levels("subsystem", "db", LevelMap::new().map(Level::Debug, Level::Info))
+            .remap_levels("subsystem", "metrics", LevelMap::new().suppress(Level::Info));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+        let chatty = log.new(o!("subsystem" => "chatty_dep"));
+
+        info!(chatty, "passing, at debug");
+        debug!(chatty, "not passing, at trace");
+        info!(log, "not passing, suppressed"; "subsystem" => "metrics");
+        warn!(chatty, "passing, at warning");
+        debug!(log, "passing, at debug"; "subsystem" => "app");
+        debug!(log, "passing, at info"; "subsystem" => "db");
+        handle.set_min_level(Level::Info);
+        debug!(log, "not passing, at debug"; "subsystem" => "app");
+        debug!(log, "passing, at info"; "subsystem" => "db");
+        let levels: Vec<_> = drain.records().iter().map(|r| (r.message.clone(), r.level)).collect();
+        assert_eq!(
+            levels,
+            vec![
+                ("passing, at debug".to_owned(), Level::Debug),
+                ("passing, at warning".to_owned(), Level::Warning),
+                ("passing, at debug".to_owned(), Level::Debug),
+                ("passing, at info".to_owned(), Level::Info),
+                ("passing, at info".to_owned(), Level::Info),
+            ]
+        );
+        assert_eq!(handle.stats().suppressed, 3);
+    }
+
+    #[test]
+    /// the map of a record is found in the pass matching its key values, which are matched at
+    /// each level the record may be forwarded at
+    fn levelmappass() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let lazy = || {
+            let computed = computed.clone();
+            slog::FnValue(move |_: &Record| {
+                computed.fetch_add(1, Ordering::Relaxed);
+                "lazy"
+            })
+        };
+
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Debug)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .filter_key_on_levels("tenant", Level::Debug..=Level::Trace)
+            .remap_levels("subsystem", "chatty", LevelMap::new().map(Level::Info, Level::Debug));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "not passing, tenant needed at debug"; "subsystem" => "chatty", "lazy" => lazy());
+        info!(log, "passing, at debug"; "tenant" => "acme", "subsystem" => "chatty", "lazy" => lazy());
+        info!(log, "passing, tenant not needed at info"; "lazy" => lazy());
+        // once for each record, and once more for each passed when the drain captures it
+        assert_eq!(computed.load(Ordering::Relaxed), 3 + 2);
+        let levels: Vec<_> = drain.records().iter().map(|r| (r.message.clone(), r.level)).collect();
+        assert_eq!(
+            levels,
+            vec![
+                ("passing, at debug".to_owned(), Level::Debug),
+                ("passing, tenant not needed at info".to_owned(), Level::Info),
+            ]
+        );
+    }
+
+    #[test]
+    /// values the matchers can't evaluate are decided by the policy, and counted
+    fn matcherrorfilter() {
+        let decide = |policy: MatchErrorPolicy| -> (usize, u64) {
+            let drain = CountingDrain::new();
+            let filter = KVFilter::new(drain.clone(), Level::Info)
+                .only_pass_any_pattern_on_all_keys(Some(
+                    vec![("client_version", vec![ValuePattern::Version(">= 2.3.0".parse().unwrap())])]
+                        .into_iter()
+                        .collect(),
+                ))
+                .always_suppress_any_pattern(Some(
+                    vec![("client_ip", vec![ValuePattern::Cidr("10.0.0.0/8".parse().unwrap())])]
+                        .into_iter()
+                        .collect(),
+                ))
+                .on_match_error(policy);
+            let handle = filter.handle();
+            let log = Logger::root(filter.fuse(), o!());
+
+            info!(log, "passing"; "client_version" => "2.4.0", "client_ip" => "8.8.8.8");
+            info!(log, "not a version"; "client_version" => "unknown");
+            info!(log, "not an address"; "client_version" => "2.4.0", "client_ip" => "localhost");
+
+            (drain.count(), handle.stats().match_errors)
+        };
+
+        assert_eq!(decide(MatchErrorPolicy::Unmatched), (2, 2));
+        assert_eq!(decide(MatchErrorPolicy::Matched), (2, 2));
+        assert_eq!(decide(MatchErrorPolicy::Suppress), (1, 2));
+    }
+
+    #[test]
+    /// records with oversized key values are forwarded as a stub, or truncated
+    fn oversizedfilter() {
+        let drain = CapturingDrain::new();
+        let huge = "x".repeat(1000);
+
+        let filter = KVFilter::new(drain.clone(), Level::Info).guard_oversized(64, OversizePolicy::Suppress);
+        let log = Logger::root(filter.fuse(), o!("version" => "1.0"));
+        info!(log, "small"; "thread" => "100");
+        info!(log, "huge"; "thread" => "100", "payload" => &huge);
+
+        let filter = KVFilter::new(drain.clone(), Level::Info).guard_oversized(64, OversizePolicy::Truncate);
+        let log = Logger::root(filter.fuse(), o!("version" => "1.0"));
+        info!(log, "truncated"; "thread" => "100", "payload" => &huge, "direction" => "send");
+
+        let records = drain.records();
+        assert_eq!(records.len(), 3);
+        assert!(records[0].has_all(&[("thread", "100"), ("version", "1.0")]));
+        assert_eq!(records[1].message, "huge");
+        assert_eq!(records[1].key_values, vec![("kvfilter_oversized_bytes".to_owned(), "1026".to_owned())]);
+
+        // slog serializes the key values of a record last to first
+        assert_eq!(records[2].get("direction"), Some("send"));
+        // 64 bytes less "direction", "send" and "payload"
+        assert_eq!(records[2].get("payload").map(str::len), Some(44));
+        assert_eq!(records[2].get("thread"), None);
+        assert_eq!(records[2].get("version"), None);
+        assert_eq!(records[2].get("kvfilter_truncated_bytes"), Some("1039"));
+    }
+
+    #[test]
+    /// records matching routing rules sent to their destination drains instead
+    fn routedfilter() {
+        let drain = CapturingDrain::new();
+        let audit = CapturingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .route_to("audit", audit.clone())
+            .honor_force_key();
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        handle.add_rule(Rule::pass("user", "admin", Level::Debug).to("audit"), None);
+        handle.add_rule(Rule::pass("user", "guest", Level::Info).to("unregistered"), None);
+        info!(log, "routed"; "user" => "admin");
+        debug!(log, "routed"; "user" => "admin", "thread" => "100");
+        trace!(log, "not routed, below the rule level"; "user" => "admin");
+        info!(log, "passing"; "thread" => "100");
+        info!(log, "not passing"; "thread" => "200");
+        info!(log, "passing, destination not registered"; "user" => "guest", "thread" => "100");
+        info!(log, "not passing, destination not registered"; "user" => "guest");
+        info!(log, "not routed, forced"; "user" => "admin", FORCE_KEY => "drop");
+        info!(log, "passing, forced rather than routed"; "user" => "admin", FORCE_KEY => "pass");
+
+        assert_eq!(audit.messages(), vec!["routed", "routed"]);
+        assert_eq!(
+            drain.messages(),
+            vec!["passing", "passing, destination not registered", "passing, forced rather than routed"]
+        );
+    }
+
+    #[test]
+    /// records and rule matches counted across the filters sharing a handle
+    fn statsfilter() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .route_to("audit", CountingDrain::new());
+        let handle = filter.handle();
+        let other = KVFilter::new(drain.clone(), Level::Info).controlled_by(&handle);
+        let log = Logger::root(filter.fuse(), o!());
+        let other_log = Logger::root(other.fuse(), o!());
+
+        let tenant = handle.add_rule(Rule::pass("tenant", "acme", Level::Info), None);
+        let admin = handle.add_rule(Rule::pass("user", "admin", Level::Info).to("audit"), None);
+        info!(log, "passing"; "thread" => "100");
+        info!(log, "passing"; "tenant" => "acme");
+        info!(other_log, "passing"; "tenant" => "acme");
+        info!(log, "not passing"; "thread" => "200");
+        info!(log, "routed"; "user" => "admin");
+
+        let stats = handle.stats();
+        assert_eq!((stats.passed, stats.suppressed, stats.routed), (3, 1, 1));
+        let matched: Vec<_> = stats.rules.iter().map(|&(id, _, count)| (id, count)).collect();
+        assert_eq!(matched, vec![(tenant, 2), (admin, 1)]);
+    }
+
+    #[test]
+    /// values of several keys passed and suppressed as combinations, not each by itself
+    fn combinationfilter() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_combination(vec!["method", "status"], vec![vec!["POST", "500"], vec!["GET", "403"]])
+            .always_suppress_any_combination(vec!["method", "path"], vec![vec!["POST", "/health"]]);
+        let log = Logger::root(filter.fuse(), o!("method" => "GET"));
+
+        info!(log, "passing"; "status" => 403);
+        info!(log, "not passing, GET with 500"; "status" => 500);
+        info!(log, "passing"; "method" => "POST", "status" => 500);
+        info!(log, "not passing, status missing"; "method" => "POST");
+        info!(log, "not passing, suppressed"; "method" => "POST", "status" => 500, "path" => "/health");
+        info!(log.new(o!("path" => "/health")), "passing, GET"; "status" => 403);
+
+        assert_eq!(drain.messages(), vec!["passing", "passing", "passing, GET"]);
+    }
+
+    #[test]
+    /// records with `FORCE_KEY` decide for themselves, and are forwarded without it
+    fn forcefilter() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .honor_force_key();
+        let _suppressing = filter.scoped_override(ScopedOverride::Suppress);
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing, forced"; FORCE_KEY => "pass", "tenant" => "globex");
+        info!(log, "not passing, forced"; FORCE_KEY => "drop", "tenant" => "acme");
+        info!(log, "not passing, not forcing"; FORCE_KEY => "maybe", "tenant" => "acme");
+        info!(log.new(o!(FORCE_KEY => "pass")), "not passing, forced in the context only"; "tenant" => "globex");
+
+        assert_eq!(drain.messages(), vec!["passing, forced"]);
+        let forced = &drain.records()[0];
+        assert_eq!(forced.get("tenant"), Some("globex"));
+        assert_eq!(forced.get(FORCE_KEY), None);
+    }
+
+    #[test]
+    /// lazy values are computed, and values formatted, only as far as the decision needs
+    fn lazyvalues() {
+        struct Costly(Arc<AtomicUsize>);
+
+        impl Display for Costly {
+            fn fmt(&self, f: &mut Formatter) -> FmtResult {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                f.write_str("costly")
+            }
+        }
+
+        let computed = Arc::new(AtomicUsize::new(0));
+        let formatted = Arc::new(AtomicUsize::new(0));
+        let lazy = || {
+            let computed = computed.clone();
+            slog::FnValue(move |_: &Record| {
+                computed.fetch_add(1, Ordering::Relaxed);
+                "lazy"
+            })
+        };
+        let counts = || (computed.swap(0, Ordering::Relaxed), formatted.swap(0, Ordering::Relaxed));
+
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])])));
+        let log = Logger::root(filter.fuse(), o!("lazy" => lazy()));
+
+        debug!(log, "not passing, below the level"; "lazy" => lazy(), "costly" => %Costly(formatted.clone()));
+        assert_eq!(counts(), (0, 0));
+        info!(log, "passing"; "tenant" => "acme", "costly" => %Costly(formatted.clone()));
+        assert_eq!(counts(), (1, 0));
+        info!(log, "not passing, suppressed on the record"; "lazy" => lazy(), "deepcomp" => 1);
+        assert_eq!(counts(), (0, 0));
+        assert_eq!(drain.count(), 1);
+
+        let drain = CountingDrain::new();
+        let filter = || {
+            KVFilter::new(drain.clone(), Level::Info)
+                .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+        };
+        let log = Logger::root(filter().fuse(), o!());
+        info!(log, "passing"; "lazy" => lazy(), "tenant" => "acme");
+        assert_eq!(counts(), (0, 0));
+        let log = Logger::root(filter().honor_force_key().fuse(), o!());
+        info!(log, "passing"; "lazy" => lazy(), "tenant" => "acme");
+        assert_eq!(counts(), (1, 0));
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    /// records failing to format or to serialize, and specs at the limits, are decided
+    /// without panicking
+    fn hostileinput() {
+        struct FailingDisplay;
+
+        impl Display for FailingDisplay {
+            fn fmt(&self, _: &mut Formatter) -> FmtResult {
+                Err(::std::fmt::Error)
+            }
+        }
+
+        /// value failing to format if `true`, to serialize otherwise
+        struct Failing(bool);
+
+        impl slog::Value for Failing {
+            fn serialize(&self, _: &Record, key: slog::Key, serializer: &mut dyn slog::Serializer) -> slog::Result {
+                if self.0 {
+                    serializer.emit_arguments(key, &format_args!("{}", FailingDisplay))
+                } else {
+                    Err(slog::Error::Other)
+                }
+            }
+        }
+
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])])))
+            .only_pass_on_regex(Regex::new("passing").unwrap())
+            .cap_per_value("client_ip", 1, Duration::MAX)
+            .pass_through_after(Level::Error, Window::Duration(Duration::MAX))
+            .honor_force_key();
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "tenant" => "acme", "deepcomp" => Failing(true));
+        info!(log, "passing"; "deepcomp" => Failing(false), "tenant" => "acme");
+        info!(log, "{}", FailingDisplay; "tenant" => "acme");
+        info!(log, "not passing"; FORCE_KEY => Failing(true), "tenant" => "globex");
+        info!(log, "not passing"; "tenant" => "a".repeat(1 << 20));
+        assert_eq!(drain.count(), 2);
+        assert_eq!(handle.stats().match_errors, 3);
+
+        handle.add_rule(Rule::pass("user", "admin", Level::Info), Some(Duration::MAX));
+        handle.trace("user", "root", Duration::MAX);
+        info!(log, "passing, by the rule"; "user" => "admin", "client_ip" => "10.0.0.1");
+        info!(log, "passing, traced"; "user" => "root");
+        info!(log, "not passing, capped"; "user" => "admin", "client_ip" => "10.0.0.1");
+        error!(log, "passing, opening the window");
+        info!(log, "passing, in the window");
+        assert_eq!(drain.count(), 6);
+
+        let clock = ManualClock::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .clock(Arc::new(clock.clone()));
+        filter.handle().add_rule(Rule::pass("user", "admin", Level::Info), Some(Duration::MAX));
+        clock.advance(Duration::MAX);
+        clock.advance(Duration::MAX);
+        let log = Logger::root(super::NonBlocking::new(filter, 1, super::OverflowPolicy::Block).unwrap().fuse(), o!());
+        info!(log, "not passing, the rule expired"; "user" => "admin");
+        drop(log);
+        assert_eq!(drain.count(), 6);
+    }
+
+    #[test]
+    /// a spec of a single key is decided by its own serializer as by the general one
+    fn singlekeyfilter() {
+        let decide = || {
+            let drain = CapturingDrain::new();
+            let filter = KVFilter::new(drain.clone(), Level::Info)
+                .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme", "42"])])))
+                .only_pass_on_regex(Regex::new(r"passing").unwrap());
+            assert!(filter.spec().compiled.single_key().is_some());
+            let log = Logger::root(filter.fuse(), o!("host" => "web1"));
+
+            info!(log, "passing"; "tenant" => "acme");
+            info!(log, "not passing"; "tenant" => "globex");
+            info!(log, "passing, a number"; "tenant" => 42);
+            info!(log, "passing, any occurrence"; "tenant" => "globex", "tenant" => "acme");
+            info!(log.new(o!("tenant" => "acme")), "passing, in the context"; "user" => "bob");
+            info!(log, "not passing, key missing"; "user" => "bob");
+            info!(log, "filtered by the message"; "tenant" => "acme");
+            warn!(log, "passing, above the level"; "tenant" => "globex");
+            drain.messages()
+        };
+
+        assert_eq!(
+            decide(),
+            vec![
+                "passing",
+                "passing, a number",
+                "passing, any occurrence",
+                "passing, in the context",
+                "passing, above the level"
+            ]
+        );
+    }
+
+    #[test]
+    /// key values of the most suppressed records reported, closest to the record first
+    fn topsuppressedfilter() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .always_suppress_any(Some(filter_list(vec![("tenant", vec!["bigcorp"])])))
+            .track_top_suppressed(2);
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!("host" => "web1"));
+        let bigcorp = log.new(o!("tenant" => "bigcorp"));
+
+        for i in 0..9 {
+            info!(bigcorp, "not passing"; "request_id" => i);
+        }
+        info!(log, "not passing"; "tenant" => "bigcorp", "region" => "eu");
+        info!(log, "passing"; "tenant" => "acme");
+
+        let stats = handle.stats();
+        assert_eq!(stats.suppressed, 10);
+        let top: Vec<_> = stats
+            .top_suppressed
+            .iter()
+            .map(|top| (top.key.as_str(), top.value.as_str(), top.suppressed))
+            .collect();
+        assert_eq!(top, vec![("host", "web1", 10), ("tenant", "bigcorp", 10)]);
+    }
+
+    #[test]
+    /// filters passing nothing for a while reported once until a record passes
+    fn starvingfilter() {
+        let drain = CapturingDrain::new();
+        let clock = ManualClock::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .clock(Arc::new(clock.clone()))
+            .watch_starvation(Duration::from_secs(60), StarvationAction::Warn);
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "not passing"; "thread" => "200");
+        clock.advance(Duration::from_secs(60));
+        info!(log, "not passing, revealing the starvation"; "thread" => "200");
+        info!(log, "not passing, reported already"; "thread" => "200");
+        info!(log, "passing"; "thread" => "100");
+        clock.advance(Duration::from_secs(30));
+        info!(log, "not passing, passed recently"; "thread" => "200");
+
+        let records = drain.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].level, Level::Warning);
+        assert_eq!(records[0].get("kvfilter_suppressed_meanwhile"), Some("2"));
+        assert_eq!(records[0].get("kvfilter_silent_for"), Some("60s"));
+        assert_eq!(records[1].message, "passing");
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let starvations = reported.clone();
+        let filter = KVFilter::new(CountingDrain::new(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .clock(Arc::new(clock.clone()))
+            .watch_starvation(
+                Duration::from_secs(60),
+                StarvationAction::Callback(Arc::new(move |starvation| {
+                    starvations.lock().unwrap().push(starvation.suppressed)
+                })),
+            );
+        let log = Logger::root(filter.fuse(), o!());
+        for _ in 0..3 {
+            info!(log, "not passing"; "thread" => "200");
+            clock.advance(Duration::from_secs(30));
+        }
+        assert_eq!(*reported.lock().unwrap(), vec![3]);
+    }
+
+    #[test]
+    /// all the records passed in learning mode, their keys and values sampled
+    fn learningfilter() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .min_level(Level::Info)
+            .learn(2);
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!("version" => "1.0"));
+
+        debug!(log, "passing"; "thread" => "100");
+        info!(log, "passing"; "thread" => "200", "tenant" => "acme");
+        info!(log, "passing"; "thread" => "300", "tenant" => "acme");
+        info!(log.new(o!("thread" => "400")), "passing"; "thread" => "200");
+        assert_eq!(drain.count(), 4);
+
+        let learned = handle.learned();
+        assert_eq!(learned.records, 4);
+        assert_eq!(learned.keys["version"].values.len(), 1);
+        let thread = &learned.keys["thread"];
+        assert_eq!(thread.records, 4);
+        assert_eq!(thread.values.iter().map(|(v, &n)| (v.as_str(), n)).collect::<Vec<_>>(), vec![("100", 1), ("200", 2)]);
+        assert_eq!(thread.other, 1);
+        assert!(learned
+            .to_string()
+            .contains("  thread: 4 records, 100 (1), 200 (2), other values (1)\n"));
+    }
+
+    #[test]
+    /// traced values of a key bounded, the least recently used dropped and counted
+    fn tracedperkeyfilter() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .trace_capacity_per_key(2);
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        let hour = Duration::from_secs(3600);
+        handle.trace("session_id", "a", hour);
+        handle.trace("session_id", "b", hour);
+        info!(log, "passing"; "session_id" => "a");
+        handle.trace("session_id", "c", hour);
+        info!(log, "passing"; "session_id" => "a");
+        info!(log, "not passing, evicted"; "session_id" => "b");
+        info!(log, "passing"; "session_id" => "c");
+
+        assert_eq!(drain.count(), 3);
+        assert_eq!(handle.stats().trace_evictions, 1);
+    }
+
+    #[test]
+    /// filter tightened while passing too many records a second, relaxed afterwards
+    fn overloadfilter() {
+        let drain = CapturingDrain::new();
+        let clock = ManualClock::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .clock(Arc::new(clock.clone()))
+            .protect_overload(5, Tightening::MinLevel(Level::Warning));
+        let log = Logger::root(filter.fuse(), o!());
+
+        for _ in 0..10 {
+            info!(log, "passing");
+            clock.advance(Duration::from_millis(100));
+        }
+        info!(log, "not passing, overloaded");
+        warn!(log, "passing, overloaded");
+        clock.advance(Duration::from_secs(2));
+        info!(log, "passing, relaxed");
+
+        assert_eq!(drain.records_with("kvfilter_pass_rate", "10").len(), 1);
+        assert_eq!(drain.records_with("kvfilter_pass_rate", "1").len(), 1);
+        let messages = drain.messages();
+        assert_eq!(messages.len(), 14);
+        assert_eq!(messages[10], "log volume above the limit, filter tightened");
+        assert_eq!(messages[11..], ["passing, overloaded", "log volume back within the limit, filter relaxed", "passing, relaxed"]);
+
+        // tightened by a stricter spec
+        let strict = KVFilter::new(slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .spec();
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .clock(Arc::new(clock.clone()))
+            .protect_overload(5, Tightening::Spec(strict));
+        let log = Logger::root(filter.fuse(), o!());
+        for _ in 0..10 {
+            info!(log, "passing"; "thread" => "200");
+            clock.advance(Duration::from_millis(100));
+        }
+        info!(log, "not passing, overloaded"; "thread" => "200");
+        info!(log, "passing, overloaded"; "thread" => "100");
+        assert_eq!(drain.count(), 12);
+    }
+
+    #[test]
+    /// records the filtered drain fails to log go to the fallback drain
+    fn fallbackfilter() {
+        struct FailingDrain;
+
+        impl Drain for FailingDrain {
+            type Ok = ();
+            type Err = io::Error;
+
+            fn log(&self, _: &Record, _: &OwnedKVList) -> io::Result<()> {
+                Err(io::Error::other("connection reset"))
+            }
+        }
+
+        let fallback = CountingDrain::new();
+        let filter = KVFilter::new(FailingDrain, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .fallback_on_error(fallback.clone());
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing, to the fallback"; "thread" => "100");
+        info!(log, "not passing"; "thread" => "200");
+
+        assert_eq!(fallback.count(), 1);
+        assert_eq!(handle.stats().drain_errors, 1);
+    }
+
+    #[test]
+    /// a suppressed record doesn't go to the fallback drain when logging the audit trail
+    /// ahead of it fails
+    fn fallbackauditfilter() {
+        struct FailingDrain;
+
+        impl Drain for FailingDrain {
+            type Ok = ();
+            type Err = io::Error;
+
+            fn log(&self, _: &Record, _: &OwnedKVList) -> io::Result<()> {
+                Err(io::Error::other("connection reset"))
+            }
+        }
+
+        let fallback = CountingDrain::new();
+        let filter = KVFilter::new(FailingDrain, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .log_audit_trail()
+            .fallback_on_error(fallback.clone());
+        let handle = filter.handle();
+        let filter = filter.deciding();
+
+        handle.set_min_level(Level::Debug);
+        let decision = filter.log(
+            &record!(Level::Info, "", &format_args!("not passing"), b!("thread" => "200")),
+            &OwnedKVList::from(o!()),
+        );
+
+        assert_eq!(decision.unwrap(), Decision::SuppressedByKV);
+        assert_eq!(fallback.count(), 0);
+        assert_eq!(handle.stats().drain_errors, 1);
+    }
+
+    #[test]
+    /// UUIDs matched in whatever textual form they are given and logged in
+    fn uuidfilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![(
+                "request_id",
+                vec!["{0E9C3F4A6B2D4C1E9F8A7B6C5D4E3F2A}"],
+            )])))
+            .normalize_key("request_id", Normalizer::Uuid);
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "request_id" => "0e9c3f4a-6b2d-4c1e-9f8a-7b6c5d4e3f2a");
+        info!(log, "passing"; "request_id" => "0E9C3F4A6B2D4C1E9F8A7B6C5D4E3F2A");
+        info!(log, "passing"; "request_id" => "urn:uuid:0e9c3f4a-6b2d-4c1e-9f8a-7b6c5d4e3f2a");
+        info!(log, "not passing"; "request_id" => "0e9c3f4a-6b2d-4c1e-9f8a-7b6c5d4e3f2b");
+        info!(log, "not passing, not a UUID"; "request_id" => "0e9c3f4a");
+
+        assert_eq!(drain.count(), 3);
+    }
+
+    #[test]
+    /// configured key values added to the passed records
+    fn injectfilter() {
+        let drain = CapturingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("pipeline", vec!["filtered"])])))
+            .inject(o!("pipeline" => "filtered", "stage" => 2));
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "not passing, injected values aren't matched");
+        info!(log, "passing"; "pipeline" => "filtered");
 
-            (*lo).push(fmt);
+        let records = drain.records();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].has_all(&[("stage", "2")]));
+    }
 
-            Ok(())
-        }
+    #[test]
+    /// rules passing nothing beyond what earlier rules pass
+    fn shadowedrulefilter() {
+        let filter = KVFilter::new(::slog::Discard, Level::Info);
+        let handle = filter.handle();
+
+        let debug = handle.add_rule(Rule::pass("tenant", "acme", Level::Debug), None);
+        let info = handle.add_rule(Rule::pass("tenant", "acme", Level::Info), None);
+        let trace = handle.add_rule(Rule::pass("tenant", "acme", Level::Trace), None);
+        handle.add_rule(Rule::pass("tenant", "globex", Level::Info), None);
+        let expiring = handle.add_rule(Rule::pass("user", "bob", Level::Debug), Some(Duration::from_secs(60)));
+        let bob = handle.add_rule(Rule::pass("user", "bob", Level::Debug), None);
+        let expiring_later = handle.add_rule(Rule::pass("user", "bob", Level::Info), Some(Duration::from_secs(30)));
+
+        assert_eq!(
+            handle.shadowed_rules(),
+            vec![(debug, trace), (info, debug), (expiring, bob), (expiring_later, expiring)]
+        );
+
+        handle.remove_rule(debug);
+        handle.remove_rule(expiring);
+        assert_eq!(handle.shadowed_rules(), vec![(info, trace), (expiring_later, bob)]);
+
+        let again = handle.add_rule(Rule::pass("tenant", "acme", Level::Trace), None);
+        assert_eq!(handle.shadowed_rules(), vec![(info, trace), (expiring_later, bob), (again, trace)]);
     }
 
-    impl<'a> Display for StringDrain {
-        fn fmt(&self, f: &mut Formatter) -> FmtResult {
-            write!(f, "none")
-        }
+    #[test]
+    /// rules applied only in some environments
+    fn conditionalrulefilter() {
+        let drain = CountingDrain::new();
+
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+        let handle = filter.handle();
+        handle.add_rule(
+            Rule::pass("tenant", "acme", Level::Info).when(Condition::Env("KVFILTER_UNSET_VAR".into())),
+            None,
+        );
+        handle.add_rule(
+            Rule::pass("tenant", "globex", Level::Info).when(Condition::DebugAssertions),
+            None,
+        );
+        assert_eq!(handle.rules().len(), if cfg!(debug_assertions) { 1 } else { 0 });
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "not passing, variable not set"; "tenant" => "acme");
+        info!(log, "passing in debug builds"; "tenant" => "globex");
+
+        assert_eq!(drain.count(), if cfg!(debug_assertions) { 1 } else { 0 });
     }
 
-    fn testkvfilter<D: Drain>(d: D) -> KVFilter<D> {
-        KVFilter::new(d, Level::Info).only_pass_any_on_all_keys(Some(
+    #[test]
+    /// suppression only when all the keys match
+    fn suppressallfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testkvfilter(drain).always_suppress_all_with(Some(
             vec![
-                (
-                    "thread".to_string(),
-                    HashSet::from_iter(vec!["100".to_string(), "200".to_string()]),
-                ),
-                (
-                    "direction".to_string(),
-                    HashSet::from_iter(vec!["send".to_string(), "receive".to_string()]),
-                ),
+                ("deepcomp", HashSet::from_iter(vec!["1", "2"])),
+                ("direction", HashSet::from_iter(vec!["send"])),
             ].into_iter()
-                .collect(),
-        ))
+                .collect::<KVFilterList<_, _>>(),
+        ));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "100", "deepcomp" => "1"));
+
+        info!(sublog, "YES: unfiltered, only deepcomp matches"; "direction" => "receive");
+        info!(sublog, "NO: filtered, both keys match"; "direction" => "send");
+        info!(mainlog, "YES: unfiltered, only direction matches";
+        "thread" => "100", "direction" => "send");
+
+        assert_eq!(out.lock().unwrap().len(), 2);
     }
 
-    fn testnegkvfilter<D: Drain>(f: KVFilter<D>) -> KVFilter<D> {
-        f.always_suppress_any(Some(
-            vec![
-                (
-                    "deepcomp".to_string(),
-                    HashSet::from_iter(vec!["1".to_string(), "2".to_string()]),
-                ),
-                (
-                    "deepercomp".to_string(),
-                    HashSet::from_iter(vec!["4".to_string(), "5".to_string()]),
-                ),
-            ].into_iter()
-                .collect(),
-        ))
+    #[test]
+    /// groups of keys suppressing only together, mixed with keys suppressing on their own,
+    /// by combining the filters of each
+    fn mixedsuppressionfilter() {
+        let drain = CountingDrain::new();
+        let suppress_all = |filters| KVFilter::new(slog::Discard, Level::Info).always_suppress_all(Some(filters));
+        let filter = AllOf::new(drain.clone())
+            .with(suppress_all(filter_list(vec![("deepcomp", vec!["1"]), ("direction", vec!["send"])])))
+            .with(suppress_all(filter_list(vec![("thread", vec!["300"]), ("direction", vec!["receive"])])))
+            .with(KVFilter::new(slog::Discard, Level::Info)
+                .always_suppress_any(Some(filter_list(vec![("user", vec!["bot"])]))));
+        let log = Logger::root(filter.fuse(), o!("thread" => "300"));
+
+        info!(log, "not passing, the first group"; "deepcomp" => 1, "direction" => "send");
+        info!(log, "not passing, the second group"; "direction" => "receive");
+        info!(log, "not passing, by the single key"; "user" => "bot");
+        info!(log, "passing, no group complete"; "deepcomp" => 1, "direction" => "sideways");
+        warn!(log, "passing, above the filtered levels"; "user" => "bot");
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[cfg(feature = "nested-values")]
+    #[derive(Clone, Serialize)]
+    struct Headers {
+        user_agent: &'static str,
+        accept: Vec<&'static str>,
     }
 
+    #[cfg(feature = "nested-values")]
+    #[derive(Clone, Serialize)]
+    struct Request {
+        method: &'static str,
+        headers: Headers,
+    }
+
+    #[cfg(feature = "nested-values")]
     #[test]
-    /// get an asserting Drain, get a couple of loggers that
-    /// have different nodes, components and see whether filtering
-    /// is applied properly on the derived `Logger` copies
-    fn nodecomponentlogfilter() {
-        assert!(Level::Critical < Level::Warning);
+    /// nested values are matched by dotted paths
+    fn nestedkvfilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = KVFilter::new(drain, Level::Info)
+            .only_pass_any_on_all_keys_with(Some(
+                vec![(
+                    "request.headers.user_agent",
+                    HashSet::from_iter(vec!["curl"]),
+                )].into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ))
+            .always_suppress_any_with(Some(
+                vec![("request.headers.accept", HashSet::from_iter(vec!["text/html"]))]
+                    .into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        let request = |user_agent, accept| {
+            slog::Serde(Request {
+                method: "GET",
+                headers: Headers { user_agent, accept },
+            })
+        };
+
+        info!(mainlog, "YES: unfiltered, nested value matches";
+        "request" => request("curl", vec!["application/json"]));
+        info!(mainlog, "NO: filtered, nested value doesn't match";
+        "request" => request("firefox", vec!["application/json"]));
+        info!(mainlog, "NO: filtered, an element of a nested sequence suppresses";
+        "request" => request("curl", vec!["application/json", "text/html"]));
+
+        assert_eq!(out.lock().unwrap().len(), 1);
+    }
 
+    #[cfg(feature = "serde_json")]
+    #[test]
+    /// JSON valued keys are matched at JSON pointers
+    fn jsonkvfilter() {
         let out = Arc::new(Mutex::new(vec![]));
 
         let drain = StringDrain {
             output: out.clone(),
         };
 
-        // build some small filter
-        let filter = testkvfilter(drain);
+        let filter = KVFilter::new(drain, Level::Info).only_pass_any_pattern_on_all_keys(Some(
+            vec![(
+                "payload",
+                vec![
+                    ValuePattern::JsonPointer("/status".into(), "failed".into()),
+                    ValuePattern::JsonPointer("/retries".into(), "3".into()),
+                ],
+            )].into_iter()
+                .collect(),
+        ));
 
-        // Get a root logger that will log into a given drain.
         let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
-        let sublog = mainlog.new(o!("thread" => "200", "sub" => "sub"));
-        let subsublog = sublog.new(o!("direction" => "send"));
-        let subsubsublog = subsublog.new(o!());
 
-        let wrongthread = mainlog.new(o!("thread" => "400", "sub" => "sub"));
+        info!(mainlog, "YES: unfiltered, string at the pointer";
+        "payload" => r#"{"status": "failed"}"#);
+        info!(mainlog, "YES: unfiltered, number at the pointer";
+        "payload" => r#"{"status": "ok", "retries": 3}"#);
+        info!(mainlog, "NO: filtered, other string at the pointer";
+        "payload" => r#"{"status": "ok"}"#);
+        info!(mainlog, "NO: filtered, not JSON"; "payload" => "failed");
 
-        info!(mainlog, "NO: filtered, main, no keys");
-        info!(mainlog, "YES: unfiltered, on of thread matches, direction matches";
-        "thread" => "100", "direction" => "send");
-        info!(mainlog,
-              "YES: unfiltered, on of thread matches, direction matches, different key order";
-        "direction" => "send", "thread" => "100");
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
 
-        warn!(mainlog, "YES: unfiltered, higher level"); // level high enough to pass anyway
+    fn combinedoutput<F, D>(combine: F) -> usize
+    where
+        F: FnOnce(StringDrain) -> D,
+        D: Drain + Send + Sync + ::std::panic::RefUnwindSafe + ::std::panic::UnwindSafe + 'static,
+        D::Err: ::std::fmt::Debug,
+    {
+        let out = Arc::new(Mutex::new(vec![]));
 
-        debug!(mainlog, "NO: filtered, level to low, no keys"); // level low
+        let drain = combine(StringDrain {
+            output: out.clone(),
+        });
 
-        info!(mainlog, "NO: filtered, wrong thread on record";
-        "thread" => "300", "direction" => "send");
+        let mainlog = Logger::root(drain.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+        let sublog = mainlog.new(o!("thread" => "100"));
 
-        info!(wrongthread, "NO: filtered, wrong thread on sublog");
+        info!(sublog, "YES/NO: both match"; "direction" => "send");
+        info!(sublog, "YES/NO: thread matches"; "direction" => "receive");
+        info!(mainlog, "YES/NO: direction matches"; "direction" => "send");
+        info!(mainlog, "YES/NO: none matches");
 
-        info!(sublog, "NO: filtered sublog, missing dirction ");
+        let count = out.lock().unwrap().len();
+        count
+    }
 
-        info!(sublog, "YES: unfiltered sublog with added directoin";
-        "direction" => "receive");
+    #[test]
+    /// combinators over a single drain
+    fn combinatorfilter() {
+        let thread = || {
+            KVFilter::new(slog::Discard, Level::Info).only_pass_any_on_all_keys_with(Some(
+                vec![("thread", HashSet::from_iter(vec!["100"]))]
+                    .into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ))
+        };
+        let direction = || {
+            KVFilter::new(slog::Discard, Level::Info).only_pass_any_on_all_keys_with(Some(
+                vec![("direction", HashSet::from_iter(vec!["send"]))]
+                    .into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ))
+        };
 
-        info!(
-            subsubsublog,
-            "YES: unfiltered subsubsublog, direction on subsublog, thread on sublog"
+        assert_eq!(
+            combinedoutput(|d| AllOf::new(d).with(thread()).with(direction())),
+            1
+        );
+        assert_eq!(
+            combinedoutput(|d| AnyOf::new(d).with(thread()).with(direction())),
+            3
+        );
+        assert_eq!(combinedoutput(|d| NotFilter::new(d).with(thread())), 2);
+        assert_eq!(
+            combinedoutput(|d| {
+                AllOf::new(d)
+                    .with(NotFilter::new(slog::Discard).with(thread()))
+                    .with(direction())
+            }),
+            1
         );
+    }
 
-        // test twice same keyword with right value will give filter match
-        let stackedthreadslog = wrongthread.new(o!("thread" => "200"));
+    #[test]
+    /// the key values of records are matched against all the combined filters in a single
+    /// pass, which stops once they're all decided
+    fn combinatorpass() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let lazy = || {
+            let computed = computed.clone();
+            slog::FnValue(move |_: &Record| {
+                computed.fetch_add(1, Ordering::Relaxed);
+                "lazy"
+            })
+        };
+        let filter = |key, value| {
+            KVFilter::new(slog::Discard, Level::Info).only_pass_any_on_all_keys(Some(filter_list(vec![(key, vec![value])])))
+        };
 
-        info!(stackedthreadslog,
-              "YES: unfiltered since one of the threads matches from inherited";
-        "direction" => "send");
+        let drain = CountingDrain::new();
+        let all = AllOf::new(drain.clone())
+            .with(filter("tenant", "acme"))
+            .with(filter("user", "admin"))
+            .with(NotFilter::new(slog::Discard).with(filter("region", "eu")));
+        let log = Logger::root(all.fuse(), o!("tenant" => "acme"));
 
-        println!("resulting output: {:#?}", *out.lock().unwrap());
+        info!(log, "passing"; "user" => "admin", "region" => "us", "lazy" => lazy());
+        assert_eq!(computed.load(Ordering::Relaxed), 1);
+        info!(log, "not passing"; "user" => "guest", "region" => "us");
+        info!(log, "not passing"; "user" => "admin", "region" => "eu");
+        assert_eq!(drain.count(), 1);
 
-        assert_eq!(out.lock().unwrap().len(), 6);
+        let drain = CountingDrain::new();
+        let any = AnyOf::new(drain.clone())
+            .with(filter("tenant", "acme"))
+            .with(filter("user", "admin"));
+        let log = Logger::root(any.fuse(), o!());
+
+        // the key values of a record are serialized from the last one
+        info!(log, "passing, decided before the lazy value"; "lazy" => lazy(), "user" => "admin", "tenant" => "acme");
+        assert_eq!(computed.load(Ordering::Relaxed), 1);
+        info!(log, "passing"; "user" => "admin");
+        info!(log, "not passing"; "user" => "guest", "tenant" => "globex");
+        assert_eq!(drain.count(), 2);
     }
 
     #[test]
-    /// get an asserting Drain, get a couple of loggers that
-    /// have different nodes, components and deep/deeper components and see whether filtering
-    /// is applied properly on the derived `Logger` copies while punching holes for the disallowed
-    /// values
-    fn negnodecomponentlogfilter() {
-        assert!(Level::Critical < Level::Warning);
+    /// stages of a chain all have to pass, seeing the level boosted by the previous ones
+    fn chainfilter() {
+        let thread = KVFilter::new(slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys_with(Some(
+                vec![("thread", HashSet::from_iter(vec!["100"]))]
+                    .into_iter()
+                    .collect::<KVFilterList<_, _>>(),
+            ))
+            .boost_level(Some(Level::Warning));
+        let direction = KVFilter::new(slog::Discard, Level::Info).only_pass_any_on_all_keys_with(Some(
+            vec![("direction", HashSet::from_iter(vec!["send"]))]
+                .into_iter()
+                .collect::<KVFilterList<_, _>>(),
+        ));
+
+        // records boosted to warning by the first stage pass the second unfiltered
+        assert_eq!(
+            combinedoutput(|d| KVFilterChain::new(d).stage(thread).stage(direction)),
+            2
+        );
+    }
+
+    #[test]
+    /// the stages of a chain match the key values in a single pass, except for keys scoped
+    /// to levels, matched at the level the stages before boost the records to
+    fn chainpass() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let lazy = || {
+            let computed = computed.clone();
+            slog::FnValue(move |_: &Record| {
+                computed.fetch_add(1, Ordering::Relaxed);
+                "lazy"
+            })
+        };
+        let filter = |level, key, value| {
+            KVFilter::new(slog::Discard, level).only_pass_any_on_all_keys(Some(filter_list(vec![(key, vec![value])])))
+        };
+
+        let drain = CountingDrain::new();
+        let chain = KVFilterChain::new(drain.clone())
+            .stage(filter(Level::Info, "tenant", "acme").boost_level(Some(Level::Warning)))
+            .stage(filter(Level::Warning, "user", "admin").filter_key_on_levels("user", Level::Info..=Level::Info));
+        let log = Logger::root(chain.fuse(), o!());
+
+        // the key values of a record are serialized from the last one
+        info!(log, "passing, user not filtered once boosted"; "lazy" => lazy(), "user" => "guest", "tenant" => "acme");
+        info!(log, "not passing"; "user" => "admin", "tenant" => "globex");
+        assert_eq!(drain.count(), 1);
+        assert_eq!(computed.swap(0, Ordering::Relaxed), 1);
+
+        let drain = CountingDrain::new();
+        let chain = KVFilterChain::new(drain.clone())
+            .stage(filter(Level::Info, "tenant", "acme"))
+            .stage(filter(Level::Info, "user", "admin"));
+        let log = Logger::root(chain.fuse(), o!());
+
+        info!(log, "passing, decided before the lazy value"; "lazy" => lazy(), "user" => "admin", "tenant" => "acme");
+        info!(log, "not passing"; "user" => "guest", "tenant" => "acme");
+        assert_eq!(drain.count(), 1);
+        assert_eq!(computed.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    /// the builder rejects nonsense configurations
+    fn builderfilter() {
+        let builder = || KVFilterBuilder::new(slog::Discard, Level::Info);
+
+        match builder().only_pass_any("thread", Vec::<&str>::new()).build() {
+            Err(KVFilterError::EmptyValues(ref key)) if key == "thread" => {}
+            _ => panic!("empty values accepted"),
+        }
+        match builder()
+            .only_pass_any("thread", vec!["100"])
+            .only_pass_any("thread", vec!["200"])
+            .build()
+        {
+            Err(KVFilterError::DuplicateKey(ref key)) if key == "thread" => {}
+            _ => panic!("duplicate key accepted"),
+        }
+        match builder()
+            .only_pass_any("thread", vec!["100", "200"])
+            .always_suppress_any("thread", vec!["200"])
+            .build()
+        {
+            Err(KVFilterError::Conflict { ref key, ref value }) if key == "thread" && value == "200" => {}
+            _ => panic!("conflict accepted"),
+        }
+        match builder().only_pass_on_regex("(").build() {
+            Err(KVFilterError::InvalidRegex(_)) => {}
+            _ => panic!("invalid regex accepted"),
+        }
+        let sized = || {
+            builder()
+                .only_pass_any("thread", vec!["100", "200"])
+                .always_suppress_any_pattern("direction", vec![ValuePattern::Prefix("se".into())])
+        };
+        match sized().max_values_per_key(1).build() {
+            Err(KVFilterError::TooManyValues { ref key, values: 2, limit: 1 }) if key == "thread" => {}
+            _ => panic!("too many values accepted"),
+        }
+        match sized().max_values_per_key(2).max_values(2).build() {
+            Err(KVFilterError::SpecTooLarge { values: 3, limit: 2 }) => {}
+            _ => panic!("too large spec accepted"),
+        }
+        assert!(sized().max_values_per_key(2).max_values(3).build().is_ok());
 
         let out = Arc::new(Mutex::new(vec![]));
 
@@ -395,62 +5169,184 @@ mod tests {
             output: out.clone(),
         };
 
-        // build some small filter
-        let filter = testnegkvfilter(testkvfilter(drain.fuse()));
+        let filter = KVFilterBuilder::new(drain, Level::Info)
+            .only_pass_any("thread", vec!["100", "200"])
+            .only_pass_any_pattern("direction", vec![ValuePattern::Prefix("se".into())])
+            .always_suppress_any("thread", vec!["300"])
+            .always_suppress_on_regex("NO")
+            .build()
+            .unwrap();
 
-        // Get a root logger that will log into a given drain.
         let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
-        let sublog = mainlog.new(o!("thread" => "200", "sub" => "sub"));
-        let subsublog = sublog.new(o!("direction" => "send"));
-        // deep match won't match
-        let subsubsublog = subsublog.new(o!("deepcomp" => "0"));
-        // deep match will filter
-        let negsubsubsublog = subsublog.new(o!("deepcomp" => "1"));
-
-        info!(mainlog, "NO: filtered, main, no keys");
-        info!(mainlog, "YES: unfiltered, on of thread matches, direction matches";
-        "thread" => "100", "direction" => "send");
-        info!(subsubsublog, "YES: unfiltered, on of thread matches, direction matches, deep doesn't apply";
-        "thread" => "100", "direction" => "send");
-        info!(negsubsubsublog, "NO: filtered, on of thread matches, direction matches, deep negative applies";
-        "thread" => "100", "direction" => "send");
-        info!(subsubsublog, "NO: filtered, on of thread matches, direction matches, deep doesn't apply but deeper does";
-        "thread" => "100", "direction" => "send", "deepercomp" => "4");
-        info!(subsubsublog, "YES: unfiltered, on of thread matches, direction matches, deep doesn't apply and deeper doesn't";
-        "thread" => "100", "direction" => "send", "deepercomp" => "7");
 
-        println!("resulting output: {:#?}", *out.lock().unwrap());
+        info!(mainlog, "YES: unfiltered, matching keys"; "thread" => "100", "direction" => "send");
+        info!(mainlog, "NO: filtered, by regex"; "thread" => "100", "direction" => "send");
 
-        assert_eq!(out.lock().unwrap().len(), 3);
+        assert_eq!(out.lock().unwrap().len(), 1);
     }
 
     #[test]
-    /// test negative and positive
-    fn regextest() {
-        assert!(Level::Critical < Level::Warning);
+    /// the macro builds the same filter as the verbose construction
+    fn kvfiltermacro() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
 
+        let filter = KVFilter::new(drain, Level::Info).only_pass_any_on_all_keys(Some(kvfilter!{
+            "thread" => ["100", "200"],
+            "direction" => ["send".to_string(), "receive"],
+        }));
+
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        info!(mainlog, "YES: unfiltered, matching keys"; "thread" => "200", "direction" => "receive");
+        info!(mainlog, "NO: filtered, missing key"; "thread" => "200");
+
+        assert_eq!(out.lock().unwrap().len(), 1);
+        assert!(kvfilter!{}.is_empty());
+    }
+
+    #[test]
+    /// patterns from the kvfilter! macro
+    fn kvfilterpatternmacro() {
         let out = Arc::new(Mutex::new(vec![]));
 
         let drain = StringDrain {
             output: out.clone(),
         };
 
-        // build some small filter
-        let filter = KVFilter::new(drain.fuse(), Level::Info)
-            .only_pass_on_regex(Regex::new(r"PASS\d:").unwrap())
-            .always_suppress_on_regex(Regex::new(r"NOPE\d:").unwrap());
+        let patterns = kvfilter!{
+            "path" => [prefix "/api", substring "admin".to_string()],
+            "latency" => [threshold "> 1.5s"],
+            "client" => [version ">= 2.3.0, < 3"],
+        };
+        assert_eq!(patterns["path"].len(), 2);
 
-        // Get a root logger that will log into a given drain.
+        let filter = KVFilter::new(drain, Level::Info).only_pass_any_pattern_on_all_keys(Some(patterns));
+
+        let mainlog = Logger::root(filter.fuse(), o!());
+
+        info!(mainlog, "YES: all matching"; "path" => "/api/users", "latency" => "2s", "client" => "2.4.1");
+        info!(mainlog, "YES: substring"; "path" => "/web/admin", "latency" => "3s", "client" => "2.3.0");
+        info!(mainlog, "NO: fast"; "path" => "/api/users", "latency" => "1s", "client" => "2.4.1");
+        info!(mainlog, "NO: old client"; "path" => "/api/users", "latency" => "2s", "client" => "1.9.0");
+        info!(mainlog, "NO: other path"; "path" => "/web", "latency" => "2s", "client" => "2.4.1");
+
+        assert_eq!(out.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid threshold")]
+    fn kvfilterpatternmacroinvalid() {
+        let _ = kvfilter!{ "latency" => [threshold "fast"] };
+    }
+
+    #[test]
+    /// filter lists from plain iterables
+    fn filterlistfrom() {
+        let statics = filter_list(vec![("thread", vec!["100", "200"]), ("direction", vec!["send"])]);
+        let owned = filter_list(
+            vec!["thread".to_string(), "direction".to_string()]
+                .into_iter()
+                .zip(vec![vec!["100".to_string(), "200".to_string()], vec!["send".to_string()]]),
+        );
+        let arrays = filter_list([("thread", ["100", "200"])]);
+
+        assert_eq!(statics, owned);
+        assert_eq!(statics["thread"], arrays["thread"]);
+        assert_eq!(statics, kvfilter!{ "thread" => ["100", "200"], "direction" => ["send"] });
+    }
+
+    #[test]
+    /// overrides apply to the current thread while their guard lives
+    fn scopedoverridefilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testkvfilter(drain).min_level(Level::Info);
+        let handle = filter.handle();
         let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
 
-        info!(mainlog, "NO: filtered, no positive");
-        info!(mainlog, "NO: NOPE2 PASS0 filtered, negative");
-        info!(mainlog, "NO: filtered, no positive");
-        info!(mainlog, "YES: PASS2: not filtered, positive");
-        info!(mainlog, "YES: {}: not filtered, positive", "PASS4");
+        info!(mainlog, "NO: filtered, no override");
+        {
+            let _verbose = handle.scoped_override(ScopedOverride::Pass);
+            debug!(mainlog, "YES: unfiltered, overridden to pass");
+            {
+                let _quiet = handle.scoped_override(ScopedOverride::Suppress);
+                warn!(mainlog, "NO: filtered, inner override suppresses");
+            }
+            info!(mainlog, "YES: unfiltered, back to the outer override");
 
-        println!("resulting output: {:#?}", *out.lock().unwrap());
+            let log = mainlog.clone();
+            ::std::thread::spawn(move || info!(log, "NO: filtered, other thread"))
+                .join()
+                .unwrap();
+        }
+        info!(mainlog, "NO: filtered, override dropped");
 
         assert_eq!(out.lock().unwrap().len(), 2);
     }
+
+    /// future logging a record on each of its `polls` polls, yielding in between
+    #[cfg(feature = "tokio")]
+    struct LoggingFuture {
+        log: Logger,
+        polls: usize,
+    }
+
+    #[cfg(feature = "tokio")]
+    impl ::std::future::Future for LoggingFuture {
+        type Output = ();
+
+        fn poll(
+            mut self: ::std::pin::Pin<&mut Self>,
+            cx: &mut ::std::task::Context,
+        ) -> ::std::task::Poll<()> {
+            debug!(self.log, "YES: unfiltered, overridden in the task");
+            self.polls -= 1;
+            if self.polls == 0 {
+                ::std::task::Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                ::std::task::Poll::Pending
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    /// task overrides follow the task across worker threads
+    fn taskoverridefilter() {
+        let out = Arc::new(Mutex::new(vec![]));
+
+        let drain = StringDrain {
+            output: out.clone(),
+        };
+
+        let filter = testkvfilter(drain).min_level(Level::Info);
+        let handle = filter.handle();
+        let mainlog = Logger::root(filter.fuse(), o!("version" => env!("CARGO_PKG_VERSION")));
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+
+        let task = runtime.spawn(handle.task_override(
+            ScopedOverride::Pass,
+            LoggingFuture {
+                log: mainlog.clone(),
+                polls: 3,
+            },
+        ));
+        runtime.block_on(task).unwrap();
+        debug!(mainlog, "NO: filtered, outside of the task");
+
+        assert_eq!(out.lock().unwrap().len(), 3);
+    }
 }
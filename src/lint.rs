@@ -0,0 +1,232 @@
+//! Detection of obviously broken specs, see `FilterSpec::lint`
+
+use std::fmt;
+use std::prelude::v1::*;
+
+use regex::Regex;
+use regex_syntax;
+use slog;
+
+use {FilterList, FilterSpec};
+
+/// Problem found in a spec by `FilterSpec::lint`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LintWarning {
+    /// a key passed on with no values or patterns, so nothing passes
+    EmptyPassValues { key: String },
+    /// a key suppressed with no values or patterns, which suppresses nothing
+    EmptySuppressValues { key: String },
+    /// values of a key both passed on and suppressed; suppression wins
+    PassedAndSuppressed { key: String, values: Vec<String> },
+    /// a key only matched on levels that pass unfiltered anyway, see
+    /// `KVFilter::filter_key_on_levels`
+    UnreachableScope { key: String },
+    /// a regex no message can match; `suppress` tells the suppressing one
+    UnmatchableRegex { suppress: bool },
+    /// a regex every message matches
+    RegexMatchesAll { suppress: bool },
+    /// the same regex passes and suppresses messages, so nothing passes
+    SameRegex,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let which = |suppress: bool| if suppress { "suppressing" } else { "passing" };
+        match *self {
+            LintWarning::EmptyPassValues { ref key } => {
+                write!(f, "key `{}` is passed on with no values, nothing passes", key)
+            }
+            LintWarning::EmptySuppressValues { ref key } => {
+                write!(f, "key `{}` is suppressed with no values, nothing is suppressed", key)
+            }
+            LintWarning::PassedAndSuppressed { ref key, ref values } => write!(
+                f,
+                "values {} of key `{}` are both passed on and suppressed",
+                values.join(", "),
+                key
+            ),
+            LintWarning::UnreachableScope { ref key } => write!(
+                f,
+                "key `{}` is only matched on levels passing unfiltered anyway",
+                key
+            ),
+            LintWarning::UnmatchableRegex { suppress } => {
+                write!(f, "{} regex can never match", which(suppress))
+            }
+            LintWarning::RegexMatchesAll { suppress } => {
+                write!(f, "{} regex matches every message", which(suppress))
+            }
+            LintWarning::SameRegex => {
+                write!(f, "the same regex passes and suppresses messages, nothing passes")
+            }
+        }
+    }
+}
+
+impl FilterSpec {
+    /// find obviously broken parts of the spec, e.g. a key passed on with an empty value
+    /// set. An empty result doesn't mean the spec does what's intended.
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let mut warnings = Vec::new();
+
+        for key in empty_keys(&self.filters) {
+            warnings.push(LintWarning::EmptyPassValues { key });
+        }
+        for key in empty_keys(&self.neg_filters)
+            .into_iter()
+            .chain(empty_keys(&self.neg_all_filters))
+        {
+            warnings.push(LintWarning::EmptySuppressValues { key });
+        }
+
+        let mut keys: Vec<_> = self.filters.values.keys().collect();
+        keys.sort();
+        for key in keys {
+            let suppressed = match self.neg_filters.values.get(key) {
+                Some(suppressed) => suppressed,
+                None => continue,
+            };
+            let mut values: Vec<String> = self.filters.values[key]
+                .intersection(suppressed)
+                .map(|value| value.to_string())
+                .collect();
+            if !values.is_empty() {
+                values.sort();
+                warnings.push(LintWarning::PassedAndSuppressed {
+                    key: key.to_string(),
+                    values,
+                });
+            }
+        }
+
+        let mut scoped: Vec<_> = self
+            .scopes
+            .iter()
+            .filter(|&(key, _)| {
+                self.filters.contains_key(key)
+                    || self.neg_filters.contains_key(key)
+                    || self.neg_all_filters.contains_key(key)
+            })
+            .filter(|&(_, levels)| {
+                // records more important than `level` pass unfiltered
+                (1..=6)
+                    .filter_map(slog::Level::from_usize)
+                    .filter(|level| levels.contains(level))
+                    .all(|level| level < self.level)
+            })
+            .map(|(key, _)| key.to_string())
+            .collect();
+        scoped.sort();
+        warnings.extend(scoped.into_iter().map(|key| LintWarning::UnreachableScope { key }));
+
+        for (regex, suppress) in self
+            .regex
+            .iter()
+            .map(|regex| (regex, false))
+            .chain(self.neg_regex.iter().map(|regex| (regex, true)))
+        {
+            match regex_lint(regex) {
+                Some(RegexLint::Unmatchable) => {
+                    warnings.push(LintWarning::UnmatchableRegex { suppress })
+                }
+                Some(RegexLint::MatchesAll) => {
+                    warnings.push(LintWarning::RegexMatchesAll { suppress })
+                }
+                None => {}
+            }
+        }
+        if let (Some(regex), Some(neg_regex)) = (self.regex.as_ref(), self.neg_regex.as_ref()) {
+            if regex.as_str() == neg_regex.as_str() {
+                warnings.push(LintWarning::SameRegex);
+            }
+        }
+
+        warnings
+    }
+}
+
+/// keys of `list` with neither values nor patterns, sorted
+fn empty_keys(list: &FilterList) -> Vec<String> {
+    let mut keys: Vec<_> = list
+        .keys()
+        .filter(|&key| {
+            list.values.get(key).is_none_or(|values| values.is_empty())
+                && list.patterns.get(key).is_none_or(|patterns| patterns.is_empty())
+        })
+        .map(|key| key.to_string())
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+enum RegexLint {
+    Unmatchable,
+    MatchesAll,
+}
+
+fn regex_lint(regex: &Regex) -> Option<RegexLint> {
+    let hir = regex_syntax::Parser::new().parse(regex.as_str()).ok()?;
+    let properties = hir.properties();
+    match properties.minimum_len() {
+        None => Some(RegexLint::Unmatchable),
+        // an empty match with no anchors or word boundaries is found in any message
+        Some(0) if properties.look_set().is_empty() => Some(RegexLint::MatchesAll),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LintWarning;
+    use regex::Regex;
+    use slog::Level;
+    use std::collections::HashSet;
+    use {filter_list, KVFilter, KVFilterList};
+
+    #[test]
+    fn lintwarnings() {
+        let mut empty = KVFilterList::<&str, &str>::new();
+        empty.insert("tenant", HashSet::new());
+
+        let spec = KVFilter::new(::slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![
+                ("thread", vec!["100", "200"]),
+                ("direction", vec!["send"]),
+            ])))
+            .always_suppress_any(Some(filter_list(vec![
+                ("thread", vec!["200", "300"]),
+                ("deepcomp", vec!["1"]),
+            ])))
+            .always_suppress_all_with(Some(empty))
+            .filter_key_on_levels("deepcomp", Level::Critical..=Level::Warning)
+            .filter_key_on_levels("direction", Level::Critical..=Level::Info)
+            .only_pass_on_regex(Regex::new("[a&&b]").unwrap())
+            .always_suppress_on_regex(Regex::new("x*").unwrap())
+            .spec();
+
+        assert_eq!(
+            spec.lint(),
+            vec![
+                LintWarning::EmptySuppressValues { key: "tenant".to_owned() },
+                LintWarning::PassedAndSuppressed {
+                    key: "thread".to_owned(),
+                    values: vec!["200".to_owned()],
+                },
+                LintWarning::UnreachableScope { key: "deepcomp".to_owned() },
+                LintWarning::UnmatchableRegex { suppress: false },
+                LintWarning::RegexMatchesAll { suppress: true },
+            ]
+        );
+        assert_eq!(
+            spec.lint()[1].to_string(),
+            "values 200 of key `thread` are both passed on and suppressed"
+        );
+
+        let spec = KVFilter::new(::slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .only_pass_on_regex(Regex::new("^pass").unwrap())
+            .spec();
+        assert!(spec.lint().is_empty());
+    }
+}
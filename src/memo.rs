@@ -0,0 +1,161 @@
+//! Memoized outcomes of the costly matchers of keys, see `KVFilter::memoize_matches`
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use small_str::SmallStr;
+use sync::{Sharded, SHARDS};
+use FilterMap;
+
+/// Which values a full memo keeps, see `KVFilter::memoize_matches`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoPolicy {
+    /// evict the value looked up least recently for the new one
+    Lru,
+    /// keep the values seen first, matching the others each time, for keys with a stable
+    /// set of hot values; lookups don't count as uses, so they take no write lock
+    FirstSeen,
+}
+
+/// Lookups of the memos of a filter since its spec was compiled, see
+/// `KVFilter::memoize_matches`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// values memoized
+    pub entries: usize,
+}
+
+impl MemoStats {
+    pub fn add(&mut self, other: MemoStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.entries += other.entries;
+    }
+}
+
+struct Memoized {
+    checked: Option<bool>,
+    /// `MatchMemo::tick` of the last lookup, for `MemoPolicy::Lru`
+    last_used: AtomicU64,
+}
+
+/// Outcomes of the costly patterns of a matcher (CIDR ranges, versions, thresholds, JSON
+/// pointers and fuzzy strings) by value, up to `capacity` values
+pub struct MatchMemo {
+    /// values per shard
+    capacity: usize,
+    policy: MemoPolicy,
+    entries: Sharded<FilterMap<SmallStr, Memoized>>,
+    tick: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl MatchMemo {
+    pub fn new(capacity: usize, policy: MemoPolicy) -> Self {
+        MatchMemo {
+            capacity: capacity.div_ceil(SHARDS).max(1),
+            policy,
+            entries: Sharded::default(),
+            tick: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// the outcome of `check` for `value`, memoized
+    pub fn get<F: FnOnce() -> Option<bool>>(&self, value: &str, check: F) -> Option<bool> {
+        let index = self.entries.index(value);
+        let tick = match self.policy {
+            MemoPolicy::Lru => self.tick.fetch_add(1, Ordering::Relaxed),
+            MemoPolicy::FirstSeen => 0,
+        };
+        if let Some(memoized) = self.entries.read(index).get(value) {
+            memoized.last_used.store(tick, Ordering::Relaxed);
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return memoized.checked;
+        }
+
+        // checked without holding the lock, other threads may look up meanwhile
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let checked = check();
+
+        let mut entries = self.entries.write(index);
+        if entries.len() >= self.capacity {
+            match self.policy {
+                MemoPolicy::Lru => {
+                    let lru = entries
+                        .iter()
+                        .min_by_key(|&(_, memoized)| memoized.last_used.load(Ordering::Relaxed))
+                        .map(|(value, _)| value.clone());
+                    if let Some(lru) = lru {
+                        entries.remove(&lru);
+                    }
+                }
+                MemoPolicy::FirstSeen => return checked,
+            }
+        }
+        entries.insert(
+            SmallStr::from(value),
+            Memoized {
+                checked,
+                last_used: AtomicU64::new(tick),
+            },
+        );
+        checked
+    }
+
+    pub fn stats(&self) -> MemoStats {
+        MemoStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: (0..SHARDS).map(|index| self.entries.read(index).len()).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchMemo, MemoPolicy, MemoStats};
+    use std::cell::Cell;
+    use sync::SHARDS;
+
+    #[test]
+    fn matchmemo() {
+        let checks = Cell::new(0);
+        let check = |matched: bool| {
+            checks.set(checks.get() + 1);
+            Some(matched)
+        };
+
+        let memo = MatchMemo::new(SHARDS, MemoPolicy::Lru);
+        assert_eq!(memo.get("/api/users", || check(true)), Some(true));
+        assert_eq!(memo.get("/api/users", || check(false)), Some(true));
+        assert_eq!(memo.get("/health", || None), None);
+        assert_eq!(memo.get("/health", || check(true)), None);
+        assert_eq!(checks.get(), 1);
+        assert_eq!(
+            memo.stats(),
+            MemoStats {
+                hits: 2,
+                misses: 2,
+                entries: 2,
+            }
+        );
+
+        // a shard holds a single value, the ones filling a shard evict each other
+        let values: Vec<String> = (0..4 * SHARDS).map(|n| format!("/api/{}", n)).collect();
+        for value in &values {
+            memo.get(value, || check(true));
+        }
+        assert!(memo.stats().entries <= SHARDS);
+        assert_eq!(memo.get(&values[4 * SHARDS - 1], || check(false)), Some(true));
+
+        let memo = MatchMemo::new(1, MemoPolicy::FirstSeen);
+        for value in &values {
+            memo.get(value, || check(true));
+        }
+        assert_eq!(memo.get(&values[0], || check(false)), Some(true));
+    }
+}
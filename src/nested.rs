@@ -0,0 +1,430 @@
+//! Flattening of nested values (`slog::Serde`) into dotted key paths, so that e.g. the
+//! `user_agent` field of a `headers` struct logged as `request` is matched by the filter
+//! key `request.headers.user_agent`.
+//!
+//! Elements of sequences are emitted under the path of the sequence itself, so that
+//! a key matches if any of the elements does.
+
+use std::error;
+use std::fmt;
+
+use serde::ser::{self, Serialize};
+use slog;
+
+/// Raised to stop walking the value, either because the emitter is decided or failed
+#[derive(Debug)]
+pub struct Stop(pub slog::Error);
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for Stop {}
+
+impl ser::Error for Stop {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        Stop(slog::Error::Other)
+    }
+}
+
+/// whether `path` is `key` or the path of a leaf of a value logged under it
+pub fn is_under(path: &str, key: &str) -> bool {
+    path.starts_with(key) && path[key.len()..].chars().next().is_none_or(|c| c == '.')
+}
+
+/// call `emit` with the dotted path and the formatted value of each leaf of `value`
+/// logged under `key`
+pub fn flatten<F>(key: &str, value: &dyn slog::SerdeValue, mut emit: F) -> slog::Result
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    let mut path = String::from(key);
+    let res = value.as_serde().serialize(Flattener {
+        path: &mut path,
+        emit: &mut emit,
+    });
+    res.map_err(|Stop(err)| err)
+}
+
+struct Flattener<'a, F: 'a> {
+    path: &'a mut String,
+    emit: &'a mut F,
+}
+
+impl<'a, F> Flattener<'a, F>
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    fn leaf(self, val: &fmt::Arguments) -> Result<(), Stop> {
+        (self.emit)(self.path, val).map_err(Stop)
+    }
+
+    fn nest(&mut self) -> Flattener<'_, F> {
+        Flattener {
+            path: self.path,
+            emit: self.emit,
+        }
+    }
+
+    /// serialize `value` at the current path extended by `name`
+    fn field<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<(), Stop> {
+        let len = self.path.len();
+        self.path.push('.');
+        self.path.push_str(name);
+        let res = value.serialize(self.nest());
+        self.path.truncate(len);
+        res
+    }
+}
+
+macro_rules! leaf {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<(), Stop> {
+                self.leaf(&format_args!("{}", v))
+            }
+        )*
+    };
+}
+
+impl<'a, F> ser::Serializer for Flattener<'a, F>
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    type Ok = ();
+    type Error = Stop;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Compound<'a, F>;
+    type SerializeMap = Compound<'a, F>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Compound<'a, F>;
+
+    leaf!(serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32,
+          serialize_i64: i64, serialize_u8: u8, serialize_u16: u16, serialize_u32: u32,
+          serialize_u64: u64, serialize_f32: f32, serialize_f64: f64, serialize_char: char,
+          serialize_str: &str);
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), Stop> {
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Stop> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Stop> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Stop> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<(), Stop> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        mut self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Stop> {
+        self.field(variant, value)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self, Stop> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self, Stop> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self, Stop> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Compound<'a, F>, Stop> {
+        Ok(Compound::new(self, Some(variant)))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Compound<'a, F>, Stop> {
+        Ok(Compound::new(self, None))
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, Stop> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Compound<'a, F>, Stop> {
+        Ok(Compound::new(self, Some(variant)))
+    }
+}
+
+macro_rules! elements {
+    ($($trait:ident :: $method:ident),*) => {
+        $(
+            impl<'a, F> ser::$trait for Flattener<'a, F>
+            where
+                F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+            {
+                type Ok = ();
+                type Error = Stop;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+                    value.serialize(self.nest())
+                }
+
+                fn end(self) -> Result<(), Stop> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+elements!(SerializeSeq::serialize_element, SerializeTuple::serialize_element,
+          SerializeTupleStruct::serialize_field);
+
+impl<'a, F> ser::SerializeStruct for Flattener<'a, F>
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Stop> {
+        self.field(name, value)
+    }
+
+    fn end(self) -> Result<(), Stop> {
+        Ok(())
+    }
+}
+
+/// Map or enum variant being flattened, restoring the path once done
+pub struct Compound<'a, F: 'a> {
+    inner: Flattener<'a, F>,
+    /// length of the path before the compound, to restore at the end
+    start: usize,
+    /// length of the path of the compound itself
+    len: usize,
+    /// whether the value of the current map entry is skipped, its key can't be a path
+    skip: bool,
+}
+
+impl<'a, F> Compound<'a, F> {
+    fn new(inner: Flattener<'a, F>, variant: Option<&str>) -> Self {
+        let start = inner.path.len();
+        if let Some(variant) = variant {
+            inner.path.push('.');
+            inner.path.push_str(variant);
+        }
+        Compound {
+            start,
+            len: inner.path.len(),
+            inner,
+            skip: false,
+        }
+    }
+
+    fn end(self) -> Result<(), Stop> {
+        self.inner.path.truncate(self.start);
+        Ok(())
+    }
+}
+
+impl<'a, F> ser::SerializeTupleVariant for Compound<'a, F>
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        value.serialize(self.inner.nest())
+    }
+
+    fn end(self) -> Result<(), Stop> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, F> ser::SerializeStructVariant for Compound<'a, F>
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Stop> {
+        self.inner.field(name, value)
+    }
+
+    fn end(self) -> Result<(), Stop> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, F> ser::SerializeMap for Compound<'a, F>
+where
+    F: FnMut(&str, &fmt::Arguments) -> slog::Result,
+{
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Stop> {
+        self.inner.path.truncate(self.len);
+        self.inner.path.push('.');
+        self.skip = key
+            .serialize(MapKey {
+                path: self.inner.path,
+            })
+            .is_err();
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        let res = if self.skip {
+            Ok(())
+        } else {
+            value.serialize(self.inner.nest())
+        };
+        self.inner.path.truncate(self.len);
+        res
+    }
+
+    fn end(self) -> Result<(), Stop> {
+        Compound::end(self)
+    }
+}
+
+/// Appends a map key to the path; keys which aren't strings or numbers can't be filtered on
+struct MapKey<'a> {
+    path: &'a mut String,
+}
+
+macro_rules! key {
+    ($($method:ident: $ty:ty),*) => {
+        $(
+            fn $method(self, v: $ty) -> Result<(), Stop> {
+                fmt::write(self.path, format_args!("{}", v)).map_err(|_| Stop(slog::Error::Other))
+            }
+        )*
+    };
+}
+
+macro_rules! unsupported_key {
+    ($($method:ident -> $ret:ty, ($($arg:ty),*)),*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<$ret, Stop> {
+                Err(Stop(slog::Error::Other))
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for MapKey<'a> {
+    type Ok = ();
+    type Error = Stop;
+    type SerializeSeq = ser::Impossible<(), Stop>;
+    type SerializeTuple = ser::Impossible<(), Stop>;
+    type SerializeTupleStruct = ser::Impossible<(), Stop>;
+    type SerializeTupleVariant = ser::Impossible<(), Stop>;
+    type SerializeMap = ser::Impossible<(), Stop>;
+    type SerializeStruct = ser::Impossible<(), Stop>;
+    type SerializeStructVariant = ser::Impossible<(), Stop>;
+
+    key!(serialize_bool: bool, serialize_i8: i8, serialize_i16: i16, serialize_i32: i32,
+         serialize_i64: i64, serialize_u8: u8, serialize_u16: u16, serialize_u32: u32,
+         serialize_u64: u64, serialize_f32: f32, serialize_f64: f64, serialize_char: char,
+         serialize_str: &str);
+
+    unsupported_key!(serialize_bytes -> (), (&[u8]),
+                     serialize_none -> (), (),
+                     serialize_unit -> (), (),
+                     serialize_unit_struct -> (), (&'static str),
+                     serialize_seq -> Self::SerializeSeq, (Option<usize>),
+                     serialize_tuple -> Self::SerializeTuple, (usize),
+                     serialize_tuple_struct -> Self::SerializeTupleStruct, (&'static str, usize),
+                     serialize_tuple_variant -> Self::SerializeTupleVariant,
+                         (&'static str, u32, &'static str, usize),
+                     serialize_map -> Self::SerializeMap, (Option<usize>),
+                     serialize_struct -> Self::SerializeStruct, (&'static str, usize),
+                     serialize_struct_variant -> Self::SerializeStructVariant,
+                         (&'static str, u32, &'static str, usize));
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<(), Stop> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Stop> {
+        Err(Stop(slog::Error::Other))
+    }
+}
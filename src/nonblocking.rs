@@ -0,0 +1,172 @@
+//! Matching and forwarding records off the logging thread
+
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use slog;
+
+use {KVFilter, KVFilterError, OwnedRecord};
+
+/// What `NonBlocking` does with records when its queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// wait for the worker to catch up
+    Block,
+    /// drop the record, counting it in `NonBlocking::dropped`
+    Drop,
+}
+
+/// `Drain` snapshotting records, like slog-async does, and evaluating a `KVFilter` and
+/// forwarding them on a worker thread, so that expensive matchers such as regexes don't
+/// add latency to the logging thread.
+///
+/// Up to `capacity` records wait in the queue. Dropping the drain waits for the worker to
+/// decide the queued records.
+///
+/// Thread scoped overrides (`KVFilter::scoped_override`) of the logging thread don't apply,
+/// since the records are decided on the worker thread.
+pub struct NonBlocking<D: slog::Drain + Send + Sync + 'static> {
+    filter: Arc<KVFilter<D>>,
+    sender: Option<SyncSender<OwnedRecord>>,
+    worker: Option<JoinHandle<()>>,
+    overflow: OverflowPolicy,
+    dropped: AtomicUsize,
+}
+
+impl<D: slog::Drain + Send + Sync + 'static> UnwindSafe for NonBlocking<D> {}
+impl<D: slog::Drain + Send + Sync + 'static> RefUnwindSafe for NonBlocking<D> {}
+
+impl<D: slog::Drain + Send + Sync + 'static> NonBlocking<D> {
+    /// drain deciding the records by `filter` on a worker thread, `KVFilterError::Spawn` if
+    /// the thread can't be spawned
+    pub fn new(filter: KVFilter<D>, capacity: usize, overflow: OverflowPolicy) -> Result<Self, KVFilterError> {
+        let filter = Arc::new(filter);
+        let (sender, receiver) = mpsc::sync_channel::<OwnedRecord>(capacity);
+
+        let worker = {
+            let filter = filter.clone();
+            thread::Builder::new()
+                .name("slog-kvfilter".to_owned())
+                .spawn(move || {
+                    for record in receiver {
+                        // errors of the inner drain have nowhere to go
+                        let _ = record.log_to(&*filter);
+                    }
+                })
+                .map_err(KVFilterError::Spawn)?
+        };
+
+        Ok(NonBlocking {
+            filter,
+            sender: Some(sender),
+            worker: Some(worker),
+            overflow,
+            dropped: AtomicUsize::new(0),
+        })
+    }
+
+    /// number of records dropped by `OverflowPolicy::Drop` so far
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<D: slog::Drain + Send + Sync + 'static> slog::Drain for NonBlocking<D> {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, info: &slog::Record, logger_values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return Ok(()),
+        };
+
+        let snapshot = OwnedRecord::new(info, logger_values);
+
+        match self.overflow {
+            // the worker only stops once the sender is dropped
+            OverflowPolicy::Block => {
+                let _ = sender.send(snapshot);
+            }
+            OverflowPolicy::Drop => {
+                if let Err(TrySendError::Full(_)) = sender.try_send(snapshot) {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        self.filter.is_enabled(level)
+    }
+}
+
+impl<D: slog::Drain + Send + Sync + 'static> Drop for NonBlocking<D> {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NonBlocking, OverflowPolicy};
+    use slog::{Drain, Level, Logger};
+    use std::collections::HashMap;
+    use testing::CapturingDrain;
+    use {filter_list, KVFilter, ValuePattern};
+
+    #[test]
+    fn nonblockingfilter() {
+        let drain = CapturingDrain::new();
+
+        {
+            let filter = KVFilter::new(drain.clone(), Level::Info)
+                .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+            let log = Logger::root(NonBlocking::new(filter, 16, OverflowPolicy::Block).unwrap(), o!());
+            let sublog = log.new(o!("thread" => "100"));
+
+            for i in 0..100 {
+                info!(sublog, "passing"; "i" => i);
+                info!(log, "not passing"; "thread" => "200");
+            }
+        }
+
+        let records = drain.records();
+        assert_eq!(records.len(), 100);
+        assert_eq!(records[99].get("i"), Some("99"));
+    }
+
+    #[test]
+    /// numbers and booleans decided off the logging thread as they are inline
+    fn nonblockingtyped() {
+        let filter = |drain: CapturingDrain| {
+            let mut patterns = HashMap::new();
+            patterns.insert("packet".to_owned(), vec![ValuePattern::Number(42u64.into())]);
+            patterns.insert("retried".to_owned(), vec![ValuePattern::Bool(true)]);
+            KVFilter::new(drain, Level::Info).only_pass_any_pattern_on_all_keys(Some(patterns))
+        };
+        let log_all = |log: &Logger| {
+            info!(log, "passing"; "packet" => 42u64, "retried" => true);
+            info!(log, "passing"; "packet" => 42.0, "retried" => true);
+            info!(log, "not passing"; "packet" => "42", "retried" => true);
+            info!(log, "not passing"; "packet" => 42u8, "retried" => "true");
+            info!(log, "not passing"; "packet" => 43i32, "retried" => true);
+        };
+
+        let inline = CapturingDrain::new();
+        log_all(&Logger::root(filter(inline.clone()).fuse(), o!()));
+
+        let nonblocking = CapturingDrain::new();
+        log_all(&Logger::root(NonBlocking::new(filter(nonblocking.clone()), 16, OverflowPolicy::Block).unwrap(), o!()));
+
+        assert_eq!(inline.records().len(), 2);
+        assert_eq!(nonblocking.records(), inline.records());
+    }
+}
@@ -0,0 +1,127 @@
+//! Numbers matched by their value rather than their text, see `ValuePattern::Number`
+
+use std::fmt::{self, Write};
+use std::str;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Repr {
+    Int(i128),
+    /// bits of a float with a fractional part, or not finite
+    Float(u64),
+}
+
+/// Number a value of a key is equal to, whatever its type, e.g. `7` matching values given
+/// by `emit_u64(7)`, `emit_i32(7)` or `emit_f64(7.0)`, and `0.5` those given by `emit_f32`
+/// or `emit_f64`.
+///
+/// Only values emitted as numbers match, not strings of the number, e.g. `"7"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Number(Repr);
+
+/// Value of a key emitted as a number or a boolean rather than as text, matched by the
+/// `ValuePattern::Number`s and `ValuePattern::Bool`s
+#[derive(Clone, Copy, Debug)]
+pub enum Typed {
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+}
+
+impl Typed {
+    /// the value as a `Number`, `None` for a boolean
+    pub fn number(self) -> Option<Number> {
+        match self {
+            Typed::U64(number) => Some(number.into()),
+            Typed::I64(number) => Some(number.into()),
+            Typed::F32(number) => Some(number.into()),
+            Typed::F64(number) => Some(number.into()),
+            Typed::Bool(_) => None,
+        }
+    }
+}
+
+impl From<i64> for Number {
+    fn from(number: i64) -> Self {
+        Number(Repr::Int(i128::from(number)))
+    }
+}
+
+impl From<u64> for Number {
+    fn from(number: u64) -> Self {
+        Number(Repr::Int(i128::from(number)))
+    }
+}
+
+impl From<f64> for Number {
+    fn from(number: f64) -> Self {
+        // whole floats are the integers, also keeping -0 equal to 0
+        if number.abs() < i128::MAX as f64 && number as i128 as f64 == number {
+            Number(Repr::Int(number as i128))
+        } else {
+            Number(Repr::Float(number.to_bits()))
+        }
+    }
+}
+
+impl From<f32> for Number {
+    /// the number as written, e.g. `0.1` rather than the `0.100000001490116` it widens to
+    fn from(number: f32) -> Self {
+        // the longest f32 written in full is under 64 bytes
+        let mut buf = Buf { bytes: [0; 64], len: 0 };
+        let parsed = write!(buf, "{}", number)
+            .ok()
+            .and_then(|_| str::from_utf8(&buf.bytes[..buf.len]).ok())
+            .and_then(|text| text.parse::<f64>().ok());
+        Number::from(parsed.unwrap_or_else(|| f64::from(number)))
+    }
+}
+
+/// bytes written on the stack
+struct Buf {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Write for Buf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Repr::Int(int) => write!(f, "{}", int),
+            Repr::Float(bits) => write!(f, "{}", f64::from_bits(bits)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Number, Typed};
+
+    #[test]
+    fn number() {
+        let seven = Number::from(7u64);
+        assert_eq!(Number::from(7.0), seven);
+        assert_eq!(Number::from(7i64), seven);
+        assert_eq!(Number::from(7f32), seven);
+        assert_ne!(Number::from(7.5), seven);
+        assert_eq!(Typed::I64(7).number(), Some(seven));
+        assert_eq!(Typed::Bool(true).number(), None);
+
+        assert_eq!(Number::from(0.5f32), Number::from(0.5));
+        assert_eq!(Number::from(0.1f32), Number::from(0.1));
+        assert_eq!(Number::from(-0.0), Number::from(0u64));
+        assert_ne!(Number::from(u64::MAX), Number::from(u64::MAX - 1));
+        assert_eq!(Number::from(1e-45f32), Number::from(1e-45));
+        assert_eq!(Number::from(0.5).to_string(), "0.5");
+        assert_eq!(Number::from(1e3).to_string(), "1000");
+    }
+}
@@ -0,0 +1,136 @@
+//! Tightening of a filter during log storms, see `KVFilter::protect_overload`
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog;
+use slog::KV;
+
+use FilterSpec;
+
+/// How `KVFilter::protect_overload` tightens the filter
+#[derive(Clone)]
+pub enum Tightening {
+    /// suppress the records less important than the level
+    MinLevel(slog::Level),
+    /// suppress the records the spec doesn't pass
+    Spec(Arc<FilterSpec>),
+}
+
+/// length of the windows the pass rate is measured over
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Pass rate measured, and whether the filter is tightened, shared by the clones of a filter
+pub struct Overload {
+    max_per_sec: u64,
+    pub tightening: Tightening,
+    /// `Clock::now` in nanoseconds when the window started, `u64::MAX` before the first record
+    window_start: AtomicU64,
+    passed: AtomicU64,
+    tightened: AtomicBool,
+}
+
+/// Change of the tightening, with the rate of the window which caused it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Tightened(u64),
+    Relaxed(u64),
+}
+
+impl Overload {
+    pub fn new(max_per_sec: u64, tightening: Tightening) -> Self {
+        Overload {
+            max_per_sec,
+            tightening,
+            window_start: AtomicU64::new(u64::MAX),
+            passed: AtomicU64::new(0),
+            tightened: AtomicBool::new(false),
+        }
+    }
+
+    pub fn transition_kv(&self, rate: u64) -> TransitionKV {
+        TransitionKV {
+            rate,
+            max_per_sec: self.max_per_sec,
+        }
+    }
+
+    pub fn is_tightened(&self) -> bool {
+        self.tightened.load(Ordering::Relaxed)
+    }
+
+    /// count a record passed by the configuration, returning the transition the end of the
+    /// window it closes causes, if any
+    pub fn passed(&self, now: Duration) -> Option<Transition> {
+        let now = now.as_nanos().min(u128::from(u64::MAX - 1)) as u64;
+        let start = match self.window_start.compare_exchange(u64::MAX, now, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => now,
+            Err(start) => start,
+        };
+        let elapsed = now.saturating_sub(start);
+        if elapsed < WINDOW.as_nanos() as u64 {
+            self.passed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        // of the records closing the window concurrently, one measures it
+        if self
+            .window_start
+            .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            self.passed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let passed = self.passed.swap(1, Ordering::Relaxed);
+        let rate = (u128::from(passed) * 1_000_000_000 / u128::from(elapsed)) as u64;
+        let tightened = rate > self.max_per_sec;
+        match (self.tightened.swap(tightened, Ordering::Relaxed), tightened) {
+            (false, true) => Some(Transition::Tightened(rate)),
+            (true, false) => Some(Transition::Relaxed(rate)),
+            _ => None,
+        }
+    }
+}
+
+/// key values of the notices of the transitions
+pub struct TransitionKV {
+    pub rate: u64,
+    pub max_per_sec: u64,
+}
+
+impl KV for TransitionKV {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_u64("kvfilter_pass_rate", self.rate)?;
+        serializer.emit_u64("kvfilter_max_pass_rate", self.max_per_sec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Overload, Tightening, Transition};
+    use slog::Level;
+    use std::time::Duration;
+
+    #[test]
+    fn passrate() {
+        let millis = Duration::from_millis;
+        let overload = Overload::new(10, Tightening::MinLevel(Level::Warning));
+
+        for i in 0..20 {
+            assert_eq!(overload.passed(millis(i * 50)), None);
+        }
+        assert!(!overload.is_tightened());
+        // 20 records within the first second
+        assert_eq!(overload.passed(millis(1000)), Some(Transition::Tightened(20)));
+        assert!(overload.is_tightened());
+
+        for i in 1..5 {
+            assert_eq!(overload.passed(millis(1000 + i * 200)), None);
+        }
+        // 5 records within the next two seconds
+        assert_eq!(overload.passed(millis(3000)), Some(Transition::Relaxed(2)));
+        assert!(!overload.is_tightened());
+    }
+}
@@ -0,0 +1,136 @@
+//! Temporary changes of filtering, limited to the current thread or, with the `tokio`
+//! feature, to a task
+
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use slog;
+
+use KVPredicate;
+
+/// Temporary change of how a `KVFilter` decides records logged on the current thread,
+/// see `KVFilter::scoped_override`
+pub enum ScopedOverride {
+    /// pass all records, e.g. for verbose logging within a request handler
+    Pass,
+    /// suppress all records
+    Suppress,
+    /// use this minimum level instead of the one of the filter
+    MinLevel(slog::Level),
+    /// additionally require the predicate to pass
+    Require(Box<dyn KVPredicate + Send + Sync>),
+}
+
+struct Entry {
+    filter: usize,
+    token: u64,
+    spec: Arc<ScopedOverride>,
+}
+
+#[derive(Default)]
+struct Overrides {
+    entries: Vec<Entry>,
+    next_token: u64,
+}
+
+thread_local! {
+    static OVERRIDES: RefCell<Overrides> = RefCell::new(Overrides::default());
+}
+
+/// Removes its override when dropped; bound to the thread it was created on
+#[must_use = "the override is removed when the guard is dropped"]
+pub struct OverrideGuard {
+    token: u64,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for OverrideGuard {
+    fn drop(&mut self) {
+        let token = self.token;
+        let _ = OVERRIDES.try_with(|overrides| {
+            if let Ok(mut overrides) = overrides.try_borrow_mut() {
+                overrides.entries.retain(|entry| entry.token != token);
+            }
+        });
+    }
+}
+
+/// override `filter` on this thread until the returned guard is dropped
+pub fn push(filter: usize, spec: ScopedOverride) -> OverrideGuard {
+    let token = OVERRIDES.with(|overrides| {
+        let mut overrides = overrides.borrow_mut();
+        let token = overrides.next_token;
+        overrides.next_token += 1;
+        overrides.entries.push(Entry {
+            filter,
+            token,
+            spec: Arc::new(spec),
+        });
+        token
+    });
+
+    OverrideGuard {
+        token,
+        _not_send: PhantomData,
+    }
+}
+
+/// innermost override of `filter` in this task, or on this thread if none
+pub fn current(filter: usize) -> Option<Arc<ScopedOverride>> {
+    #[cfg(feature = "tokio")]
+    {
+        let spec = TASK_OVERRIDES
+            .try_with(|overrides| {
+                overrides
+                    .iter()
+                    .rev()
+                    .find(|&&(f, _)| f == filter)
+                    .map(|(_, spec)| spec.clone())
+            })
+            .ok()
+            .and_then(|spec| spec);
+        if spec.is_some() {
+            return spec;
+        }
+    }
+
+    OVERRIDES
+        .try_with(|overrides| {
+            overrides.try_borrow().ok().and_then(|overrides| {
+                overrides
+                    .entries
+                    .iter()
+                    .rev()
+                    .find(|entry| entry.filter == filter)
+                    .map(|entry| entry.spec.clone())
+            })
+        })
+        .ok()
+        .and_then(|spec| spec)
+}
+
+#[cfg(feature = "tokio")]
+type TaskOverrides = Arc<Vec<(usize, Arc<ScopedOverride>)>>;
+
+#[cfg(feature = "tokio")]
+tokio::task_local! {
+    static TASK_OVERRIDES: TaskOverrides;
+}
+
+/// run `future` with `filter` overridden, wherever the task gets polled
+#[cfg(feature = "tokio")]
+pub fn scope<F: Future>(
+    filter: usize,
+    spec: ScopedOverride,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    let mut overrides = TASK_OVERRIDES
+        .try_with(|overrides| (**overrides).clone())
+        .unwrap_or_default();
+    overrides.push((filter, Arc::new(spec)));
+
+    TASK_OVERRIDES.scope(Arc::new(overrides), future)
+}
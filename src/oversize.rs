@@ -0,0 +1,149 @@
+//! Guard against records with key values too large for the drains downstream, see
+//! `KVFilter::guard_oversized`
+
+use std::fmt::{self, Write};
+use std::prelude::v1::*;
+
+use slog;
+use slog::KV;
+
+/// What `KVFilter::guard_oversized` does with oversized records
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// forward the message alone, with `kvfilter_oversized_bytes` in place of the key values
+    Suppress,
+    /// forward the key values fitting the limit, the last one cut short, with
+    /// `kvfilter_truncated_bytes`
+    Truncate,
+}
+
+/// bytes of the keys and the formatted values of `record` and `logger_values`, if above
+/// `limit`
+pub fn oversized(record: &slog::Record, logger_values: &slog::OwnedKVList, limit: usize) -> Option<usize> {
+    let mut counter = ByteCounter(0);
+    let _ = record.kv().serialize(record, &mut counter);
+    let _ = logger_values.serialize(record, &mut counter);
+    if counter.0 > limit {
+        Some(counter.0)
+    } else {
+        None
+    }
+}
+
+struct ByteCounter(usize);
+
+impl fmt::Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+impl slog::Serializer for ByteCounter {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        self.0 += key.len();
+        let _ = self.write_fmt(*val);
+        Ok(())
+    }
+}
+
+/// `kvfilter_oversized_bytes` of a suppressed record
+pub struct OversizedKV(pub usize);
+
+impl KV for OversizedKV {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_usize("kvfilter_oversized_bytes", self.0)
+    }
+}
+
+/// Key values of a record and its logger context cut to `limit` bytes, followed by
+/// `kvfilter_truncated_bytes`
+pub struct TruncatedKV<'a> {
+    pub record: slog::BorrowedKV<'a>,
+    pub logger_values: &'a slog::OwnedKVList,
+    pub limit: usize,
+    pub size: usize,
+}
+
+impl<'a> KV for TruncatedKV<'a> {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        let mut truncating = Truncating {
+            inner: serializer,
+            remaining: self.limit,
+            truncated: false,
+            buf: String::new(),
+        };
+        self.record.serialize(record, &mut truncating)?;
+        self.logger_values.serialize(record, &mut truncating)?;
+        truncating.inner.emit_usize("kvfilter_truncated_bytes", self.size)
+    }
+}
+
+struct Truncating<'a> {
+    inner: &'a mut dyn slog::Serializer,
+    remaining: usize,
+    /// whether the limit was reached, the rest of the key values being left out
+    truncated: bool,
+    buf: String,
+}
+
+impl<'a> slog::Serializer for Truncating<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if self.truncated {
+            return Ok(());
+        }
+
+        self.buf.clear();
+        let _ = self.buf.write_fmt(*val);
+        let size = key.len() + self.buf.len();
+        if size <= self.remaining {
+            self.remaining -= size;
+            return self.inner.emit_str(key, &self.buf);
+        }
+
+        self.truncated = true;
+        if key.len() >= self.remaining {
+            return Ok(());
+        }
+        let mut end = self.remaining - key.len();
+        while !self.buf.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.inner.emit_str(key, &self.buf[..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::oversized;
+    use slog::{Level, OwnedKVList, Record, RecordLocation, RecordStatic};
+
+    #[test]
+    fn recordsize() {
+        static LOCATION: RecordLocation = RecordLocation {
+            file: "",
+            line: 0,
+            column: 0,
+            function: "",
+            module: "",
+        };
+        let rs = RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level: Level::Info,
+        };
+        let kv = o!("thread" => 100, "direction" => "send");
+        let context = OwnedKVList::from(o!("version" => "1.0"));
+        let size = |limit| {
+            oversized(
+                &Record::new(&rs, &format_args!("message not counted"), ::slog::BorrowedKV(&kv)),
+                &context,
+                limit,
+            )
+        };
+
+        // "thread" + "100" + "direction" + "send" + "version" + "1.0"
+        assert_eq!(size(31), Some(32));
+        assert_eq!(size(32), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Records copied out of the logging call, to be logged later, see `OwnedRecord`
+
+use std::fmt;
+use std::prelude::v1::*;
+
+use slog;
+use slog::KV;
+
+use combinators::{text, SerializedKV, SerializedValue};
+
+/// Record with its message and key values formatted, and the logger context it was logged
+/// in, owned so that it can be held, e.g. in a queue or a buffer, and logged to a drain
+/// later by `log_to`.
+///
+/// Numbers and booleans keep their type, so that the record is decided as it was
+/// originally, e.g. by `ValuePattern::Number`s. Values of the record failing to serialize
+/// are left out. The logger context is shared
+/// rather than copied, like slog-async does, so its lazy values are evaluated when the
+/// record is logged.
+#[derive(Clone)]
+pub struct OwnedRecord {
+    location: slog::RecordLocation,
+    tag: String,
+    level: slog::Level,
+    message: String,
+    kv: SerializedKV,
+    logger_values: slog::OwnedKVList,
+}
+
+impl OwnedRecord {
+    pub fn new(record: &slog::Record, logger_values: &slog::OwnedKVList) -> Self {
+        let mut kv = SerializedKV(Vec::new());
+        let _ = record.kv().serialize(record, &mut kv);
+
+        OwnedRecord {
+            location: *record.location(),
+            tag: record.tag().to_owned(),
+            level: record.level(),
+            message: text(record.msg()),
+            kv,
+            logger_values: logger_values.clone(),
+        }
+    }
+
+    pub fn level(&self) -> slog::Level {
+        self.level
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn location(&self) -> &slog::RecordLocation {
+        &self.location
+    }
+
+    /// key values of the record, as serialized when it was copied, without the logger context
+    pub fn key_values(&self) -> impl Iterator<Item = (&str, &SerializedValue)> {
+        self.kv.0.iter().map(|&(key, ref value)| (key, value))
+    }
+
+    pub fn logger_values(&self) -> &slog::OwnedKVList {
+        &self.logger_values
+    }
+
+    /// log the record to `drain`, as it was logged originally
+    pub fn log_to<D: slog::Drain + ?Sized>(&self, drain: &D) -> Result<D::Ok, D::Err> {
+        let rs = slog::RecordStatic {
+            location: &self.location,
+            tag: &self.tag,
+            level: self.level,
+        };
+        drain.log(
+            &slog::Record::new(&rs, &format_args!("{}", self.message), slog::BorrowedKV(&self.kv)),
+            &self.logger_values,
+        )
+    }
+}
+
+impl fmt::Debug for OwnedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedRecord")
+            .field("level", &self.level)
+            .field("message", &self.message)
+            .field("key_values", &self.kv.0)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OwnedRecord;
+    use slog::{self, Drain, Level, Logger};
+    use std::sync::{Arc, Mutex};
+    use testing::CapturingDrain;
+
+    /// drain keeping the records to log them later
+    #[derive(Clone, Default)]
+    struct Held(Arc<Mutex<Vec<OwnedRecord>>>);
+
+    impl Drain for Held {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(OwnedRecord::new(record, logger_values));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ownedrecord() {
+        let held = Held::default();
+        {
+            let log = Logger::root(held.clone().fuse(), o!("version" => "1"));
+            let sublog = log.new(o!("thread" => 100));
+            let packet = String::from("send");
+            warn!(sublog, "logged {}", 1; "packet" => &packet, "late" => slog::FnValue(|_| "evaluated"));
+        }
+
+        let records = held.0.lock().unwrap();
+        let record = &records[0];
+        assert_eq!((record.level(), record.message()), (Level::Warning, "logged 1"));
+        assert_eq!(
+            record.key_values().map(|(key, value)| (key, value.to_string())).collect::<Vec<_>>(),
+            vec![("late", "evaluated".to_owned()), ("packet", "send".to_owned())]
+        );
+
+        let drain = CapturingDrain::new();
+        record.log_to(&drain).unwrap();
+        record.log_to(&drain).unwrap();
+        let replayed = drain.records();
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(replayed[1].message, "logged 1");
+        assert!(replayed[1].has_all(&[("packet", "send"), ("thread", "100"), ("version", "1")]));
+    }
+}
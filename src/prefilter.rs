@@ -0,0 +1,171 @@
+//! Drains of loggers whose context alone suppresses their records, see `KVFilter::child_drain`
+
+use std::fmt;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+
+use slog;
+
+#[cfg(feature = "std")]
+use overrides;
+use KVFilter;
+
+/// Drain of a logger logging to a filter, suppressing the records the spec decides by their
+/// key values without looking at them if the context of the logger alone suppresses them,
+/// e.g. by a negative key: these are counted as suppressed. The others, e.g. above the level
+/// of the spec, passed by rules or forced by `FORCE_KEY`, are decided by the filter, as are
+/// all the records once the spec is updated, or of loggers whose context doesn't suppress
+/// them.
+pub struct Prefiltered<D: slog::Drain> {
+    filter: KVFilter<D>,
+    /// level of the spec the context suppressed the records of, and `SharedSpec::replaced`
+    /// when it was current
+    suppressed: Option<(slog::Level, usize)>,
+}
+
+impl<D: slog::Drain> Prefiltered<D> {
+    /// drain of a logger of `logger_values` logging to `filter`
+    ///
+    /// Specs whose decisions depend on the record beyond its key values, e.g. by keys scoped
+    /// to some levels, `KeyPosition::Nearest`, triggers or a selector, are never prefiltered.
+    /// Values computed from each record by `slog::FnValue` are computed once, for an empty
+    /// record.
+    pub fn new(filter: KVFilter<D>, logger_values: &slog::OwnedKVList) -> Self {
+        static LOCATION: slog::RecordLocation = slog::RecordLocation {
+            file: file!(),
+            line: line!(),
+            column: column!(),
+            function: "",
+            module: module_path!(),
+        };
+
+        // read before the spec, so that a spec replacing it meanwhile isn't taken for it
+        let replaced = filter.spec.replaced();
+        let spec = filter.spec.load_full();
+        let decided_by_context = spec.selector.is_none()
+            && spec.triggers.is_empty()
+            && spec.pass_through.is_none()
+            && spec.budget.is_none()
+            && spec.log_compat.is_none()
+            && spec.level_maps.is_empty()
+            && !spec.compiled.is_scoped()
+            && !spec.compiled.has_nearest();
+        if !decided_by_context {
+            return Prefiltered {
+                filter,
+                suppressed: None,
+            };
+        }
+
+        let rs = slog::RecordStatic {
+            location: &LOCATION,
+            tag: "",
+            level: spec.level,
+        };
+        let suppressed = filter.is_context_suppressed(
+            &spec,
+            &slog::Record::new(&rs, &format_args!(""), slog::BorrowedKV(&())),
+            logger_values,
+        );
+        let suppressed = if suppressed { Some((spec.level, replaced)) } else { None };
+        Prefiltered { filter, suppressed }
+    }
+
+    /// whether the context of the logger suppresses its records, as long as the spec isn't
+    /// replaced
+    pub fn is_prefiltered(&self) -> bool {
+        self.suppressed.is_some()
+    }
+
+    /// whether `record` is to be decided by the filter rather than suppressed right away
+    fn is_undecided(&self, record: &slog::Record) -> bool {
+        let filter = &self.filter;
+        let (level, replaced) = match self.suppressed {
+            Some(suppressed) => suppressed,
+            None => return true,
+        };
+        #[cfg(feature = "std")]
+        let overridden = overrides::current(filter.min_level.id()).is_some();
+        #[cfg(not(feature = "std"))]
+        let overridden = false;
+        record.level() < level
+            || filter.spec.replaced() != replaced
+            || !filter.rules.is_empty()
+            || filter.learner.is_learning()
+            || overridden
+            || filter.forced(record).is_some()
+    }
+}
+
+impl<D: slog::Drain> UnwindSafe for Prefiltered<D> {}
+impl<D: slog::Drain> RefUnwindSafe for Prefiltered<D> {}
+
+impl<D: slog::Drain> fmt::Debug for Prefiltered<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Prefiltered")
+    }
+}
+
+impl<D: slog::Drain> slog::Drain for Prefiltered<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if self.is_undecided(record) {
+            return self.filter.log(record, logger_values);
+        }
+        self.filter.counters.suppressed();
+        Ok(None)
+    }
+
+    fn is_enabled(&self, level: slog::Level) -> bool {
+        self.filter.is_enabled(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::{self, Drain, Level, Logger};
+    use testing::CapturingDrain;
+    use {filter_list, KVFilter, Rule, ValueList, FORCE_KEY};
+
+    #[test]
+    fn childlogger() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])])))
+            .honor_force_key();
+        let handle = filter.handle();
+        let log = Logger::root(filter.clone().fuse(), o!("tenant" => "acme"));
+        let child = |values| {
+            let child = log.new(values);
+            let drain = filter.child_drain(child.list());
+            (drain.is_prefiltered(), Logger::root(drain.fuse(), slog::OwnedKV(child.list().clone())))
+        };
+        let (prefiltered, muted) = child(o!("deepcomp" => 1));
+        assert!(prefiltered);
+        let (prefiltered, other) = child(o!("deepcomp" => 2));
+        assert!(!prefiltered);
+
+        info!(muted, "not passing"; "user" => "admin");
+        info!(muted.new(o!("region" => "eu")), "not passing");
+        info!(muted, "passing, forced"; FORCE_KEY => "pass");
+        warn!(muted, "passing, above the level");
+        info!(other, "passing");
+        assert_eq!(drain.messages(), vec!["passing, forced", "passing, above the level", "passing"]);
+        let forced = &drain.records()[0];
+        assert_eq!((forced.get("tenant"), forced.get("deepcomp")), (Some("acme"), Some("1")));
+        assert_eq!(handle.stats().suppressed, 2);
+
+        drain.clear();
+        let rule = handle.add_rule(Rule::pass("user", "admin", Level::Info), None);
+        info!(muted, "passing, by the rule"; "user" => "admin");
+        handle.remove_rule(rule);
+        handle
+            .begin_update()
+            .remove_key(ValueList::Suppress, "deepcomp")
+            .commit();
+        info!(muted, "passing, by the updated spec");
+        assert_eq!(drain.messages(), vec!["passing, by the rule", "passing, by the updated spec"]);
+    }
+}
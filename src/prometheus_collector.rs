@@ -0,0 +1,226 @@
+//! Counts of the records decided by a filter as Prometheus metrics, see `PrometheusCollector`
+
+use std::collections::HashMap;
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{Counter, LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::{Encoder, TextEncoder};
+
+use KVFilterHandle;
+
+/// A `prometheus::core::Collector` of `KVFilterHandle::stats`, for registering with the
+/// `prometheus::Registry` of a metrics endpoint along with its other metrics:
+///
+/// ```text
+/// # HELP kvfilter_records_total Records decided by the filter.
+/// # TYPE kvfilter_records_total counter
+/// kvfilter_records_total{decision="passed"} 42
+/// kvfilter_records_total{decision="suppressed"} 1337
+/// kvfilter_records_total{decision="routed"} 0
+/// # HELP kvfilter_rule_matches_total Records matched by a runtime rule.
+/// # TYPE kvfilter_rule_matches_total counter
+/// kvfilter_rule_matches_total{rule="1",key="tenant",value="acme"} 7
+/// ```
+///
+/// followed by `kvfilter_key_records_total{key,value,decision}` of the keys of
+/// `KVFilter::count_by_key`, if any.
+///
+/// The metrics are read anew on each `collect`, i.e. on each scrape.
+#[derive(Clone)]
+pub struct PrometheusCollector {
+    handle: KVFilterHandle,
+    namespace: String,
+    labels: HashMap<String, String>,
+    descs: Vec<Desc>,
+}
+
+const RECORDS: (&str, &str, &[&str]) = ("records_total", "Records decided by the filter.", &["decision"]);
+const RULE_MATCHES: (&str, &str, &[&str]) = (
+    "rule_matches_total",
+    "Records matched by a runtime rule.",
+    &["rule", "key", "value"],
+);
+const KEY_RECORDS: (&str, &str, &[&str]) = (
+    "key_records_total",
+    "Records decided by the filter by the value of a key.",
+    &["key", "value", "decision"],
+);
+
+impl PrometheusCollector {
+    pub fn new(handle: &KVFilterHandle) -> Self {
+        PrometheusCollector {
+            handle: handle.clone(),
+            namespace: "kvfilter".to_owned(),
+            labels: HashMap::new(),
+            descs: Vec::new(),
+        }
+        .described()
+    }
+
+    /// prefix of the metric names, `kvfilter` by default
+    ///
+    /// # Panics
+    ///
+    /// if the metric names made with `namespace` aren't valid Prometheus metric names
+    pub fn namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.namespace = namespace.into();
+        self.described()
+    }
+
+    /// add `name="value"` to the labels of all the metrics, e.g. to tell the filters of
+    /// several loggers apart
+    ///
+    /// # Panics
+    ///
+    /// if `name` isn't a valid Prometheus label name, or is one of the labels of the metrics
+    pub fn const_label<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.labels.insert(name.into(), value.into());
+        self.described()
+    }
+
+    /// the metrics in the text exposition format, version 0.0.4, for a collector not
+    /// registered with a `prometheus::Registry`
+    pub fn encode(&self) -> String {
+        let mut out = Vec::new();
+        TextEncoder::new()
+            .encode(&self.collect(), &mut out)
+            .expect("metric families of the collector have metrics");
+        String::from_utf8(out).expect("text format is UTF-8")
+    }
+
+    fn described(mut self) -> Self {
+        self.descs = [RECORDS, RULE_MATCHES, KEY_RECORDS]
+            .iter()
+            .map(|&(name, help, labels)| {
+                let name = format!("{}_{}", self.namespace, name);
+                let labels = labels.iter().map(|&label| label.to_owned()).collect();
+                Desc::new(name, help.to_owned(), labels, self.labels.clone())
+                    .unwrap_or_else(|err| panic!("invalid metric of PrometheusCollector: {}", err))
+            })
+            .collect();
+        self
+    }
+
+    /// counters of the metric of `desc` by the values of its labels, or `None` if it has none
+    fn family<I>(desc: &Desc, counters: I) -> Option<MetricFamily>
+    where
+        I: IntoIterator<Item = (Vec<String>, u64)>,
+    {
+        let metrics: Vec<Metric> = counters
+            .into_iter()
+            .map(|(values, count)| {
+                let mut labels = desc.const_label_pairs.clone();
+                labels.extend(desc.variable_labels.iter().zip(values).map(|(name, value)| {
+                    let mut label = LabelPair::default();
+                    label.set_name(name.clone());
+                    label.set_value(value);
+                    label
+                }));
+                let mut counter = Counter::default();
+                counter.set_value(count as f64);
+                let mut metric = Metric::default();
+                metric.set_label(labels);
+                metric.set_counter(counter);
+                metric
+            })
+            .collect();
+        if metrics.is_empty() {
+            return None;
+        }
+        let mut family = MetricFamily::default();
+        family.set_name(desc.fq_name.clone());
+        family.set_help(desc.help.clone());
+        family.set_field_type(MetricType::COUNTER);
+        family.set_metric(metrics);
+        Some(family)
+    }
+}
+
+impl Collector for PrometheusCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let stats = self.handle.stats();
+        let records = [
+            ("passed", stats.passed),
+            ("suppressed", stats.suppressed),
+            ("routed", stats.routed),
+        ];
+        let records = records.iter().map(|&(decision, count)| (vec![decision.to_owned()], count));
+        let matches = stats.rules.iter().map(|(id, rule, count)| {
+            (vec![id.to_string(), rule.key().to_owned(), rule.value().to_owned()], *count)
+        });
+        let keys = stats.keys.iter().flat_map(|count| {
+            let labels = move |decision: &str| vec![count.key.clone(), count.value.clone(), decision.to_owned()];
+            vec![(labels("passed"), count.passed), (labels("suppressed"), count.suppressed)]
+        });
+
+        vec![
+            Self::family(&self.descs[0], records),
+            Self::family(&self.descs[1], matches),
+            Self::family(&self.descs[2], keys),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrometheusCollector;
+    use prometheus::{Encoder, Registry, TextEncoder};
+    use slog::{Drain, Level, Logger};
+    use testing::CountingDrain;
+    use {filter_list, KVFilter, Rule};
+
+    #[test]
+    fn prometheusmetrics() {
+        let filter = KVFilter::new(CountingDrain::new(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        handle.add_rule(Rule::pass("tenant", "ac\"me", Level::Info), None);
+        info!(log, "passing"; "thread" => "100");
+        info!(log, "passing"; "tenant" => "ac\"me");
+        info!(log, "not passing"; "thread" => "200");
+        info!(log, "not passing"; "thread" => "300");
+
+        let collector = PrometheusCollector::new(&handle);
+        assert_eq!(
+            collector.encode(),
+            "# HELP kvfilter_records_total Records decided by the filter.\n\
+             # TYPE kvfilter_records_total counter\n\
+             kvfilter_records_total{decision=\"passed\"} 2\n\
+             kvfilter_records_total{decision=\"suppressed\"} 2\n\
+             kvfilter_records_total{decision=\"routed\"} 0\n\
+             # HELP kvfilter_rule_matches_total Records matched by a runtime rule.\n\
+             # TYPE kvfilter_rule_matches_total counter\n\
+             kvfilter_rule_matches_total{rule=\"1\",key=\"tenant\",value=\"ac\\\"me\"} 1\n"
+        );
+
+        assert!(!collector.encode().contains("kvfilter_key_records_total"));
+
+        let registry = Registry::new();
+        registry
+            .register(Box::new(collector.namespace("app_log").const_label("logger", "http")))
+            .unwrap();
+        let mut out = Vec::new();
+        TextEncoder::new().encode(&registry.gather(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("app_log_records_total{logger=\"http\",decision=\"passed\"} 2\n"));
+        assert!(out.contains(
+            "app_log_rule_matches_total{logger=\"http\",rule=\"1\",key=\"tenant\",value=\"ac\\\"me\"} 1\n"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "not a valid label name")]
+    fn prometheusinvalidlabel() {
+        let filter = KVFilter::new(CountingDrain::new(), Level::Info);
+        let _ = PrometheusCollector::new(&filter.handle()).const_label("a-b", "c");
+    }
+}
@@ -0,0 +1,423 @@
+//! Filters of a running process published on a local socket, so that sidecar tooling finds
+//! them by the PID of the process to inspect and update them, see `Registry`
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use sync::Mutex;
+use {ConfigSource, FilterSpec, KVFilterError, KVFilterHandle, RemoteConfig};
+
+/// socket of the registry of the process `pid` in `dir`
+fn socket_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("kvfilter-{}.sock", pid))
+}
+
+/// requests served at the same time, those beyond refused as busy
+const MAX_SERVING: usize = 16;
+
+/// Configuration set by a client, applied by the `RemoteConfig` of its filter
+#[derive(Clone, Default)]
+struct Pushed(Arc<Mutex<Option<String>>>);
+
+impl ConfigSource for Pushed {
+    fn fetch(&mut self) -> io::Result<Option<String>> {
+        Ok(self.0.lock().take())
+    }
+}
+
+struct Published {
+    handle: KVFilterHandle,
+    pushed: Pushed,
+    config: RemoteConfig<Pushed>,
+}
+
+type Filters = Arc<Mutex<BTreeMap<String, Published>>>;
+
+/// Filters of this process published by their names on the Unix socket
+/// `kvfilter-<pid>.sock` of a directory, the temporary one by default, where the
+/// `RegistryClient` of the PID reaches them, e.g. that of the `kvfilterctl` example.
+///
+/// Requests and responses are text, a request on a single line:
+///
+/// * `list`: the names of the filters, one per line
+/// * `spec NAME`: the minimum level, the spec as the changes from a spec passing everything
+///   (see `FilterSpec::diff`) and the rules of the filter
+/// * `stats NAME`: the counts of the filter, see `KVFilterHandle::stats`
+/// * `set NAME CONFIG`: apply a configuration in the format of `KVFilter::directives` as
+///   `RemoteConfig` does, with the actor `registry`
+///
+/// A response starts with a line of `ok`, followed by the body, or with one of `error`
+/// and the reason. Each connection is served on a thread of its own, so a client slow to
+/// send its request holds up neither other clients nor dropping the registry, which removes
+/// the socket.
+///
+/// Only processes of the same effective user may connect: the socket is readable and
+/// writable by its owner only, and requests of peers of another user are refused.
+pub struct Registry {
+    path: PathBuf,
+    filters: Filters,
+    stopped: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Registry {
+    /// registry of this process, bound in the temporary directory
+    pub fn bind() -> Result<Self, KVFilterError> {
+        Registry::bind_in(env::temp_dir())
+    }
+
+    /// registry of this process, bound in `dir`, replacing the socket left by a process
+    /// of the same PID which didn't remove it
+    pub fn bind_in<P: AsRef<Path>>(dir: P) -> Result<Self, KVFilterError> {
+        let path = socket_path(dir.as_ref(), process::id());
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).map_err(KVFilterError::Registry)?;
+        // peers connecting before this are still refused by their user, see `serve`
+        if let Err(err) = fs::set_permissions(&path, fs::Permissions::from_mode(0o600)) {
+            let _ = fs::remove_file(&path);
+            return Err(KVFilterError::Registry(err));
+        }
+
+        let filters = Filters::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let filters = filters.clone();
+            let stopped = stopped.clone();
+            let serving = Arc::new(AtomicUsize::new(0));
+            thread::Builder::new()
+                .name("slog-kvfilter-registry".to_owned())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        if stopped.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        // a client going away has nothing to be told
+                        if let Ok(stream) = stream {
+                            spawn_serve(&filters, &serving, stream);
+                        }
+                    }
+                })
+                .map_err(KVFilterError::Spawn)?
+        };
+
+        Ok(Registry {
+            path,
+            filters,
+            stopped,
+            worker: Some(worker),
+        })
+    }
+
+    /// socket the registry is bound to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// publish the filter of `handle` as `name`, replacing the filter published as `name`
+    /// before if any. Names are single words, e.g. `app` or `audit-log`.
+    pub fn publish<N: Into<String>>(&self, name: N, handle: &KVFilterHandle) {
+        let pushed = Pushed::default();
+        self.filters.lock().insert(
+            name.into(),
+            Published {
+                handle: handle.clone(),
+                pushed: pushed.clone(),
+                config: RemoteConfig::new(handle, pushed).actor("registry"),
+            },
+        );
+    }
+
+    /// stop publishing the filter published as `name`, returning whether there was one. The
+    /// configuration set through the registry stays in effect.
+    pub fn unpublish(&self, name: &str) -> bool {
+        self.filters.lock().remove(name).is_some()
+    }
+}
+
+impl Drop for Registry {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // wakes the worker up to see it's stopped
+        let _ = UnixStream::connect(&self.path);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// count of the connections being served, decremented when its thread is done
+struct Serving(Arc<AtomicUsize>);
+
+impl Drop for Serving {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// serve `stream` on a thread of its own, or refuse it if `MAX_SERVING` are served already
+fn spawn_serve(filters: &Filters, serving: &Arc<AtomicUsize>, stream: UnixStream) {
+    if serving.fetch_add(1, Ordering::Relaxed) >= MAX_SERVING {
+        serving.fetch_sub(1, Ordering::Relaxed);
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+        let _ = (&stream).write_all(b"error busy\n");
+        return;
+    }
+    let guard = Serving(serving.clone());
+    let filters = filters.clone();
+    // a connection that couldn't be served is just closed
+    let _ = thread::Builder::new().name("slog-kvfilter-registry-client".to_owned()).spawn(move || {
+        let _guard = guard;
+        let _ = serve(&filters, stream);
+    });
+}
+
+/// effective user of the process at the other end of `stream`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // the kernel fills in at most `len` bytes of `cred`, a valid `ucred`
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res == 0 {
+        Ok(cred.uid)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// effective user of the process at the other end of `stream`
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let (mut uid, mut gid) = (0, 0);
+    // only writes the two ids
+    match unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } {
+        0 => Ok(uid),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn serve(filters: &Filters, stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    // safe to call, reads the user of this process only
+    if peer_uid(&stream)? != unsafe { libc::geteuid() } {
+        return (&stream).write_all(b"error permission denied\n");
+    }
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+
+    let response = match respond(filters, request.trim_end()) {
+        Ok(body) => format!("ok\n{}", body),
+        Err(reason) => format!("error {}\n", reason),
+    };
+    (&stream).write_all(response.as_bytes())
+}
+
+fn respond(filters: &Filters, request: &str) -> Result<String, String> {
+    let mut words = request.splitn(3, ' ');
+    let command = words.next().unwrap_or("");
+    if command == "list" {
+        return Ok(filters.lock().keys().map(|name| format!("{}\n", name)).collect());
+    }
+
+    let name = words.next().ok_or_else(|| format!("no filter named by {}", command))?;
+    let mut filters = filters.lock();
+    let published = filters.get_mut(name).ok_or_else(|| format!("no filter {}", name))?;
+    let mut body = String::new();
+    match command {
+        "spec" => {
+            let handle = &published.handle;
+            let spec = handle.spec();
+            let _ = writeln!(body, "min_level {}", handle.min_level().as_str());
+            for change in FilterSpec::new(spec.level).diff(&spec) {
+                let _ = writeln!(body, "{}", change);
+            }
+            for (id, rule) in handle.rules() {
+                let _ = writeln!(body, "rule {} {:?}", id, rule);
+            }
+        }
+        "stats" => {
+            let stats = published.handle.stats();
+            let _ = writeln!(body, "passed {}", stats.passed);
+            let _ = writeln!(body, "suppressed {}", stats.suppressed);
+            let _ = writeln!(body, "routed {}", stats.routed);
+            let _ = writeln!(body, "match_errors {}", stats.match_errors);
+            let _ = writeln!(body, "drain_errors {}", stats.drain_errors);
+            let _ = writeln!(body, "invalid_directives {}", stats.invalid_directives);
+            let _ = writeln!(body, "memo_hits {}", stats.memo.hits);
+            let _ = writeln!(body, "memo_misses {}", stats.memo.misses);
+        }
+        "set" => {
+            *published.pushed.0.lock() = Some(words.next().unwrap_or("").to_owned());
+            published.config.poll().map_err(|err| err.to_string())?;
+        }
+        _ => return Err(format!("unknown request {}", command)),
+    }
+    Ok(body)
+}
+
+/// Client of the `Registry` of a process, e.g. of a `kvfilterctl` tool
+#[derive(Clone, Debug)]
+pub struct RegistryClient {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl RegistryClient {
+    /// client of the registry of the process `pid`, bound in the temporary directory
+    pub fn new(pid: u32) -> Self {
+        RegistryClient::in_dir(env::temp_dir(), pid)
+    }
+
+    /// client of the registry of the process `pid`, bound in `dir`
+    pub fn in_dir<P: AsRef<Path>>(dir: P, pid: u32) -> Self {
+        RegistryClient {
+            path: socket_path(dir.as_ref(), pid),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// give up on requests taking longer than `timeout` between reads or writes, 5 seconds
+    /// by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// names of the filters the process publishes
+    pub fn list(&self) -> Result<Vec<String>, KVFilterError> {
+        Ok(self.request("list")?.lines().map(str::to_owned).collect())
+    }
+
+    /// minimum level, spec and rules of the filter `name`, as text
+    pub fn spec(&self, name: &str) -> Result<String, KVFilterError> {
+        self.request(&format!("spec {}", name))
+    }
+
+    /// counts of the filter `name`, as text
+    pub fn stats(&self, name: &str) -> Result<String, KVFilterError> {
+        self.request(&format!("stats {}", name))
+    }
+
+    /// apply `config`, in the format of `KVFilter::directives`, to the filter `name`
+    pub fn set(&self, name: &str, config: &str) -> Result<(), KVFilterError> {
+        self.request(&format!("set {} {}", name, config)).map(|_| ())
+    }
+
+    /// body of the response to `request`, see `Registry`
+    pub fn request(&self, request: &str) -> Result<String, KVFilterError> {
+        if request.contains('\n') {
+            return Err(KVFilterError::Rejected("request of more than one line".to_owned()));
+        }
+        let mut stream = UnixStream::connect(&self.path).map_err(KVFilterError::Registry)?;
+        let exchange = |stream: &mut UnixStream| -> io::Result<String> {
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            stream.write_all(format!("{}\n", request).as_bytes())?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+        let response = exchange(&mut stream).map_err(KVFilterError::Registry)?;
+
+        match response.split_once('\n') {
+            Some(("ok", body)) => Ok(body.to_owned()),
+            Some((status, _)) if status.starts_with("error ") => {
+                Err(KVFilterError::Rejected(status["error ".len()..].to_owned()))
+            }
+            _ => Err(KVFilterError::Registry(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed response",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Registry, RegistryClient};
+    use slog::{Drain, Level, Logger};
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::time::{Duration, Instant};
+    use testing::CountingDrain;
+    use {filter_list, KVFilter, KVFilterError};
+
+    #[test]
+    fn registry() {
+        let dir = env::temp_dir().join(format!("slog-kvfilter-registry-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        let registry = Registry::bind_in(&dir).unwrap();
+        registry.publish("app", &handle);
+        assert_eq!(fs::metadata(registry.path()).unwrap().permissions().mode() & 0o777, 0o600);
+        let client = RegistryClient::in_dir(&dir, process::id());
+        // a client not sending its request holds up no other one
+        let idle = UnixStream::connect(registry.path()).unwrap();
+        let started = Instant::now();
+        assert_eq!(client.list().unwrap(), vec!["app"]);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        info!(log, "passing"; "tenant" => "acme");
+        info!(log, "not passing"; "tenant" => "globex");
+        client.set("app", "tenant=globex").unwrap();
+        info!(log, "passing, by the rule"; "tenant" => "globex");
+        assert_eq!(drain.count(), 2);
+
+        let spec = client.spec("app").unwrap();
+        assert!(spec.lines().any(|line| line == "+ Pass tenant: acme"), "{}", spec);
+        assert!(spec.lines().any(|line| line.starts_with("rule ")), "{}", spec);
+        assert!(client.stats("app").unwrap().lines().any(|line| line == "passed 2"));
+        assert!(handle.audit_trail().iter().all(|e| e.actor.as_deref() == Some("registry")));
+
+        match client.set("app", "=") {
+            Err(KVFilterError::Rejected(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match client.stats("cache") {
+            Err(KVFilterError::Rejected(ref reason)) if reason == "no filter cache" => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let path = registry.path().to_owned();
+        let started = Instant::now();
+        drop(registry);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(!path.exists());
+        drop(idle);
+        match client.list() {
+            Err(KVFilterError::Registry(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        let _ = fs::remove_dir(&dir);
+    }
+}
@@ -0,0 +1,422 @@
+//! Filter configuration fetched from a central config service, applied through a handle
+//! whenever it changes
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use regex::Regex;
+
+use directives;
+use sync::Mutex;
+use {FilterSpec, KVFilterError, KVFilterHandle, Placeholders, RuleId};
+
+/// Source of a filter configuration in the format of `KVFilter::directives`, e.g.
+/// `warn,my_app::db=debug,tenant=acme`, see `RemoteConfig`
+pub trait ConfigSource: Send {
+    /// the configuration, or `None` if it hasn't changed since the last fetch
+    fn fetch(&mut self) -> io::Result<Option<String>>;
+}
+
+/// `ConfigSource` fetching the configuration by HTTP `GET`s of a plain `http://` URL, e.g.
+/// `http://127.0.0.1:8500/v1/kv/logging/filter?raw` of a Consul agent, or an S3 bucket or
+/// etcd gateway behind a TLS terminating proxy.
+///
+/// The `ETag` of the response is sent back as `If-None-Match`, so that an unchanged
+/// configuration costs the service a `304 Not Modified` only.
+pub struct HttpSource {
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+    etag: Option<String>,
+}
+
+impl HttpSource {
+    /// source fetching `url`, of the form `http://host[:port][/path]`
+    pub fn new(url: &str) -> Result<Self, KVFilterError> {
+        let invalid = || KVFilterError::InvalidUrl(url.to_owned());
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(HttpSource {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+            timeout: Duration::from_secs(10),
+            etag: None,
+        })
+    }
+
+    /// give up on requests taking longer than `timeout` to connect, or between reads,
+    /// 10 seconds by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host not resolved");
+        for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl ConfigSource for HttpSource {
+    fn fetch(&mut self) -> io::Result<Option<String>> {
+        let mut stream = self.connect()?;
+        // HTTP/1.0 spares decoding chunked responses, the connection is closed after each
+        let mut request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n", self.path, self.host);
+        if let Some(ref etag) = self.etag {
+            request.push_str(&format!("If-None-Match: {}\r\n", etag));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = BufReader::new(stream);
+        let mut line = String::new();
+        response.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed status line"))?;
+
+        let mut etag = None;
+        loop {
+            line.clear();
+            if response.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("etag") {
+                    etag = Some(value.trim().to_owned());
+                }
+            }
+        }
+
+        match status {
+            304 => Ok(None),
+            200 => {
+                let mut body = String::new();
+                response.read_to_string(&mut body)?;
+                self.etag = etag;
+                Ok(Some(body))
+            }
+            status => Err(io::Error::other(format!("unexpected status {}", status))),
+        }
+    }
+}
+
+/// reporter of the invalid directives of configurations, see `RemoteConfig::skip_invalid`
+type Report = Box<dyn FnMut(&KVFilterError) + Send>;
+
+/// Applies the configuration of a `ConfigSource` through a handle on top of the spec the
+/// filter had when created, so that the service needs to hold the changes only:
+///
+/// * the spec, with the module levels and the message regex of the configuration, is
+///   replaced at once
+/// * the level of a bare `warn` directive sets the minimum level
+/// * the rules of `key=value` clauses replace the ones of the previous configuration
+///
+/// The changes are recorded in the audit trail with the actor `remote config`, see `actor`.
+pub struct RemoteConfig<S: ConfigSource> {
+    source: S,
+    handle: KVFilterHandle,
+    base: Arc<FilterSpec>,
+    rules: Vec<RuleId>,
+    placeholders: Option<Placeholders>,
+    /// reports the directives left out of a configuration, see `skip_invalid`
+    on_invalid: Option<Report>,
+}
+
+impl<S: ConfigSource + 'static> RemoteConfig<S> {
+    pub fn new(handle: &KVFilterHandle, source: S) -> Self {
+        RemoteConfig {
+            source,
+            handle: handle.with_actor("remote config"),
+            base: handle.spec(),
+            rules: Vec::new(),
+            placeholders: None,
+            on_invalid: None,
+        }
+    }
+
+    /// record the changes in the audit trail with `actor` rather than `remote config`
+    pub fn actor<A: Into<String>>(mut self, actor: A) -> Self {
+        self.handle = self.handle.with_actor(actor);
+        self
+    }
+
+    /// resolve the `${NAME}` placeholders of each configuration fetched, see
+    /// `KVFilter::directives_with`. A configuration with a placeholder without a value is
+    /// not applied at all.
+    pub fn placeholders(mut self, placeholders: Placeholders) -> Self {
+        self.placeholders = Some(placeholders);
+        self
+    }
+
+    /// apply the valid directives of a configuration with invalid ones, e.g. of a typo,
+    /// leaving the others out, rather than keep the previous configuration in effect.
+    /// Each directive left out, or an invalid regex, is passed to `report` and counted
+    /// in `FilterStats::invalid_directives`. Placeholders still have to be resolved.
+    pub fn skip_invalid<F: FnMut(&KVFilterError) + Send + 'static>(mut self, report: F) -> Self {
+        self.on_invalid = Some(Box::new(report));
+        self
+    }
+
+    /// fetch the configuration and apply it if changed, returning whether it was. A
+    /// configuration that doesn't parse is not applied at all, unless `skip_invalid`.
+    pub fn poll(&mut self) -> Result<bool, KVFilterError> {
+        let config = match self.source.fetch().map_err(KVFilterError::Fetch)? {
+            Some(config) => config,
+            None => return Ok(false),
+        };
+        let config = match self.placeholders {
+            Some(ref placeholders) => placeholders.resolve(&config)?,
+            None => config,
+        };
+        let (directives, regex) = match self.on_invalid {
+            Some(ref mut report) => {
+                let (directives, mut errors) = directives::parse_valid(&config);
+                let regex = match directives.regex {
+                    Some(ref regex) => Regex::new(regex).map_err(|err| errors.push(err.into())).ok(),
+                    None => None,
+                };
+                for error in &errors {
+                    report(error);
+                }
+                self.handle.counters.invalid_directives(errors.len());
+                (directives, regex)
+            }
+            None => {
+                let directives = directives::parse(&config)?;
+                let regex = match directives.regex {
+                    Some(ref regex) => Some(Regex::new(regex)?),
+                    None => None,
+                };
+                (directives, regex)
+            }
+        };
+
+        let mut spec = (*self.base).clone();
+        spec.modules.extend(directives.modules);
+        if regex.is_some() {
+            spec.regex = regex;
+        }
+        self.handle.set_spec(Arc::new(spec));
+
+        if let Some(level) = directives.default {
+            self.handle.set_min_level(level);
+        }
+        for id in self.rules.drain(..) {
+            self.handle.remove_rule(id);
+        }
+        for rule in directives.rules {
+            self.rules.push(self.handle.add_rule(rule, None));
+        }
+        Ok(true)
+    }
+
+    /// poll every `interval` on a thread of its own until the returned poller is dropped
+    pub fn spawn(mut self, interval: Duration) -> ConfigPoller {
+        let last_error = Arc::new(Mutex::new(None));
+        let (stop, stopped) = mpsc::channel();
+
+        let errors = last_error.clone();
+        let worker = thread::spawn(move || loop {
+            let result = self.poll();
+            *errors.lock() = result.err().map(|err| err.to_string());
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => break,
+            }
+        });
+
+        ConfigPoller {
+            stop: Some(stop),
+            worker: Some(worker),
+            last_error,
+        }
+    }
+}
+
+/// Thread polling a `ConfigSource`, see `RemoteConfig::spawn`. Dropping it stops the
+/// polling, waiting for a fetch in progress.
+pub struct ConfigPoller {
+    stop: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl ConfigPoller {
+    /// why the last poll failed, `None` if it succeeded, the previous configuration staying in
+    /// effect
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().clone()
+    }
+}
+
+impl Drop for ConfigPoller {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigSource, HttpSource, RemoteConfig};
+    use slog::{Drain, Level, Logger};
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use testing::CountingDrain;
+    use {filter_list, KVFilter, KVFilterError, Placeholders};
+
+    struct Scripted(VecDeque<Option<&'static str>>);
+
+    impl ConfigSource for Scripted {
+        fn fetch(&mut self) -> io::Result<Option<String>> {
+            Ok(self.0.pop_front().and_then(|config| config.map(str::to_owned)))
+        }
+    }
+
+    #[test]
+    fn remoteconfig() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Warning)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        let source = Scripted(vec![Some("warn,tenant=acme"), None, Some("tenant=globex"), Some("=")].into());
+        let mut config = RemoteConfig::new(&handle, source);
+
+        assert!(config.poll().unwrap());
+        assert_eq!(handle.min_level(), Level::Warning);
+        warn!(log, "passing"; "tenant" => "acme");
+        warn!(log, "passing"; "thread" => "100");
+        warn!(log, "not passing"; "tenant" => "globex");
+
+        assert!(!config.poll().unwrap());
+        assert!(config.poll().unwrap());
+        warn!(log, "not passing"; "tenant" => "acme");
+        warn!(log, "passing"; "tenant" => "globex");
+
+        match config.poll() {
+            Err(KVFilterError::InvalidDirective(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        warn!(log, "passing, previous configuration in effect"; "tenant" => "globex");
+
+        assert_eq!(drain.count(), 4);
+        assert!(handle.audit_trail().iter().all(|e| e.actor.as_deref() == Some("remote config")));
+    }
+
+    #[test]
+    fn skipinvalidremoteconfig() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Warning)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])));
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let source = Scripted(vec![Some("tenant=acme"), Some("info,=,tenant=globex,off/(")].into());
+        let mut config = RemoteConfig::new(&handle, source).skip_invalid({
+            let reported = reported.clone();
+            move |err| reported.lock().unwrap().push(err.to_string())
+        });
+
+        assert!(config.poll().unwrap());
+        assert!(config.poll().unwrap());
+        assert_eq!(handle.min_level(), Level::Info);
+        info!(log, "passing, by the valid rule"; "tenant" => "globex");
+        info!(log, "not passing, previous rule removed"; "tenant" => "acme");
+        info!(log, "passing, no regex applied"; "thread" => "100");
+        assert_eq!(drain.count(), 2);
+        assert_eq!(reported.lock().unwrap().len(), 3);
+        assert_eq!(handle.stats().invalid_directives, 3);
+    }
+
+    #[test]
+    fn placeholderremoteconfig() {
+        let drain = CountingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Warning);
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        let source = Scripted(vec![Some("info,tenant=${TENANT}"), Some("tenant=${REGION}")].into());
+        let mut config = RemoteConfig::new(&handle, source).placeholders(Placeholders::new().value("TENANT", "acme"));
+
+        assert!(config.poll().unwrap());
+        debug!(log, "passing"; "tenant" => "acme");
+        debug!(log, "not passing"; "tenant" => "${TENANT}");
+
+        match config.poll() {
+            Err(KVFilterError::UndefinedPlaceholder(ref name)) if name == "REGION" => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        debug!(log, "passing, previous configuration in effect"; "tenant" => "acme");
+        assert_eq!(drain.count(), 2);
+    }
+
+    #[test]
+    fn httpsource() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in &[
+                "HTTP/1.0 200 OK\r\nETag: \"v1\"\r\n\r\nwarn,tenant=acme",
+                "HTTP/1.0 304 Not Modified\r\nETag: \"v1\"\r\n\r\n",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let len = stream.read(&mut request).unwrap();
+                requests.push(String::from_utf8_lossy(&request[..len]).into_owned());
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let mut source = HttpSource::new(&format!("http://127.0.0.1:{}/v1/kv/filter?raw", port)).unwrap();
+        assert_eq!(source.fetch().unwrap().as_deref(), Some("warn,tenant=acme"));
+        assert_eq!(source.fetch().unwrap(), None);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /v1/kv/filter?raw HTTP/1.0\r\n"));
+        assert!(!requests[0].contains("If-None-Match"));
+        assert!(requests[1].contains("If-None-Match: \"v1\"\r\n"));
+
+        assert!(HttpSource::new("https://example.com/").is_err());
+        assert!(HttpSource::new("http://:80/").is_err());
+    }
+}
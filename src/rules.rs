@@ -0,0 +1,588 @@
+//! Rules added to a filter at runtime through its `KVFilterHandle`
+
+use std::cmp;
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::env;
+use std::fmt;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog;
+use slog::KV;
+
+use caps::{GroupCap, GroupCounts};
+use clock::{Clock, DefaultClock};
+use correlation::CorrelationRegistry;
+use sync::{Mutex, RwLock};
+use FilterStr;
+#[cfg(feature = "chrono")]
+use schedule::{Schedule, ScheduleState};
+
+/// Rule letting records with a key of a given value pass down to a level, e.g.
+/// "`tenant=acme` at debug"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    key: FilterStr,
+    value: FilterStr,
+    level: slog::Level,
+    #[cfg(feature = "chrono")]
+    schedule: Option<Schedule>,
+    condition: Option<Condition>,
+    /// name of the drain the rule routes records to, see `Rule::to`
+    destination: Option<FilterStr>,
+    /// level the rule forwards more important records at, see `Rule::demote_to`
+    demotion: Option<slog::Level>,
+}
+
+/// Condition of the environment a rule only applies in, see `Rule::when`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// the environment variable is set, to anything, never without `std`
+    Env(FilterStr),
+    /// built with `debug_assertions`, i.e. usually not a release build
+    DebugAssertions,
+}
+
+impl Condition {
+    pub fn holds(&self) -> bool {
+        match *self {
+            #[cfg(feature = "std")]
+            Condition::Env(ref var) => env::var_os(&**var).is_some(),
+            #[cfg(not(feature = "std"))]
+            Condition::Env(_) => false,
+            Condition::DebugAssertions => cfg!(debug_assertions),
+        }
+    }
+}
+
+impl Rule {
+    /// pass records at least as important as `level` having `key` of `value`, on the
+    /// record or in the logger context, regardless of the other filters
+    pub fn pass<K: Into<FilterStr>, V: Into<FilterStr>>(key: K, value: V, level: slog::Level) -> Self {
+        Rule {
+            key: key.into(),
+            value: value.into(),
+            level,
+            #[cfg(feature = "chrono")]
+            schedule: None,
+            condition: None,
+            destination: None,
+            demotion: None,
+        }
+    }
+
+    /// send the matching records to the drain registered as `destination` by
+    /// `KVFilter::route_to` instead of the filtered drain, e.g. `user=admin` records to an
+    /// audit file. Records matching no such rule, or naming a destination not registered,
+    /// are filtered as usual.
+    pub fn to<S: Into<FilterStr>>(mut self, destination: S) -> Self {
+        self.destination = Some(destination.into());
+        self
+    }
+
+    /// forward the matching records more important than `level` at `level`, e.g. noisy
+    /// `Info` records of a third party library as `Debug`, leaving it to the level based
+    /// drains downstream to keep or drop them. Routed records are sent as they are.
+    pub fn demote_to(mut self, level: slog::Level) -> Self {
+        self.demotion = Some(level);
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// only apply the rule if `condition` holds when it's added, e.g.
+    /// `Condition::Env("ENABLE_DB_TRACE".into())`, so that one configuration serves all the
+    /// environments. Rules whose condition doesn't hold aren't added at all.
+    pub fn when(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// only apply the rule within the time window of `schedule`, e.g. verbose billing
+    /// logs during the nightly reconciliation run
+    #[cfg(feature = "chrono")]
+    pub fn during(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+}
+
+/// When a record with a key of a given value comes at least at a level, enable a group of
+/// rules for a while, e.g. the first `subsystem=db` error turning on debug logs of
+/// `subsystem=db` for two minutes.
+///
+/// The trigger doesn't fire again until the rules it enabled have all expired.
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    /// identifies the state of the trigger, shared by its clones
+    id: u64,
+    key: FilterStr,
+    value: FilterStr,
+    level: slog::Level,
+    rules: Vec<Rule>,
+    duration: Option<Duration>,
+    records: Option<usize>,
+}
+
+static TRIGGER_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Trigger {
+    /// fire on records at least as important as `level` having `key` of `value`, whether
+    /// they pass or not
+    pub fn on<K: Into<FilterStr>, V: Into<FilterStr>>(key: K, value: V, level: slog::Level) -> Self {
+        Trigger {
+            id: TRIGGER_ID.fetch_add(1, Ordering::Relaxed),
+            key: key.into(),
+            value: value.into(),
+            level,
+            rules: Vec::new(),
+            duration: None,
+            records: None,
+        }
+    }
+
+    /// rule to enable when fired
+    pub fn enable(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// keep the rules enabled for `duration`
+    pub fn for_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// keep each of the rules enabled for `records` records passing thanks to it
+    pub fn for_records(mut self, records: usize) -> Self {
+        self.records = Some(records);
+        self
+    }
+
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// whether a record of `level` with `value` of the key fires the trigger
+    pub(crate) fn fires(&self, level: slog::Level, value: &str) -> bool {
+        level.is_at_least(self.level) && self.value == value
+    }
+}
+
+/// How long suppression stays off once a record opens the pass-through window, see
+/// `KVFilter::pass_through_after`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Duration(Duration),
+    Records(usize),
+}
+
+/// Identifies a rule added by `KVFilterHandle::add_rule`, to remove it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RuleId(u64);
+
+impl fmt::Display for RuleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+struct ActiveRule {
+    id: RuleId,
+    rule: Rule,
+    /// `Clock::now` when the rule expires
+    expires: Option<Duration>,
+    /// number of records the rule passes before it expires
+    remaining: Option<AtomicUsize>,
+    /// number of records the rule matched
+    matched: AtomicU64,
+    #[cfg(feature = "chrono")]
+    schedule: Option<ScheduleState>,
+}
+
+impl ActiveRule {
+    /// whether this rule passes all the records `other` does, for at least as long
+    fn shadows(&self, other: &ActiveRule) -> bool {
+        let (rule, other_rule) = (&self.rule, &other.rule);
+        #[cfg(feature = "chrono")]
+        let same_schedule = rule.schedule.is_none() || rule.schedule == other_rule.schedule;
+        #[cfg(not(feature = "chrono"))]
+        let same_schedule = true;
+
+        rule.key == other_rule.key
+            && rule.value == other_rule.value
+            && rule.level >= other_rule.level
+            && rule.destination == other_rule.destination
+            && rule.demotion == other_rule.demotion
+            && same_schedule
+            && self.remaining.is_none()
+            && match (self.expires, other.expires) {
+                (None, _) => true,
+                (Some(expires), Some(other_expires)) => expires >= other_expires,
+                (Some(_), None) => false,
+            }
+    }
+
+    fn is_expired(&self, now: Option<Duration>) -> bool {
+        self.remaining
+            .as_ref()
+            .is_some_and(|remaining| remaining.load(Ordering::Relaxed) == 0)
+            || now.is_some_and(|now| self.expires.is_some_and(|expires| expires <= now))
+    }
+
+    #[cfg(feature = "chrono")]
+    fn in_schedule(&self, clock: &dyn Clock) -> bool {
+        self.schedule.as_ref().is_none_or(|schedule| schedule.is_active(clock))
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    fn in_schedule(&self, _clock: &dyn Clock) -> bool {
+        true
+    }
+}
+
+/// Rules of a filter shared with its handles
+pub struct RuntimeRules {
+    /// number of rules, checked before taking the lock so that filters without rules
+    /// don't pay for them
+    len: AtomicUsize,
+    rules: RwLock<Vec<ActiveRule>>,
+    next_id: Mutex<u64>,
+    clock: RwLock<Arc<dyn Clock>>,
+    traced: CorrelationRegistry,
+    /// rules enabled by each fired trigger
+    triggered: Mutex<HashMap<u64, Vec<RuleId>>>,
+    /// nanoseconds of `Clock::now` until which the pass-through window is open, 0 if closed
+    window_until: AtomicU64,
+    /// records the pass-through window is open for
+    window_records: AtomicUsize,
+    caps: GroupCounts,
+}
+
+impl Default for RuntimeRules {
+    fn default() -> Self {
+        RuntimeRules {
+            len: AtomicUsize::new(0),
+            rules: RwLock::new(Vec::new()),
+            next_id: Mutex::new(0),
+            clock: RwLock::new(Arc::new(DefaultClock::default())),
+            traced: CorrelationRegistry::default(),
+            triggered: Mutex::new(HashMap::new()),
+            window_until: AtomicU64::new(0),
+            window_records: AtomicUsize::new(0),
+            caps: GroupCounts::default(),
+        }
+    }
+}
+
+impl RuntimeRules {
+    pub fn set_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.write() = clock;
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.read().now()
+    }
+
+    /// wall time of the clock, see `Clock::since_epoch`
+    pub fn since_epoch(&self) -> Duration {
+        self.clock.read().since_epoch()
+    }
+
+    pub fn add(&self, rule: Rule, ttl: Option<Duration>) -> RuleId {
+        self.add_limited(rule, ttl, None)
+    }
+
+    /// add a rule expiring after `ttl` or after passing `records`, whichever comes first
+    fn add_limited(&self, rule: Rule, ttl: Option<Duration>, records: Option<usize>) -> RuleId {
+        let id = {
+            let mut next_id = self.next_id.lock();
+            *next_id += 1;
+            RuleId(*next_id)
+        };
+        if !rule.condition.as_ref().is_none_or(Condition::holds) {
+            return id;
+        }
+
+        let mut rules = self.rules.write();
+        rules.push(ActiveRule {
+            id,
+            #[cfg(feature = "chrono")]
+            schedule: rule.schedule.clone().map(ScheduleState::new),
+            rule,
+            expires: ttl.map(|ttl| self.now().saturating_add(ttl)),
+            remaining: records.map(AtomicUsize::new),
+            matched: AtomicU64::new(0),
+        });
+        self.len.store(rules.len(), Ordering::Relaxed);
+        id
+    }
+
+    /// remove the rule, returning whether it was still there
+    pub fn remove(&self, id: RuleId) -> bool {
+        let mut rules = self.rules.write();
+        let len = rules.len();
+        rules.retain(|active| active.id != id);
+        self.len.store(rules.len(), Ordering::Relaxed);
+        rules.len() != len
+    }
+
+    /// enable the rules of `trigger` unless they are still enabled from the last time
+    pub fn fire(&self, trigger: &Trigger) {
+        let mut triggered = self.triggered.lock();
+
+        if let Some(ids) = triggered.get(&trigger.id) {
+            self.prune();
+            let rules = self.rules.read();
+            if rules.iter().any(|active| ids.contains(&active.id)) {
+                return;
+            }
+        }
+
+        let ids = trigger
+            .rules
+            .iter()
+            .map(|rule| self.add_limited(rule.clone(), trigger.duration, trigger.records))
+            .collect();
+        triggered.insert(trigger.id, ids);
+    }
+
+    /// open the pass-through window, or renew it if open
+    pub fn open_window(&self, window: Window) {
+        match window {
+            Window::Duration(duration) => {
+                let until = self.now().saturating_add(duration).as_nanos().min(u128::from(u64::MAX)) as u64;
+                self.window_until.fetch_max(until, Ordering::Relaxed);
+            }
+            Window::Records(records) => {
+                self.window_records.fetch_max(records, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// whether there are no rules and no values traced, passing records the spec doesn't
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Relaxed) == 0 && self.traced.is_empty()
+    }
+
+    /// whether the pass-through window is open, using up one of its records if counted
+    pub fn in_window(&self) -> bool {
+        if self
+            .window_records
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return true;
+        }
+
+        // don't read the clock unless needed
+        let until = self.window_until.load(Ordering::Relaxed);
+        until != 0 && self.now().as_nanos() < u128::from(until)
+    }
+
+    /// whether a record with `value` of the key of `cap` is within the cap, counting it
+    pub fn admit(&self, cap: &GroupCap, value: String) -> bool {
+        self.caps.admit(cap, value, self.now())
+    }
+
+    /// pass records carrying `value` of `key` at any level for `ttl`
+    pub fn trace(&self, key: FilterStr, value: String, ttl: Duration) {
+        let now = self.now();
+        self.traced.insert(key, value, now.saturating_add(ttl), now);
+    }
+
+    pub fn untrace(&self, key: &str, value: &str) -> bool {
+        self.traced.remove(key, value)
+    }
+
+    pub fn set_trace_capacity(&self, capacity: usize) {
+        self.traced.set_capacity(capacity);
+    }
+
+    pub fn set_trace_capacity_per_key(&self, capacity: usize) {
+        self.traced.set_per_key_capacity(capacity);
+    }
+
+    /// traced values dropped to make room for others
+    pub fn trace_evictions(&self) -> u64 {
+        self.traced.evictions()
+    }
+
+    /// rules which haven't expired yet
+    pub fn active(&self) -> Vec<(RuleId, Rule)> {
+        self.prune();
+        let rules = self.rules.read();
+        rules
+            .iter()
+            .map(|active| (active.id, active.rule.clone()))
+            .collect()
+    }
+
+    /// rules added which haven't expired yet, with the number of records they matched
+    pub fn counts(&self) -> Vec<(RuleId, Rule, u64)> {
+        self.prune();
+        let rules = self.rules.read();
+        rules
+            .iter()
+            .map(|active| (active.id, active.rule.clone(), active.matched.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// rules passing nothing that another rule doesn't pass already, with the other rule;
+    /// of equivalent rules, the later ones are reported
+    pub fn shadowed(&self) -> Vec<(RuleId, RuleId)> {
+        self.prune();
+        let rules = self.rules.read();
+        rules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, shadowed)| {
+                rules
+                    .iter()
+                    .enumerate()
+                    .find(|&(other, active)| {
+                        other != index
+                            && active.shadows(shadowed)
+                            && (other < index || !shadowed.shadows(active))
+                    })
+                    .map(|(_, active)| (shadowed.id, active.id))
+            })
+            .collect()
+    }
+
+    /// least important level of the rules, if any
+    pub fn lowest(&self) -> Option<slog::Level> {
+        if !self.traced.is_empty() {
+            return Some(slog::Level::Trace);
+        }
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let rules = self.rules.read();
+        rules.iter().map(|active| active.rule.level).max()
+    }
+
+    fn prune(&self) {
+        let now = {
+            let rules = self.rules.read();
+            // don't read the clock unless needed
+            let now = if rules.iter().any(|active| active.expires.is_some()) {
+                Some(self.now())
+            } else {
+                None
+            };
+            if !rules.iter().any(|active| active.is_expired(now)) {
+                return;
+            }
+            now
+        };
+
+        let mut rules = self.rules.write();
+        rules.retain(|active| !active.is_expired(now));
+        self.len.store(rules.len(), Ordering::Relaxed);
+    }
+
+    /// level `record` passes at if a rule or a traced value passes it, see
+    /// `Rule::demote_to`
+    pub fn passed_at(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Option<slog::Level> {
+        if !self.traced.is_empty() && self.traced.is_match(record, logger_values, self.now()) {
+            return Some(record.level());
+        }
+        let (_, demotion) = self.matching(record, logger_values, false)?;
+        Some(demotion.map_or(record.level(), |demotion| cmp::max(record.level(), demotion)))
+    }
+
+    /// destination of the routing rule matching `record`, see `Rule::to`
+    pub fn route(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) -> Option<FilterStr> {
+        self.matching(record, logger_values, true).and_then(|(destination, _)| destination)
+    }
+
+    /// destination and demotion of the rule matching `record`, of the routing rules only if
+    /// `routed`, of the others otherwise
+    fn matching(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+        routed: bool,
+    ) -> Option<(Option<FilterStr>, Option<slog::Level>)> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        self.prune();
+
+        let rules = self.rules.read();
+        let clock = self.clock.read();
+        let mut ser = RuleSerializer {
+            rules: &rules,
+            clock: &**clock,
+            level: record.level(),
+            routed,
+            value: String::new(),
+            matched: None,
+        };
+        // an error means a rule matched already, or a value failed to serialize
+        let _ = record
+            .kv()
+            .serialize(record, &mut ser)
+            .and_then(|_| logger_values.serialize(record, &mut ser));
+
+        let index = ser.matched?;
+        rules[index].matched.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref remaining) = rules[index].remaining {
+            // rules used up concurrently may pass a record too many
+            let _ = remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                n.checked_sub(1)
+            });
+        }
+        let rule = &rules[index].rule;
+        Some((rule.destination.clone(), rule.demotion))
+    }
+}
+
+struct RuleSerializer<'a> {
+    rules: &'a [ActiveRule],
+    clock: &'a dyn Clock,
+    level: slog::Level,
+    /// whether to match the routing rules rather than the others
+    routed: bool,
+    value: String,
+    /// index of the matching rule
+    matched: Option<usize>,
+}
+
+impl<'a> slog::Serializer for RuleSerializer<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        let mut formatted = false;
+
+        for (index, active) in self.rules.iter().enumerate() {
+            let rule = &active.rule;
+            if rule.key != key
+                || rule.destination.is_some() != self.routed
+                || !self.level.is_at_least(rule.level)
+                || active.is_expired(None)
+                || !active.in_schedule(self.clock)
+            {
+                continue;
+            }
+            if !formatted {
+                self.value.clear();
+                fmt::write(&mut self.value, *val)?;
+                formatted = true;
+            }
+            if rule.value == self.value {
+                self.matched = Some(index);
+                return Err(slog::Error::Other);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! Sampling consistent for all the records carrying a value of a key, e.g. a trace id
+
+use FilterStr;
+
+/// Keeps the records whose value of `key` hashes below `ratio`, see `KVFilter::sample_by_key`
+#[derive(Clone, Debug)]
+pub struct Sampling {
+    pub key: FilterStr,
+    /// hashes below this are kept
+    threshold: u64,
+}
+
+impl Sampling {
+    pub fn new(key: FilterStr, ratio: f64) -> Self {
+        let threshold = if ratio >= 1.0 {
+            u64::MAX
+        } else if ratio > 0.0 {
+            (ratio * u64::MAX as f64) as u64
+        } else {
+            0
+        };
+        Sampling { key, threshold }
+    }
+
+    /// whether records with `value` of the key are kept
+    pub fn keeps(&self, value: &str) -> bool {
+        self.threshold == u64::MAX || mix(fnv1a(value.as_bytes())) < self.threshold
+    }
+}
+
+/// 64-bit FNV-1a, a stable hash (along with `mix`) so that processes sampling by the same ratio agree on
+/// the values they keep
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// finalizer of MurmurHash3, spreading values differing in their last bytes, e.g.
+/// `trace-1` and `trace-2`, over the high bits compared with the threshold
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fnv1a, Sampling};
+
+    #[test]
+    fn stablesampling() {
+        // reference values of the FNV-1a specification
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let all = Sampling::new("trace_id".into(), 1.0);
+        let none = Sampling::new("trace_id".into(), 0.0);
+        let half = Sampling::new("trace_id".into(), 0.5);
+        let ids: Vec<String> = (0..1000).map(|id| format!("trace-{}", id)).collect();
+
+        assert!(ids.iter().all(|id| all.keeps(id)));
+        assert!(!ids.iter().any(|id| none.keeps(id)));
+        let kept = ids.iter().filter(|id| half.keeps(id)).count();
+        assert!(kept > 400 && kept < 600, "kept {}", kept);
+        assert!(ids.iter().all(|id| half.keeps(id) == half.keeps(id)));
+    }
+}
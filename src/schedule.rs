@@ -0,0 +1,176 @@
+//! Time windows during which runtime rules are active
+
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Utc, Weekday};
+
+use clock::Clock;
+
+/// Daily time window in UTC, optionally on some days of the week only, e.g. 01:00 to
+/// 03:00 for a nightly run. A window ending before it starts spans midnight, and is
+/// attributed to the day it starts on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    start: NaiveTime,
+    end: NaiveTime,
+    /// bit per weekday, by `Weekday::num_days_from_monday`
+    weekdays: u8,
+}
+
+const ALL_WEEKDAYS: u8 = 0b111_1111;
+
+impl Schedule {
+    /// window from `start` to `end` every day; the window covers whole days if they are equal
+    pub fn daily(start: NaiveTime, end: NaiveTime) -> Self {
+        Schedule {
+            start,
+            end,
+            weekdays: ALL_WEEKDAYS,
+        }
+    }
+
+    /// restrict the window to the days starting on one of `weekdays`
+    pub fn on(mut self, weekdays: &[Weekday]) -> Self {
+        self.weekdays = weekdays
+            .iter()
+            .fold(0, |mask, day| mask | 1 << day.num_days_from_monday());
+        self
+    }
+
+    fn on_day(&self, day: Weekday) -> bool {
+        self.weekdays & 1 << day.num_days_from_monday() != 0
+    }
+
+    /// whether the window is open at `at`
+    pub fn is_active(&self, at: DateTime<Utc>) -> bool {
+        let time = at.time();
+        let day = at.weekday();
+
+        if self.start < self.end {
+            self.on_day(day) && self.start <= time && time < self.end
+        } else if self.start > self.end {
+            (self.on_day(day) && self.start <= time) || (self.on_day(day.pred()) && time < self.end)
+        } else {
+            self.on_day(day)
+        }
+    }
+
+    /// the first instant after `at` when the window opens or closes, or None if it never does
+    pub fn next_transition(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let active = self.is_active(at);
+        let today = at.date_naive();
+
+        // the window repeats weekly, so a transition, if any, is within the next 8 days
+        let mut candidates: Vec<DateTime<Utc>> = (0..9)
+            .flat_map(|days| {
+                let date = today + Duration::days(days);
+                vec![
+                    date.and_time(self.start).and_utc(),
+                    date.and_time(self.end).and_utc(),
+                    date.and_time(NaiveTime::MIN).and_utc(),
+                ]
+            })
+            .filter(|&candidate| candidate > at)
+            .collect();
+        candidates.sort();
+
+        candidates
+            .into_iter()
+            .find(|&candidate| self.is_active(candidate) != active)
+    }
+}
+
+/// Schedule with whether it's active and its next transition cached, so that checking it
+/// is a couple of atomic loads and reading the clock until the transition comes
+pub struct ScheduleState {
+    schedule: Schedule,
+    active: AtomicBool,
+    /// milliseconds since the epoch
+    next_transition: AtomicI64,
+}
+
+impl ScheduleState {
+    pub fn new(schedule: Schedule) -> Self {
+        // computed on the first check
+        ScheduleState {
+            schedule,
+            active: AtomicBool::new(false),
+            next_transition: AtomicI64::new(i64::MIN),
+        }
+    }
+
+    pub fn is_active(&self, clock: &dyn Clock) -> bool {
+        let now = DateTime::from_timestamp_millis(clock.since_epoch().as_millis() as i64);
+        self.is_active_at(now.unwrap_or(DateTime::<Utc>::MAX_UTC))
+    }
+
+    fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        if now.timestamp_millis() < self.next_transition.load(Ordering::Acquire) {
+            return self.active.load(Ordering::Acquire);
+        }
+
+        let active = self.schedule.is_active(now);
+        let next = self
+            .schedule
+            .next_transition(now)
+            .map_or(i64::MAX, |next| next.timestamp_millis());
+        self.active.store(active, Ordering::Release);
+        self.next_transition.store(next, Ordering::Release);
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Schedule, ScheduleState};
+    use chrono::{DateTime, NaiveDate, NaiveTime, Utc, Weekday};
+
+    fn time(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    /// 2024-01-01 was a monday
+    fn at(day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_time(time(hour, min))
+            .and_utc()
+    }
+
+    #[test]
+    fn dailywindow() {
+        let schedule = Schedule::daily(time(1, 0), time(3, 0));
+
+        assert!(!schedule.is_active(at(1, 0, 59)));
+        assert!(schedule.is_active(at(1, 1, 0)));
+        assert!(!schedule.is_active(at(1, 3, 0)));
+        assert_eq!(schedule.next_transition(at(1, 0, 0)), Some(at(1, 1, 0)));
+        assert_eq!(schedule.next_transition(at(1, 2, 0)), Some(at(1, 3, 0)));
+        assert_eq!(schedule.next_transition(at(1, 4, 0)), Some(at(2, 1, 0)));
+    }
+
+    #[test]
+    fn overnightweekdaywindow() {
+        // friday night to saturday morning
+        let schedule = Schedule::daily(time(22, 0), time(2, 0)).on(&[Weekday::Fri]);
+
+        assert!(!schedule.is_active(at(4, 23, 0)));
+        assert!(schedule.is_active(at(5, 23, 0)));
+        assert!(schedule.is_active(at(6, 1, 0)));
+        assert!(!schedule.is_active(at(6, 23, 0)));
+        assert_eq!(schedule.next_transition(at(1, 12, 0)), Some(at(5, 22, 0)));
+        assert_eq!(schedule.next_transition(at(5, 23, 0)), Some(at(6, 2, 0)));
+
+        assert_eq!(Schedule::daily(time(0, 0), time(0, 0)).next_transition(at(1, 0, 0)), None);
+    }
+
+    #[test]
+    fn cachedstate() {
+        let state = ScheduleState::new(Schedule::daily(time(1, 0), time(3, 0)));
+
+        assert!(!state.is_active_at(at(1, 0, 0)));
+        assert!(!state.is_active_at(at(1, 0, 30)));
+        assert!(state.is_active_at(at(1, 1, 30)));
+        assert!(!state.is_active_at(at(1, 3, 30)));
+    }
+}
@@ -0,0 +1,311 @@
+//! Semantic versions and requirements on them, see `ValuePattern::Version`
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::prelude::v1::*;
+use std::str::FromStr;
+
+use KVFilterError;
+
+/// Semantic version, e.g. `2.3.1` or `3.0.0-beta.2`. A leading `v` and build metadata are
+/// ignored, missing minor and patch numbers are zero.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// pre-release identifiers, e.g. `beta.2`, empty for releases
+    pub pre: String,
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+            pre: String::new(),
+        }
+    }
+}
+
+/// version with the possibly missing numbers, as written in a requirement
+struct Partial {
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: String,
+    /// whether a number is `*` or `x` rather than left out
+    wildcard: bool,
+}
+
+impl Partial {
+    fn parse(s: &str) -> Option<Partial> {
+        let s = s.trim();
+        let s = s.strip_prefix('v').unwrap_or(s);
+        let s = s.split_once('+').map_or(s, |(version, _build)| version);
+        let (numbers, pre) = match s.split_once('-') {
+            Some((_, "")) => return None,
+            Some((numbers, pre)) => (numbers, pre),
+            None => (s, ""),
+        };
+
+        let wildcard = numbers.split('.').any(|number| matches!(number, "*" | "x" | "X"));
+        let mut numbers = numbers.split('.').map(|number| match number {
+            "*" | "x" | "X" => Ok(None),
+            number if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => {
+                number.parse().map(Some).map_err(|_| ())
+            }
+            _ => Err(()),
+        });
+        let major = numbers.next()?.ok()??;
+        let minor = numbers.next().map_or(Ok(None), |minor| minor).ok()?;
+        let patch = numbers.next().map_or(Ok(None), |patch| patch).ok()?;
+        if numbers.next().is_some() || minor.is_none() && patch.is_some() {
+            return None;
+        }
+        Some(Partial {
+            major,
+            minor,
+            patch,
+            pre: pre.to_owned(),
+            wildcard,
+        })
+    }
+
+    /// the version with the missing numbers zero
+    fn lowest(&self) -> Version {
+        Version {
+            major: self.major,
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+        }
+    }
+
+    /// the lowest version above all the versions matching the given numbers, `None` if the
+    /// numbers are the highest there are
+    fn next(&self) -> Option<Version> {
+        Some(match (self.minor, self.patch) {
+            (None, _) => Version::new(self.major.checked_add(1)?, 0, 0),
+            (Some(minor), None) => Version::new(self.major, minor.checked_add(1)?, 0),
+            (Some(minor), Some(patch)) => Version::new(self.major, minor, patch.checked_add(1)?),
+        })
+    }
+}
+
+impl FromStr for Version {
+    type Err = KVFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Partial::parse(s)
+            .filter(|partial| !partial.wildcard)
+            .map(|partial| partial.lowest())
+            .ok_or_else(|| KVFilterError::InvalidVersion(s.to_owned()))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                // pre-releases precede their release
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => cmp_pre(&self.pre, &other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// precedence of pre-release identifiers, numeric ones compared numerically and below
+/// alphanumeric ones
+fn cmp_pre(a: &str, b: &str) -> Ordering {
+    let mut a = a.split('.');
+    let mut b = b.split('.');
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) => {
+                let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    (Ok(_), Err(_)) => Ordering::Less,
+                    (Err(_), Ok(_)) => Ordering::Greater,
+                    (Err(_), Err(_)) => a.cmp(b),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            write!(f, "-{}", self.pre)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+/// Requirement on versions, e.g. `>= 2.3.0, < 3`, with the operators of Cargo:
+///
+/// * `>`, `>=`, `<`, `<=` compare with the missing numbers of partial versions zero, except
+///   `> 2.3` and `<= 2.3`, which stand for `>= 2.4.0` and `< 2.4.0`
+/// * `=2.3` matches any `2.3.x`
+/// * `~2.3.1` matches patch updates, `>= 2.3.1, < 2.4.0`
+/// * `^2.3.1`, or bare `2.3.1`, matches compatible updates, `>= 2.3.1, < 3.0.0`, or for
+///   `^0.2.3` `>= 0.2.3, < 0.3.0`
+/// * `*` matches any version
+///
+/// All the comma separated comparators have to match. Pre-releases compare below their
+/// release, so that `< 3` matches `3.0.0-beta`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VersionReq {
+    source: String,
+    bounds: Vec<(Op, Version)>,
+}
+
+impl VersionReq {
+    pub fn matches(&self, version: &Version) -> bool {
+        self.bounds.iter().all(|&(op, ref bound)| match op {
+            Op::Gt => version > bound,
+            Op::Ge => version >= bound,
+            Op::Lt => version < bound,
+            Op::Le => version <= bound,
+        })
+    }
+
+    /// whether `value` is a version matching the requirement
+    pub fn matches_str(&self, value: &str) -> bool {
+        value
+            .parse::<Version>()
+            .is_ok_and(|version| self.matches(&version))
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = KVFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KVFilterError::InvalidVersion(s.to_owned());
+
+        let mut bounds = Vec::new();
+        for comparator in s.split(',').map(str::trim) {
+            if comparator == "*" {
+                continue;
+            }
+            let (op, version) = match comparator.find(|c: char| c.is_ascii_alphanumeric()) {
+                Some(index) => (comparator[..index].trim(), &comparator[index..]),
+                None => return Err(invalid()),
+            };
+            let partial = Partial::parse(version).ok_or_else(invalid)?;
+            let exact = partial.patch.is_some();
+            match op {
+                ">" if exact => bounds.push((Op::Gt, partial.lowest())),
+                ">" => bounds.push((Op::Ge, partial.next().ok_or_else(invalid)?)),
+                ">=" => bounds.push((Op::Ge, partial.lowest())),
+                "<" => bounds.push((Op::Lt, partial.lowest())),
+                "<=" if exact => bounds.push((Op::Le, partial.lowest())),
+                "<=" => bounds.push((Op::Lt, partial.next().ok_or_else(invalid)?)),
+                "=" if exact => {
+                    bounds.push((Op::Ge, partial.lowest()));
+                    bounds.push((Op::Le, partial.lowest()));
+                }
+                "=" => {
+                    bounds.push((Op::Ge, partial.lowest()));
+                    bounds.push((Op::Lt, partial.next().ok_or_else(invalid)?));
+                }
+                "~" => {
+                    let upper = match partial.minor {
+                        Some(minor) => minor.checked_add(1).map(|minor| Version::new(partial.major, minor, 0)),
+                        None => partial.major.checked_add(1).map(|major| Version::new(major, 0, 0)),
+                    }
+                    .ok_or_else(invalid)?;
+                    bounds.push((Op::Ge, partial.lowest()));
+                    bounds.push((Op::Lt, upper));
+                }
+                "^" | "" => {
+                    let upper = match (partial.major, partial.minor, partial.patch) {
+                        (0, Some(0), Some(patch)) => patch.checked_add(1).map(|patch| Version::new(0, 0, patch)),
+                        (0, Some(minor), _) => minor.checked_add(1).map(|minor| Version::new(0, minor, 0)),
+                        (major, _, _) => major.checked_add(1).map(|major| Version::new(major, 0, 0)),
+                    }
+                    .ok_or_else(invalid)?;
+                    bounds.push((Op::Ge, partial.lowest()));
+                    bounds.push((Op::Lt, upper));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(VersionReq {
+            source: s.trim().to_owned(),
+            bounds,
+        })
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Version, VersionReq};
+
+    fn matching(req: &str, versions: &[&str]) -> Vec<bool> {
+        let req: VersionReq = req.parse().unwrap();
+        versions.iter().map(|version| req.matches_str(version)).collect()
+    }
+
+    #[test]
+    fn versionreqs() {
+        let versions = ["2.2.9", "2.3.0", "v2.9.1", "3.0.0-beta.1", "3.0.0", "not a version"];
+        assert_eq!(
+            matching(">= 2.3.0, < 3", &versions),
+            vec![false, true, true, true, false, false]
+        );
+        assert_eq!(matching("^2.3", &versions), matching(">=2.3.0, <3.0.0", &versions));
+        assert_eq!(matching("2.3", &versions), matching("^2.3", &versions));
+        assert_eq!(matching("~2.3.0", &["2.3.5", "2.4.0"]), vec![true, false]);
+        assert_eq!(matching("^0.2.3", &["0.2.9", "0.3.0"]), vec![true, false]);
+        assert_eq!(matching("=2.3", &["2.3.7", "2.4.0"]), vec![true, false]);
+        assert_eq!(matching("=2.3.1", &["2.3.1", "2.3.1+build.5", "2.3.2"]), vec![true, true, false]);
+        assert_eq!(matching("> 2.3", &["2.3.9", "2.4.0"]), vec![false, true]);
+        assert_eq!(matching("<= 2", &["2.9.9", "3.0.0"]), vec![true, false]);
+        assert_eq!(matching("*", &["0.0.1"]), vec![true]);
+
+        assert!("3.0.0-alpha".parse::<Version>().unwrap() < "3.0.0-alpha.1".parse().unwrap());
+        assert!("3.0.0-alpha.2".parse::<Version>().unwrap() < "3.0.0-alpha.10".parse().unwrap());
+        assert!("3.0.0-alpha.2".parse::<Version>().unwrap() < "3.0.0-beta".parse().unwrap());
+
+        assert!(">= two".parse::<VersionReq>().is_err());
+        assert!("=> 2".parse::<VersionReq>().is_err());
+        assert!("1.2.3.4".parse::<VersionReq>().is_err());
+        assert!("2.*".parse::<Version>().is_err());
+        assert!("^18446744073709551615".parse::<VersionReq>().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Keys and values of filters stored without a heap allocation of their own, see `SmallStr`
+
+use std::borrow::{Borrow, Cow};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::str;
+use std::sync::Arc;
+
+use FilterStr;
+
+/// bytes of the strings stored inline, so that a `SmallStr` is as large as a `String`
+const INLINE: usize = 22;
+
+/// Immutable string of a filter, cheap to clone as the spec is copied on each update:
+/// `&'static str`s are borrowed, strings of up to 22 bytes, as most keys and many values
+/// are, are stored inline, and longer ones are shared.
+#[derive(Clone)]
+pub enum SmallStr {
+    Static(&'static str),
+    Inline { len: u8, bytes: [u8; INLINE] },
+    Shared(Arc<str>),
+}
+
+impl SmallStr {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            SmallStr::Static(s) => s,
+            // only ever built from the bytes of a `str`, see `From<&str>`, and read on
+            // each lookup, so not validated again
+            SmallStr::Inline { len, ref bytes } => unsafe { str::from_utf8_unchecked(&bytes[..len as usize]) },
+            SmallStr::Shared(ref s) => s,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for SmallStr {
+    fn from(s: &'a str) -> Self {
+        if s.len() <= INLINE {
+            let mut bytes = [0; INLINE];
+            bytes[..s.len()].copy_from_slice(s.as_bytes());
+            SmallStr::Inline {
+                len: s.len() as u8,
+                bytes,
+            }
+        } else {
+            SmallStr::Shared(s.into())
+        }
+    }
+}
+
+impl From<FilterStr> for SmallStr {
+    fn from(s: FilterStr) -> Self {
+        match s {
+            Cow::Borrowed(s) => SmallStr::Static(s),
+            Cow::Owned(s) => SmallStr::from(s.as_str()),
+        }
+    }
+}
+
+impl Deref for SmallStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for SmallStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for SmallStr {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for SmallStr {
+    fn eq(&self, other: &SmallStr) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for SmallStr {}
+
+impl PartialEq<str> for SmallStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for SmallStr {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for SmallStr {
+    fn partial_cmp(&self, other: &SmallStr) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SmallStr {
+    fn cmp(&self, other: &SmallStr) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+/// hashes like `str`, as required by `Borrow<str>`
+impl Hash for SmallStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl fmt::Debug for SmallStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SmallStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SmallStr;
+    use std::borrow::Cow;
+    use std::collections::HashSet;
+    use std::mem;
+
+    #[test]
+    fn smallstr() {
+        assert_eq!(mem::size_of::<SmallStr>(), mem::size_of::<String>());
+
+        let long = "x".repeat(23);
+        let strs = [
+            SmallStr::from(Cow::Borrowed("tenant")),
+            SmallStr::from(Cow::<str>::Owned("subsystem".to_owned())),
+            SmallStr::from(Cow::<str>::Owned(long.clone())),
+        ];
+        match (&strs[0], &strs[1], &strs[2]) {
+            (&SmallStr::Static(_), &SmallStr::Inline { .. }, &SmallStr::Shared(_)) => {}
+            other => panic!("stored as {:?}", other),
+        }
+
+        let set: HashSet<SmallStr> = strs.iter().cloned().collect();
+        assert!(set.contains("subsystem"));
+        assert!(set.contains(long.as_str()));
+        assert_eq!(strs[0], "tenant");
+        assert_eq!(strs[1], SmallStr::from("subsystem"));
+    }
+}
@@ -0,0 +1,308 @@
+//! Support of `static_kvfilter!`, generating filters specialized to a spec fixed at
+//! compile time.
+
+use std::fmt;
+
+/// Define a `Drain` type passing records like `KVFilter` configured by
+/// `only_pass_any_on_all_keys` and `always_suppress_any` would, for a spec known at
+/// compile time. Keys and values are matched by `match` statements over `&str`, with no
+/// hashing, and values are formatted on the stack, so nothing is allocated at runtime.
+///
+/// Records more important than the level given to `new` pass unfiltered. At most 64 keys
+/// can be passed on.
+///
+/// ```
+/// # #[macro_use] extern crate slog;
+/// # #[macro_use] extern crate slog_kvfilter;
+/// use slog::{Drain, Level, Logger};
+/// use slog_kvfilter::testing::CountingDrain;
+///
+/// static_kvfilter! {
+///     /// passes database records, unless from the cache warmup
+///     pub struct DbFilter {
+///         pass { "subsystem" => ["db", "cache"] }
+///         suppress { "phase" => ["warmup"] }
+///     }
+/// }
+///
+/// # fn main() {
+/// let drain = CountingDrain::new();
+/// let log = Logger::root(DbFilter::new(drain.clone(), Level::Info).fuse(), o!());
+/// info!(log, "passing"; "subsystem" => "db");
+/// info!(log, "not passing"; "subsystem" => "db", "phase" => "warmup");
+/// info!(log, "not passing"; "subsystem" => "web");
+/// assert_eq!(drain.count(), 1);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! static_kvfilter {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            pass { $($key:literal => [$($value:literal),+ $(,)?]),* $(,)? }
+            $(suppress { $($skey:literal => [$($svalue:literal),+ $(,)?]),* $(,)? })?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name<D: $crate::__slog::Drain> {
+            drain: D,
+            level: $crate::__slog::Level,
+        }
+
+        impl<D: $crate::__slog::Drain> $name<D> {
+            /// filter records of `level` and less important ones
+            pub fn new(drain: D, level: $crate::__slog::Level) -> Self {
+                $name { drain, level }
+            }
+
+            fn matches(
+                record: &$crate::__slog::Record,
+                logger_values: &$crate::__slog::OwnedKVList,
+            ) -> bool {
+                use $crate::__slog as slog;
+                use $crate::__slog::KV;
+
+                const KEYS: &[&str] = &[$($key),*];
+                const ALL: u64 = $crate::static_filter::all_keys(KEYS.len());
+                const LEN: usize = $crate::static_filter::max_len(
+                    &[$($($value,)*)* $($($($svalue,)*)*)?]
+                );
+
+                struct Matcher {
+                    matched: u64,
+                    suppressed: bool,
+                    buf: $crate::static_filter::StackStr<LEN>,
+                }
+
+                impl slog::Serializer for Matcher {
+                    #[allow(unused_variables)]
+                    fn emit_arguments(
+                        &mut self,
+                        key: slog::Key,
+                        val: &$crate::__private::Arguments,
+                    ) -> slog::Result {
+                        $(
+                            match &*key {
+                                $(
+                                    $skey => match self.buf.format(val) {
+                                        $(Some($svalue))|+ => {
+                                            self.suppressed = true;
+                                            return Err(slog::Error::Other);
+                                        }
+                                        _ => {}
+                                    },
+                                )*
+                                _ => {}
+                            }
+                        )?
+                        match &*key {
+                            $(
+                                $key => {
+                                    const INDEX: usize = $crate::static_filter::index_of(KEYS, $key);
+                                    if self.matched & 1 << INDEX == 0 {
+                                        match self.buf.format(val) {
+                                            $(Some($value))|+ => self.matched |= 1 << INDEX,
+                                            _ => {}
+                                        }
+                                    }
+                                }
+                            )*
+                            _ => {}
+                        }
+                        Ok(())
+                    }
+                }
+
+                let mut matcher = Matcher {
+                    matched: 0,
+                    suppressed: false,
+                    buf: $crate::static_filter::StackStr::new(),
+                };
+                // an error means a value was suppressed, or failed to serialize
+                let _ = record
+                    .kv()
+                    .serialize(record, &mut matcher)
+                    .and_then(|_| logger_values.serialize(record, &mut matcher));
+                !matcher.suppressed && matcher.matched == ALL
+            }
+        }
+
+        impl<D: $crate::__slog::Drain> $crate::KVPredicate for $name<D> {
+            fn is_match(
+                &self,
+                record: &$crate::__slog::Record,
+                logger_values: &$crate::__slog::OwnedKVList,
+            ) -> bool {
+                record.level() < self.level || Self::matches(record, logger_values)
+            }
+        }
+
+        impl<D: $crate::__slog::Drain> $crate::__slog::Drain for $name<D> {
+            type Ok = Option<D::Ok>;
+            type Err = D::Err;
+
+            fn log(
+                &self,
+                record: &$crate::__slog::Record,
+                logger_values: &$crate::__slog::OwnedKVList,
+            ) -> Result<Self::Ok, Self::Err> {
+                if $crate::KVPredicate::is_match(self, record, logger_values) {
+                    self.drain.log(record, logger_values).map(Some)
+                } else {
+                    Ok(None)
+                }
+            }
+
+            fn is_enabled(&self, level: $crate::__slog::Level) -> bool {
+                self.drain.is_enabled(level)
+            }
+        }
+    };
+}
+
+/// position of `key` in `keys`
+#[doc(hidden)]
+pub const fn index_of(keys: &[&str], key: &str) -> usize {
+    let mut index = 0;
+    while index < keys.len() {
+        if eq(keys[index].as_bytes(), key.as_bytes()) {
+            return index;
+        }
+        index += 1;
+    }
+    panic!("unknown key")
+}
+
+const fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// mask of `keys` matched keys
+#[doc(hidden)]
+pub const fn all_keys(keys: usize) -> u64 {
+    assert!(keys <= 64, "at most 64 keys can be passed on");
+    if keys == 64 {
+        !0
+    } else {
+        (1 << keys) - 1
+    }
+}
+
+/// length of the longest of `values`
+#[doc(hidden)]
+pub const fn max_len(values: &[&str]) -> usize {
+    let mut len = 0;
+    let mut index = 0;
+    while index < values.len() {
+        if values[index].len() > len {
+            len = values[index].len();
+        }
+        index += 1;
+    }
+    len
+}
+
+/// Buffer formatting values of up to `N` bytes on the stack; longer values can't equal
+/// any of the values matched against anyway
+#[doc(hidden)]
+pub struct StackStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflown: bool,
+}
+
+impl<const N: usize> Default for StackStr<N> {
+    fn default() -> Self {
+        StackStr::new()
+    }
+}
+
+impl<const N: usize> StackStr<N> {
+    pub fn new() -> Self {
+        StackStr {
+            buf: [0; N],
+            len: 0,
+            overflown: false,
+        }
+    }
+
+    /// `val` as a string, unless longer than `N`
+    pub fn format<'a>(&'a mut self, val: &'a fmt::Arguments) -> Option<&'a str> {
+        if let Some(value) = val.as_str() {
+            return Some(value);
+        }
+
+        self.len = 0;
+        self.overflown = false;
+        if fmt::write(self, *val).is_err() || self.overflown {
+            return None;
+        }
+        // only whole `str`s are copied in
+        ::std::str::from_utf8(&self.buf[..self.len]).ok()
+    }
+}
+
+impl<const N: usize> fmt::Write for StackStr<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > N {
+            self.overflown = true;
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::{Drain, Level, Logger};
+    use testing::CountingDrain;
+
+    static_kvfilter! {
+        struct Przygienda {
+            pass { "thread" => ["100", "200"], "direction" => ["send", "receive"] }
+            suppress { "deepcomp" => ["1", "2"], "deepercomp" => ["4", "5"] }
+        }
+    }
+
+    static_kvfilter! {
+        struct PassOnly {
+            pass { "packet" => ["42"] }
+        }
+    }
+
+    #[test]
+    fn staticfilter() {
+        let drain = CountingDrain::new();
+        let log = Logger::root(Przygienda::new(drain.clone(), Level::Info).fuse(), o!());
+        let sublog = log.new(o!("thread" => 200));
+
+        info!(log, "not passing, no keys");
+        info!(log, "passing"; "thread" => "100", "direction" => "send");
+        info!(sublog, "passing, thread formatted in context"; "direction" => "receive");
+        info!(sublog, "not passing, direction doesn't match"; "direction" => "sideways");
+        info!(sublog, "not passing, suppressed"; "direction" => "send", "deepcomp" => 2);
+        info!(sublog, "not passing, long value"; "direction" => "send".repeat(100));
+        warn!(log, "passing unfiltered");
+
+        assert_eq!(drain.count(), 3);
+
+        let drain = CountingDrain::new();
+        let log = Logger::root(PassOnly::new(drain.clone(), Level::Info).fuse(), o!());
+        info!(log, "passing"; "packet" => 42);
+        info!(log, "not passing"; "packet" => 7);
+        assert_eq!(drain.count(), 1);
+    }
+}
@@ -0,0 +1,206 @@
+//! Counts of the records decided by a filter, see `KVFilterHandle::stats`
+
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use sync::{Mutex, RwLock};
+
+use slog;
+
+use memo::MemoStats;
+use rules::{Rule, RuleId};
+use topk::TopValues;
+use {FilterMap, FilterStr, KVMap};
+
+/// number of values of a key counted apart, the records with further values are counted
+/// under `OTHER`
+const VALUES_PER_KEY: usize = 256;
+
+/// value the records are counted under once `VALUES_PER_KEY` values of the key are counted
+const OTHER: &str = "~other";
+
+/// records passed and suppressed by the value of a key
+type ValueCounts = FilterMap<String, (u64, u64)>;
+
+/// Counts shared between a filter and its handles
+#[derive(Default)]
+pub struct Counters {
+    passed: AtomicU64,
+    suppressed: AtomicU64,
+    routed: AtomicU64,
+    match_errors: AtomicU64,
+    drain_errors: AtomicU64,
+    invalid_directives: AtomicU64,
+    /// keys of `KVFilter::count_by_key`
+    keys: RwLock<Vec<FilterStr>>,
+    by_key: Mutex<FilterMap<FilterStr, ValueCounts>>,
+    /// pairs reported by `KVFilter::track_top_suppressed`, none counted if zero
+    top_k: AtomicUsize,
+    top_suppressed: Mutex<TopValues>,
+}
+
+impl Counters {
+    pub fn passed(&self) {
+        self.passed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn suppressed(&self) {
+        self.suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn routed(&self) {
+        self.routed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn match_errors(&self, errors: usize) {
+        if errors > 0 {
+            self.match_errors.fetch_add(errors as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn drain_error(&self) {
+        self.drain_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "remote")]
+    pub fn invalid_directives(&self, invalid: usize) {
+        self.invalid_directives.fetch_add(invalid as u64, Ordering::Relaxed);
+    }
+
+    pub fn count_by_key(&self, key: FilterStr) {
+        let mut keys = self.keys.write();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+
+    /// count a record by the values `find` returns for the keys counted by, if any
+    pub fn by_key<F: Fn(&str) -> Option<String>>(&self, passed: bool, find: F) {
+        let keys = self.keys.read();
+        if keys.is_empty() {
+            return;
+        }
+        let values: Vec<_> = keys.iter().filter_map(|key| find(key).map(|value| (key, value))).collect();
+        if values.is_empty() {
+            return;
+        }
+
+        let mut by_key = self.by_key.lock();
+        for (key, value) in values {
+            let counts = by_key.entry(key.clone()).or_default();
+            let value = if counts.len() < VALUES_PER_KEY || counts.contains_key(&value) {
+                value
+            } else {
+                OTHER.to_owned()
+            };
+            let count = counts.entry(value).or_insert((0, 0));
+            if passed {
+                count.0 += 1;
+            } else {
+                count.1 += 1;
+            }
+        }
+    }
+
+    pub fn track_top_suppressed(&self, k: usize) {
+        *self.top_suppressed.lock() = TopValues::new(k);
+        self.top_k.store(k, Ordering::Relaxed);
+    }
+
+    /// count the key values of a suppressed record, the one closest to the record of each
+    /// key, if tracking the top ones
+    pub fn top_suppressed(&self, record: &slog::Record, logger_values: &slog::OwnedKVList) {
+        if self.top_k.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let map = KVMap::new(record, logger_values);
+        let mut seen: Vec<&str> = Vec::with_capacity(map.len());
+        let mut top = self.top_suppressed.lock();
+        for (key, value) in map.iter() {
+            if !seen.contains(&key) {
+                seen.push(key);
+                top.count(key, value.to_owned());
+            }
+        }
+    }
+
+    pub fn get(&self, rules: Vec<(RuleId, Rule, u64)>) -> FilterStats {
+        let by_key = self.by_key.lock();
+        let mut keys: Vec<KeyCount> = by_key
+            .iter()
+            .flat_map(|(key, counts)| {
+                counts.iter().map(move |(value, &(passed, suppressed))| KeyCount {
+                    key: key.to_string(),
+                    value: value.clone(),
+                    passed,
+                    suppressed,
+                })
+            })
+            .collect();
+        keys.sort_by(|a, b| (&a.key, &a.value).cmp(&(&b.key, &b.value)));
+
+        FilterStats {
+            passed: self.passed.load(Ordering::Relaxed),
+            suppressed: self.suppressed.load(Ordering::Relaxed),
+            routed: self.routed.load(Ordering::Relaxed),
+            match_errors: self.match_errors.load(Ordering::Relaxed),
+            drain_errors: self.drain_errors.load(Ordering::Relaxed),
+            invalid_directives: self.invalid_directives.load(Ordering::Relaxed),
+            rules,
+            keys,
+            top_suppressed: self.top_suppressed.lock().top(),
+            trace_evictions: 0,
+            memo: MemoStats::default(),
+        }
+    }
+}
+
+/// Records decided by the filters sharing a handle since they were created, see
+/// `KVFilterHandle::stats`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterStats {
+    /// records forwarded to the filtered drain
+    pub passed: u64,
+    pub suppressed: u64,
+    /// records sent to the drains of `KVFilter::route_to`
+    pub routed: u64,
+    /// values the matchers couldn't evaluate, see `KVFilter::on_match_error`
+    pub match_errors: u64,
+    /// records the filtered drain failed to log, see `KVFilter::fallback_on_error`
+    pub drain_errors: u64,
+    /// directives left out of the configurations applied by `RemoteConfig::skip_invalid`
+    pub invalid_directives: u64,
+    /// rules added which haven't expired yet, with the number of records they matched
+    pub rules: Vec<(RuleId, Rule, u64)>,
+    /// records by the values of the keys of `KVFilter::count_by_key`, ordered by the key
+    /// and the value
+    pub keys: Vec<KeyCount>,
+    /// key values carried by the most suppressed records, the most suppressed first, see
+    /// `KVFilter::track_top_suppressed`
+    pub top_suppressed: Vec<TopSuppressed>,
+    /// values traced by `KVFilterHandle::trace` and `KVFilter::sticky_on_key` dropped to
+    /// make room for others, see `KVFilter::trace_capacity`
+    pub trace_evictions: u64,
+    /// lookups of the memos of the costly matchers, see `KVFilter::memoize_matches`
+    pub memo: MemoStats,
+}
+
+/// Records passed and suppressed carrying a value of a key, see `KVFilter::count_by_key`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyCount {
+    pub key: String,
+    /// the value, `~other` for the values beyond the first 256 of the key
+    pub value: String,
+    pub passed: u64,
+    pub suppressed: u64,
+}
+
+/// Suppressed records carrying a value of a key, see `KVFilter::track_top_suppressed`,
+/// e.g. 9000 of the 10000 `FilterStats::suppressed` records with `tenant` of `bigcorp`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TopSuppressed {
+    pub key: String,
+    pub value: String,
+    /// the records counted, at most `error` more than the records which carried the value
+    pub suppressed: u64,
+    pub error: u64,
+}
@@ -0,0 +1,259 @@
+//! Counts of the records decided by a filter pushed to a StatsD server, see
+//! `StatsdReporter`
+
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use sync::Mutex;
+use {FilterStats, KVFilterHandle};
+
+/// most bytes of the metrics sent in one datagram, fitting the usual MTU of 1500 bytes
+const MAX_DATAGRAM: usize = 1432;
+
+/// Pushes the counts of `KVFilterHandle::stats` to a StatsD server as counters of the
+/// records decided since the last report, e.g.
+///
+/// ```text
+/// kvfilter.passed:42|c
+/// kvfilter.suppressed:1337|c
+/// kvfilter.routed:0|c
+/// kvfilter.key.tenant.acme.passed:7|c
+/// kvfilter.key.tenant.acme.suppressed:98|c
+/// ```
+///
+/// for the keys of `KVFilter::count_by_key`. With `dogstatsd`, the key and its value are
+/// sent as tags instead, `kvfilter.key.passed:7|c|#key:tenant,value:acme`.
+pub struct StatsdReporter {
+    socket: UdpSocket,
+    prefix: String,
+    dogstatsd: bool,
+    tags: Vec<String>,
+    handle: KVFilterHandle,
+    /// stats at the last report
+    reported: FilterStats,
+}
+
+impl StatsdReporter {
+    /// reporter sending to `addr`, e.g. `"127.0.0.1:8125"`
+    pub fn new<A: ToSocketAddrs>(handle: &KVFilterHandle, addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        Ok(StatsdReporter {
+            socket,
+            prefix: "kvfilter".to_owned(),
+            dogstatsd: false,
+            tags: Vec::new(),
+            handle: handle.clone(),
+            reported: FilterStats::default(),
+        })
+    }
+
+    /// prefix of the metric names, `kvfilter` by default
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// send the counts by key as DogStatsD tags rather than in the metric names
+    pub fn dogstatsd(mut self) -> Self {
+        self.dogstatsd = true;
+        self
+    }
+
+    /// add the DogStatsD `tag`, e.g. `service:billing`, to all the metrics, implies
+    /// `dogstatsd`
+    pub fn tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.dogstatsd = true;
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// send the records decided since the last report, counters not changed since being
+    /// left out
+    pub fn report(&mut self) -> io::Result<()> {
+        let stats = self.handle.stats();
+        let mut lines = Vec::new();
+
+        for &(name, count, reported) in &[
+            ("passed", stats.passed, self.reported.passed),
+            ("suppressed", stats.suppressed, self.reported.suppressed),
+            ("routed", stats.routed, self.reported.routed),
+        ] {
+            self.counter(&mut lines, name, &[], count - reported);
+        }
+
+        for count in &stats.keys {
+            let reported = self
+                .reported
+                .keys
+                .iter()
+                .find(|reported| reported.key == count.key && reported.value == count.value)
+                .map_or((0, 0), |reported| (reported.passed, reported.suppressed));
+            for &(decision, records) in &[
+                ("passed", count.passed - reported.0),
+                ("suppressed", count.suppressed - reported.1),
+            ] {
+                if self.dogstatsd {
+                    let tags = [format!("key:{}", count.key), format!("value:{}", count.value)];
+                    self.counter(&mut lines, &format!("key.{}", decision), &tags, records);
+                } else {
+                    let name = format!("key.{}.{}.{}", sanitize(&count.key), sanitize(&count.value), decision);
+                    self.counter(&mut lines, &name, &[], records);
+                }
+            }
+        }
+
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.socket.send(datagram.as_bytes())?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+
+        self.reported = stats;
+        Ok(())
+    }
+
+    fn counter(&self, lines: &mut Vec<String>, name: &str, tags: &[String], count: u64) {
+        if count == 0 {
+            return;
+        }
+        let mut line = format!("{}.{}:{}|c", self.prefix, name, count);
+        let tags: Vec<&str> = self.tags.iter().chain(tags).map(|tag| tag.as_str()).collect();
+        if !tags.is_empty() {
+            line.push_str("|#");
+            line.push_str(&tags.iter().map(|tag| sanitize_tag(tag)).collect::<Vec<_>>().join(","));
+        }
+        lines.push(line);
+    }
+
+    /// report every `interval` on a thread of its own until the returned thread is dropped
+    pub fn spawn(mut self, interval: Duration) -> ReportingThread {
+        let last_error = Arc::new(Mutex::new(None));
+        let (stop, stopped) = mpsc::channel();
+
+        let errors = last_error.clone();
+        let worker = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let result = self.report();
+                *errors.lock() = result.err().map(|err| err.to_string());
+            }
+        });
+
+        ReportingThread {
+            stop: Some(stop),
+            worker: Some(worker),
+            last_error,
+        }
+    }
+}
+
+/// `name` with the characters StatsD gives a meaning to replaced by `_`
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | '.' | ',' | '\n' | ' ' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// `tag` with the characters DogStatsD gives a meaning to replaced by `_`, keeping the `:`
+/// of the tag value
+fn sanitize_tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| match c {
+            '|' | '@' | '#' | ',' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Thread pushing the counts to a StatsD server, see `StatsdReporter::spawn`. Dropping it
+/// stops the reporting.
+pub struct ReportingThread {
+    stop: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl ReportingThread {
+    /// why the last report failed, `None` if it succeeded
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().clone()
+    }
+}
+
+impl Drop for ReportingThread {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsdReporter;
+    use slog::{Drain, Level, Logger};
+    use std::net::UdpSocket;
+    use std::time::Duration;
+    use testing::CountingDrain;
+    use {filter_list, KVFilter};
+
+    #[test]
+    fn statsdreporter() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let receive = || {
+            let mut buf = [0; 2048];
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        };
+
+        let filter = KVFilter::new(CountingDrain::new(), Level::Info)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("thread", vec!["100"])])))
+            .count_by_key("tenant");
+        let handle = filter.handle();
+        let log = Logger::root(filter.fuse(), o!());
+
+        info!(log, "passing"; "thread" => "100", "tenant" => "acme.eu");
+        info!(log, "not passing"; "thread" => "200", "tenant" => "acme.eu");
+        info!(log, "not passing"; "thread" => "200");
+
+        let mut reporter = StatsdReporter::new(&handle, server.local_addr().unwrap()).unwrap();
+        reporter.report().unwrap();
+        assert_eq!(
+            receive(),
+            "kvfilter.passed:1|c\n\
+             kvfilter.suppressed:2|c\n\
+             kvfilter.key.tenant.acme_eu.passed:1|c\n\
+             kvfilter.key.tenant.acme_eu.suppressed:1|c"
+        );
+
+        info!(log, "not passing"; "thread" => "200", "tenant" => "acme.eu");
+        let mut reporter = reporter.prefix("app.log").tag("service:billing");
+        reporter.report().unwrap();
+        assert_eq!(
+            receive(),
+            "app.log.suppressed:1|c|#service:billing\n\
+             app.log.key.suppressed:1|c|#service:billing,key:tenant,value:acme.eu"
+        );
+
+        let stats = handle.stats();
+        assert_eq!((stats.keys[0].passed, stats.keys[0].suppressed), (1, 2));
+    }
+}
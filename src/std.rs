@@ -0,0 +1,29 @@
+//! What the crate uses of `std`, made of `core` and `alloc` in builds without the `std`
+//! feature, so that the modules refer to the same paths either way
+
+pub use alloc::{borrow, fmt, slice, str};
+pub use core::{cmp, error, hash, mem, net, ops, option, panic, time};
+
+pub mod collections {
+    pub use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+    pub use hashbrown::{HashMap, HashSet};
+}
+
+pub mod sync {
+    pub use alloc::sync::Arc;
+
+    pub mod atomic {
+        pub use core::sync::atomic::*;
+        pub use portable_atomic::AtomicU64;
+    }
+}
+
+pub mod prelude {
+    pub mod v1 {
+        pub use alloc::borrow::ToOwned;
+        pub use alloc::boxed::Box;
+        pub use alloc::string::{String, ToString};
+        pub use alloc::vec::Vec;
+        pub use core::prelude::v1::*;
+    }
+}
@@ -0,0 +1,95 @@
+//! Count of the records suppressed since the last one passed, attached to the next
+//! passing record as key values.
+
+use std::fmt::Write;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use sync::Mutex;
+
+use slog;
+use slog::KV;
+
+use FilterMap;
+
+/// Suppressed records counted for `KVFilter::summarize_suppressed`
+pub struct Summary {
+    /// number of the most frequent keys of the suppressed records to report
+    top_keys: usize,
+    count: AtomicUsize,
+    keys: Mutex<FilterMap<String, usize>>,
+}
+
+impl Summary {
+    pub fn new(top_keys: usize) -> Self {
+        Summary {
+            top_keys,
+            count: AtomicUsize::new(0),
+            keys: Mutex::new(FilterMap::default()),
+        }
+    }
+
+    /// count a suppressed record
+    pub fn suppressed(&self, record: &slog::Record) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        if self.top_keys == 0 {
+            return;
+        }
+        let mut keys = self.keys.lock();
+        let _ = record.kv().serialize(record, &mut KeyCounter(&mut keys));
+    }
+
+    /// the summary since the last call, if any records have been suppressed
+    pub fn take(&self) -> Option<SummaryKV> {
+        // checked first so that passing records don't take the lock when nothing was suppressed
+        if self.count.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let count = self.count.swap(0, Ordering::Relaxed);
+
+        let mut top = String::new();
+        if self.top_keys > 0 {
+            let mut keys = self.keys.lock();
+            let mut counted: Vec<_> = keys.drain().collect();
+            counted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            for (key, count) in counted.iter().take(self.top_keys) {
+                if !top.is_empty() {
+                    top.push_str(", ");
+                }
+                let _ = write!(top, "{}: {}", key, count);
+            }
+        }
+
+        Some(SummaryKV { count, top })
+    }
+}
+
+struct KeyCounter<'a>(&'a mut FilterMap<String, usize>);
+
+impl<'a> slog::Serializer for KeyCounter<'a> {
+    fn emit_arguments(&mut self, key: slog::Key, _: &::std::fmt::Arguments) -> slog::Result {
+        match self.0.get_mut(key) {
+            Some(count) => *count += 1,
+            None => {
+                self.0.insert(key.to_string(), 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `kvfilter_suppressed_since_last` and, if counted, `kvfilter_top_suppressed_keys`
+pub struct SummaryKV {
+    count: usize,
+    top: String,
+}
+
+impl KV for SummaryKV {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_usize("kvfilter_suppressed_since_last", self.count)?;
+        if !self.top.is_empty() {
+            serializer.emit_str("kvfilter_top_suppressed_keys", &self.top)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Locks of the filters and their handles, e.g. of rules, caps and counts by key
+//!
+//! Locking never fails: a thread panicking while holding a lock leaves the state as it was
+//! when it panicked, which is still fit for filtering. With the `parking_lot` feature the
+//! locks are those of `parking_lot`, which never poison, without the `std` feature the
+//! spinning ones of `spin`, otherwise those of `std::sync`. All the locks of the crate are
+//! taken through this module, as is the `ArcSwap` of the spec, a lock itself without `std`.
+
+use std::hash::{Hash, Hasher};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::prelude::v1::*;
+#[cfg(all(feature = "std", not(feature = "parking_lot")))]
+use std::sync;
+#[cfg(not(feature = "std"))]
+use std::sync::Arc;
+
+#[cfg(feature = "parking_lot")]
+use parking_lot as lock;
+#[cfg(not(feature = "std"))]
+use spin as lock;
+
+#[cfg(any(feature = "parking_lot", not(feature = "std")))]
+pub use self::lock::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(all(feature = "std", not(feature = "parking_lot")))]
+pub use std::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "std")]
+pub use arc_swap::{ArcSwap, Guard};
+
+/// number of shards of a `Sharded`
+pub const SHARDS: usize = 16;
+
+#[derive(Default)]
+#[cfg(any(feature = "parking_lot", not(feature = "std")))]
+pub struct Mutex<T>(lock::Mutex<T>);
+
+#[derive(Default)]
+#[cfg(all(feature = "std", not(feature = "parking_lot")))]
+pub struct Mutex<T>(sync::Mutex<T>);
+
+impl<T> Mutex<T> {
+    #[cfg(any(feature = "parking_lot", not(feature = "std")))]
+    pub const fn new(value: T) -> Self {
+        Mutex(lock::Mutex::new(value))
+    }
+
+    #[cfg(all(feature = "std", not(feature = "parking_lot")))]
+    pub const fn new(value: T) -> Self {
+        Mutex(sync::Mutex::new(value))
+    }
+
+    #[cfg(any(feature = "parking_lot", not(feature = "std")))]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock()
+    }
+
+    /// the lock unless already taken, e.g. by the code interrupted, for the state of a
+    /// `static` standing in for a thread local without `std`
+    #[cfg(not(feature = "std"))]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.0.try_lock()
+    }
+
+    #[cfg(all(feature = "std", not(feature = "parking_lot")))]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Default)]
+#[cfg(any(feature = "parking_lot", not(feature = "std")))]
+pub struct RwLock<T>(lock::RwLock<T>);
+
+#[derive(Default)]
+#[cfg(all(feature = "std", not(feature = "parking_lot")))]
+pub struct RwLock<T>(sync::RwLock<T>);
+
+impl<T> RwLock<T> {
+    #[cfg(any(feature = "parking_lot", not(feature = "std")))]
+    pub const fn new(value: T) -> Self {
+        RwLock(lock::RwLock::new(value))
+    }
+
+    #[cfg(all(feature = "std", not(feature = "parking_lot")))]
+    pub const fn new(value: T) -> Self {
+        RwLock(sync::RwLock::new(value))
+    }
+
+    #[cfg(any(feature = "parking_lot", not(feature = "std")))]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read()
+    }
+
+    #[cfg(all(feature = "std", not(feature = "parking_lot")))]
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[cfg(any(feature = "parking_lot", not(feature = "std")))]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write()
+    }
+
+    #[cfg(all(feature = "std", not(feature = "parking_lot")))]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// as those of `std::sync`, which poison: the state left by a panic is still fit for use
+impl<T> UnwindSafe for Mutex<T> {}
+impl<T> RefUnwindSafe for Mutex<T> {}
+impl<T> UnwindSafe for RwLock<T> {}
+impl<T> RefUnwindSafe for RwLock<T> {}
+
+/// `Arc` replaced while read by others, locking without `std`
+#[cfg(not(feature = "std"))]
+pub struct ArcSwap<T>(RwLock<Arc<T>>);
+
+/// what `ArcSwap::load` returns
+#[cfg(not(feature = "std"))]
+pub type Guard<T> = T;
+
+#[cfg(not(feature = "std"))]
+impl<T> ArcSwap<T> {
+    pub fn new(value: Arc<T>) -> Self {
+        ArcSwap(RwLock::new(value))
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        self.0.read().clone()
+    }
+
+    pub fn load_full(&self) -> Arc<T> {
+        self.load()
+    }
+
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        ::std::mem::replace(&mut *self.0.write(), value)
+    }
+}
+
+/// FNV-1a, cheaper than the hasher of `HashMap` as the shard is found on each record
+struct ShardHasher(u64);
+
+impl Hasher for ShardHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// State split in `SHARDS` shards locked apart, the ones of keys, e.g. the values capped,
+/// found by their hash, so that threads logging different values rarely wait on each other
+pub struct Sharded<T> {
+    shards: Box<[RwLock<T>]>,
+}
+
+impl<T: Default> Default for Sharded<T> {
+    fn default() -> Self {
+        Sharded {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        }
+    }
+}
+
+impl<T> Sharded<T> {
+    /// shard of `key`
+    pub fn index<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        let mut hasher = ShardHasher(0xcbf2_9ce4_8422_2325);
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    pub fn read(&self, index: usize) -> RwLockReadGuard<'_, T> {
+        self.shards[index].read()
+    }
+
+    pub fn write(&self, index: usize) -> RwLockWriteGuard<'_, T> {
+        self.shards[index].write()
+    }
+
+    /// all the shards, locked in order so that this never deadlocks with other threads
+    /// locking them all, or locking just one at a time
+    pub fn write_all(&self) -> Vec<RwLockWriteGuard<'_, T>> {
+        self.shards.iter().map(RwLock::write).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mutex, Sharded, SHARDS};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn poisoned() {
+        let counts = Arc::new(Mutex::new(0));
+        let panicking = counts.clone();
+        let _ = thread::spawn(move || {
+            let mut count = panicking.lock();
+            *count += 1;
+            panic!("while holding the lock");
+        })
+        .join();
+
+        *counts.lock() += 1;
+        assert_eq!(*counts.lock(), 2);
+    }
+
+    #[test]
+    fn sharded() {
+        let sharded: Sharded<Vec<&str>> = Sharded::default();
+        for value in &["acme", "globex", "initech"] {
+            sharded.write(sharded.index(value)).push(value);
+        }
+        assert_eq!(sharded.index("acme"), sharded.index(&"acme".to_owned()));
+        assert!(sharded.read(sharded.index("globex")).contains(&"globex"));
+
+        let shards = sharded.write_all();
+        assert_eq!(shards.len(), SHARDS);
+        assert_eq!(shards.iter().map(|shard| shard.len()).sum::<usize>(), 3);
+    }
+}
@@ -0,0 +1,114 @@
+//! Placeholders in configurations, e.g. `tenant=${TENANT}`, see `Placeholders`
+
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::env;
+use std::prelude::v1::*;
+
+use KVFilterError;
+
+/// Values of the `${NAME}` placeholders of a configuration, resolved when it's loaded, so
+/// that configurations of several environments can be one, e.g.
+/// `warn,deploy_env=${DEPLOY_ENV}`, see `KVFilter::directives_with` and
+/// `RemoteConfig::placeholders`.
+///
+/// A placeholder not given a value is looked up in the environment, if enabled by
+/// `from_env`, and is an error otherwise. `$$` stands for a `$`.
+#[derive(Clone, Debug, Default)]
+pub struct Placeholders {
+    values: HashMap<String, String>,
+    #[cfg(feature = "std")]
+    env: bool,
+}
+
+impl Placeholders {
+    /// placeholders with no values
+    pub fn new() -> Self {
+        Placeholders::default()
+    }
+
+    /// placeholders resolved from the environment variables of their names
+    #[cfg(feature = "std")]
+    pub fn from_env() -> Self {
+        Placeholders {
+            env: true,
+            ..Placeholders::default()
+        }
+    }
+
+    /// resolve `${name}` to `value`, rather than from the environment
+    pub fn value<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.values.insert(name.into(), value.into());
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Option<String> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            #[cfg(feature = "std")]
+            None if self.env => env::var(name).ok(),
+            None => None,
+        }
+    }
+
+    /// `text` with its placeholders replaced by their values
+    pub fn resolve(&self, text: &str) -> Result<String, KVFilterError> {
+        let mut resolved = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(index) = rest.find('$') {
+            resolved.push_str(&rest[..index]);
+            rest = &rest[index + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                resolved.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let end = after
+                    .find('}')
+                    .ok_or_else(|| KVFilterError::InvalidPlaceholder(format!("${}", rest)))?;
+                let name = &after[..end];
+                if name.is_empty() {
+                    return Err(KVFilterError::InvalidPlaceholder("${}".to_owned()));
+                }
+                let value = self
+                    .lookup(name)
+                    .ok_or_else(|| KVFilterError::UndefinedPlaceholder(name.to_owned()))?;
+                resolved.push_str(&value);
+                rest = &after[end + 1..];
+            } else {
+                resolved.push('$');
+            }
+        }
+        resolved.push_str(rest);
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Placeholders;
+    use KVFilterError;
+
+    #[test]
+    fn placeholders() {
+        let placeholders = Placeholders::new().value("DEPLOY_ENV", "staging").value("HOSTNAME", "web1");
+
+        assert_eq!(
+            placeholders.resolve("warn,env=${DEPLOY_ENV},host=${HOSTNAME}").unwrap(),
+            "warn,env=staging,host=web1"
+        );
+        assert_eq!(placeholders.resolve("price=$$5,$x").unwrap(), "price=$5,$x");
+        match placeholders.resolve("region=${REGION}") {
+            Err(KVFilterError::UndefinedPlaceholder(ref name)) if name == "REGION" => {}
+            other => panic!("resolved to {:?}", other),
+        }
+        assert!(placeholders.resolve("env=${DEPLOY_ENV").is_err());
+        assert!(placeholders.resolve("env=${}").is_err());
+
+        let path = ::std::env::var("PATH").unwrap();
+        assert_eq!(Placeholders::from_env().resolve("${PATH}").unwrap(), path);
+        assert_eq!(
+            Placeholders::from_env().value("PATH", "given").resolve("${PATH}").unwrap(),
+            "given"
+        );
+    }
+}
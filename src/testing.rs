@@ -0,0 +1,451 @@
+//! Drains for asserting on filter behavior in tests, e.g.
+//!
+//! ```
+//! # #[macro_use] extern crate slog;
+//! # extern crate slog_kvfilter;
+//! use slog::{Drain, Level, Logger};
+//! use slog_kvfilter::testing::CountingDrain;
+//! use slog_kvfilter::KVFilter;
+//!
+//! # fn main() {
+//! let drain = CountingDrain::new();
+//! let log = Logger::root(KVFilter::new(drain.clone(), Level::Info).fuse(), o!());
+//! info!(log, "passing");
+//! assert_eq!(drain.count(), 1);
+//! # }
+//! ```
+
+use std::fmt;
+use std::prelude::v1::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog;
+
+use clock::Clock;
+use sync::Mutex;
+use KVMap;
+
+/// Drain counting the records that made it through; clones share the count
+#[derive(Clone, Default)]
+pub struct CountingDrain {
+    count: Arc<AtomicUsize>,
+}
+
+impl CountingDrain {
+    pub fn new() -> Self {
+        CountingDrain::default()
+    }
+
+    /// number of records logged so far
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl slog::Drain for CountingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, _: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Level, message and key values of a record captured by `CapturingDrain`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedRecord {
+    pub level: slog::Level,
+    pub message: String,
+    /// key values of the record followed by those of the logger context
+    pub key_values: Vec<(String, String)>,
+}
+
+impl CapturedRecord {
+    fn new(record: &slog::Record, logger_values: &slog::OwnedKVList) -> Self {
+        let mut message = String::new();
+        let _ = fmt::write(&mut message, *record.msg());
+
+        CapturedRecord {
+            level: record.level(),
+            message,
+            key_values: KVMap::new(record, logger_values)
+                .into_vec()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        }
+    }
+
+    /// value of `key`, the one closest to the record if given several times
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.key_values
+            .iter()
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// whether the record has all of `key_values`
+    pub fn has_all(&self, key_values: &[(&str, &str)]) -> bool {
+        key_values
+            .iter()
+            .all(|&(key, value)| self.get(key) == Some(value))
+    }
+}
+
+/// Drain capturing the level and message of the records that made it through; clones
+/// share the captured records
+#[derive(Clone, Default)]
+pub struct CapturingDrain {
+    records: Arc<Mutex<Vec<CapturedRecord>>>,
+}
+
+impl CapturingDrain {
+    pub fn new() -> Self {
+        CapturingDrain::default()
+    }
+
+    /// records logged so far, oldest first
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.records.lock().clone()
+    }
+
+    /// messages of the records logged so far, oldest first
+    pub fn messages(&self) -> Vec<String> {
+        self.records().into_iter().map(|r| r.message).collect()
+    }
+
+    /// records logged so far with `key` of `value`, see `CapturedRecord::get`
+    pub fn records_with(&self, key: &str, value: &str) -> Vec<CapturedRecord> {
+        self.records_where(|r| r.has_all(&[(key, value)]))
+    }
+
+    /// records logged so far at `level`
+    pub fn records_at(&self, level: slog::Level) -> Vec<CapturedRecord> {
+        self.records_where(|r| r.level == level)
+    }
+
+    /// records logged so far satisfying `predicate`
+    pub fn records_where<F: Fn(&CapturedRecord) -> bool>(&self, predicate: F) -> Vec<CapturedRecord> {
+        self.records().into_iter().filter(|r| predicate(r)).collect()
+    }
+
+    /// forget the records logged so far
+    pub fn clear(&self) {
+        self.records.lock().clear();
+    }
+}
+
+/// `Clock` standing still until advanced, for testing rules expiring after their TTL, caps
+/// and time windows without sleeping; clones share the time
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    now: Arc<Mutex<Duration>>,
+    /// `Clock::since_epoch` when created
+    epoch: Duration,
+}
+
+impl ManualClock {
+    /// clock starting at the Unix epoch
+    pub fn new() -> Self {
+        ManualClock::default()
+    }
+
+    /// clock starting at `since_epoch` after the Unix epoch, e.g. within the time window
+    /// of a rule
+    pub fn starting_at(since_epoch: Duration) -> Self {
+        ManualClock {
+            epoch: since_epoch,
+            ..ManualClock::default()
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock();
+        *now = now.saturating_add(by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock()
+    }
+
+    fn since_epoch(&self) -> Duration {
+        self.epoch + self.now()
+    }
+}
+
+/// `CapturingDrain` under the name mocking libraries use
+pub type MockDrain = CapturingDrain;
+
+impl slog::Drain for CapturingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        let captured = CapturedRecord::new(record, logger_values);
+
+        self.records.lock().push(captured);
+        Ok(())
+    }
+}
+
+/// How many records an expectation of `AssertingDrain` allows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expected {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expected::Exactly(count) => write!(f, "exactly {}", count),
+            Expected::AtLeast(count) => write!(f, "at least {}", count),
+        }
+    }
+}
+
+struct Expectation {
+    key_values: Vec<(String, String)>,
+    expected: Expected,
+}
+
+/// Drain checking that the records made it through as expected, e.g. that exactly one
+/// record with `event=checkout` passed.
+///
+/// Expectations are given upfront, records are captured as they come and `verify` panics
+/// with the unmet expectations and the captured records. Clones share the records.
+#[derive(Clone, Default)]
+pub struct AssertingDrain {
+    captured: CapturingDrain,
+    expectations: Arc<Mutex<Vec<Expectation>>>,
+}
+
+impl AssertingDrain {
+    pub fn new() -> Self {
+        AssertingDrain::default()
+    }
+
+    /// expect `expected` records with all of `key_values`, a record can satisfy several
+    /// expectations
+    pub fn expect(self, expected: Expected, key_values: &[(&str, &str)]) -> Self {
+        self.expectations.lock().push(Expectation {
+            key_values: key_values
+                .iter()
+                .map(|&(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            expected,
+        });
+        self
+    }
+
+    /// records captured so far, see `CapturingDrain::records`
+    pub fn records(&self) -> Vec<CapturedRecord> {
+        self.captured.records()
+    }
+
+    /// panic unless all the expectations are met
+    pub fn verify(&self) {
+        let records = self.records();
+        let expectations = self.expectations.lock();
+
+        let mut unmet = String::new();
+        for expectation in expectations.iter() {
+            let key_values: Vec<(&str, &str)> = expectation
+                .key_values
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            let count = records.iter().filter(|r| r.has_all(&key_values)).count();
+            let met = match expectation.expected {
+                Expected::Exactly(expected) => count == expected,
+                Expected::AtLeast(expected) => count >= expected,
+            };
+            if !met {
+                unmet.push_str(&format!(
+                    "  expected {} records with {:?}, got {}\n",
+                    expectation.expected, key_values, count
+                ));
+            }
+        }
+
+        if !unmet.is_empty() {
+            let mut captured = String::new();
+            for record in &records {
+                captured.push_str(&format!(
+                    "  {} {} {:?}\n",
+                    record.level.as_short_str(),
+                    record.message,
+                    record.key_values
+                ));
+            }
+            panic!(
+                "unmet log expectations:\n{}captured records:\n{}",
+                unmet, captured
+            );
+        }
+    }
+}
+
+impl slog::Drain for AssertingDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record,
+        logger_values: &slog::OwnedKVList,
+    ) -> Result<(), slog::Never> {
+        self.captured.log(record, logger_values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AssertingDrain, CapturedRecord, CapturingDrain, Expected, ManualClock, MockDrain};
+    use slog::{Drain, Level, Logger};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use {KVFilter, Rule};
+
+    #[test]
+    /// records passing the filter are captured in order
+    fn capturingdrain() {
+        let drain = CapturingDrain::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info).min_level(Level::Info);
+        let log = Logger::root(filter.fuse(), o!());
+
+        debug!(log, "suppressed");
+        warn!(log, "first {}", 1);
+        info!(log.new(o!("thread" => 100)), "second"; "packet" => "send");
+
+        assert_eq!(
+            drain.records(),
+            vec![
+                CapturedRecord {
+                    level: Level::Warning,
+                    message: "first 1".to_string(),
+                    key_values: vec![],
+                },
+                CapturedRecord {
+                    level: Level::Info,
+                    message: "second".to_string(),
+                    key_values: vec![
+                        ("packet".to_string(), "send".to_string()),
+                        ("thread".to_string(), "100".to_string()),
+                    ],
+                },
+            ]
+        );
+        assert_eq!(drain.messages(), vec!["first 1", "second"]);
+    }
+
+    #[test]
+    /// rules expire by the manual clock
+    fn manualclock() {
+        let drain = CapturingDrain::new();
+        let clock = ManualClock::new();
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Info)
+            .clock(Arc::new(clock.clone()));
+        filter.handle().add_rule(
+            Rule::pass("tenant", "acme", Level::Debug),
+            Some(Duration::from_secs(15 * 60)),
+        );
+        let log = Logger::root(filter.fuse(), o!("tenant" => "acme"));
+
+        debug!(log, "rule active");
+        clock.advance(Duration::from_secs(14 * 60));
+        debug!(log, "rule still active");
+        clock.advance(Duration::from_secs(60));
+        debug!(log, "rule expired");
+
+        assert_eq!(drain.messages(), vec!["rule active", "rule still active"]);
+    }
+
+    #[test]
+    #[cfg(feature = "chrono")]
+    /// rules apply within their time window by the manual clock
+    fn scheduledmanualclock() {
+        use chrono::NaiveTime;
+        use Schedule;
+
+        let drain = CapturingDrain::new();
+        // 2024-01-01 00:30 UTC
+        let clock = ManualClock::starting_at(Duration::from_secs(1_704_067_200 + 30 * 60));
+        let filter = KVFilter::new(drain.clone(), Level::Info)
+            .min_level(Level::Info)
+            .clock(Arc::new(clock.clone()));
+        let time = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+        let nightly = Schedule::daily(time(1), time(3));
+        filter
+            .handle()
+            .add_rule(Rule::pass("tenant", "acme", Level::Debug).during(nightly), None);
+        let log = Logger::root(filter.fuse(), o!("tenant" => "acme"));
+
+        debug!(log, "before the window");
+        clock.advance(Duration::from_secs(60 * 60));
+        debug!(log, "within the window");
+        clock.advance(Duration::from_secs(2 * 60 * 60));
+        debug!(log, "after the window");
+
+        assert_eq!(drain.messages(), vec!["within the window"]);
+    }
+
+    #[test]
+    /// captured records are queried by their key values and levels
+    fn mockdrain() {
+        let drain = MockDrain::new();
+        let log = Logger::root(drain.clone().fuse(), o!());
+        let acme = log.new(o!("tenant" => "acme"));
+
+        info!(acme, "first");
+        warn!(acme, "second"; "tenant" => "globex");
+        info!(log, "third"; "tenant" => "acme");
+
+        let messages = |records: Vec<CapturedRecord>| -> Vec<String> {
+            records.into_iter().map(|r| r.message).collect()
+        };
+        assert_eq!(messages(drain.records_with("tenant", "acme")), vec!["first", "third"]);
+        assert_eq!(messages(drain.records_with("tenant", "globex")), vec!["second"]);
+        assert_eq!(messages(drain.records_at(Level::Warning)), vec!["second"]);
+
+        drain.clear();
+        assert!(drain.records().is_empty());
+    }
+
+    fn checkout(drain: &AssertingDrain) {
+        let log = Logger::root(drain.clone().fuse(), o!("service" => "shop"));
+        info!(log, "checkout"; "event" => "checkout");
+        info!(log, "browse"; "event" => "browse");
+        info!(log, "browse"; "event" => "browse");
+    }
+
+    #[test]
+    /// met expectations verify
+    fn assertingdrain() {
+        let drain = AssertingDrain::new()
+            .expect(Expected::Exactly(1), &[("event", "checkout"), ("service", "shop")])
+            .expect(Expected::AtLeast(1), &[("event", "browse")])
+            .expect(Expected::Exactly(0), &[("event", "refund")]);
+        checkout(&drain);
+        drain.verify();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected exactly 1 records with [(\"event\", \"browse\")], got 2")]
+    /// unmet expectations panic
+    fn assertingdrainunmet() {
+        let drain = AssertingDrain::new().expect(Expected::Exactly(1), &[("event", "browse")]);
+        checkout(&drain);
+        drain.verify();
+    }
+}
@@ -0,0 +1,190 @@
+//! Comparisons of durations and sizes written with units, see `ValuePattern::Threshold`
+
+use std::fmt;
+use std::prelude::v1::*;
+use std::str::FromStr;
+
+use KVFilterError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Cmp {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Quantity {
+    /// in nanoseconds
+    Duration,
+    /// in bytes
+    Size,
+}
+
+/// units with the number of nanoseconds or bytes in them
+const UNITS: &[(&str, Quantity, u64)] = &[
+    ("ns", Quantity::Duration, 1),
+    ("us", Quantity::Duration, 1_000),
+    ("µs", Quantity::Duration, 1_000),
+    ("ms", Quantity::Duration, 1_000_000),
+    ("s", Quantity::Duration, 1_000_000_000),
+    ("m", Quantity::Duration, 60_000_000_000),
+    ("min", Quantity::Duration, 60_000_000_000),
+    ("h", Quantity::Duration, 3_600_000_000_000),
+    ("d", Quantity::Duration, 86_400_000_000_000),
+    ("b", Quantity::Size, 1),
+    ("kb", Quantity::Size, 1_000),
+    ("mb", Quantity::Size, 1_000_000),
+    ("gb", Quantity::Size, 1_000_000_000),
+    ("tb", Quantity::Size, 1_000_000_000_000),
+    ("kib", Quantity::Size, 1 << 10),
+    ("mib", Quantity::Size, 1 << 20),
+    ("gib", Quantity::Size, 1 << 30),
+    ("tib", Quantity::Size, 1 << 40),
+];
+
+/// unit of `unit`, sizes case insensitively
+fn unit(unit: &str) -> Option<(Quantity, u64)> {
+    UNITS
+        .iter()
+        .find(|&&(name, quantity, _)| match quantity {
+            Quantity::Duration => name == unit,
+            Quantity::Size => name.eq_ignore_ascii_case(unit),
+        })
+        .map(|&(_, quantity, scale)| (quantity, scale))
+}
+
+/// `value` split into the number and its unit, if any
+fn parse_quantity(value: &str) -> Option<(f64, Option<(Quantity, u64)>)> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e'))
+        .unwrap_or(value.len());
+    let number: f64 = value[..split].parse().ok()?;
+    match value[split..].trim() {
+        "" => Some((number, None)),
+        name => unit(name).map(|unit| (number, Some(unit))),
+    }
+}
+
+/// Comparison of a value with a duration or a size, e.g. `> 1.5s` or `>= 10MiB`, by one of
+/// `>`, `>=`, `<`, `<=` or `=`.
+///
+/// Durations are in `ns`, `us` or `µs`, `ms`, `s`, `m` or `min`, `h` or `d`, sizes in `B`,
+/// the decimal `KB`, `MB`, `GB`, `TB` or the binary `KiB`, `MiB`, `GiB`, `TiB`. Values are
+/// compared if they are written with a unit of the same kind, e.g. `1.7s` or `250ms` as
+/// `Duration`s debug format them, or if they are bare numbers, taken as seconds or bytes,
+/// e.g. of `emit_u64`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Threshold {
+    cmp: Cmp,
+    quantity: Quantity,
+    /// in nanoseconds or bytes
+    limit: u64,
+    source: String,
+}
+
+impl Threshold {
+    /// whether `value` compares with the threshold as required
+    pub fn matches_str(&self, value: &str) -> bool {
+        self.compare_str(value).unwrap_or(false)
+    }
+
+    /// whether `value` compares with the threshold as required, `None` if it isn't a
+    /// quantity of the same kind
+    pub fn compare_str(&self, value: &str) -> Option<bool> {
+        let value = match parse_quantity(value)? {
+            (number, Some((quantity, scale))) if quantity == self.quantity => number * scale as f64,
+            (number, None) => match self.quantity {
+                Quantity::Duration => number * 1e9,
+                Quantity::Size => number,
+            },
+            _ => return None,
+        };
+
+        let limit = self.limit as f64;
+        Some(match self.cmp {
+            Cmp::Gt => value > limit,
+            Cmp::Ge => value >= limit,
+            Cmp::Lt => value < limit,
+            Cmp::Le => value <= limit,
+            Cmp::Eq => value == limit,
+        })
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = KVFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KVFilterError::InvalidThreshold(s.to_owned());
+        let trimmed = s.trim();
+        let (cmp, rest) = [
+            (">=", Cmp::Ge),
+            ("<=", Cmp::Le),
+            (">", Cmp::Gt),
+            ("<", Cmp::Lt),
+            ("=", Cmp::Eq),
+        ]
+        .iter()
+        .find_map(|&(op, cmp)| trimmed.strip_prefix(op).map(|rest| (cmp, rest)))
+        .ok_or_else(invalid)?;
+
+        let (number, (quantity, scale)) = match parse_quantity(rest) {
+            Some((number, Some(unit))) if number >= 0.0 => (number, unit),
+            _ => return Err(invalid()),
+        };
+        let limit = number * scale as f64;
+        if limit > u64::MAX as f64 {
+            return Err(invalid());
+        }
+
+        Ok(Threshold {
+            cmp,
+            quantity,
+            // rounded, `f64::round` needing `std`, the limit not being negative
+            limit: (limit + 0.5) as u64,
+            source: trimmed.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Threshold;
+    use std::time::Duration;
+
+    fn matching(threshold: &str, values: &[&str]) -> Vec<bool> {
+        let threshold: Threshold = threshold.parse().unwrap();
+        values.iter().map(|value| threshold.matches_str(value)).collect()
+    }
+
+    #[test]
+    fn thresholds() {
+        let slow = format!("{:?}", Duration::from_millis(1700));
+        assert_eq!(
+            matching("> 1.5s", &[&slow, "250ms", "1.5s", "2", "2 min", "3MB", "slow"]),
+            vec![true, false, false, true, true, false, false]
+        );
+        assert_eq!(matching(">=1.5s", &["1500ms", "1499999us"]), vec![true, false]);
+        assert_eq!(
+            matching(">= 10MiB", &["10485760", "10MB", "12 mib", "1GB", "10s"]),
+            vec![true, false, true, true, false]
+        );
+        assert_eq!(matching("< 1KiB", &["1023", "1.5kb"]), vec![true, false]);
+        assert_eq!(matching("= 1h", &["60m", "3600s", "61min"]), vec![true, true, false]);
+
+        assert!("1.5s".parse::<Threshold>().is_err());
+        assert!("> 1.5".parse::<Threshold>().is_err());
+        assert!("> 1.5 parsecs".parse::<Threshold>().is_err());
+        assert!("> -1s".parse::<Threshold>().is_err());
+    }
+}
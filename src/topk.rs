@@ -0,0 +1,97 @@
+//! Key values carried by the most suppressed records, counted in bounded space, see
+//! `KVFilter::track_top_suppressed`
+
+use std::prelude::v1::*;
+
+use stats::TopSuppressed;
+use FilterMap;
+
+/// pairs counted for each one reported, so that the counts of the reported ones are close
+const COUNTED_PER_REPORTED: usize = 10;
+
+/// Space-Saving sketch of the most frequent pairs of a key and a value: a pair not counted
+/// yet replaces the least counted one once full, taking over its count, which bounds how
+/// much the pair is overcounted by
+#[derive(Default)]
+pub struct TopValues {
+    /// number of pairs reported
+    k: usize,
+    /// count of each pair, and the count it took over
+    counts: FilterMap<(String, String), (u64, u64)>,
+}
+
+impl TopValues {
+    pub fn new(k: usize) -> Self {
+        TopValues {
+            k,
+            counts: FilterMap::default(),
+        }
+    }
+
+    pub fn count(&mut self, key: &str, value: String) {
+        let pair = (key.to_owned(), value);
+        if let Some(count) = self.counts.get_mut(&pair) {
+            count.0 += 1;
+            return;
+        }
+
+        let mut taken_over = 0;
+        if self.counts.len() >= self.k * COUNTED_PER_REPORTED {
+            let least = self
+                .counts
+                .iter()
+                .min_by_key(|&(_, &(count, _))| count)
+                .map(|(pair, &(count, _))| (pair.clone(), count));
+            match least {
+                Some((least, count)) => {
+                    self.counts.remove(&least);
+                    taken_over = count;
+                }
+                None => return,
+            }
+        }
+        self.counts.insert(pair, (taken_over + 1, taken_over));
+    }
+
+    /// the `k` most counted pairs, the most counted first
+    pub fn top(&self) -> Vec<TopSuppressed> {
+        let mut top: Vec<_> = self
+            .counts
+            .iter()
+            .map(|((key, value), &(suppressed, error))| TopSuppressed {
+                key: key.clone(),
+                value: value.clone(),
+                suppressed,
+                error,
+            })
+            .collect();
+        top.sort_by(|a, b| {
+            b.suppressed
+                .cmp(&a.suppressed)
+                .then_with(|| (&a.key, &a.value).cmp(&(&b.key, &b.value)))
+        });
+        top.truncate(self.k);
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopValues;
+
+    #[test]
+    fn spacesaving() {
+        let mut top = TopValues::new(1);
+        for i in 0..1000 {
+            top.count("tenant", "bigcorp".to_owned());
+            // more values than counted, each one replacing another
+            top.count("request_id", i.to_string());
+        }
+        assert_eq!(top.counts.len(), 10);
+
+        let top = top.top();
+        assert_eq!(top.len(), 1);
+        assert_eq!((top[0].key.as_str(), top[0].value.as_str()), ("tenant", "bigcorp"));
+        assert!(top[0].suppressed >= 1000 && top[0].suppressed - top[0].error <= 1000);
+    }
+}
@@ -0,0 +1,325 @@
+//! `tracing_subscriber` filter deciding events by a `KVFilter`, so that a single
+//! configuration filters both slog and tracing
+
+use std::fmt;
+use std::sync::Arc;
+
+use slog;
+use slog::KV;
+use tracing_core::field::{Field, Visit};
+use tracing_core::span;
+use tracing_core::subscriber::Interest;
+use tracing_core::{Event, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+use combinators::SerializedKV;
+use sync::RwLock;
+use {KVFilter, KVPredicate};
+
+/// per-layer `Filter` passing the tracing events that `filter` would pass as slog records.
+///
+/// The fields of an event are its key values, and the fields of its enclosing spans make
+/// up its logger context. The `message` field is the message of the record, and the target
+/// of the event its tag. The logger context of a span is built once it's created, and the
+/// fields of events are matched where they are, so that deciding an event copies nothing.
+///
+/// ```
+/// # extern crate slog;
+/// # extern crate slog_kvfilter;
+/// # extern crate tracing_subscriber;
+/// use slog_kvfilter::{filter_list, KVFilter, TracingFilter};
+/// use tracing_subscriber::layer::{Layer, SubscriberExt};
+///
+/// # fn main() {
+/// # let layer = tracing_subscriber::layer::Identity::new();
+/// let filter = KVFilter::new(slog::Discard, slog::Level::Info)
+///     .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])));
+/// let subscriber = tracing_subscriber::registry().with(layer.with_filter(TracingFilter::new(filter)));
+/// # drop(subscriber);
+/// # }
+/// ```
+pub struct TracingFilter {
+    filter: KVFilter<slog::Discard>,
+    /// logger context of the events outside of any span
+    root: slog::OwnedKVList,
+}
+
+impl TracingFilter {
+    pub fn new(filter: KVFilter<slog::Discard>) -> Self {
+        TracingFilter {
+            filter,
+            root: slog::OwnedKVList::from(slog::OwnedKV(())),
+        }
+    }
+}
+
+/// fields of a span, recorded when it's created and later, shared with the logger contexts
+/// of the spans it encloses
+#[derive(Clone)]
+struct SpanFields(Arc<RwLock<SerializedKV>>);
+
+impl KV for SpanFields {
+    fn serialize(&self, record: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        self.0.read().serialize(record, serializer)
+    }
+}
+
+/// stored in the extensions of a span, the logger context of its events: its fields, then
+/// those of its enclosing spans, so that no context is built for each event
+struct SpanContext {
+    fields: SpanFields,
+    context: slog::OwnedKVList,
+}
+
+/// fields of an event, emitted straight to the serializer of the filter
+struct EventKV<'a>(&'a Event<'a>);
+
+impl<'a> KV for EventKV<'a> {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        let mut visitor = FieldVisitor {
+            serializer,
+            result: Ok(()),
+        };
+        self.0.record(&mut visitor);
+        visitor.result
+    }
+}
+
+/// emits fields as slog key values, numbers and booleans by their typed emits, setting
+/// aside the message
+struct FieldVisitor<'a> {
+    serializer: &'a mut dyn slog::Serializer,
+    /// the first error of the serializer, after which the other fields are skipped
+    result: slog::Result,
+}
+
+impl<'a> FieldVisitor<'a> {
+    fn emit<F: FnOnce(&mut dyn slog::Serializer, slog::Key) -> slog::Result>(&mut self, field: &Field, emit: F) {
+        if field.name() != "message" && self.result.is_ok() {
+            self.result = emit(&mut *self.serializer, field.name());
+        }
+    }
+}
+
+impl<'a> Visit for FieldVisitor<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.emit(field, |serializer, key| serializer.emit_str(key, value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.emit(field, |serializer, key| serializer.emit_u64(key, value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.emit(field, |serializer, key| serializer.emit_i64(key, value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.emit(field, |serializer, key| serializer.emit_f64(key, value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.emit(field, |serializer, key| serializer.emit_bool(key, value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.emit(field, |serializer, key| serializer.emit_arguments(key, &format_args!("{:?}", value)));
+    }
+}
+
+/// the `message` field of an event, formatted only if the message is matched
+struct EventMessage<'a>(&'a Event<'a>);
+
+impl<'a> fmt::Display for EventMessage<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut visitor = MessageVisitor { f, result: Ok(()) };
+        self.0.record(&mut visitor);
+        visitor.result
+    }
+}
+
+struct MessageVisitor<'a, 'b: 'a> {
+    f: &'a mut fmt::Formatter<'b>,
+    result: fmt::Result,
+}
+
+impl<'a, 'b> Visit for MessageVisitor<'a, 'b> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.result = self.f.write_str(value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.result = write!(self.f, "{:?}", value);
+        }
+    }
+}
+
+fn level(level: &tracing_core::Level) -> slog::Level {
+    match *level {
+        tracing_core::Level::ERROR => slog::Level::Error,
+        tracing_core::Level::WARN => slog::Level::Warning,
+        tracing_core::Level::INFO => slog::Level::Info,
+        tracing_core::Level::DEBUG => slog::Level::Debug,
+        tracing_core::Level::TRACE => slog::Level::Trace,
+    }
+}
+
+static LOCATION: slog::RecordLocation = slog::RecordLocation {
+    file: "",
+    line: 0,
+    column: 0,
+    function: "",
+    module: "",
+};
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Filter<S> for TracingFilter {
+    fn enabled(&self, metadata: &Metadata, _: &Context<S>) -> bool {
+        metadata.is_span() || self.filter.enabled_level(level(metadata.level())).is_some()
+    }
+
+    fn callsite_enabled(&self, _: &'static Metadata<'static>) -> Interest {
+        // the minimum level may change at runtime, so don't let tracing cache the decision
+        Interest::sometimes()
+    }
+
+    fn event_enabled(&self, event: &Event, cx: &Context<S>) -> bool {
+        let metadata = event.metadata();
+
+        // the innermost span with fields recorded holds those of the enclosing ones too
+        let span = cx
+            .event_scope(event)
+            .into_iter()
+            .flatten()
+            .find(|span| span.extensions().get::<SpanContext>().is_some());
+        let extensions = span.as_ref().map(|span| span.extensions());
+        let context = extensions
+            .as_ref()
+            .and_then(|extensions| extensions.get::<SpanContext>())
+            .map_or(&self.root, |span| &span.context);
+
+        let rs = slog::RecordStatic {
+            location: &LOCATION,
+            tag: metadata.target(),
+            level: level(metadata.level()),
+        };
+        let kv = EventKV(event);
+        KVPredicate::is_match(
+            &self.filter,
+            &slog::Record::new(&rs, &format_args!("{}", EventMessage(event)), slog::BorrowedKV(&kv)),
+            context,
+        )
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes, id: &span::Id, cx: Context<S>) {
+        if let Some(span) = cx.span(id) {
+            let mut fields = SerializedKV(Vec::new());
+            attrs.record(&mut FieldVisitor {
+                serializer: &mut fields,
+                result: Ok(()),
+            });
+            let fields = SpanFields(Arc::new(RwLock::new(fields)));
+
+            let parent = span
+                .scope()
+                .skip(1)
+                .find_map(|parent| parent.extensions().get::<SpanContext>().map(|parent| parent.context.clone()))
+                .unwrap_or_else(|| self.root.clone());
+            let context = slog::OwnedKVList::from(slog::OwnedKV((fields.clone(), parent)));
+            span.extensions_mut().insert(SpanContext { fields, context });
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record, cx: Context<S>) {
+        if let Some(span) = cx.span(id) {
+            if let Some(span_context) = span.extensions().get::<SpanContext>() {
+                values.record(&mut FieldVisitor {
+                    serializer: &mut *span_context.fields.0.write(),
+                    result: Ok(()),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TracingFilter;
+    use slog::{self, Level};
+    use regex::Regex;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing;
+    use tracing_core::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry;
+    use {filter_list, KVFilter, ValuePattern};
+
+    struct CountingLayer(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for CountingLayer {
+        fn on_event(&self, _: &Event, _: Context<S>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn tracingfilter() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let filter = KVFilter::new(slog::Discard, Level::Info)
+            .min_level(Level::Debug)
+            .only_pass_any_on_all_keys(Some(filter_list(vec![("tenant", vec!["acme"])])))
+            .always_suppress_any(Some(filter_list(vec![("deepcomp", vec!["1"])])));
+        let subscriber = registry().with(CountingLayer(count.clone()).with_filter(TracingFilter::new(filter)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::trace!(tenant = "acme", "below the minimum level");
+            tracing::debug!(tenant = "acme", "passing on the event fields");
+            tracing::debug!(tenant = "other", "not matching");
+            tracing::debug!(tenant = "acme", deepcomp = 1, "suppressed");
+            tracing::warn!("unfiltered level");
+
+            let span = tracing::info_span!("request", tenant = "acme");
+            let _entered = span.enter();
+            tracing::debug!("passing on the span fields");
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    /// numbers and booleans of events and spans, and span fields recorded later
+    fn tracingfiltertyped() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let mut patterns = HashMap::new();
+        patterns.insert("packet".to_owned(), vec![ValuePattern::Number(42u64.into())]);
+        patterns.insert("retried".to_owned(), vec![ValuePattern::Bool(true)]);
+        let filter = KVFilter::new(slog::Discard, Level::Info)
+            .only_pass_any_pattern_on_all_keys(Some(patterns))
+            .always_suppress_on_regex(Regex::new("^ignored").unwrap());
+        let subscriber = registry().with(CountingLayer(count.clone()).with_filter(TracingFilter::new(filter)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(packet = 42u64, retried = true, "passing");
+            tracing::info!(packet = 42i64, retried = true, "ignored by the message");
+            tracing::info!(packet = "42", retried = true, "not passing, text");
+            tracing::info!(packet = 42u64, retried = "true", "not passing, text");
+
+            let outer = tracing::info_span!("request", retried = tracing::field::Empty);
+            let _outer = outer.enter();
+            let inner = tracing::info_span!("send", packet = 42u64);
+            let _inner = inner.enter();
+            tracing::info!("not passing, not retried yet");
+            outer.record("retried", true);
+            tracing::info!("passing on the fields of both spans");
+        });
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+}
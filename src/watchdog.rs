@@ -0,0 +1,118 @@
+//! Detection of filters suppressing everything for a while, see `KVFilter::watch_starvation`
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use slog;
+use slog::KV;
+
+/// What `KVFilter::watch_starvation` does once no record passed for a while
+#[derive(Clone)]
+pub enum StarvationAction {
+    /// log a `Warning` record with the `Starvation` to the drain of the filter, unfiltered
+    Warn,
+    /// call the function with the `Starvation`, on the thread logging the record that
+    /// revealed it
+    Callback(Arc<dyn Fn(&Starvation) + Send + Sync>),
+}
+
+impl fmt::Debug for StarvationAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StarvationAction::Warn => f.write_str("Warn"),
+            StarvationAction::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+/// Filter suppressing all the records for a while, see `KVFilter::watch_starvation`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Starvation {
+    /// time since a record last passed, or since the first record came
+    pub silent_for: Duration,
+    /// records suppressed meanwhile
+    pub suppressed: u64,
+}
+
+impl KV for Starvation {
+    fn serialize(&self, _: &slog::Record, serializer: &mut dyn slog::Serializer) -> slog::Result {
+        serializer.emit_arguments("kvfilter_silent_for", &format_args!("{:?}", self.silent_for))?;
+        serializer.emit_u64("kvfilter_suppressed_meanwhile", self.suppressed)
+    }
+}
+
+/// `last_passed` before any record came
+const UNSEEN: u64 = u64::MAX;
+
+/// State of the watch, shared by the clones of a filter
+pub struct Watchdog {
+    after: Duration,
+    pub action: StarvationAction,
+    /// `Clock::now` in nanoseconds when a record last passed, or the first record came,
+    /// `UNSEEN` before
+    last_passed: AtomicU64,
+    suppressed: AtomicU64,
+    /// whether the current starvation was reported already
+    reported: AtomicBool,
+}
+
+impl Watchdog {
+    pub fn new(after: Duration, action: StarvationAction) -> Self {
+        Watchdog {
+            after,
+            action,
+            last_passed: AtomicU64::new(UNSEEN),
+            suppressed: AtomicU64::new(0),
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    pub fn passed(&self, now: Duration) {
+        self.last_passed.store(nanos(now), Ordering::Relaxed);
+        self.suppressed.store(0, Ordering::Relaxed);
+        self.reported.store(false, Ordering::Relaxed);
+    }
+
+    /// the starvation a suppressed record reveals, reported once until a record passes
+    pub fn suppressed(&self, now: Duration) -> Option<Starvation> {
+        let suppressed = self.suppressed.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = nanos(now);
+        let last_passed = match self.last_passed.compare_exchange(UNSEEN, now, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => now,
+            Err(last_passed) => last_passed,
+        };
+        let silent_for = Duration::from_nanos(now.saturating_sub(last_passed));
+        if silent_for < self.after || self.reported.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        Some(Starvation { silent_for, suppressed })
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u128::from(u64::MAX)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StarvationAction, Watchdog};
+    use std::time::Duration;
+
+    #[test]
+    fn starvation() {
+        let secs = Duration::from_secs;
+        let watchdog = Watchdog::new(secs(60), StarvationAction::Warn);
+
+        assert_eq!(watchdog.suppressed(secs(10)), None);
+        assert_eq!(watchdog.suppressed(secs(30)), None);
+        let starvation = watchdog.suppressed(secs(71)).unwrap();
+        assert_eq!((starvation.silent_for, starvation.suppressed), (secs(61), 3));
+        assert_eq!(watchdog.suppressed(secs(120)), None);
+
+        watchdog.passed(secs(200));
+        assert_eq!(watchdog.suppressed(secs(259)), None);
+        assert_eq!(watchdog.suppressed(secs(260)).map(|s| s.suppressed), Some(2));
+    }
+}
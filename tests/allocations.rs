@@ -0,0 +1,111 @@
+//! The matching hot path must not allocate once warmed up.
+//!
+//! Counting is done by a global allocator, hence this lives in its own test binary.
+
+#[macro_use]
+extern crate slog;
+extern crate regex;
+#[macro_use]
+extern crate slog_kvfilter;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::iter::FromIterator;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+
+use regex::Regex;
+use slog::{Drain, Level, Logger};
+use slog_kvfilter::KVFilter;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+fn filter_list(key: &str, values: &[&str]) -> slog_kvfilter::KVFilterList {
+    vec![(
+        key.to_string(),
+        HashSet::from_iter(values.iter().map(|v| v.to_string())),
+    )].into_iter()
+        .collect()
+}
+
+fn log_records(log: &Logger, sublog: &Logger) {
+    info!(sublog, "passing"; "direction" => "send", "packet" => 42);
+    info!(sublog, "not passing"; "direction" => "receive");
+    info!(sublog, "not passing"; "direction" => "send", "deepcomp" => 1);
+    debug!(log, "not passing");
+}
+
+fn assert_no_allocations<F>(filter: F)
+where
+    F: Drain + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    F::Err: Debug,
+{
+    let log = Logger::root(filter.fuse(), o!("version" => "1"));
+    let sublog = log.new(o!("thread" => "100"));
+
+    // warm up the thread local buffers and caches
+    log_records(&log, &sublog);
+
+    let before = allocations();
+    for _ in 0..100 {
+        log_records(&log, &sublog);
+    }
+
+    assert_eq!(allocations() - before, 0);
+}
+
+#[test]
+fn matching_does_not_allocate() {
+    let filter = || {
+        KVFilter::new(slog::Discard, Level::Info)
+            .only_pass_any_on_all_keys(Some(
+                filter_list("thread", &["100", "200"])
+                    .into_iter()
+                    .chain(filter_list("direction", &["send"]))
+                    .collect(),
+            ))
+            .always_suppress_any(Some(filter_list("deepcomp", &["1", "2"])))
+    };
+
+    assert_no_allocations(filter());
+    assert_no_allocations(
+        filter()
+            .only_pass_on_regex(Regex::new("pass").unwrap())
+            .always_suppress_on_regex(Regex::new("not").unwrap()),
+    );
+}
+
+static_kvfilter! {
+    struct StaticFilter {
+        pass { "thread" => ["100", "200"], "direction" => ["send"] }
+        suppress { "deepcomp" => ["1", "2"] }
+    }
+}
+
+#[test]
+fn static_matching_does_not_allocate() {
+    assert_no_allocations(StaticFilter::new(slog::Discard, Level::Info));
+}